@@ -16,10 +16,31 @@ const WORLD_MODELS: &'static [WorldModel] = &[
     //include_world_model!("resources/models/triangle.glb"),
 ];
 
-/// TODO: output files to update on when changed
+/// The models that instances in the world models use, by their instance_mesh name
+const INSTANCE_MODELS: &'static [InstanceModel] = &[
+    InstanceModel::new("tree", include_world_model!("resources/models/tree.glb")),
+];
+
 fn main() {
+    // Tell cargo to rerun build.rs if any of the models change
+    for model in WORLD_MODELS {
+        println!("cargo:rerun-if-changed=./{}", model.filename());
+    }
+    for instance_model in INSTANCE_MODELS {
+        println!("cargo:rerun-if-changed=./{}", instance_model.model().filename());
+    }
+
     build_log!("Building world models");
-    // A hack because otherwise it tries to delete it later and fails
-    std::fs::create_dir_all(CHUNK_OUTPUT_DIR).unwrap();
-    WorldBuilder::new(CHUNK_OUTPUT_DIR, WORLD_MODELS).build_world_models();
+    let result = WorldBuilder::new(CHUNK_OUTPUT_DIR, WORLD_MODELS)
+        .with_instance_models(INSTANCE_MODELS)
+        .build_world_models();
+    match result {
+        Ok(summary) => build_log!("Built world: {}", summary),
+        Err(errors) => {
+            for err in errors.0.iter() {
+                build_log!("{}", err);
+            }
+            panic!("{}", errors);
+        }
+    }
 }
@@ -19,6 +19,18 @@ pub enum AttribBinding {
     Weights = 7
 }
 
+#[derive(EnumIter, Copy, Clone)]
 pub enum TextureSlot {
-    BaseColor = 0
+    BaseColor = 0,
+    Emissive = 1
+}
+
+impl TextureSlot {
+    /// Get the name of the sampler uniform for this slot in the standard shaders
+    pub fn sampler_name(&self) -> &'static str {
+        match self {
+            TextureSlot::BaseColor => "tex_base_color",
+            TextureSlot::Emissive => "tex_emissive"
+        }
+    }
 }
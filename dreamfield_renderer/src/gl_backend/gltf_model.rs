@@ -16,6 +16,7 @@ use super::{bindings, JointParams, Joint, ToStd140};
 use super::lights::LightType;
 use cgmath::{Matrix4, Vector3, Matrix};
 use serde::{Deserialize, Serialize};
+use dreamfield_system::world::texture_pipeline;
 
 pub use gltf_animation::{GltfAnimation, GltfAnimationKeyframe};
 use gltf_transform::{GltfTransformHierarchy, GltfTransform};
@@ -256,7 +257,7 @@ impl GltfModel {
 
         // Downsample if enabled
         if let Some(downsample_bits) = TEXTURE_BITS {
-            Texture::quantize_to_bit_depth(&mut pixels, downsample_bits);
+            texture_pipeline::quantize_to_bit_depth(&mut pixels, downsample_bits);
         }
 
         let (format, ty, pixels) = (gl::RGBA, gl::UNSIGNED_BYTE, &pixels);
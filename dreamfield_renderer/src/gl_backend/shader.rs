@@ -42,8 +42,9 @@ impl ShaderProgram {
             // Create ShaderProgram instance
             let program = ShaderProgram { id };
 
-            // Set standard uniform block bindings
+            // Set standard uniform block bindings and texture slots
             program.set_standard_uniform_block_bindings();
+            program.set_standard_texture_slots();
 
             program
         })
@@ -78,6 +79,16 @@ impl ShaderProgram {
         }
     }
 
+    /// Set the texture slots for all the standard samplers
+    fn set_standard_texture_slots(&self) {
+        for slot in bindings::TextureSlot::iter() {
+            let loc = self.get_loc(slot.sampler_name());
+            if loc != -1 {
+                unsafe { gl::ProgramUniform1i(self.id, loc, slot as i32) }
+            }
+        }
+    }
+
     /// Bind the shader program
     pub fn use_program(&self) {
         unsafe { gl::UseProgram(self.id) };
@@ -4,6 +4,7 @@ use std::error::Error;
 use gl::types::*;
 use image::DynamicImage;
 use image::io::Reader;
+use dreamfield_system::world::texture_pipeline::quantize_to_bit_depth;
 use super::bindings;
 
 /// A texture
@@ -58,7 +59,7 @@ impl Texture {
         let mut data = image_rgba8.into_vec();
 
         if let Some(downsample_to_bits) = downsample_to_bits {
-            Self::quantize_to_bit_depth(&mut data, downsample_to_bits);
+            quantize_to_bit_depth(&mut data, downsample_to_bits);
         }
 
         let (source_format, source_type) = (gl::RGBA, gl::UNSIGNED_BYTE);
@@ -99,6 +100,17 @@ impl Texture {
         }
     }
 
+    /// Set the wrap and filter params
+    pub fn set_params(&self, params: &TextureParams) {
+        unsafe {
+            gl::TextureParameteri(self.id, gl::TEXTURE_WRAP_S, params.horz_wrap as i32);
+            gl::TextureParameteri(self.id, gl::TEXTURE_WRAP_T, params.vert_wrap as i32);
+
+            gl::TextureParameteri(self.id, gl::TEXTURE_MIN_FILTER, params.min_filter as i32);
+            gl::TextureParameteri(self.id, gl::TEXTURE_MAG_FILTER, params.mag_filter as i32);
+        }
+    }
+
     /// Generate mipmaps
     pub fn gen_mipmaps(&self) {
         unsafe { gl::GenerateTextureMipmap(self.id) }
@@ -120,75 +132,6 @@ impl Texture {
         }
     }
 
-    /// Convert RGBA8 to RGBA5551
-    pub fn convert_rgba8_to_rgba5551(buf: &[u8], out_vec: &mut Vec<u8>) {
-        let input_bytes = buf.len();
-
-        if (input_bytes % 4) != 0 {
-            panic!("convert_rgba8_to_rgba5551: Input buffer needs to be a multiple of 4");
-        }
-
-        let output_bytes = input_bytes / 2;
-        out_vec.resize(output_bytes, 0);
-
-        for i in 0 .. buf.len() / 4 {
-            let mut r = buf[i * 4] as u16;
-            let mut g = buf[i * 4 + 1] as u16;
-            let mut b = buf[i * 4 + 2] as u16;
-            let mut a = buf[i * 4 + 3] as u16;
-
-            // Convert from 0..255 to 0..31
-            r = r * 31 / 255;
-            g = g * 31 / 255;
-            b = b * 31 / 255;
-
-            // Alpha maps 0 to 0, and any other value to 1
-            a = match a {
-                0 => 0,
-                _ => 1
-            };
-
-            // Now convert it back to 2 bytes
-            r = r << 11;
-            g = g << 6;
-            b = b << 1;
-            let converted = r | g | b | a;
-
-            out_vec[i * 2 + 1] = (converted >> 8) as u8;
-            out_vec[i * 2 + 0] = (converted & 0b11111111) as u8;
-        }
-    }
-
-    /// Quantize a RGBA888 texture to a certain bit depth, leaving it as RGBA8888
-    pub fn quantize_to_bit_depth(buf: &mut [u8], target_component_depth: u8) {
-        if target_component_depth < 1 || target_component_depth > 8 {
-            panic!("quantize_to_bit_depth: target_component_depth should be (1..8)");
-        }
-
-        if (buf.len() % 4) != 0 {
-            panic!("quantize_to_bit_depth: Input buffer needs to be a multiple of 4");
-        }
-
-        let multiplier = (1 << target_component_depth) as f32 / 255.0;
-
-        for i in 0 .. buf.len() / 4 {
-            let mut r = buf[i * 4] as f32;
-            let mut g = buf[i * 4 + 1] as f32;
-            let mut b = buf[i * 4 + 2] as f32;
-            let mut a = buf[i * 4 + 3] as f32;
-
-            r = f32::floor(r * multiplier) / multiplier;
-            g = f32::floor(g * multiplier) / multiplier;
-            b = f32::floor(b * multiplier) / multiplier;
-            a = f32::floor(a * multiplier) / multiplier;
-
-            buf[i * 4] = r as u8;
-            buf[i * 4 + 1] = g as u8;
-            buf[i * 4 + 2] = b as u8;
-            buf[i * 4 + 3] = a as u8;
-        }
-    }
-
     /// Get the width of the texture
     pub fn width(&self) -> i32 {
         self.width
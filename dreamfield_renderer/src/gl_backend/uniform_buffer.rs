@@ -188,14 +188,34 @@ impl UniformBuffer<GlobalParams> {
 #[derive(UniformSetters)]
 pub struct MaterialParams {
     pub has_base_color_texture: std140::boolean,
-    pub base_color: std140::vec4
+    pub base_color: std140::vec4,
+    pub base_color_uv_transform: std140::mat3x3,
+    pub has_emissive_texture: std140::boolean,
+    pub emissive: std140::vec3,
+    pub emissive_uv_transform: std140::mat3x3,
+    pub alpha_mode: std140::int,
+    pub alpha_cutoff: std140::float,
+    pub use_vertex_color: std140::boolean
 }
 
+/// The alpha modes for MaterialParams::alpha_mode, which match the defines in uniforms.glsl
+pub const ALPHA_MODE_OPAQUE: i32 = 0;
+pub const ALPHA_MODE_MASK: i32 = 1;
+pub const ALPHA_MODE_BLEND: i32 = 2;
+
 impl Default for MaterialParams {
     fn default() -> Self {
         MaterialParams {
             has_base_color_texture: false.to_std140(),
-            base_color: vec4(1.0, 1.0, 1.0, 1.0).to_std140()
+            base_color: vec4(1.0, 1.0, 1.0, 1.0).to_std140(),
+            base_color_uv_transform: Matrix3::identity().to_std140(),
+            has_emissive_texture: false.to_std140(),
+            emissive: vec3(0.0, 0.0, 0.0).to_std140(),
+            emissive_uv_transform: Matrix3::identity().to_std140(),
+            // Clip low opacity fragments by default, which is what all materials used to do
+            alpha_mode: ALPHA_MODE_MASK.to_std140(),
+            alpha_cutoff: (0.1).to_std140(),
+            use_vertex_color: true.to_std140()
         }
     }
 }
@@ -5,7 +5,7 @@ use std::time::Duration;
 
 use bevy_ecs::query::Without;
 use bevy_ecs::system::{Local, Res, Query, ResMut, ParamSet};
-use cgmath::{SquareMatrix, Matrix4, vec2, InnerSpace, vec4, vec3};
+use cgmath::{SquareMatrix, Matrix3, Matrix4, Vector3, vec2, InnerSpace, vec4, vec3};
 use dreamfield_system::intersection::{Collider, Shape};
 use renderer_resources::RendererResources;
 use crate::gl_backend::*;
@@ -13,10 +13,10 @@ use crate::gl_backend::bindings::AttribBinding;
 use crate::resources::{ModelManager, TextureManager, ShaderManager, FontManager};
 use crate::components::{PlayerCamera, Visual, ScreenEffect, RunTime, TextBox, DiagnosticsTextBox};
 use dreamfield_system::WindowSettings;
-use dreamfield_system::world::WorldChunkManager;
-use dreamfield_system::world::world_chunk::{WorldChunk, WorldChunkMesh, ChunkIndex};
-use dreamfield_system::world::world_texture::WorldTexture;
-use dreamfield_system::world::wrapped_vectors::WrappedVector3;
+use dreamfield_system::world::{WorldChunkManager, ChunkAnchor, ChunkState};
+use dreamfield_system::world::world_chunk::{WorldChunk, WorldChunkMesh, ChunkIndex, WorldChunkMaterial, WorldChunkAlphaMode,
+    WorldChunkTextureRef};
+use dreamfield_system::world::world_texture::{WorldTexture, TextureIndex};
 use dreamfield_system::resources::{SimTime, Diagnostics};
 use dreamfield_system::components::{Transform, Disabled};
 
@@ -39,6 +39,9 @@ pub fn renderer_system(
 {
     let local = &mut *local;
 
+    // Free the gl meshes and textures for any world chunks that have been evicted
+    free_evicted_chunks(local, &mut world);
+
     // Update window size if it's changed
     if window_settings.is_added() || window_settings.is_changed() {
         let (width, height) = window_settings.window_size;
@@ -52,6 +55,7 @@ pub fn renderer_system(
     }
     else {
         log::warn!("No player camera");
+        world.set_camera_anchors(Vec::new());
         return;
     }
 
@@ -123,6 +127,9 @@ pub fn renderer_system(
     if player_camera.render_world {
         draw_world(local, &mut world, &models, &player_camera);
     }
+    else {
+        world.set_camera_anchors(Vec::new());
+    }
 
     // Draw visuals
     {
@@ -167,70 +174,86 @@ fn draw_world(local: &mut RendererResources, mut world: &mut ResMut<WorldChunkMa
     let pos = cam_transform.w.truncate();
     let forward = cam_transform * vec4(0.0, 0.0, -1.0, 0.0);
 
-    // Work out what chunks can be seen by creating a triangle between the camera position and the
-    // corners of the far clip plane, in 2D, and then walk all the chunks between them and draw them
-    let forward_xz = vec2(forward.x, forward.z).normalize();
-    // TODO: this doesn't take into account that you might be looking down and seeing behind you a
-    // little bit, as a bodge we move the position back a couple units before looking as this is
-    // only a problem if you're looking down and there's the edge of a chunk just behind you. This,
-    // along with the fact the test is in 2d, do mean that we overdraw a bit though.
-    let pos_xz = vec2(pos.x, pos.z) - forward_xz * 2.0;
-
-    // The view of the world grid (in 2d) forms a triangle between the corners of the far clip
-    // plane and the camera position. To draw the chunks within the camera view, we want to figure
-    // out those two points in the distance.
+    // Work out what chunks can be seen by creating a pyramid between the camera position and the
+    // corners of the far clip plane, and then walk all the chunks between them and draw them
+    let forward = forward.truncate().normalize();
+    let right = (cam_transform * vec4(1.0, 0.0, 0.0, 0.0)).truncate().normalize();
+    let up = (cam_transform * vec4(0.0, 1.0, 0.0, 0.0)).truncate().normalize();
+    // TODO: the near clip plane isn't a point, so you can see a little bit behind the camera's
+    // position. As a bodge we move the position back a couple units before looking, as this is
+    // only a problem if there's the edge of a chunk just behind you, though it means we overdraw
+    // a bit.
+    let view_pos = pos - forward * 2.0;
+
+    // The view of the world forms a pyramid between the corners of the far clip plane and the
+    // camera position. To draw the chunks within the camera view, we want to figure out those four
+    // points in the distance.
     //
-    // To do this, we can divide this triangle into two right-angle triangles, where the line from
-    // the camera position to the point `far_point` straight ahead of the camera on the far clip plane
-    // forms one edge, and the clip plane corners form the third point on each triangle.
-    //
-    // To figure out the corner points, we then first figure out what far point is, and then rotate
-    // the forward vector by 90 degrees to get right_xz:
+    // To do this, we first figure out the point `far_point` straight ahead of the camera on the
+    // far clip plane, and then move from it along the camera's right and up vectors to get to each
+    // corner.
     let far_clip = camera.clip_range.y;
-    let far_point = pos_xz + forward_xz * camera.fog_range.y;
-    let right_xz = vec2(-forward_xz.y, forward_xz.x);
+    let far_point = view_pos + forward * camera.fog_range.y;
 
-    // We then calculate the "half width" of the far clip plane using trigonometry, which is the
-    // distance between far_point and the corner point.
+    // We then calculate the "half height" of the far clip plane using trigonometry, which is the
+    // distance between far_point and the top edge of the plane, as the fov is vertical, and then
+    // scale it by the aspect ratio to get the "half width".
     // This can't be a const right now but it could be if f32::tan was...
-    let far_clip_half_width: f32 = far_clip * f32::tan(0.5 * camera.render_fov_rad);
-
-    // And then we multiply this by the right vector and add it to get the corner point, and then
-    // do the opposite to get the other corner point.
-    let corner_a = far_point + right_xz * far_clip_half_width;
-    let corner_b = far_point - right_xz * far_clip_half_width;
-
-    // Then, take the min and max of all three points, and use it to create an AABB for the view.
+    let far_clip_half_height: f32 = far_clip * f32::tan(0.5 * camera.render_fov_rad);
+    let far_clip_right = right * far_clip_half_height * camera.render_aspect;
+    let far_clip_up = up * far_clip_half_height;
+
+    let corners = [
+        far_point + far_clip_right + far_clip_up,
+        far_point + far_clip_right - far_clip_up,
+        far_point - far_clip_right + far_clip_up,
+        far_point - far_clip_right - far_clip_up,
+    ];
+
+    // Then, take the min and max of all five points, and use it to create an AABB for the view.
     // We can then draw all world chunks that intersect this AABB. As an optimization, we could
-    // draw only the ones that are actually within the triangle, but I don't think it's necessary.
-    let view_aabb_min = vec2(
-        f32::min(pos_xz.x, f32::min(corner_a.x, corner_b.x)),
-        f32::min(pos_xz.y, f32::min(corner_a.y, corner_b.y))
-    );
-    let view_aabb_max = vec2(
-        f32::max(pos_xz.x, f32::max(corner_a.x, corner_b.x)),
-        f32::max(pos_xz.y, f32::max(corner_a.y, corner_b.y))
-    );
+    // draw only the ones that are actually within the pyramid, but I don't think it's necessary.
+    let (view_aabb_min, view_aabb_max) = corners
+        .iter()
+        .fold((view_pos, view_pos), |(min, max), corner| {
+            (vec3(f32::min(min.x, corner.x), f32::min(min.y, corner.y), f32::min(min.z, corner.z)),
+             vec3(f32::max(max.x, corner.x), f32::max(max.y, corner.y), f32::max(max.z, corner.z)))
+        });
+
+    // Keep the chunks around the view loaded, in every direction so that turning around doesn't
+    // cause them to be loaded again
+    let view_radius = (corners[0] - view_pos).magnitude();
+    world.set_camera_anchors(vec![ChunkAnchor::new(view_pos, view_radius)]);
+
+    // Draw the opaque and alpha masked meshes in each chunk, and then the alpha blended ones from
+    // back to front, so that they blend with everything behind them
+    let mut blended_meshes = Vec::new();
+    for chunk_index in WorldChunk::chunks_between(&view_aabb_min, &view_aabb_max, world.chunk_height()) {
+        draw_world_chunk(local, &mut world, &models, chunk_index, &pos, &mut blended_meshes);
+    }
 
-    // Get chunk indexes at corners
-    let (view_min_chunk_x, view_min_chunk_z) = WorldChunk::point_to_chunk_index_2d(&view_aabb_min);
-    let (view_max_chunk_x, view_max_chunk_z) = WorldChunk::point_to_chunk_index_2d(&view_aabb_max);
+    draw_blended_world_meshes(local, &mut world, blended_meshes);
 
-    for chunk_x in view_min_chunk_x..=view_max_chunk_x {
-        for chunk_z in view_min_chunk_z..=view_max_chunk_z {
-            draw_world_chunk(local, &mut world, &models, (chunk_x, chunk_z));
-        }
+    // Reset the render state the materials changed
+    unsafe {
+        gl::Disable(gl::CULL_FACE);
+        gl::Disable(gl::BLEND);
+        gl::DepthMask(gl::TRUE);
     }
 }
 
-/// Draw a WorldChunk
+/// Draw a WorldChunk. Chunks that are still being loaded in the background are skipped until
+/// they're ready, rather than stalling the frame. Alpha blended meshes aren't drawn, but are added
+/// to `blended_meshes` along with their distance from the camera, to be drawn once the rest of the
+/// world has been.
 fn draw_world_chunk(local: &mut RendererResources, world: &mut ResMut<WorldChunkManager>, models: &Res<ModelManager>,
-    chunk_index: ChunkIndex)
+    chunk_index: ChunkIndex, camera_pos: &Vector3<f32>, blended_meshes: &mut Vec<(f32, ChunkIndex, usize)>)
 {
     let mut textures_to_load = Vec::new();
 
-    if let Some(chunk) = world.get_or_load_chunk(chunk_index) {
-        // Draw instances in chunk
+    if let ChunkState::Loaded(Some(chunk)) = world.chunk_state(chunk_index) {
+        // Draw instances in chunk, which aren't culled as the world materials might have left it on
+        unsafe { gl::Disable(gl::CULL_FACE); }
         for instance in chunk.instances().iter() {
             // Get reference to model from the renderer resources cache, loading it if it's not in there
             let model = local.models
@@ -240,9 +263,8 @@ fn draw_world_chunk(local: &mut RendererResources, world: &mut ResMut<WorldChunk
                     Arc::new(GltfModel::from_buf(data).unwrap())
                 });
 
-            for WrappedVector3(point) in instance.points().iter() {
-                let transform = Matrix4::from_translation(*point);
-                model.render(&transform, &mut local.ubo_global, &mut local.ubo_joints, true);
+            for point in instance.points().iter() {
+                model.render(&point.transform(), &mut local.ubo_global, &mut local.ubo_joints, true);
             }
         }
 
@@ -252,43 +274,148 @@ fn draw_world_chunk(local: &mut RendererResources, world: &mut ResMut<WorldChunk
         local.ubo_joints.set_skinning_enabled(&false);
         local.ubo_joints.upload_changed();
 
-        for mesh in chunk.meshes().iter() {
-            // Bind material
-            local.ubo_material.set_has_base_color_texture(&false);
-            if let Some(material) = mesh.material() {
-                if let Some(texture_id) = material.base_color_tex() {
-                    if let Some(texture) = local.world_textures.get(texture_id) {
-                        local.ubo_material.set_has_base_color_texture(&true);
-                        texture.bind(TextureSlot::BaseColor);
-                    }
-                    else {
-                        // Annoyingly we can't just load it now as we still have world borrowed
-                        // mutably through the reference to chunk
-                        textures_to_load.push(*texture_id);
-                    }
-                }
-                else {
-                }
-
-                local.ubo_material.set_base_color(material.base_color().as_vec());
-                local.ubo_material.bind(bindings::UniformBlockBinding::MaterialParams);
+        for (mesh_index, mesh) in chunk.meshes().iter().enumerate() {
+            let alpha_mode = mesh.material().as_ref().map(|material| material.alpha_mode());
+            if alpha_mode == Some(WorldChunkAlphaMode::Blend) {
+                let distance = mesh.aabb().min_max()
+                    .map(|(min, max)| (0.5 * (min + max) - camera_pos).magnitude())
+                    .unwrap_or(0.0);
+                blended_meshes.push((distance, chunk_index, mesh_index));
             }
             else {
-                local.ubo_material.set_base_color(&vec4(1.0, 1.0, 1.0, 1.0));
-                local.ubo_material.set_has_base_color_texture(&false);
-                local.ubo_material.bind(bindings::UniformBlockBinding::MaterialParams);
+                draw_world_mesh(local, mesh, &mut textures_to_load);
+            }
+        }
+    }
+
+    load_world_textures(local, world, textures_to_load);
+}
+
+/// Draw the alpha blended world meshes, from back to front
+fn draw_blended_world_meshes(local: &mut RendererResources, world: &mut ResMut<WorldChunkManager>,
+    mut blended_meshes: Vec<(f32, ChunkIndex, usize)>)
+{
+    let mut textures_to_load = Vec::new();
+
+    blended_meshes.sort_by(|(a, _, _), (b, _, _)| b.total_cmp(a));
+
+    for (_, chunk_index, mesh_index) in blended_meshes {
+        if let ChunkState::Loaded(Some(chunk)) = world.chunk_state(chunk_index) {
+            draw_world_mesh(local, &chunk.meshes()[mesh_index], &mut textures_to_load);
+        }
+    }
+
+    load_world_textures(local, world, textures_to_load);
+}
+
+/// Bind a world mesh's material and draw it. Any textures that aren't loaded yet are added to
+/// `textures_to_load`, as we can't load them while the chunk is still borrowed from the world.
+fn draw_world_mesh(local: &mut RendererResources, mesh: &WorldChunkMesh, textures_to_load: &mut Vec<TextureIndex>) {
+    // Bind material
+    match mesh.material() {
+        Some(material) => bind_world_material(local, material, textures_to_load),
+        None => {
+            local.ubo_material.set_base_color(&vec4(1.0, 1.0, 1.0, 1.0));
+            local.ubo_material.set_has_base_color_texture(&false);
+            local.ubo_material.set_has_emissive_texture(&false);
+            local.ubo_material.set_emissive(&vec3(0.0, 0.0, 0.0));
+            local.ubo_material.set_alpha_mode(&ALPHA_MODE_OPAQUE);
+            local.ubo_material.set_use_vertex_color(&true);
+            unsafe {
+                gl::Enable(gl::CULL_FACE);
+                gl::Disable(gl::BLEND);
+                gl::DepthMask(gl::TRUE);
             }
-            local.ubo_material.upload_changed();
+        }
+    }
+    local.ubo_material.bind(bindings::UniformBlockBinding::MaterialParams);
+
+    // Draw mesh
+    let count = mesh.indices().len() as i32;
+    let mesh = get_gl_mesh(local, &mesh);
+    mesh.draw_indexed(gl::PATCHES, count);
+}
+
+/// Set the material params and render state for a world material, and bind its textures
+fn bind_world_material(local: &mut RendererResources, material: &WorldChunkMaterial,
+    textures_to_load: &mut Vec<TextureIndex>)
+{
+    let has_base_color_texture = bind_world_texture(local, material.base_color_tex(), TextureSlot::BaseColor,
+        textures_to_load);
+    local.ubo_material.set_has_base_color_texture(&has_base_color_texture);
+    local.ubo_material.set_base_color(material.base_color().as_vec());
+    local.ubo_material.set_base_color_uv_transform(&material.base_color_tex()
+        .map(|tex| tex.transform().matrix())
+        .unwrap_or(Matrix3::identity()));
+
+    let has_emissive_texture = bind_world_texture(local, material.emissive_tex(), TextureSlot::Emissive,
+        textures_to_load);
+    local.ubo_material.set_has_emissive_texture(&has_emissive_texture);
+    local.ubo_material.set_emissive(material.emissive().as_vec());
+    local.ubo_material.set_emissive_uv_transform(&material.emissive_tex()
+        .map(|tex| tex.transform().matrix())
+        .unwrap_or(Matrix3::identity()));
+
+    let alpha_mode = match material.alpha_mode() {
+        WorldChunkAlphaMode::Opaque => ALPHA_MODE_OPAQUE,
+        WorldChunkAlphaMode::Mask => ALPHA_MODE_MASK,
+        WorldChunkAlphaMode::Blend => ALPHA_MODE_BLEND,
+    };
+    local.ubo_material.set_alpha_mode(&alpha_mode);
+    local.ubo_material.set_alpha_cutoff(&material.alpha_cutoff());
+    local.ubo_material.set_use_vertex_color(&material.vertex_colors());
+
+    unsafe {
+        if material.double_sided() {
+            gl::Disable(gl::CULL_FACE);
+        }
+        else {
+            gl::Enable(gl::CULL_FACE);
+        }
+
+        // Blended meshes are drawn after everything else, and shouldn't hide each other
+        if material.alpha_mode() == WorldChunkAlphaMode::Blend {
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            gl::DepthMask(gl::FALSE);
+        }
+        else {
+            gl::Disable(gl::BLEND);
+            gl::DepthMask(gl::TRUE);
+        }
+    }
+}
+
+/// Bind a world texture to a slot with its sampler settings, returning whether it was bound. If the
+/// texture isn't loaded yet, it's added to `textures_to_load` instead.
+fn bind_world_texture(local: &mut RendererResources, texture_ref: Option<&WorldChunkTextureRef>, slot: TextureSlot,
+    textures_to_load: &mut Vec<TextureIndex>) -> bool
+{
+    let texture_ref = match texture_ref {
+        Some(texture_ref) => texture_ref,
+        None => return false
+    };
 
-            // Draw mesh
-            let count = mesh.indices().len() as i32;
-            let mesh = get_gl_mesh(local, &mesh);
-            mesh.draw_indexed(gl::PATCHES, count);
+    match local.world_textures.get(&texture_ref.texture()) {
+        Some(texture) => {
+            let sampler = texture_ref.sampler();
+            texture.set_params(&TextureParams::new(sampler.wrap_s, sampler.wrap_t, sampler.min_filter,
+                sampler.mag_filter));
+            texture.bind(slot);
+            true
+        }
+        None => {
+            textures_to_load.push(texture_ref.texture());
+            false
         }
     }
+}
 
-    // Load textures, a bit late but otherwise we end up borrowing world twice because we're still
-    // iterating the chunk's meshes... sigh
+/// Load world textures, a bit late but otherwise we end up borrowing world twice because we're
+/// still iterating the chunk's meshes... sigh
+fn load_world_textures(local: &mut RendererResources, world: &mut ResMut<WorldChunkManager>,
+    textures_to_load: Vec<TextureIndex>)
+{
     for tex_idx in textures_to_load {
         if let Some(texture) = world.get_or_load_texture(tex_idx) {
             get_gl_texture(local, &texture);
@@ -296,13 +423,25 @@ fn draw_world_chunk(local: &mut RendererResources, world: &mut ResMut<WorldChunk
     }
 }
 
+/// Free the gl meshes and textures for world chunks that have been evicted
+fn free_evicted_chunks(local: &mut RendererResources, world: &mut WorldChunkManager) {
+    for evicted in world.take_evicted_chunks() {
+        for mesh_index in evicted.mesh_indices.iter() {
+            local.world_meshes.remove(mesh_index);
+        }
+        for texture_index in evicted.texture_indices.iter() {
+            local.world_textures.remove(texture_index);
+        }
+    }
+}
+
 // Get the gl mesh for a world mesh
 fn get_gl_mesh<'a>(local: &'a mut RendererResources, mesh: &WorldChunkMesh) -> &'a Mesh {
     local.world_meshes
         .entry(mesh.index())
         .or_insert_with(|| {
             // TODO: pregenerate them as u32, or just load them as u16
-            let index_buffer = mesh.indices().iter().map(|i| *i as u32).collect::<Vec<u32>>();
+            let index_buffer = mesh.indices().iter().collect::<Vec<u32>>();
             let buffer_layout = vec![
                 VertexAttrib {
                     index: AttribBinding::Positions as u32,
@@ -337,8 +476,13 @@ fn get_gl_texture<'a>(local: &'a mut RendererResources, texture: &WorldTexture)
         .or_insert_with(|| {
             let dest_format = gl::SRGB8_ALPHA8;
             let tex_params = TextureParams::repeat_nearest();
-            Texture::new_from_buf(&texture.pixels(), texture.width() as i32, texture.height() as i32, texture.format(),
-                gl::UNSIGNED_BYTE, dest_format, tex_params).expect("Failed to create world texture")
+            let gl_texture = Texture::new_from_buf(&texture.pixels(), texture.width() as i32, texture.height() as i32,
+                texture.format(), texture.pixel_type(), dest_format, tex_params).expect("Failed to create world texture");
+
+            // The sampler is set when the texture's bound, as materials can use different ones, and
+            // some might want mipmaps
+            gl_texture.gen_mipmaps();
+            gl_texture
         })
 }
 
@@ -404,8 +548,12 @@ fn draw_colliders(local: &mut RendererResources, models: &Res<ModelManager>,
 
     local.ubo_material.set_has_base_color_texture(&false);
     local.ubo_material.set_base_color(&vec4(1.0, 1.0, 1.0, 1.0));
+    local.ubo_material.set_use_vertex_color(&true);
     local.ubo_material.bind(bindings::UniformBlockBinding::MaterialParams);
 
+    // Spheroids are drawn as a sphere model, and the other shapes as lines along their edges
+    let mut lines = Vec::new();
+
     for (transform, collider) in colliders_query.iter() {
         // Get sphere model, loading it if it isn't already loaded
         let sphere_model = local.models
@@ -415,7 +563,7 @@ fn draw_colliders(local: &mut RendererResources, models: &Res<ModelManager>,
                 Arc::new(GltfModel::from_buf(data).unwrap())
             });
 
-        match collider.shape {
+        match &collider.shape {
             Shape::BoundingSpheroid(offset, radius) => {
                 let pos = transform.pos + offset;
                 let transform = {
@@ -425,9 +573,108 @@ fn draw_colliders(local: &mut RendererResources, models: &Res<ModelManager>,
                 };
                 sphere_model.render(&transform, &mut local.ubo_global, &mut local.ubo_joints, true);
             }
-            _ => panic!("draw_colliders: unimplemented collider type for {:?}", collider.shape)
+            Shape::BoundingBox(offset, half_extents) => {
+                let center = transform.pos + transform.rot * offset;
+                let corner = |i: usize| {
+                    let sign = |bit: usize| if i & bit != 0 { 1.0 } else { -1.0 };
+                    center + transform.rot * vec3(sign(1) * half_extents.x, sign(2) * half_extents.y,
+                        sign(4) * half_extents.z)
+                };
+
+                // Each edge joins two corners that differ along one axis
+                for i in 0..8 {
+                    for bit in [1, 2, 4] {
+                        if i & bit == 0 {
+                            lines.push(corner(i));
+                            lines.push(corner(i | bit));
+                        }
+                    }
+                }
+            }
+            Shape::Capsule(offset, half_height, radius) => {
+                let (a, b) = Shape::capsule_ends(&transform.pos, &transform.rot, offset, *half_height);
+                push_capsule_lines(&mut lines, a, b, transform.rot * vec3(1.0, 0.0, 0.0),
+                    transform.rot * vec3(0.0, 0.0, 1.0), *radius);
+            }
+            Shape::ConvexHull(hull) => {
+                let point = |i: usize| transform.pos + transform.rot * hull.points()[i];
+                for face in hull.faces().iter() {
+                    // Edges are shared by two faces, so only draw them from one side
+                    for (i, from) in face.iter().enumerate() {
+                        let to = face[(i + 1) % face.len()];
+                        if *from < to {
+                            lines.push(point(*from));
+                            lines.push(point(to));
+                        }
+                    }
+                }
+            }
         }
     }
+
+    if lines.is_empty() {
+        return;
+    }
+
+    // Draw the lines unlit, by making them emissive
+    local.ps1_shader.use_program();
+
+    local.ubo_global.set_mat_model_derive(&Matrix4::identity());
+    local.ubo_global.upload_changed();
+    local.ubo_joints.set_skinning_enabled(&false);
+    local.ubo_joints.upload_changed();
+
+    local.ubo_material.set_has_base_color_texture(&false);
+    local.ubo_material.set_has_emissive_texture(&false);
+    local.ubo_material.set_emissive(&vec3(1.0, 1.0, 1.0));
+    local.ubo_material.set_alpha_mode(&ALPHA_MODE_OPAQUE);
+    local.ubo_material.bind(bindings::UniformBlockBinding::MaterialParams);
+
+    local.collider_lines.clear_vertex_buffer();
+    for point in lines.iter() {
+        local.collider_lines.push_vec3(*point);
+    }
+    local.collider_lines.draw_arrays(gl::LINES, 0, lines.len() as i32);
+
+    local.ubo_material.set_emissive(&vec3(0.0, 0.0, 0.0));
+}
+
+/// Push the lines for a capsule with ends a and b, which are two circles around its ends, the lines
+/// between them, and the arcs over its ends. The x and z axes are perpendicular to the capsule.
+fn push_capsule_lines(lines: &mut Vec<Vector3<f32>>, a: Vector3<f32>, b: Vector3<f32>, x_axis: Vector3<f32>,
+    z_axis: Vector3<f32>, radius: f32)
+{
+    const SEGMENTS: usize = 16;
+
+    let up = if (b - a).magnitude2() > 0.0 { (b - a).normalize() } else { x_axis.cross(z_axis).normalize() };
+    let angle = |i: usize| i as f32 * std::f32::consts::TAU / SEGMENTS as f32;
+
+    for i in 0..SEGMENTS {
+        let (sin0, cos0) = angle(i).sin_cos();
+        let (sin1, cos1) = angle(i + 1).sin_cos();
+
+        // The circles around each end
+        for end in [a, b] {
+            lines.push(end + (x_axis * cos0 + z_axis * sin0) * radius);
+            lines.push(end + (x_axis * cos1 + z_axis * sin1) * radius);
+        }
+
+        // The arcs over each end, which are the top half of a circle through the axis in each plane
+        for side in [x_axis, z_axis] {
+            if i < SEGMENTS / 2 {
+                lines.push(b + (side * cos0 + up * sin0) * radius);
+                lines.push(b + (side * cos1 + up * sin1) * radius);
+                lines.push(a + (side * cos0 - up * sin0) * radius);
+                lines.push(a + (side * cos1 - up * sin1) * radius);
+            }
+        }
+    }
+
+    // The lines between the ends
+    for side in [x_axis, -x_axis, z_axis, -z_axis] {
+        lines.push(a + side * radius);
+        lines.push(b + side * radius);
+    }
 }
 
 /// Render a screen effect
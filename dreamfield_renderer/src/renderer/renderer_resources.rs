@@ -3,6 +3,7 @@ use std::sync::Arc;
 use bevy_ecs::world::{FromWorld, World};
 use crate::gl_backend::{Mesh, EditableMesh, VertexAttrib, Texture, GltfModel, UniformBuffer,
     Framebuffer, GlobalParams, JointParams, ShaderProgram, MaterialParams};
+use crate::gl_backend::bindings::AttribBinding;
 use crate::resources::ShaderManager;
 
 /// The renderer state resource
@@ -14,6 +15,7 @@ pub struct RendererResources {
     pub framebuffer_size: Option<(i32, i32)>,
     pub framebuffer: Option<Framebuffer>,
     pub yiq_framebuffer: Option<Framebuffer>,
+    pub ps1_shader: Arc<ShaderProgram>,
     pub ps1_tess_shader: Arc<ShaderProgram>,
     pub composite_yiq_shader: Arc<ShaderProgram>,
     pub composite_resolve_shader: Arc<ShaderProgram>,
@@ -22,6 +24,7 @@ pub struct RendererResources {
     pub world_meshes: HashMap<i32, Mesh>,
     pub world_textures: HashMap<i32, Texture>,
     pub text_mesh: EditableMesh,
+    pub collider_lines: EditableMesh,
 }
 
 impl FromWorld for RendererResources {
@@ -55,10 +58,15 @@ impl FromWorld for RendererResources {
             VertexAttrib { index: 1, size: 2, attrib_type: gl::FLOAT },
         ]);
 
+        let collider_lines = EditableMesh::new(vec![
+            VertexAttrib { index: AttribBinding::Positions as u32, size: 3, attrib_type: gl::FLOAT },
+        ]);
+
         // Load shaders
         // TODO: it would be nice if the shaders were specified by components on entities instead
         // of hardcoded here, and the composite/resolve were converted to screen-space effects
         let mut shaders = world.get_resource_mut::<ShaderManager>().expect("Failed to get shader manager");
+        let ps1_shader = shaders.get("ps1").unwrap().clone();
         let ps1_tess_shader = shaders.get("ps1_tess").unwrap().clone();
         let composite_yiq_shader = shaders.get("composite_yiq").unwrap().clone();
         let composite_resolve_shader = shaders.get("composite_resolve").unwrap().clone();
@@ -72,6 +80,7 @@ impl FromWorld for RendererResources {
             framebuffer_size: None,
             framebuffer: None,
             yiq_framebuffer: None,
+            ps1_shader,
             ps1_tess_shader,
             composite_yiq_shader,
             composite_resolve_shader,
@@ -79,7 +88,8 @@ impl FromWorld for RendererResources {
             models: HashMap::new(),
             world_meshes: HashMap::new(),
             world_textures: HashMap::new(),
-            text_mesh
+            text_mesh,
+            collider_lines,
         }
     }
 }
@@ -0,0 +1,177 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use cgmath::{Vector3, vec3, ElementWise};
+use speedy::Writable;
+use dreamfield_system::intersection::{self, Triangle};
+use dreamfield_system::rng::XorShiftRng;
+use dreamfield_system::world::WorldChunkManager;
+use dreamfield_system::world::aabb::Aabb;
+use dreamfield_system::world::chunk_source::{ChunkSource, ChunkSourceError};
+use dreamfield_system::world::world_build_manifest::{WorldBuildManifest, WORLD_BUILD_MANIFEST_FILENAME};
+use dreamfield_system::world::world_chunk::{CollisionLayer, WorldChunk, WorldChunkCollisionMesh, WorldChunkIndices,
+    CHUNK_SIZE, DEFAULT_CHUNK_HEIGHT};
+use dreamfield_system::world::world_collision::{CollisionLayers, WorldCollision};
+
+const USAGE: &str = "Usage: dreamfield-collisionbench [--sweeps <count>]
+
+Benchmarks sweeping the player's collider against a chunk of bumpy terrain at various triangle
+densities, through world collision and with a linear scan of every triangle, which is what world
+collision did before it had a bvh. Build with --release for meaningful numbers.
+
+  --sweeps <count>  The number of sweeps to time for each density, at least 1 (default: 10000)";
+
+/// The number of quads along each side of the terrain for each run
+const TERRAIN_RESOLUTIONS: [usize; 4] = [4, 16, 32, 64];
+
+/// The number of sweeps to time if none is given
+const DEFAULT_SWEEPS: usize = 10000;
+
+/// How high the terrain is, so that it's all inside chunk 0, 0, 0
+const TERRAIN_HEIGHT: f32 = 1.0;
+
+/// A sweep to time, in ellipsoid space
+struct Sweep {
+    start: Vector3<f32>,
+    velocity: Vector3<f32>,
+}
+
+/// A world's files in memory, so that the benchmark loads its terrain the same way as the game
+struct MemoryChunkSource {
+    files: HashMap<String, Vec<u8>>,
+}
+
+impl ChunkSource for MemoryChunkSource {
+    fn read_file(&self, filename: &str) -> Result<Option<Cow<'_, [u8]>>, ChunkSourceError> {
+        Ok(self.files.get(filename).map(|data| Cow::Borrowed(data.as_slice())))
+    }
+
+    fn describe(&self) -> String {
+        "benchmark terrain".to_string()
+    }
+}
+
+fn main() {
+    let mut sweep_count = DEFAULT_SWEEPS;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next().map(|count| count.parse::<usize>())) {
+            ("--sweeps", Some(Ok(count))) if count > 0 => sweep_count = count,
+            _ => {
+                eprintln!("{USAGE}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // The same ellipsoid as the player's collider
+    let radius = vec3(0.5, 0.9, 0.5);
+    let cbm = vec3(1.0 / radius.x, 1.0 / radius.y, 1.0 / radius.z);
+
+    for resolution in TERRAIN_RESOLUTIONS {
+        let triangles = build_terrain(resolution);
+        let triangle_count = triangles.len();
+
+        let mut world = WorldChunkManager::new(Box::new(terrain_source(&triangles)));
+        let mut collision = WorldCollision::default();
+
+        // Sweep across the terrain in random directions, mostly walking with some falling, like
+        // the player controller's moves and ground probes
+        let mut rng = XorShiftRng::new(0x2545f491);
+        let sweeps: Vec<Sweep> = (0..sweep_count)
+            .map(|_| Sweep {
+                start: vec3(rng.next_f32() * CHUNK_SIZE, TERRAIN_HEIGHT + 0.5 + rng.next_f32(), rng.next_f32() * CHUNK_SIZE)
+                    .mul_element_wise(cbm),
+                velocity: vec3(rng.next_f32() - 0.5, -rng.next_f32(), rng.next_f32() - 0.5).mul_element_wise(cbm),
+            })
+            .collect();
+
+        // The first sweep loads the chunk and builds its bvh, so time it separately
+        let load_start = Instant::now();
+        sweep_world(&mut world, &mut collision, &sweeps[0], cbm);
+        let load_time = load_start.elapsed();
+
+        let (linear_time, linear_hits) = time_sweeps(&sweeps, |sweep| sweep_linear(&triangles, sweep, cbm));
+        let (world_time, world_hits) = time_sweeps(&sweeps, |sweep| sweep_world(&mut world, &mut collision, sweep, cbm));
+
+        if linear_hits != world_hits {
+            eprintln!("World collision and the linear scan disagree on {resolution}x{resolution} terrain");
+            std::process::exit(1);
+        }
+
+        println!("{resolution}x{resolution} terrain ({triangle_count} triangles, loaded in {load_time:?}): \
+            linear {:?} per sweep, world collision {:?} per sweep, {:.1}x faster",
+            linear_time / sweep_count as u32, world_time / sweep_count as u32,
+            linear_time.as_secs_f64() / world_time.as_secs_f64());
+    }
+}
+
+/// Build a chunk of bumpy terrain with a given number of quads along each side
+fn build_terrain(resolution: usize) -> Vec<Triangle> {
+    let step = CHUNK_SIZE / resolution as f32;
+    let point = |x: usize, z: usize| {
+        let (x, z) = (x as f32 * step, z as f32 * step);
+        vec3(x, TERRAIN_HEIGHT + 0.25 * f32::sin(x * 0.7) * f32::cos(z * 0.5), z)
+    };
+
+    let mut triangles = Vec::with_capacity(resolution * resolution * 2);
+    for x in 0..resolution {
+        for z in 0..resolution {
+            let (a, b, c, d) = (point(x, z), point(x + 1, z), point(x + 1, z + 1), point(x, z + 1));
+            triangles.push(Triangle::new(a, c, b));
+            triangles.push(Triangle::new(a, d, c));
+        }
+    }
+
+    triangles
+}
+
+/// Write the terrain to a chunk file and a world build manifest, as the world builder would
+fn terrain_source(triangles: &[Triangle]) -> MemoryChunkSource {
+    let mut aabb = Aabb::new();
+    let mut positions = Vec::with_capacity(triangles.len() * 9);
+    for triangle in triangles {
+        for vertex in [triangle.a, triangle.b, triangle.c] {
+            aabb.expand_with_point(&vertex);
+            positions.extend_from_slice(&[vertex.x, vertex.y, vertex.z]);
+        }
+    }
+    let indices = WorldChunkIndices::new((0..triangles.len() as u32 * 3).collect());
+
+    let mut chunk = WorldChunk::new();
+    chunk.add_collision_mesh(WorldChunkCollisionMesh::new(aabb, CollisionLayer::Solid, positions, indices));
+
+    let manifest = WorldBuildManifest::new(Vec::new(), Vec::new(), false, DEFAULT_CHUNK_HEIGHT, 0.0, Vec::new(),
+        Default::default());
+
+    let mut files = HashMap::new();
+    files.insert(WorldChunk::filename((0, 0, 0)), chunk.write_to_file_data((0, 0, 0), DEFAULT_CHUNK_HEIGHT).unwrap());
+    files.insert(WORLD_BUILD_MANIFEST_FILENAME.to_string(), manifest.write_to_vec().unwrap());
+    MemoryChunkSource { files }
+}
+
+/// Time some sweeps, returning the total time and the time of impact of each, which also stops
+/// the sweeps from being optimised away
+fn time_sweeps(sweeps: &[Sweep], mut sweep: impl FnMut(&Sweep) -> Option<f32>) -> (Duration, Vec<Option<f32>>) {
+    let start = Instant::now();
+    let hits = sweeps.iter().map(&mut sweep).collect();
+    (start.elapsed(), hits)
+}
+
+/// Sweep against every triangle
+fn sweep_linear(triangles: &[Triangle], sweep: &Sweep, cbm: Vector3<f32>) -> Option<f32> {
+    triangles
+        .iter()
+        .filter_map(|triangle| intersection::toi_unit_sphere_triangle(sweep.start, sweep.velocity, &triangle.apply_cbm(cbm)))
+        .map(|(toi, _, _)| toi)
+        .filter(|toi| *toi >= 0.0)
+        .min_by(f32::total_cmp)
+}
+
+/// Sweep through world collision, as the player controller does
+fn sweep_world(world: &mut WorldChunkManager, collision: &mut WorldCollision, sweep: &Sweep, cbm: Vector3<f32>)
+    -> Option<f32>
+{
+    collision.sweep_unit_sphere(world, sweep.start, sweep.velocity, cbm, CollisionLayers::ALL, None)
+        .map(|result| result.toi())
+}
@@ -0,0 +1,134 @@
+use std::path::PathBuf;
+use dreamfield_system::world::world_builder::{WorldBuilder, WorldModel, InstanceModel};
+use dreamfield_system::world::chunk_source::ChunkPack;
+use dreamfield_system::world::world_chunk::DEFAULT_CHUNK_HEIGHT;
+
+/// The directory to output chunks to if none is given, the same one build.rs uses
+const DEFAULT_OUTPUT_DIR: &str = "target/world_chunks";
+
+const USAGE: &str = "Usage: dreamfield-worldbuild [--out <dir>] [--pack <file>] [--force] [--atlas]
+    [--chunk-height <height>|none] [--instance-model <name>=<model.glb>]... <model.glb>...
+
+Builds world chunks from gltf models, only importing the models that have changed since the last
+build and rewriting the chunks they contribute to.
+
+  --out <dir>    The directory to write chunks to (default: target/world_chunks)
+  --pack <file>  Also write the chunks to a single chunk pack file
+  --force        Import every model and rewrite every chunk, even if the models haven't changed
+  --atlas        Put the small textures in each chunk into atlases, so that more meshes can be merged
+  --chunk-height <height>|none
+                 The height to split the world into chunks at, or none to not split it vertically
+                 (default: the chunk size, 16)
+  --instance-model <name>=<model.glb>
+                 The model that instances with an instance_mesh of <name> use, which their colliders
+                 are fitted to if their extras don't give any bounds";
+
+/// Command line options
+struct Options {
+    out_dir: PathBuf,
+    pack_path: Option<PathBuf>,
+    force: bool,
+    atlas: bool,
+    chunk_height: Option<f32>,
+    instance_model_paths: Vec<(String, PathBuf)>,
+    model_paths: Vec<PathBuf>,
+}
+
+impl Options {
+    /// Parse the process's command line arguments, returning an error message if they're invalid
+    fn parse() -> Result<Self, String> {
+        let mut options = Self {
+            out_dir: DEFAULT_OUTPUT_DIR.into(),
+            pack_path: None,
+            force: false,
+            atlas: false,
+            chunk_height: DEFAULT_CHUNK_HEIGHT,
+            instance_model_paths: Vec::new(),
+            model_paths: Vec::new(),
+        };
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--out" => options.out_dir = args.next().ok_or("--out expects a directory")?.into(),
+                "--pack" => options.pack_path = Some(args.next().ok_or("--pack expects a filename")?.into()),
+                "--force" => options.force = true,
+                "--atlas" => options.atlas = true,
+                "--chunk-height" => {
+                    let arg = args.next().ok_or("--chunk-height expects a height or none")?;
+                    options.chunk_height = match arg.as_str() {
+                        "none" => None,
+                        _ => Some(arg.parse().map_err(|_| format!("Invalid chunk height {arg}"))?)
+                    };
+                }
+                "--instance-model" => {
+                    let arg = args.next().ok_or("--instance-model expects a name and a filename")?;
+                    let (name, path) = arg.split_once('=').ok_or("--instance-model expects <name>=<model.glb>")?;
+                    options.instance_model_paths.push((name.to_string(), path.into()));
+                }
+                "--help" | "-h" => return Err(String::new()),
+                _ if arg.starts_with("--") => return Err(format!("Unknown argument {arg}")),
+                _ => options.model_paths.push(arg.into()),
+            }
+        }
+
+        if options.model_paths.is_empty() {
+            return Err("No models given".to_string());
+        }
+
+        Ok(options)
+    }
+}
+
+fn main() {
+    let options = match Options::parse() {
+        Ok(options) => options,
+        Err(err) => {
+            if !err.is_empty() {
+                eprintln!("{err}\n");
+            }
+            eprintln!("{USAGE}");
+            std::process::exit(1);
+        }
+    };
+
+    // Load models
+    let models: Vec<WorldModel> = options.model_paths
+        .iter()
+        .map(|path| WorldModel::load(path).unwrap_or_else(|err| {
+            eprintln!("Failed to load model {}: {err}", path.display());
+            std::process::exit(1);
+        }))
+        .collect();
+
+    let instance_models: Vec<InstanceModel> = options.instance_model_paths
+        .iter()
+        .map(|(name, path)| InstanceModel::load(name, path).unwrap_or_else(|err| {
+            eprintln!("Failed to load instance model {}: {err}", path.display());
+            std::process::exit(1);
+        }))
+        .collect();
+
+    // Build world
+    let summary = WorldBuilder::new(&options.out_dir, &models)
+        .with_force(options.force)
+        .with_atlas(options.atlas)
+        .with_chunk_height(options.chunk_height)
+        .with_instance_models(&instance_models)
+        .build_world_models()
+        .unwrap_or_else(|errors| {
+            eprintln!("{errors}");
+            std::process::exit(1);
+        });
+
+    println!("Built world to {}: {}", options.out_dir.display(), summary);
+
+    // Pack world
+    if let Some(pack_path) = &options.pack_path {
+        if let Err(err) = ChunkPack::write_from_dir(&options.out_dir, pack_path) {
+            eprintln!("Failed to write chunk pack {}: {err}", pack_path.display());
+            std::process::exit(1);
+        }
+        println!("Wrote chunk pack {}", pack_path.display());
+    }
+}
@@ -0,0 +1,150 @@
+use std::error::Error;
+use std::fmt;
+use speedy::{Readable, Writable};
+
+/// The length of a file header
+pub const FILE_HEADER_LEN: usize = 8;
+
+/// The magic number and format version at the start of each of the game's binary files, so that
+/// other files and old versions of a format give a useful error rather than failing to decode
+#[derive(Readable, Writable, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FileHeader {
+    pub magic: [u8; 4],
+    pub version: u32,
+}
+
+impl FileHeader {
+    pub const fn new(magic: [u8; 4], version: u32) -> Self {
+        Self {
+            magic,
+            version,
+        }
+    }
+
+    /// Read the header at the start of some data, or None if it's too short to have one
+    pub fn read(data: &[u8]) -> Option<Self> {
+        if data.len() < FILE_HEADER_LEN {
+            return None;
+        }
+
+        let mut magic = [0; 4];
+        magic.copy_from_slice(&data[0..4]);
+        Some(Self::new(magic, u32::from_le_bytes([data[4], data[5], data[6], data[7]])))
+    }
+
+    /// Get the header as it's written at the start of a file
+    pub fn to_bytes(&self) -> [u8; FILE_HEADER_LEN] {
+        let mut bytes = [0; FILE_HEADER_LEN];
+        bytes[0..4].copy_from_slice(&self.magic);
+        bytes[4..8].copy_from_slice(&self.version.to_le_bytes());
+        bytes
+    }
+
+    /// Check that some data starts with this header, where format is the name of the file format
+    /// for error messages
+    pub fn check(&self, data: &[u8], format: &'static str) -> Result<(), FileHeaderError> {
+        match Self::read(data) {
+            Some(header) if header.magic != self.magic => Err(FileHeaderError::WrongFormat(format)),
+            Some(header) if header.version != self.version => Err(FileHeaderError::UnsupportedVersion {
+                format,
+                version: header.version,
+                expected: self.version,
+            }),
+            Some(_) => Ok(()),
+            None => Err(FileHeaderError::WrongFormat(format))
+        }
+    }
+}
+
+/// An error checking a file header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileHeaderError {
+    /// The data is too short for a header, or doesn't start with the format's magic
+    WrongFormat(&'static str),
+    /// The data is a version of the format that can't be read
+    UnsupportedVersion { format: &'static str, version: u32, expected: u32 },
+}
+
+impl fmt::Display for FileHeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileHeaderError::WrongFormat(format) => write!(f, "Not a valid {format}"),
+            FileHeaderError::UnsupportedVersion { format, version, expected } =>
+                write!(f, "Unsupported {format} version {version}, expected {expected}"),
+        }
+    }
+}
+
+impl Error for FileHeaderError {}
+
+/// Helpers for testing the file formats, which the game's tests use too
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util {
+    use std::fmt::Display;
+    use std::path::PathBuf;
+    use super::{FileHeader, FILE_HEADER_LEN};
+
+    /// Get a path in the temp dir for a test to write to, unique to the test process
+    pub fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("dreamfield_test_{}_{name}", std::process::id()))
+    }
+
+    /// Check that a reader accepts some valid data for a format, but rejects it with the wrong magic,
+    /// a different version, or truncated in the middle of its header. header_offset is where the
+    /// file header is in the data.
+    pub fn assert_rejects_bad_headers<T, E: Display>(valid: &[u8], header_offset: usize,
+        read: impl Fn(&[u8]) -> Result<T, E>)
+    {
+        if let Err(err) = read(valid) {
+            panic!("Valid data was rejected: {err}");
+        }
+
+        let header = FileHeader::read(&valid[header_offset..]).expect("Valid data has no header");
+        let header_range = header_offset..header_offset + FILE_HEADER_LEN;
+
+        let mut wrong_magic = valid.to_vec();
+        wrong_magic[header_range.clone()].copy_from_slice(&FileHeader::new(*b"NOPE", header.version).to_bytes());
+        match read(&wrong_magic) {
+            Ok(_) => panic!("Data with the wrong magic was accepted"),
+            Err(err) => assert!(err.to_string().contains("Not a valid"), "Wrong error for the wrong magic: {err}"),
+        }
+
+        let newer_version = header.version + 1;
+        let mut wrong_version = valid.to_vec();
+        wrong_version[header_range].copy_from_slice(&FileHeader::new(header.magic, newer_version).to_bytes());
+        match read(&wrong_version) {
+            Ok(_) => panic!("Data with version {newer_version} was accepted"),
+            Err(err) => assert!(err.to_string().contains(&format!("version {newer_version}")),
+                "Wrong error for version {newer_version}: {err}"),
+        }
+
+        assert!(read(&valid[..header_offset + FILE_HEADER_LEN - 2]).is_err(), "Truncated data was accepted");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: FileHeader = FileHeader::new(*b"TEST", 3);
+
+    #[test]
+    fn round_trip() {
+        let mut data = HEADER.to_bytes().to_vec();
+        data.extend_from_slice(b"contents");
+
+        assert_eq!(FileHeader::read(&data), Some(HEADER));
+        assert_eq!(HEADER.check(&data, "test file"), Ok(()));
+        assert_eq!(data[0..FILE_HEADER_LEN], HEADER.write_to_vec().unwrap()[..]);
+    }
+
+    #[test]
+    fn bad_headers() {
+        let wrong_magic = FileHeader::new(*b"NOPE", 3).to_bytes();
+        assert_eq!(HEADER.check(&wrong_magic, "test file"), Err(FileHeaderError::WrongFormat("test file")));
+        assert_eq!(HEADER.check(&wrong_magic[..4], "test file"), Err(FileHeaderError::WrongFormat("test file")));
+
+        let err = HEADER.check(&FileHeader::new(*b"TEST", 2).to_bytes(), "test file").unwrap_err();
+        assert_eq!(err.to_string(), "Unsupported test file version 2, expected 3");
+    }
+}
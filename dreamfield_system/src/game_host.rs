@@ -1,11 +1,14 @@
 use std::time::Instant;
+use std::path::PathBuf;
 
 use bevy_ecs::{world::World, schedule::Schedule};
-use glfw::{Action, Context, Key};
+use glfw::{Action, Context, Key, JoystickId, GamepadButton};
 use crate::fixed_timestep::FixedTimestep;
 use crate::resources::{SimTime, Diagnostics};
-use crate::input::{InputState, InputName};
+use crate::input::{InputState, InputName, InputAxis};
+use crate::input_map::{InputMap, InputBinding, MouseSettings, INPUT_MAP_FILENAME};
 use crate::glfw_system::GlfwWindow;
+use crate::replay::{InputRecording, RecordedPlayerState, RecordingStart};
 
 use bevy_ecs::prelude::*;
 
@@ -32,7 +35,9 @@ impl Default for WindowSettings {
 /// while rendering as fast as it can (or at the user's vsync setting)  
 pub struct GameHost {
     window: GlfwWindow,
-    update_timestep: f64
+    update_timestep: f64,
+    input_recording_path: Option<PathBuf>,
+    input_recording_start: Option<RecordingStart>,
 }
 
 impl GameHost {
@@ -43,11 +48,22 @@ impl GameHost {
 
         Self {
             window,
-            update_timestep
+            update_timestep,
+            input_recording_path: None,
+            input_recording_start: None,
         }
     }
 
-    pub fn run(&mut self, mut world: World, mut update_schedule: Schedule, mut render_schedule: Schedule) {
+    /// Record the input for every update, and save it to a file when the game exits, along with what
+    /// the game started from so that it's only replayed from the same start
+    pub fn record_input(&mut self, path: impl Into<PathBuf>, start: RecordingStart) {
+        self.input_recording_path = Some(path.into());
+        self.input_recording_start = Some(start);
+    }
+
+    /// Run the game until the window is closed, returning the world so that the game can clean up
+    /// afterwards, e.g. by saving
+    pub fn run(&mut self, mut world: World, mut update_schedule: Schedule, mut render_schedule: Schedule) -> World {
         // If the window size was too big for the monitor, the initial size can end up smaller than
         // we expect, with no FramebufferSize event... To make sure it's right, we query and set it
         // here.
@@ -62,50 +78,61 @@ impl GameHost {
         // Mouse movement
         let (mut mouse_x, mut mouse_y) = self.window.window.get_cursor_pos();
 
-        // Colemak mode for luci (hax) until we support key rebinding
-        let mut colemak_mode = false;
+        // Input recording
+        let mut input_recording = self.input_recording_start.map(|start| {
+            InputRecording::new(self.update_timestep, start, fixed_timestep.sim_time(), world.resource::<InputState>())
+        });
+
+        // Inputs pressed by scrolling, which are released after an update
+        let mut scroll_inputs = Vec::new();
+
+        // The buttons held on the gamepad last frame
+        let mut gamepad_buttons = [false; (glfw::ffi::GAMEPAD_BUTTON_LAST + 1) as usize];
 
         // Start main loop
         while !self.window.window.should_close() {
+            let mouse_was_captured = self.window.is_mouse_captured();
+
             // Handle events
             world.resource_scope(|world, mut input_state: Mut<InputState>| {
-                world.resource_scope(|_, mut render_settings| {
-                    input_state.mouse_scroll = 0.0;
-                    for event in self.window.poll_events() {
-                        Self::handle_window_event(&mut self.window, event, &mut input_state, &mut render_settings, &mut colemak_mode);
-                    }
+                world.resource_scope(|world, mut render_settings| {
+                    world.resource_scope(|_, mut input_map| {
+                        input_state.mouse_scroll = 0.0;
+                        for event in self.window.poll_events() {
+                            Self::handle_window_event(&mut self.window, event, &mut input_state, &mut render_settings,
+                                &mut input_map, &mut scroll_inputs);
+                        }
+                    });
+                });
+            });
+
+            // Poll gamepad
+            world.resource_scope(|world, mut input_state: Mut<InputState>| {
+                world.resource_scope(|_, mut input_map: Mut<InputMap>| {
+                    Self::poll_gamepad(&self.window, &mut gamepad_buttons, &mut input_state, &mut input_map);
                 });
             });
 
             // Handle mouse movement
-            world.resource_scope(|_, mut input_state| {
-                (mouse_x, mouse_y) = Self::handle_mouse_movement(&self.window, (mouse_x, mouse_y), &mut input_state);
+            world.resource_scope(|world, mut input_state: Mut<InputState>| {
+                let mouse_settings = world.resource::<InputMap>().mouse();
+                (mouse_x, mouse_y) = Self::handle_mouse_movement(&self.window, (mouse_x, mouse_y), mouse_was_captured,
+                    &mut input_state, mouse_settings);
             });
 
             // Update at fixed timestep
             fixed_timestep.update_actual_time(self.window.glfw.get_time());
             while fixed_timestep.should_update() {
-                // Update sim time
-                world.resource_scope(|_, mut sim_time: Mut<SimTime>| {
-                    sim_time.sim_time = fixed_timestep.sim_time();
-                    sim_time.sim_time_delta = self.update_timestep;
-                });
-
-                // Simulate game state
-                let update_start = Instant::now();
-                update_schedule.run(&mut world);
-                let update_time = update_start.elapsed();
+                if let Some(input_recording) = &mut input_recording {
+                    input_recording.push_frame(world.resource::<InputState>());
+                }
 
-                // Update diagnostics
-                world.resource_scope(|_, mut diagnostics: Mut<Diagnostics>| {
-                    diagnostics.update_time = update_time;
-                });
+                run_fixed_update(&mut world, &mut update_schedule, fixed_timestep.sim_time(), self.update_timestep);
 
-                // Save old input states, we do this after each update so that we don't have a
-                // 'first input' in multiple updates.
+                // Release scroll inputs once an update has seen them
                 world.resource_scope(|_, mut input_state: Mut<InputState>| {
-                    for i in 0..input_state.inputs.len() {
-                        input_state.last_inputs[i] = input_state.inputs[i];
+                    for input in scroll_inputs.drain(..) {
+                        input_state.inputs[input as usize] = false;
                     }
                 });
             }
@@ -121,26 +148,42 @@ impl GameHost {
                 diagnostics.render_time = render_time;
             });
         }
+
+        // Save input recording
+        if let (Some(mut input_recording), Some(path)) = (input_recording, &self.input_recording_path) {
+            let diagnostics = world.resource::<Diagnostics>();
+            input_recording.set_end_state(RecordedPlayerState::new(diagnostics.player_pos, diagnostics.player_pitch_yaw));
+
+            match input_recording.save(path) {
+                Ok(()) => log::info!("Saved input recording of {} updates to {}", input_recording.update_count(), path.display()),
+                Err(err) => log::error!("Failed to save input recording to {}: {err}", path.display()),
+            }
+        }
+
+        world
     }
 
     /// Handle events
     fn handle_window_event(window: &mut GlfwWindow, event: glfw::WindowEvent, input_state: &mut Mut<InputState>,
-                           renderer_settings: &mut Mut<WindowSettings>, colemak_mode: &mut bool)
+                           renderer_settings: &mut Mut<WindowSettings>, input_map: &mut Mut<InputMap>,
+                           scroll_inputs: &mut Vec<InputName>)
     {
-        let input_map_func = match colemak_mode {
-            true => Self::map_game_inputs_colemak,
-            false => Self::map_game_inputs_default
-        };
-
         match event {
             glfw::WindowEvent::FramebufferSize(width, height) => {
                 renderer_settings.window_size = (width, height);
             }
-            glfw::WindowEvent::MouseButton(_, Action::Press, _) => {
+            glfw::WindowEvent::MouseButton(button, Action::Press, _) => {
+                // The first click just captures the mouse, so it doesn't also trigger an input
                 if !window.is_mouse_captured() {
                     window.set_mouse_captured(true);
                     input_state.cursor_captured = true;
                 }
+                else {
+                    Self::handle_binding_pressed(InputBinding::MouseButton(button), input_state, input_map);
+                }
+            }
+            glfw::WindowEvent::MouseButton(button, Action::Release, _) => {
+                Self::handle_binding_released(InputBinding::MouseButton(button), input_state, input_map);
             }
             glfw::WindowEvent::Key(Key::LeftAlt, _, Action::Press, _) | glfw::WindowEvent::Focus(false) => {
                 if window.is_mouse_captured() {
@@ -154,10 +197,6 @@ impl GameHost {
             glfw::WindowEvent::Key(Key::F3, _, Action::Press, _) => {
                 renderer_settings.collider_debug = !renderer_settings.collider_debug;
             }
-            glfw::WindowEvent::Key(Key::F9, _, Action::Press, _) => {
-                *colemak_mode = !(*colemak_mode);
-                log::info!("Colemak mode {}", if *colemak_mode { "enabled" } else { "disabled "});
-            }
             glfw::WindowEvent::Key(key, _, Action::Press, _) => {
                 // In debug mode only, let escape exit instantly
                 #[cfg(debug_assertions)]
@@ -165,81 +204,146 @@ impl GameHost {
                     window.window.set_should_close(true);
                 }
 
-                if let Some(input) = input_map_func(key) {
-                    input_state.inputs[input as usize] = true;
-                }
+                Self::handle_binding_pressed(InputBinding::Key(key), input_state, input_map);
             }
             glfw::WindowEvent::Key(key, _, Action::Release, _) => {
-                if let Some(input) = input_map_func(key) {
-                    input_state.inputs[input as usize] = false;
-                }
+                Self::handle_binding_released(InputBinding::Key(key), input_state, input_map);
             }
             glfw::WindowEvent::Scroll(_, vert) => {
                 input_state.mouse_scroll = vert;
+
+                // Scrolling has no release, so inputs bound to it get released after the next update
+                let binding = match vert {
+                    v if v > 0.0 => InputBinding::ScrollUp,
+                    v if v < 0.0 => InputBinding::ScrollDown,
+                    _ => return
+                };
+
+                if input_map.pending_rebind().is_none() {
+                    scroll_inputs.extend(input_map.inputs_for(binding));
+                }
+                Self::handle_binding_pressed(binding, input_state, input_map);
             }
             _ => {}
         }
     }
 
-    /// Map game inputs from the keyboard
-    fn map_game_inputs_default(key: Key) -> Option<InputName> {
-        match key {
-            Key::W => Some(InputName::CamForwards),
-            Key::A => Some(InputName::CamStrafeLeft),
-            Key::S => Some(InputName::CamBackwards),
-            Key::D => Some(InputName::CamStrafeRight),
-            Key::I => Some(InputName::CamLookUp),
-            Key::J => Some(InputName::CamLookLeft),
-            Key::K => Some(InputName::CamLookDown),
-            Key::L => Some(InputName::CamLookRight),
-            Key::Up => Some(InputName::CamLookUp),
-            Key::Left => Some(InputName::CamLookLeft),
-            Key::Down => Some(InputName::CamLookDown),
-            Key::Right => Some(InputName::CamLookRight),
-            Key::LeftShift => Some(InputName::Run),
-            Key::E => Some(InputName::Use),
-            Key::Space => Some(InputName::Jump),
-            Key::Escape => Some(InputName::Pause),
-            Key::U => Some(InputName::Debug),
-            Key::F1 => Some(InputName::EnableDiagnostics),
-            _ => None
+    /// Press the game inputs mapped to a binding, or complete a pending rebind with it
+    fn handle_binding_pressed(binding: InputBinding, input_state: &mut InputState, input_map: &mut InputMap) {
+        if input_map.pending_rebind().is_some() {
+            match input_map.complete_rebind(binding) {
+                Ok(Some(input)) => {
+                    log::info!("Bound {:?} to {}", input, binding);
+                    if let Err(err) = input_map.save(INPUT_MAP_FILENAME) {
+                        log::error!("Failed to save input map: {err}");
+                    }
+                }
+                Ok(None) => log::info!("Cancelled rebind"),
+                Err(err) => log::info!("Can't bind {binding}: {err}"),
+            }
+            return;
+        }
+
+        for input in input_map.inputs_for(binding) {
+            input_state.inputs[input as usize] = true;
         }
     }
 
-    /// Map game inputs from colemak (hax)
-    fn map_game_inputs_colemak(key: Key) -> Option<InputName> {
-        match key {
-            Key::W => Some(InputName::CamForwards),
-            Key::A => Some(InputName::CamStrafeLeft),
-            Key::R => Some(InputName::CamBackwards),
-            Key::S => Some(InputName::CamStrafeRight),
-            Key::U => Some(InputName::CamLookUp),
-            Key::N => Some(InputName::CamLookLeft),
-            Key::E => Some(InputName::CamLookDown),
-            Key::I => Some(InputName::CamLookRight),
-            Key::Up => Some(InputName::CamLookUp),
-            Key::Left => Some(InputName::CamLookLeft),
-            Key::Down => Some(InputName::CamLookDown),
-            Key::Right => Some(InputName::CamLookRight),
-            Key::LeftShift => Some(InputName::Run),
-            Key::F => Some(InputName::Use),
-            Key::Space => Some(InputName::Jump),
-            Key::Escape => Some(InputName::Pause),
-            Key::F1 => Some(InputName::EnableDiagnostics),
-            _ => None
+    /// Release the game inputs mapped to a binding
+    fn handle_binding_released(binding: InputBinding, input_state: &mut InputState, input_map: &InputMap) {
+        for input in input_map.inputs_for(binding) {
+            input_state.inputs[input as usize] = false;
         }
     }
 
-    /// Handle mouse movement
-    fn handle_mouse_movement(window: &GlfwWindow, (old_mouse_x, old_mouse_y): (f64, f64),
-                             input_state: &mut InputState) -> (f64, f64)
+    /// Poll the first connected gamepad, pressing or releasing the inputs bound to its buttons, and
+    /// setting the analog axes from its sticks
+    fn poll_gamepad(window: &GlfwWindow, gamepad_buttons: &mut [bool], input_state: &mut InputState,
+                    input_map: &mut InputMap)
+    {
+        let gamepad_state = (0..=glfw::ffi::JOYSTICK_LAST)
+            .filter_map(JoystickId::from_i32)
+            .map(|id| window.glfw.get_joystick(id))
+            .find(|joystick| joystick.is_gamepad())
+            .and_then(|joystick| joystick.get_gamepad_state());
+
+        // Buttons, which release everything if the gamepad is disconnected
+        let buttons = (0..=glfw::ffi::GAMEPAD_BUTTON_LAST).filter_map(GamepadButton::from_i32);
+        for (button, was_pressed) in buttons.zip(gamepad_buttons.iter_mut()) {
+            let pressed = gamepad_state
+                .as_ref()
+                .map(|state| state.get_button_state(button) == Action::Press)
+                .unwrap_or(false);
+
+            if pressed != *was_pressed {
+                *was_pressed = pressed;
+                match pressed {
+                    true => Self::handle_binding_pressed(InputBinding::GamepadButton(button), input_state, input_map),
+                    false => Self::handle_binding_released(InputBinding::GamepadButton(button), input_state, input_map),
+                }
+            }
+        }
+
+        // Axes
+        input_state.axes = [0.0; InputAxis::Last as usize];
+        if let Some(gamepad_state) = gamepad_state {
+            let settings = input_map.gamepad();
+            for binding in settings.axes.iter() {
+                let value = settings.apply_deadzone(gamepad_state.get_axis(binding.gamepad_axis));
+                input_state.axes[binding.input_axis as usize] += value * binding.sensitivity;
+            }
+        }
+    }
+
+    /// Handle mouse movement. The movement is accumulated until an update consumes it, so that it
+    /// doesn't get lost or applied twice when the framerate differs from the update rate.
+    fn handle_mouse_movement(window: &GlfwWindow, (old_mouse_x, old_mouse_y): (f64, f64), mouse_was_captured: bool,
+                             input_state: &mut InputState, mouse_settings: &MouseSettings) -> (f64, f64)
     {
         let (mouse_x, mouse_y) = window.window.get_cursor_pos();
-        
-        let (mouse_dx, mouse_dy) = (mouse_x - old_mouse_x, mouse_y - old_mouse_y);
 
-        input_state.mouse_diff = (mouse_dx, mouse_dy);
+        // Only look while the mouse is captured. The cursor can jump when it gets captured, so we
+        // skip the frame it happens too.
+        if mouse_was_captured && window.is_mouse_captured() {
+            let (mouse_dx, mouse_dy) = (mouse_x - old_mouse_x, mouse_y - old_mouse_y);
+            let mouse_dy = if mouse_settings.invert_y { -mouse_dy } else { mouse_dy };
+
+            input_state.mouse_diff.0 += mouse_dx * mouse_settings.sensitivity;
+            input_state.mouse_diff.1 += mouse_dy * mouse_settings.sensitivity;
+        }
 
         (mouse_x, mouse_y)
     }
 }
+
+/// Run a single fixed update of the update schedule at the given sim time
+pub(crate) fn run_fixed_update(world: &mut World, update_schedule: &mut Schedule, sim_time: f64, sim_time_delta: f64) {
+    // Update sim time
+    world.resource_scope(|_, mut sim_time_res: Mut<SimTime>| {
+        sim_time_res.sim_time = sim_time;
+        sim_time_res.sim_time_delta = sim_time_delta;
+    });
+
+    // Simulate game state
+    let update_start = Instant::now();
+    update_schedule.run(world);
+    let update_time = update_start.elapsed();
+
+    // Clear removed components, so that each update only sees the components removed since the
+    // last one
+    world.clear_trackers();
+
+    // Update diagnostics
+    world.resource_scope(|_, mut diagnostics: Mut<Diagnostics>| {
+        diagnostics.update_time = update_time;
+    });
+
+    // Save old input states, we do this after each update so that we don't have a
+    // 'first input' in multiple updates. The mouse movement has been consumed by the update too.
+    world.resource_scope(|_, mut input_state: Mut<InputState>| {
+        for i in 0..input_state.inputs.len() {
+            input_state.last_inputs[i] = input_state.inputs[i];
+        }
+        input_state.mouse_diff = (0.0, 0.0);
+    });
+}
@@ -0,0 +1,112 @@
+use bevy_ecs::{world::World, schedule::Schedule};
+use crate::fixed_timestep::FixedTimestep;
+use crate::game_host::run_fixed_update;
+use crate::input::InputState;
+use crate::replay::InputRecording;
+
+use bevy_ecs::prelude::*;
+
+/// A game host that runs the update schedule without a window, from a virtual clock and a script
+/// of input frames. The render schedule is skipped entirely, so this can be used to run the sim on
+/// machines without a display, e.g. for soak tests in CI.
+pub struct HeadlessGameHost {
+    update_timestep: f64,
+    frame_time: f64,
+    virtual_time: f64,
+    sim_time: f64,
+    fixed_timestep: FixedTimestep,
+}
+
+impl HeadlessGameHost {
+    /// Create a new headless game host. `frame_time` is the amount of virtual time that passes for
+    /// each scripted input frame, it can be shorter or longer than the update timestep in order to
+    /// simulate frames that run no updates or several.
+    pub fn new(update_timestep: f64, frame_time: f64) -> Self {
+        Self {
+            update_timestep,
+            frame_time,
+            virtual_time: 0.0,
+            sim_time: 0.0,
+            fixed_timestep: FixedTimestep::new(update_timestep, 0.0),
+        }
+    }
+
+    /// Run every frame of an input script, returning the total number of updates that were run
+    pub fn run(&mut self, world: &mut World, update_schedule: &mut Schedule,
+        frames: impl IntoIterator<Item=InputState>) -> u32
+    {
+        frames.into_iter()
+            .map(|input| self.run_frame(world, update_schedule, &input))
+            .sum()
+    }
+
+    /// Run a single frame with the given input, returning the number of updates that were run
+    pub fn run_frame(&mut self, world: &mut World, update_schedule: &mut Schedule, input: &InputState) -> u32 {
+        // Apply the scripted input. We don't take last_inputs from the script, as that's maintained
+        // by the updates themselves so that 'just pressed' works the same as it does with a window.
+        // Mouse movement accumulates until an update consumes it, also like with a window.
+        world.resource_scope(|_, mut input_state: Mut<InputState>| {
+            input_state.inputs = input.inputs;
+            input_state.axes = input.axes;
+            input_state.cursor_captured = input.cursor_captured;
+            input_state.mouse_diff.0 += input.mouse_diff.0;
+            input_state.mouse_diff.1 += input.mouse_diff.1;
+            input_state.mouse_scroll = input.mouse_scroll;
+        });
+
+        // Advance the virtual clock and update at fixed timestep
+        self.virtual_time += self.frame_time;
+        self.fixed_timestep.update_actual_time(self.virtual_time);
+
+        let mut update_count = 0;
+        while self.fixed_timestep.should_update() {
+            self.sim_time = self.fixed_timestep.sim_time();
+            run_fixed_update(world, update_schedule, self.sim_time, self.update_timestep);
+            update_count += 1;
+        }
+
+        update_count
+    }
+
+    /// Replay an input recording, returning the number of updates that were run. This runs exactly
+    /// one update per recorded frame, stepping the sim time the same way the fixed timestep did
+    /// when it was recorded, rather than going through the virtual clock.
+    pub fn run_recording(&mut self, world: &mut World, update_schedule: &mut Schedule, recording: &InputRecording) -> u32 {
+        if recording.update_timestep() != self.update_timestep {
+            log::warn!("Replaying input recorded at a timestep of {}s, instead of {}s",
+                recording.update_timestep(), self.update_timestep);
+        }
+
+        // Restore the input state the recording started from
+        world.resource_scope(|_, mut input_state: Mut<InputState>| {
+            recording.apply_start(&mut input_state);
+        });
+
+        let timestep = recording.update_timestep();
+        self.sim_time = recording.start_sim_time();
+
+        let mut update_count = 0;
+        for frame in recording.frames() {
+            world.resource_scope(|_, mut input_state: Mut<InputState>| {
+                frame.apply(&mut input_state);
+            });
+
+            self.virtual_time += timestep;
+            self.sim_time += timestep;
+            run_fixed_update(world, update_schedule, self.sim_time, timestep);
+            update_count += 1;
+        }
+
+        update_count
+    }
+
+    /// Get the current virtual time
+    pub fn virtual_time(&self) -> f64 {
+        self.virtual_time
+    }
+
+    /// Get the current sim time
+    pub fn sim_time(&self) -> f64 {
+        self.sim_time
+    }
+}
@@ -1,3 +1,5 @@
+use serde::{Serialize, Deserialize};
+
 /// Input events
 #[derive(Copy, Clone)]
 pub enum InputEvent {
@@ -7,7 +9,7 @@ pub enum InputEvent {
 }
 
 /// Input names
-#[derive(Copy, Clone)]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum InputName {
     CamForwards,
     CamBackwards,
@@ -26,12 +28,45 @@ pub enum InputName {
     Last
 }
 
+impl InputName {
+    /// All the input names, excluding Last
+    pub const ALL: [InputName; InputName::Last as usize] = [
+        InputName::CamForwards,
+        InputName::CamBackwards,
+        InputName::CamStrafeLeft,
+        InputName::CamStrafeRight,
+        InputName::CamLookUp,
+        InputName::CamLookLeft,
+        InputName::CamLookDown,
+        InputName::CamLookRight,
+        InputName::Run,
+        InputName::Jump,
+        InputName::Use,
+        InputName::Debug,
+        InputName::Pause,
+        InputName::EnableDiagnostics,
+    ];
+}
+
+/// Analog input axes, from sources such as gamepad sticks. The name of each axis is the direction
+/// that's positive.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum InputAxis {
+    MoveForwards,
+    MoveRight,
+    LookLeft,
+    LookUp,
+    Last
+}
+
 /// The current input state
 #[derive(Copy, Clone)]
 pub struct InputState {
     pub inputs: [bool; InputName::Last as usize],
     pub last_inputs: [bool; InputName::Last as usize],
+    pub axes: [f32; InputAxis::Last as usize],
     pub cursor_captured: bool,
+    /// The look movement from the mouse since the last update, in radians, positive is right and down
     pub mouse_diff: (f64, f64),
     pub mouse_scroll: f64,
 }
@@ -41,6 +76,7 @@ impl InputState {
         Self {
             inputs: [false; InputName::Last as usize],
             last_inputs: [false; InputName::Last as usize],
+            axes: [0.0; InputAxis::Last as usize],
             cursor_captured: false,
             mouse_diff: (0.0, 0.0),
             mouse_scroll: 0.0,
@@ -62,14 +98,30 @@ impl InputState {
         self.inputs[name as usize] && !self.last_inputs[name as usize]
     }
 
+    /// Set whether the input is held, e.g. for scripting input
+    pub fn set_held(&mut self, name: InputName, held: bool) {
+        self.inputs[name as usize] = held;
+    }
+
+    /// Get the value of an analog axis
+    pub fn get_axis(&self, axis: InputAxis) -> f32 {
+        self.axes[axis as usize]
+    }
+
+    /// Set the value of an analog axis, e.g. for scripting input
+    pub fn set_axis(&mut self, axis: InputAxis, value: f32) {
+        self.axes[axis as usize] = value;
+    }
+
     /// Clear whether just pressed or released
     pub fn clear_just_pressed(&mut self, name: InputName) {
         self.last_inputs[name as usize] = self.inputs[name as usize];
     }
 
-    // Get the look input as a normalized float from 1 to -1. The first element is the left/right
-    // look input, where positive is movement to the right, and the second element is up/down
-    // movement, where positive is movement up.
+    // Get the look input, combining the digital inputs with the analog axes. The first element is
+    // the left/right look input, where positive is movement to the left, and the second element is
+    // up/down movement, where positive is movement up. This isn't clamped, so that analog look
+    // sensitivity can go above the keyboard look speed.
     pub fn get_look_input(&self) -> (f32, f32) {
         let inputs = &self.inputs;
 
@@ -90,12 +142,12 @@ impl InputState {
             _ => 0.0
         };
 
-        (cam_look_horizontal, cam_look_vertical)
+        (cam_look_horizontal + self.get_axis(InputAxis::LookLeft), cam_look_vertical + self.get_axis(InputAxis::LookUp))
     }
 
-    /// Get the movement input as a normalize float from 1 to -1. The first element is the
-    /// forward/back movement where positive is forward, and the second element is left/right
-    /// movement where positive is right.
+    /// Get the movement input as a normalize float from 1 to -1, combining the digital inputs with
+    /// the analog axes. The first element is the forward/back movement where positive is forward,
+    /// and the second element is left/right movement where positive is right.
     pub fn get_movement_input(&self) -> (f32, f32) {
         let inputs = self.inputs;
 
@@ -116,6 +168,9 @@ impl InputState {
             _ => 0.0
         };
 
+        let forward_cam_movement = f32::clamp(forward_cam_movement + self.get_axis(InputAxis::MoveForwards), -1.0, 1.0);
+        let right_cam_movement = f32::clamp(right_cam_movement + self.get_axis(InputAxis::MoveRight), -1.0, 1.0);
+
         (forward_cam_movement, right_cam_movement)
     }
 }
@@ -0,0 +1,560 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use glfw::{Key, MouseButton, GamepadButton, GamepadAxis};
+use serde::{Serialize, Deserialize};
+use crate::input::{InputName, InputAxis};
+
+/// The filename the input map is loaded from and saved to
+pub const INPUT_MAP_FILENAME: &'static str = "input_map.ron";
+
+/// A physical input that can be bound to a game input
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum InputBinding {
+    Key(#[serde(with = "key_name")] Key),
+    MouseButton(#[serde(with = "mouse_button_number")] MouseButton),
+    ScrollUp,
+    ScrollDown,
+    GamepadButton(#[serde(with = "gamepad_button_name")] GamepadButton),
+}
+
+impl fmt::Display for InputBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputBinding::Key(key) => write!(f, "{key:?}"),
+            InputBinding::MouseButton(button) => write!(f, "Mouse {}", *button as i32 + 1),
+            InputBinding::ScrollUp => write!(f, "Scroll up"),
+            InputBinding::ScrollDown => write!(f, "Scroll down"),
+            InputBinding::GamepadButton(button) => write!(f, "Gamepad {button:?}"),
+        }
+    }
+}
+
+/// Why a binding couldn't be changed
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BindingError {
+    /// The binding is already bound to another game input
+    AlreadyBound(InputName),
+    /// Pause always needs a key, so that the menus can't become unreachable
+    PauseNeedsKey,
+}
+
+impl fmt::Display for BindingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindingError::AlreadyBound(input) => write!(f, "Already bound to {input:?}"),
+            BindingError::PauseNeedsKey => write!(f, "Pause must be bound to a key"),
+        }
+    }
+}
+
+impl Error for BindingError {}
+
+/// The mouse settings
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MouseSettings {
+    /// The look speed in radians per pixel of mouse movement
+    pub sensitivity: f64,
+    pub invert_y: bool,
+}
+
+impl Default for MouseSettings {
+    fn default() -> Self {
+        Self {
+            sensitivity: 0.0025,
+            invert_y: false,
+        }
+    }
+}
+
+/// Maps a gamepad axis to an analog input axis
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+pub struct GamepadAxisBinding {
+    pub input_axis: InputAxis,
+    #[serde(with = "gamepad_axis_name")]
+    pub gamepad_axis: GamepadAxis,
+    /// The scale applied to the axis after the deadzone, negative values invert the axis
+    pub sensitivity: f32,
+}
+
+/// The gamepad settings
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GamepadSettings {
+    /// Axis values with a magnitude below this are treated as zero
+    pub deadzone: f32,
+    pub axes: Vec<GamepadAxisBinding>,
+}
+
+impl GamepadSettings {
+    /// Apply the deadzone to a raw axis value, rescaling so the output still goes smoothly from 0 to 1
+    pub fn apply_deadzone(&self, value: f32) -> f32 {
+        if value.abs() < self.deadzone {
+            0.0
+        }
+        else {
+            value.signum() * (value.abs() - self.deadzone) / (1.0 - self.deadzone)
+        }
+    }
+}
+
+impl Default for GamepadSettings {
+    fn default() -> Self {
+        // Gamepad Y axes are positive downwards, so they're inverted to get forwards and up
+        Self {
+            deadzone: 0.2,
+            axes: vec![
+                GamepadAxisBinding { input_axis: InputAxis::MoveForwards, gamepad_axis: GamepadAxis::AxisLeftY, sensitivity: -1.0 },
+                GamepadAxisBinding { input_axis: InputAxis::MoveRight, gamepad_axis: GamepadAxis::AxisLeftX, sensitivity: 1.0 },
+                GamepadAxisBinding { input_axis: InputAxis::LookLeft, gamepad_axis: GamepadAxis::AxisRightX, sensitivity: -1.0 },
+                GamepadAxisBinding { input_axis: InputAxis::LookUp, gamepad_axis: GamepadAxis::AxisRightY, sensitivity: -0.75 },
+            ]
+        }
+    }
+}
+
+/// The input map resource, a data-driven map from keys, mouse buttons and scrolling to game
+/// inputs. Each game input can have several bindings, and they can be rebound at runtime.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InputMap {
+    bindings: BTreeMap<InputName, Vec<InputBinding>>,
+    #[serde(default)]
+    mouse: MouseSettings,
+    #[serde(default)]
+    gamepad: GamepadSettings,
+    #[serde(skip)]
+    pending_rebind: Option<InputName>,
+    #[serde(skip)]
+    rebind_error: Option<BindingError>,
+}
+
+impl InputMap {
+    /// Create an empty input map with no bindings
+    pub fn new() -> Self {
+        Self {
+            bindings: BTreeMap::new(),
+            mouse: MouseSettings::default(),
+            gamepad: GamepadSettings::default(),
+            pending_rebind: None,
+            rebind_error: None,
+        }
+    }
+
+    /// Create an input map from a list of bindings, failing if a binding is given to two game inputs
+    pub fn new_with_bindings(bindings: &[(InputName, InputBinding)]) -> Result<Self, BindingError> {
+        let mut input_map = Self::new();
+        for (input, binding) in bindings.iter() {
+            input_map.add_binding(*input, *binding)?;
+        }
+        Ok(input_map)
+    }
+
+    /// The default bindings for colemak keyboards
+    pub fn colemak() -> Self {
+        Self::new_with_bindings(&[
+            (InputName::CamForwards, InputBinding::Key(Key::W)),
+            (InputName::CamStrafeLeft, InputBinding::Key(Key::A)),
+            (InputName::CamBackwards, InputBinding::Key(Key::R)),
+            (InputName::CamStrafeRight, InputBinding::Key(Key::S)),
+            (InputName::CamLookUp, InputBinding::Key(Key::U)),
+            (InputName::CamLookLeft, InputBinding::Key(Key::N)),
+            (InputName::CamLookDown, InputBinding::Key(Key::E)),
+            (InputName::CamLookRight, InputBinding::Key(Key::I)),
+            (InputName::CamLookUp, InputBinding::Key(Key::Up)),
+            (InputName::CamLookLeft, InputBinding::Key(Key::Left)),
+            (InputName::CamLookDown, InputBinding::Key(Key::Down)),
+            (InputName::CamLookRight, InputBinding::Key(Key::Right)),
+            (InputName::Run, InputBinding::Key(Key::LeftShift)),
+            (InputName::Use, InputBinding::Key(Key::F)),
+            (InputName::Jump, InputBinding::Key(Key::Space)),
+            (InputName::Pause, InputBinding::Key(Key::Escape)),
+            (InputName::EnableDiagnostics, InputBinding::Key(Key::F1)),
+            (InputName::CamForwards, InputBinding::GamepadButton(GamepadButton::ButtonDpadUp)),
+            (InputName::CamBackwards, InputBinding::GamepadButton(GamepadButton::ButtonDpadDown)),
+            (InputName::CamStrafeLeft, InputBinding::GamepadButton(GamepadButton::ButtonDpadLeft)),
+            (InputName::CamStrafeRight, InputBinding::GamepadButton(GamepadButton::ButtonDpadRight)),
+            (InputName::Run, InputBinding::GamepadButton(GamepadButton::ButtonLeftThumb)),
+            (InputName::Jump, InputBinding::GamepadButton(GamepadButton::ButtonA)),
+            (InputName::Use, InputBinding::GamepadButton(GamepadButton::ButtonX)),
+            (InputName::Pause, InputBinding::GamepadButton(GamepadButton::ButtonStart)),
+        ]).expect("Conflicting colemak bindings")
+    }
+
+    /// Load the input map from a file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let contents = std::fs::read_to_string(path)?;
+        let mut input_map: Self = ron::from_str(&contents)?;
+        input_map.ensure_pause_key();
+        Ok(input_map)
+    }
+
+    /// Load the input map from a file, or use the default bindings if it doesn't exist or is invalid
+    pub fn load_or_default(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        if !path.exists() {
+            log::info!("No input map at {}, using default bindings", path.display());
+            return Self::default();
+        }
+
+        Self::load(path).unwrap_or_else(|err| {
+            log::error!("Failed to load input map from {}, using default bindings: {err}", path.display());
+            Self::default()
+        })
+    }
+
+    /// Save the input map to a file
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        let contents = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::new())?;
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
+
+    /// Get the game inputs a binding is mapped to
+    pub fn inputs_for(&self, binding: InputBinding) -> impl Iterator<Item=InputName> + '_ {
+        self.bindings
+            .iter()
+            .filter(move |(_, bindings)| bindings.contains(&binding))
+            .map(|(input, _)| *input)
+    }
+
+    /// Get the bindings for a game input
+    pub fn bindings(&self, input: InputName) -> &[InputBinding] {
+        self.bindings
+            .get(&input)
+            .map(|bindings| bindings.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Add a binding for a game input, refusing if it's already bound to another game input
+    pub fn add_binding(&mut self, input: InputName, binding: InputBinding) -> Result<(), BindingError> {
+        self.check_unbound(input, binding)?;
+
+        let bindings = self.bindings.entry(input).or_insert_with(Vec::new);
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+        Ok(())
+    }
+
+    /// Check a binding isn't bound to any game input other than the given one
+    fn check_unbound(&self, input: InputName, binding: InputBinding) -> Result<(), BindingError> {
+        match self.inputs_for(binding).find(|other_input| *other_input != input) {
+            Some(other_input) => Err(BindingError::AlreadyBound(other_input)),
+            None => Ok(())
+        }
+    }
+
+    /// Check whether pause would still have a key with the given bindings
+    fn check_pause_key(input: InputName, bindings: &[InputBinding]) -> Result<(), BindingError> {
+        if input == InputName::Pause && !bindings.iter().any(|binding| matches!(binding, InputBinding::Key(_))) {
+            Err(BindingError::PauseNeedsKey)
+        }
+        else {
+            Ok(())
+        }
+    }
+
+    /// Bind pause to escape if it has no key, which can happen if the input map file was edited by hand
+    fn ensure_pause_key(&mut self) {
+        if Self::check_pause_key(InputName::Pause, self.bindings(InputName::Pause)).is_ok() {
+            return;
+        }
+
+        log::warn!("Pause has no key in the input map, binding it to Escape");
+        let escape = InputBinding::Key(Key::Escape);
+        for bindings in self.bindings.values_mut() {
+            bindings.retain(|b| *b != escape);
+        }
+        self.bindings.entry(InputName::Pause).or_insert_with(Vec::new).push(escape);
+    }
+
+    /// Get the mouse settings
+    pub fn mouse(&self) -> &MouseSettings {
+        &self.mouse
+    }
+
+    /// Get the mouse settings mutably
+    pub fn mouse_mut(&mut self) -> &mut MouseSettings {
+        &mut self.mouse
+    }
+
+    /// Get the gamepad settings
+    pub fn gamepad(&self) -> &GamepadSettings {
+        &self.gamepad
+    }
+
+    /// Get the gamepad settings mutably
+    pub fn gamepad_mut(&mut self) -> &mut GamepadSettings {
+        &mut self.gamepad
+    }
+
+    /// Remove a binding from a game input, refusing to remove pause's last key
+    pub fn remove_binding(&mut self, input: InputName, binding: InputBinding) -> Result<(), BindingError> {
+        let remaining: Vec<InputBinding> = self.bindings(input).iter().copied().filter(|b| *b != binding).collect();
+        Self::check_pause_key(input, &remaining)?;
+        self.bindings.insert(input, remaining);
+        Ok(())
+    }
+
+    /// Remove all bindings from a game input, which isn't allowed for pause
+    pub fn clear_bindings(&mut self, input: InputName) -> Result<(), BindingError> {
+        Self::check_pause_key(input, &[])?;
+        self.bindings.remove(&input);
+        Ok(())
+    }
+
+    /// Replace all of the key and button bindings with another input map's, e.g. to reset them to a
+    /// default layout, keeping the mouse and gamepad settings
+    pub fn reset_bindings(&mut self, defaults: &InputMap) {
+        self.bindings = defaults.bindings.clone();
+        self.cancel_rebind();
+    }
+
+    /// Start rebinding a game input, the next key or button pressed will replace its bindings
+    pub fn start_rebind(&mut self, input: InputName) {
+        self.pending_rebind = Some(input);
+        self.rebind_error = None;
+    }
+
+    /// Cancel a pending rebind
+    pub fn cancel_rebind(&mut self) {
+        self.pending_rebind = None;
+        self.rebind_error = None;
+    }
+
+    /// Get the game input that's currently waiting to be rebound, if any
+    pub fn pending_rebind(&self) -> Option<InputName> {
+        self.pending_rebind
+    }
+
+    /// Get why the last attempt to complete the pending rebind was refused, if it was
+    pub fn rebind_error(&self) -> Option<BindingError> {
+        self.rebind_error
+    }
+
+    /// Complete a pending rebind with the given binding, returning the game input that was rebound.
+    /// Bindings used by other game inputs are refused and the rebind stays pending, except for pause's
+    /// bindings, which cancel it.
+    pub fn complete_rebind(&mut self, binding: InputBinding) -> Result<Option<InputName>, BindingError> {
+        let input = match self.pending_rebind {
+            Some(input) => input,
+            None => return Ok(None)
+        };
+
+        let result = self.check_unbound(input, binding)
+            .and_then(|_| Self::check_pause_key(input, &[binding]));
+        match result {
+            Ok(()) => {
+                self.cancel_rebind();
+                self.bindings.insert(input, vec![binding]);
+                Ok(Some(input))
+            }
+            Err(BindingError::AlreadyBound(InputName::Pause)) => {
+                self.cancel_rebind();
+                Ok(None)
+            }
+            Err(err) => {
+                self.rebind_error = Some(err);
+                Err(err)
+            }
+        }
+    }
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        Self::new_with_bindings(&[
+            (InputName::CamForwards, InputBinding::Key(Key::W)),
+            (InputName::CamStrafeLeft, InputBinding::Key(Key::A)),
+            (InputName::CamBackwards, InputBinding::Key(Key::S)),
+            (InputName::CamStrafeRight, InputBinding::Key(Key::D)),
+            (InputName::CamLookUp, InputBinding::Key(Key::I)),
+            (InputName::CamLookLeft, InputBinding::Key(Key::J)),
+            (InputName::CamLookDown, InputBinding::Key(Key::K)),
+            (InputName::CamLookRight, InputBinding::Key(Key::L)),
+            (InputName::CamLookUp, InputBinding::Key(Key::Up)),
+            (InputName::CamLookLeft, InputBinding::Key(Key::Left)),
+            (InputName::CamLookDown, InputBinding::Key(Key::Down)),
+            (InputName::CamLookRight, InputBinding::Key(Key::Right)),
+            (InputName::Run, InputBinding::Key(Key::LeftShift)),
+            (InputName::Use, InputBinding::Key(Key::E)),
+            (InputName::Jump, InputBinding::Key(Key::Space)),
+            (InputName::Pause, InputBinding::Key(Key::Escape)),
+            (InputName::Debug, InputBinding::Key(Key::U)),
+            (InputName::EnableDiagnostics, InputBinding::Key(Key::F1)),
+            (InputName::CamForwards, InputBinding::GamepadButton(GamepadButton::ButtonDpadUp)),
+            (InputName::CamBackwards, InputBinding::GamepadButton(GamepadButton::ButtonDpadDown)),
+            (InputName::CamStrafeLeft, InputBinding::GamepadButton(GamepadButton::ButtonDpadLeft)),
+            (InputName::CamStrafeRight, InputBinding::GamepadButton(GamepadButton::ButtonDpadRight)),
+            (InputName::Run, InputBinding::GamepadButton(GamepadButton::ButtonLeftThumb)),
+            (InputName::Jump, InputBinding::GamepadButton(GamepadButton::ButtonA)),
+            (InputName::Use, InputBinding::GamepadButton(GamepadButton::ButtonX)),
+            (InputName::Pause, InputBinding::GamepadButton(GamepadButton::ButtonStart)),
+        ]).expect("Conflicting default bindings")
+    }
+}
+
+/// Serialize keys by name, as glfw::Key doesn't implement serde
+mod key_name {
+    use glfw::Key;
+    use serde::{Serializer, Deserializer, Deserialize, de::Error};
+
+    /// All the keys that can be bound
+    const KEYS: &'static [Key] = &[
+        Key::Space, Key::Apostrophe, Key::Comma, Key::Minus, Key::Period, Key::Slash,
+        Key::Num0, Key::Num1, Key::Num2, Key::Num3, Key::Num4, Key::Num5, Key::Num6, Key::Num7, Key::Num8, Key::Num9,
+        Key::Semicolon, Key::Equal,
+        Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I, Key::J, Key::K, Key::L, Key::M,
+        Key::N, Key::O, Key::P, Key::Q, Key::R, Key::S, Key::T, Key::U, Key::V, Key::W, Key::X, Key::Y, Key::Z,
+        Key::LeftBracket, Key::Backslash, Key::RightBracket, Key::GraveAccent, Key::World1, Key::World2,
+        Key::Escape, Key::Enter, Key::Tab, Key::Backspace, Key::Insert, Key::Delete,
+        Key::Right, Key::Left, Key::Down, Key::Up, Key::PageUp, Key::PageDown, Key::Home, Key::End,
+        Key::CapsLock, Key::ScrollLock, Key::NumLock, Key::PrintScreen, Key::Pause,
+        Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6, Key::F7, Key::F8, Key::F9, Key::F10, Key::F11,
+        Key::F12, Key::F13, Key::F14, Key::F15, Key::F16, Key::F17, Key::F18, Key::F19, Key::F20, Key::F21,
+        Key::F22, Key::F23, Key::F24, Key::F25,
+        Key::Kp0, Key::Kp1, Key::Kp2, Key::Kp3, Key::Kp4, Key::Kp5, Key::Kp6, Key::Kp7, Key::Kp8, Key::Kp9,
+        Key::KpDecimal, Key::KpDivide, Key::KpMultiply, Key::KpSubtract, Key::KpAdd, Key::KpEnter, Key::KpEqual,
+        Key::LeftShift, Key::LeftControl, Key::LeftAlt, Key::LeftSuper,
+        Key::RightShift, Key::RightControl, Key::RightAlt, Key::RightSuper, Key::Menu,
+    ];
+
+    pub fn serialize<S: Serializer>(key: &Key, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{key:?}"))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Key, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        KEYS.iter()
+            .find(|key| format!("{key:?}") == name)
+            .copied()
+            .ok_or_else(|| D::Error::custom(format!("Unknown key {name}")))
+    }
+}
+
+/// Serialize gamepad buttons by name, as glfw::GamepadButton doesn't implement serde
+mod gamepad_button_name {
+    use glfw::GamepadButton;
+    use serde::{Serializer, Deserializer, Deserialize, de::Error};
+
+    pub fn serialize<S: Serializer>(button: &GamepadButton, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{button:?}"))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<GamepadButton, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        (0..=glfw::ffi::GAMEPAD_BUTTON_LAST)
+            .filter_map(GamepadButton::from_i32)
+            .find(|button| format!("{button:?}") == name)
+            .ok_or_else(|| D::Error::custom(format!("Unknown gamepad button {name}")))
+    }
+}
+
+/// Serialize gamepad axes by name, as glfw::GamepadAxis doesn't implement serde
+mod gamepad_axis_name {
+    use glfw::GamepadAxis;
+    use serde::{Serializer, Deserializer, Deserialize, de::Error};
+
+    pub fn serialize<S: Serializer>(axis: &GamepadAxis, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{axis:?}"))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<GamepadAxis, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        (0..=glfw::ffi::GAMEPAD_AXIS_LAST)
+            .filter_map(GamepadAxis::from_i32)
+            .find(|axis| format!("{axis:?}") == name)
+            .ok_or_else(|| D::Error::custom(format!("Unknown gamepad axis {name}")))
+    }
+}
+
+/// Serialize mouse buttons by their number starting from 1, as glfw::MouseButton doesn't implement serde
+mod mouse_button_number {
+    use glfw::MouseButton;
+    use serde::{Serializer, Deserializer, Deserialize, de::Error};
+
+    pub fn serialize<S: Serializer>(button: &MouseButton, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i32(*button as i32 + 1)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<MouseButton, D::Error> {
+        let number = i32::deserialize(deserializer)?;
+        MouseButton::from_i32(number - 1)
+            .ok_or_else(|| D::Error::custom(format!("Unknown mouse button {number}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bindings_are_not_stolen() {
+        let mut input_map = InputMap::default();
+        assert_eq!(input_map.add_binding(InputName::Jump, InputBinding::Key(Key::E)),
+            Err(BindingError::AlreadyBound(InputName::Use)));
+        assert_eq!(input_map.inputs_for(InputBinding::Key(Key::E)).collect::<Vec<_>>(), vec![InputName::Use]);
+        assert_eq!(input_map.add_binding(InputName::Jump, InputBinding::Key(Key::Q)), Ok(()));
+    }
+
+    #[test]
+    fn rebinds_refuse_bound_keys_and_stay_pending() {
+        let mut input_map = InputMap::default();
+        input_map.start_rebind(InputName::Jump);
+        assert_eq!(input_map.complete_rebind(InputBinding::Key(Key::E)), Err(BindingError::AlreadyBound(InputName::Use)));
+        assert_eq!(input_map.pending_rebind(), Some(InputName::Jump));
+        assert_eq!(input_map.rebind_error(), Some(BindingError::AlreadyBound(InputName::Use)));
+
+        assert_eq!(input_map.complete_rebind(InputBinding::Key(Key::Q)), Ok(Some(InputName::Jump)));
+        assert_eq!(input_map.bindings(InputName::Jump), &[InputBinding::Key(Key::Q)]);
+        assert_eq!(input_map.pending_rebind(), None);
+    }
+
+    #[test]
+    fn pause_cancels_rebinds() {
+        let mut input_map = InputMap::default();
+        input_map.start_rebind(InputName::Jump);
+        assert_eq!(input_map.complete_rebind(InputBinding::Key(Key::Escape)), Ok(None));
+        assert_eq!(input_map.pending_rebind(), None);
+        assert_eq!(input_map.inputs_for(InputBinding::Key(Key::Escape)).collect::<Vec<_>>(), vec![InputName::Pause]);
+    }
+
+    #[test]
+    fn pause_always_has_a_key() {
+        let mut input_map = InputMap::default();
+        input_map.start_rebind(InputName::Pause);
+        assert_eq!(input_map.complete_rebind(InputBinding::MouseButton(MouseButton::Button4)), Err(BindingError::PauseNeedsKey));
+        assert_eq!(input_map.clear_bindings(InputName::Pause), Err(BindingError::PauseNeedsKey));
+        assert_eq!(input_map.remove_binding(InputName::Pause, InputBinding::Key(Key::Escape)), Err(BindingError::PauseNeedsKey));
+        assert_eq!(input_map.remove_binding(InputName::Pause, InputBinding::GamepadButton(GamepadButton::ButtonStart)), Ok(()));
+
+        let mut input_map: InputMap = ron::from_str("(bindings: { Debug: [Key(\"Escape\")] })").unwrap();
+        input_map.ensure_pause_key();
+        assert_eq!(input_map.bindings(InputName::Pause), &[InputBinding::Key(Key::Escape)]);
+        assert!(input_map.bindings(InputName::Debug).is_empty());
+    }
+
+    #[test]
+    fn resetting_bindings_keeps_settings() {
+        let mut input_map = InputMap::default();
+        input_map.mouse_mut().sensitivity = 0.01;
+        input_map.mouse_mut().invert_y = true;
+        input_map.add_binding(InputName::Debug, InputBinding::Key(Key::F5)).unwrap();
+        input_map.start_rebind(InputName::Jump);
+
+        input_map.reset_bindings(&InputMap::colemak());
+
+        assert_eq!(input_map.bindings(InputName::CamBackwards), InputMap::colemak().bindings(InputName::CamBackwards));
+        assert!(!input_map.bindings(InputName::Debug).contains(&InputBinding::Key(Key::F5)));
+        assert_eq!(input_map.pending_rebind(), None);
+        assert_eq!(input_map.mouse().sensitivity, 0.01);
+        assert!(input_map.mouse().invert_y);
+    }
+
+    #[test]
+    fn layouts_have_no_conflicts() {
+        // These panic if a binding is given to two inputs
+        InputMap::default();
+        InputMap::colemak();
+    }
+}
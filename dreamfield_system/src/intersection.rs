@@ -1,21 +1,90 @@
 mod intersection_tests;
+mod convex_hull;
+mod polytope;
 
 use std::collections::HashSet;
 
-use bevy_ecs::{prelude::{Component, Entity}, system::{ResMut, Query}, query::Changed};
-use cgmath::Vector3;
+use bevy_ecs::{prelude::{Component, Entity, RemovedComponents}, system::{ResMut, Query}, query::Changed};
+use cgmath::{Vector3, Matrix3, vec3};
 
 pub use intersection_tests::*;
+pub use convex_hull::ConvexHull;
+pub use polytope::Polytope;
 
-use crate::{world::{world_chunk::ChunkIndex, WorldChunkManager}, components::{EntityName, Transform}};
+use crate::{world::{world_chunk::ChunkIndex, WorldChunkManager, aabb::Aabb}, components::{EntityName, Transform}};
 
-/// An ADT of collision shapes
+/// An ADT of collision shapes. Offsets and points are relative to the entity's position.
 #[derive(Debug, Clone)]
 pub enum Shape {
-    /// A spheroid with an offset and radiuses
+    /// A spheroid with an offset and radiuses. Spheroids stay upright rather than rotating with the
+    /// entity, as they're used for characters.
     BoundingSpheroid(Vector3<f32>, Vector3<f32>),
-    /// A bounding obx
-    BoundingBox
+    /// A box with an offset and half extents, which rotates with the entity
+    BoundingBox(Vector3<f32>, Vector3<f32>),
+    /// A capsule with an offset, the half height of the line between the centers of its ends along
+    /// its y axis, and its radius, which rotates with the entity
+    Capsule(Vector3<f32>, f32, f32),
+    /// A convex hull, which rotates with the entity
+    ConvexHull(ConvexHull),
+}
+
+impl Shape {
+    /// Get the world space aabb of the shape for an entity with a given position and rotation
+    pub fn world_aabb(&self, pos: &Vector3<f32>, rot: &Matrix3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+        match self {
+            Shape::BoundingSpheroid(offset, radius) => {
+                let center = pos + offset;
+                (center - radius, center + radius)
+            },
+            Shape::BoundingBox(offset, half_extents) => {
+                // The aabb reaches as far along each axis as the box's rotated axes do together
+                let center = pos + rot * offset;
+                let extents = (rot.x * half_extents.x).map(f32::abs)
+                    + (rot.y * half_extents.y).map(f32::abs)
+                    + (rot.z * half_extents.z).map(f32::abs);
+                (center - extents, center + extents)
+            },
+            Shape::Capsule(offset, half_height, radius) => {
+                let (a, b) = Self::capsule_ends(pos, rot, offset, *half_height);
+                let radius = vec3(*radius, *radius, *radius);
+                (Aabb::vec_min(&a, &b) - radius, Aabb::vec_max(&a, &b) + radius)
+            },
+            Shape::ConvexHull(hull) => {
+                let mut aabb = Aabb::new();
+                for point in hull.points() {
+                    aabb.expand_with_point(&(pos + rot * point));
+                }
+                aabb.min_max().map(|(min, max)| (*min, *max)).unwrap_or((*pos, *pos))
+            }
+        }
+    }
+
+    /// Get the center and half extents of the shape's bounds before it's rotated
+    pub fn local_bounds(&self) -> (Vector3<f32>, Vector3<f32>) {
+        match self {
+            Shape::BoundingSpheroid(offset, radius) => (*offset, *radius),
+            Shape::BoundingBox(offset, half_extents) => (*offset, *half_extents),
+            Shape::Capsule(offset, half_height, radius) => (*offset, vec3(*radius, half_height + radius, *radius)),
+            Shape::ConvexHull(hull) => {
+                let mut aabb = Aabb::new();
+                for point in hull.points() {
+                    aabb.expand_with_point(point);
+                }
+                aabb.min_max()
+                    .map(|(min, max)| (0.5 * min + 0.5 * max, 0.5 * max - 0.5 * min))
+                    .unwrap_or((vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0)))
+            }
+        }
+    }
+
+    /// Get the world space centers of the ends of a capsule
+    pub fn capsule_ends(pos: &Vector3<f32>, rot: &Matrix3<f32>, offset: &Vector3<f32>, half_height: f32)
+        -> (Vector3<f32>, Vector3<f32>)
+    {
+        let center = pos + rot * offset;
+        let axis = rot * vec3(0.0, half_height, 0.0);
+        (center - axis, center + axis)
+    }
 }
 
 /// A component for representing an entity's collider
@@ -45,3 +114,11 @@ pub fn update_world_chunks_system(mut world: ResMut<WorldChunkManager>,
         world.update_entity_location(e, transform, &mut collider, name);
     }
 }
+
+/// A system for removing entities from the world chunks when their collider is removed or they're
+/// despawned, so that nothing collides with them any more
+pub fn remove_colliders_system(mut world: ResMut<WorldChunkManager>, removed: RemovedComponents<Collider>) {
+    for e in removed.iter() {
+        world.remove_entity(e);
+    }
+}
@@ -0,0 +1,393 @@
+use std::collections::{HashMap, HashSet};
+use cgmath::{Vector2, Vector3, InnerSpace, vec2, vec3};
+
+/// How far a point can be in front of a face of a hull and still count as being on it, relative to
+/// the size of the hull, so that coplanar points don't stop a face from being found
+const HULL_PLANE_EPSILON: f32 = 1e-5;
+
+/// A convex hull, stored as the points it was built from along with its faces, so that it can be
+/// swept against like the world's triangles
+#[derive(Debug, Clone)]
+pub struct ConvexHull {
+    points: Vec<Vector3<f32>>,
+    /// The indices of each face's corners, wound counter-clockwise looking at the face from outside
+    /// the hull
+    faces: Vec<Vec<usize>>,
+}
+
+impl ConvexHull {
+    /// Build the hull around some points with quickhull, merging coplanar triangles into a single
+    /// face. Points inside the hull, or on its faces or edges, aren't corners of any face. Points
+    /// that are all in a plane give a flat hull with a face on each side, and fewer than three
+    /// points or points all on a line give a hull with no faces.
+    pub fn new(points: Vec<Vector3<f32>>) -> Self {
+        let size = points
+            .iter()
+            .map(|p| p.magnitude())
+            .fold(0.0, f32::max);
+        let epsilon = HULL_PLANE_EPSILON * f32::max(size, 1.0);
+
+        let faces = quickhull(&points, epsilon);
+
+        Self {
+            points,
+            faces,
+        }
+    }
+
+    /// Get the points the hull was built from
+    pub fn points(&self) -> &[Vector3<f32>] {
+        &self.points
+    }
+
+    /// Get the faces of the hull, as indices into its points
+    pub fn faces(&self) -> &[Vec<usize>] {
+        &self.faces
+    }
+
+    /// Get the triangles of the hull's faces, as fans around the first corner of each face
+    pub fn triangles(&self) -> impl Iterator<Item=[usize; 3]> + '_ {
+        self.faces
+            .iter()
+            .flat_map(|face| (1..face.len() - 1).map(move |i| [face[0], face[i], face[i + 1]]))
+    }
+}
+
+/// A triangle of a hull while it's being built, along with the points in front of it that the
+/// hull still needs to be expanded to
+struct HullTriangle {
+    corners: [usize; 3],
+    normal: Vector3<f32>,
+    /// The distance of the triangle's plane from the origin along its normal
+    distance: f32,
+    outside: Vec<usize>,
+}
+
+impl HullTriangle {
+    fn new(points: &[Vector3<f32>], corners: [usize; 3]) -> Self {
+        let [a, b, c] = corners.map(|i| points[i]);
+        let normal = (b - a).cross(c - a).normalize();
+        Self {
+            corners,
+            normal,
+            distance: normal.dot(a),
+            outside: Vec::new(),
+        }
+    }
+
+    /// Get how far a point is in front of the triangle's plane
+    fn height(&self, point: Vector3<f32>) -> f32 {
+        self.normal.dot(point) - self.distance
+    }
+
+    /// Get the triangle's edges, in winding order
+    fn edges(&self) -> [(usize, usize); 3] {
+        let [a, b, c] = self.corners;
+        [(a, b), (b, c), (c, a)]
+    }
+}
+
+/// Build the faces of the hull around some points
+fn quickhull(points: &[Vector3<f32>], epsilon: f32) -> Vec<Vec<usize>> {
+    if points.len() < 3 {
+        return Vec::new();
+    }
+
+    // Start with the biggest tetrahedron we can easily find, a point on the edge of the hull, the
+    // point furthest from it, the point furthest from the line between them, and the point furthest
+    // from the plane of all three
+    let farthest = |distance: &dyn Fn(Vector3<f32>) -> f32| {
+        (0..points.len())
+            .map(|i| (i, distance(points[i])))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .unwrap()
+    };
+
+    let (first, _) = farthest(&|p| -p.x);
+    let origin = points[first];
+
+    let (second, length) = farthest(&|p| (p - origin).magnitude());
+    if length <= epsilon {
+        return Vec::new();
+    }
+    let line = (points[second] - origin) / length;
+
+    let (third, line_distance) = farthest(&|p| {
+        let offset = p - origin;
+        (offset - line * offset.dot(line)).magnitude()
+    });
+    if line_distance <= epsilon {
+        return Vec::new();
+    }
+    let plane_normal = line.cross(points[third] - origin).normalize();
+
+    let (fourth, plane_distance) = farthest(&|p| plane_normal.dot(p - origin).abs());
+    if plane_distance <= epsilon {
+        let face = convex_polygon(points, (0..points.len()).collect(), plane_normal, epsilon);
+        let back = face.iter().rev().copied().collect();
+        return vec![face, back];
+    }
+
+    // Wind each face of the tetrahedron away from the corner that isn't on it
+    let simplex = [first, second, third, fourth];
+    let mut triangles: Vec<HullTriangle> = [[0, 1, 2], [0, 1, 3], [0, 2, 3], [1, 2, 3]]
+        .iter()
+        .map(|[i, j, k]| {
+            let other = simplex[6 - i - j - k];
+            let triangle = HullTriangle::new(points, [simplex[*i], simplex[*j], simplex[*k]]);
+            if triangle.height(points[other]) > 0.0 {
+                HullTriangle::new(points, [simplex[*i], simplex[*k], simplex[*j]])
+            }
+            else {
+                triangle
+            }
+        })
+        .collect();
+
+    let remaining = (0..points.len()).filter(|i| !simplex.contains(i));
+    assign_outside_points(points, remaining, &mut triangles, epsilon);
+
+    // Expand the hull to the furthest point in front of each triangle until none are left outside,
+    // replacing the triangles that point can see with a cone from their outline to it
+    while let Some(triangle) = triangles.iter().find(|t| !t.outside.is_empty()) {
+        let apex = *triangle.outside
+            .iter()
+            .max_by(|a, b| triangle.height(points[**a]).total_cmp(&triangle.height(points[**b])))
+            .unwrap();
+
+        let (visible, kept): (Vec<HullTriangle>, Vec<HullTriangle>) = triangles
+            .into_iter()
+            .partition(|t| t.height(points[apex]) > epsilon);
+
+        let visible_edges: HashSet<(usize, usize)> = visible.iter().flat_map(|t| t.edges()).collect();
+        let mut cone: Vec<HullTriangle> = visible_edges
+            .iter()
+            .filter(|(a, b)| !visible_edges.contains(&(*b, *a)))
+            .map(|(a, b)| HullTriangle::new(points, [*a, *b, apex]))
+            .collect();
+
+        let orphans = visible
+            .into_iter()
+            .flat_map(|t| t.outside)
+            .filter(|i| *i != apex);
+        assign_outside_points(points, orphans, &mut cone, epsilon);
+
+        triangles = kept;
+        triangles.extend(cone);
+    }
+
+    merge_coplanar_triangles(points, &triangles, epsilon)
+}
+
+/// Give each point to the triangle it's furthest in front of, dropping those that aren't in front
+/// of any of them
+fn assign_outside_points(points: &[Vector3<f32>], indices: impl Iterator<Item=usize>,
+    triangles: &mut [HullTriangle], epsilon: f32)
+{
+    for i in indices {
+        let furthest = triangles
+            .iter_mut()
+            .map(|t| (t.height(points[i]), t))
+            .max_by(|(a, _), (b, _)| a.total_cmp(b));
+
+        if let Some((height, triangle)) = furthest {
+            if height > epsilon {
+                triangle.outside.push(i);
+            }
+        }
+    }
+}
+
+/// Merge neighbouring triangles of a finished hull that are in the same plane into single faces
+fn merge_coplanar_triangles(points: &[Vector3<f32>], triangles: &[HullTriangle], epsilon: f32) -> Vec<Vec<usize>> {
+    let edge_triangles: HashMap<(usize, usize), usize> = triangles
+        .iter()
+        .enumerate()
+        .flat_map(|(i, t)| t.edges().map(|edge| (edge, i)))
+        .collect();
+
+    // Flood fill each group of triangles that are coplanar with their neighbours
+    let mut group = vec![usize::MAX; triangles.len()];
+    let mut faces = Vec::new();
+    for seed in 0..triangles.len() {
+        if group[seed] != usize::MAX {
+            continue;
+        }
+
+        group[seed] = seed;
+        let mut stack = vec![seed];
+        let mut corners = Vec::new();
+        let mut normal = vec3(0.0, 0.0, 0.0);
+        while let Some(i) = stack.pop() {
+            let triangle = &triangles[i];
+            corners.extend(triangle.corners);
+            normal += triangle.normal;
+
+            for (a, b) in triangle.edges() {
+                if let Some(&neighbour) = edge_triangles.get(&(b, a)) {
+                    let coplanar = triangles[neighbour].corners
+                        .iter()
+                        .all(|corner| triangle.height(points[*corner]).abs() <= epsilon);
+                    if group[neighbour] == usize::MAX && coplanar {
+                        group[neighbour] = seed;
+                        stack.push(neighbour);
+                    }
+                }
+            }
+        }
+
+        corners.sort_unstable();
+        corners.dedup();
+        let face = convex_polygon(points, corners, normal.normalize(), epsilon);
+        if face.len() >= 3 {
+            faces.push(face);
+        }
+    }
+
+    faces
+}
+
+/// Get the corners of the convex polygon around some points in a plane, wound counter-clockwise
+/// looking at the plane from the side its normal faces. Points on the polygon's edges are left out,
+/// so that fanning it into triangles doesn't make any of them degenerate.
+fn convex_polygon(points: &[Vector3<f32>], indices: Vec<usize>, normal: Vector3<f32>, epsilon: f32) -> Vec<usize> {
+    // Project the points onto a basis for the plane, which is counter-clockwise around the normal
+    let axis = if normal.x.abs() < 0.9 { vec3(1.0, 0.0, 0.0) } else { vec3(0.0, 1.0, 0.0) };
+    let u = (axis - normal * normal.dot(axis)).normalize();
+    let v = normal.cross(u);
+    let project = |i: usize| vec2(u.dot(points[i]), v.dot(points[i]));
+
+    let mut sorted = indices;
+    sorted.sort_by(|a, b| {
+        let (a, b) = (project(*a), project(*b));
+        a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y))
+    });
+
+    // Andrew's monotone chain, keeping only the points where the outline turns left
+    let turns_left = |a: Vector2<f32>, b: Vector2<f32>, c: Vector2<f32>| {
+        let (ab, ac) = (b - a, c - a);
+        ab.x * ac.y - ab.y * ac.x > epsilon * ac.magnitude()
+    };
+
+    let mut polygon: Vec<usize> = Vec::with_capacity(sorted.len() + 1);
+    for pass in [sorted.as_slice(), &sorted.iter().rev().copied().collect::<Vec<_>>()] {
+        let start = polygon.len();
+        for &i in pass.iter() {
+            while polygon.len() >= start + 2
+                && !turns_left(project(polygon[polygon.len() - 2]), project(polygon[polygon.len() - 1]), project(i))
+            {
+                polygon.pop();
+            }
+            polygon.push(i);
+        }
+
+        // The last point of each half is the first point of the other
+        polygon.pop();
+    }
+
+    polygon
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::XorShiftRng;
+
+    /// The corners of a cube from -1 to 1
+    fn cube_corners() -> Vec<Vector3<f32>> {
+        (0..8)
+            .map(|i| {
+                let sign = |bit: usize| if i & bit != 0 { 1.0 } else { -1.0 };
+                vec3(sign(1), sign(2), sign(4))
+            })
+            .collect()
+    }
+
+    /// Get the normal of a face from its first three corners
+    fn face_normal(hull: &ConvexHull, face: &[usize]) -> Vector3<f32> {
+        let [a, b, c] = [face[0], face[1], face[2]].map(|i| hull.points()[i]);
+        (b - a).cross(c - a).normalize()
+    }
+
+    /// Check that every point is on or behind every face, and that each edge is shared by exactly
+    /// two faces which wind it in opposite directions
+    fn assert_closed_and_convex(hull: &ConvexHull) {
+        let mut edges = HashSet::new();
+        for face in hull.faces() {
+            let normal = face_normal(hull, face);
+            for point in hull.points() {
+                assert!(normal.dot(point - hull.points()[face[0]]) <= 1e-4, "{point:?} is in front of {face:?}");
+            }
+
+            for (i, from) in face.iter().enumerate() {
+                assert!(edges.insert((*from, face[(i + 1) % face.len()])), "Edge used twice in {face:?}");
+            }
+        }
+
+        for (from, to) in edges.iter() {
+            assert!(edges.contains(&(*to, *from)), "Edge {from}, {to} only has one face");
+        }
+    }
+
+    #[test]
+    fn cube_has_six_square_faces() {
+        let mut points = cube_corners();
+        // Points inside the cube, in the middle of a face and along an edge aren't corners
+        points.extend([vec3(0.2, 0.5, -0.3), vec3(0.0, 0.0, 1.0), vec3(1.0, 1.0, 0.0)]);
+        let hull = ConvexHull::new(points);
+
+        assert_eq!(hull.faces().len(), 6);
+        for face in hull.faces() {
+            assert_eq!(face.len(), 4);
+            assert!(face.iter().all(|i| *i < 8));
+            let normal = face_normal(&hull, face);
+            assert!(normal.dot(hull.points()[face[0]]) > 0.99, "{face:?} faces into the cube");
+        }
+        assert_eq!(hull.triangles().count(), 12);
+        assert_closed_and_convex(&hull);
+    }
+
+    #[test]
+    fn tetrahedron() {
+        let hull = ConvexHull::new(vec![vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0),
+            vec3(0.0, 0.0, 1.0), vec3(0.1, 0.1, 0.1)]);
+
+        assert_eq!(hull.faces().len(), 4);
+        assert!(hull.faces().iter().all(|face| face.len() == 3 && !face.contains(&4)));
+        assert_closed_and_convex(&hull);
+    }
+
+    #[test]
+    fn random_points() {
+        let mut rng = XorShiftRng::new(0x5bd1e995);
+        let points = (0..200)
+            .map(|_| vec3(rng.next_f32(), rng.next_f32(), rng.next_f32()) * 4.0 - vec3(2.0, 2.0, 2.0))
+            .collect();
+        let hull = ConvexHull::new(points);
+
+        assert!(hull.faces().len() >= 4);
+        assert_closed_and_convex(&hull);
+    }
+
+    #[test]
+    fn flat_points_have_a_face_on_each_side() {
+        let hull = ConvexHull::new(vec![vec3(0.0, 1.0, 0.0), vec3(2.0, 1.0, 0.0), vec3(2.0, 1.0, 2.0),
+            vec3(0.0, 1.0, 2.0), vec3(1.0, 1.0, 1.0), vec3(1.0, 1.0, 0.0)]);
+
+        assert_eq!(hull.faces().len(), 2);
+        let (front, back) = (&hull.faces()[0], &hull.faces()[1]);
+        assert_eq!(front.len(), 4);
+        assert!(front.iter().all(|i| *i < 4));
+        assert_eq!(back.iter().rev().collect::<Vec<_>>(), front.iter().collect::<Vec<_>>());
+        assert!(face_normal(&hull, front).dot(face_normal(&hull, back)) < -0.99);
+        assert_eq!(hull.triangles().count(), 4);
+        assert_closed_and_convex(&hull);
+    }
+
+    #[test]
+    fn lines_and_points_have_no_faces() {
+        assert!(ConvexHull::new(vec![vec3(0.0, 0.0, 0.0), vec3(1.0, 1.0, 1.0)]).faces().is_empty());
+        assert!(ConvexHull::new(vec![vec3(0.0, 0.0, 0.0), vec3(1.0, 1.0, 1.0), vec3(2.0, 2.0, 2.0)]).faces().is_empty());
+        assert!(ConvexHull::new(vec![vec3(1.0, 0.0, 0.0); 5]).faces().is_empty());
+    }
+}
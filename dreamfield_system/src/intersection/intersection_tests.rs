@@ -1,4 +1,4 @@
-use cgmath::{Vector3, vec3, InnerSpace, ElementWise};
+use cgmath::{Vector3, Matrix3, vec3, InnerSpace, ElementWise};
 
 /// A plane primitive
 #[derive(Copy, Clone)]
@@ -53,7 +53,7 @@ impl Sphere {
 }
 
 /// A triangle primitive
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Triangle {
     pub a: Vector3<f32>,
     pub b: Vector3<f32>,
@@ -96,6 +96,23 @@ impl Triangle {
     }
 }
 
+/// The corners of a box's faces, wound to face outwards, where each corner's bits are whether it's
+/// on the positive side of the x, y and z axes, as in box_corners
+pub const BOX_FACES: [[usize; 3]; 12] = [
+    [0, 4, 6], [0, 6, 2], [1, 3, 7], [1, 7, 5],
+    [0, 1, 5], [0, 5, 4], [2, 6, 7], [2, 7, 3],
+    [0, 2, 3], [0, 3, 1], [4, 5, 7], [4, 7, 6],
+];
+
+/// Get the corners of a box with a given center, rotation and half extents, where each corner's bits
+/// are whether it's on the positive side of the box's x, y and z axes
+pub fn box_corners(center: &Vector3<f32>, rot: &Matrix3<f32>, half_extents: &Vector3<f32>) -> [Vector3<f32>; 8] {
+    [0, 1, 2, 3, 4, 5, 6, 7].map(|i| {
+        let sign = |bit: usize| if i & bit != 0 { 1.0 } else { -1.0 };
+        center + rot * vec3(sign(1) * half_extents.x, sign(2) * half_extents.y, sign(4) * half_extents.z)
+    })
+}
+
 /// Time of intersection between a swept unit sphere and a triangle. This can be used to do swept
 /// ellipsoid tests by first transforming the sphere center, velocity, and triangle vertices from
 /// R3 (world space) to e-space (ellipsoid space, where the ellipsoid is a unit sphere with radius
@@ -219,30 +236,9 @@ pub fn toi_unit_sphere_triangle(center: Vector3<f32>, velocity: Vector3<f32>, tr
 
         // Intersect each edge
         for i in 0..3 {
-            let p1 = vertices[i];
-            let p2 = vertices[(i+1) % 3];
-
-            let edge = p2 - p1;
-            let center_to_vertex = p1 - center;
-
-            let edge_magnitude2 = edge.magnitude2();
-            let edge_dot_velocity = edge.dot(velocity);
-            let edge_dot_center_to_vertex = edge.dot(center_to_vertex);
-
-            let a = edge_magnitude2 * -velocity_magnitude2
-                + edge_dot_velocity * edge_dot_velocity;
-            let b = edge_magnitude2 * (2.0 * velocity.dot(center_to_vertex))
-                - 2.0 * edge_dot_velocity * edge_dot_center_to_vertex;
-            let c = edge_magnitude2 * (1.0 - center_to_vertex.magnitude2())
-                + edge_dot_center_to_vertex * edge_dot_center_to_vertex;
-
-            if let Some(new_t) = lowest_root(a, b, c, t) {
-                // Check if intersection is within line segment
-                let f = (edge.dot(velocity) * new_t - edge.dot(center_to_vertex)) / edge.magnitude2();
-                if f >= 0.0 && f <= 1.0 {
-                    t = new_t;
-                    collision_point = Some(p1 + f * edge);
-                }
+            if let Some((new_t, point)) = toi_unit_sphere_edge(center, velocity, vertices[i], vertices[(i+1) % 3], t) {
+                t = new_t;
+                collision_point = Some(point);
             }
         }
     }
@@ -260,6 +256,268 @@ pub fn toi_unit_sphere_point(center: Vector3<f32>, velocity: Vector3<f32>, point
     lowest_root(a, b, c, 1000.0)
 }
 
+/// Time of intersection between a swept unit sphere and a line segment, up to a maximum time, which
+/// is the same as a ray against a capsule with a radius of 1. The return values are the time of
+/// impact and the point on the segment that was hit.
+pub fn toi_unit_sphere_segment(center: Vector3<f32>, velocity: Vector3<f32>, p1: Vector3<f32>, p2: Vector3<f32>,
+    max: f32) -> Option<(f32, Vector3<f32>)>
+{
+    let mut hit = None;
+    let mut t = max;
+
+    // Intersect each end
+    for p in [p1, p2] {
+        let a = velocity.magnitude2();
+        let b = 2.0 * velocity.dot(center - p);
+        let c = (p - center).magnitude2() - 1.0;
+        if let Some(new_t) = lowest_root(a, b, c, t) {
+            t = new_t;
+            hit = Some((t, p));
+        }
+    }
+
+    // Intersect the line between them
+    toi_unit_sphere_edge(center, velocity, p1, p2, t).or(hit)
+}
+
+/// Time of intersection between a swept unit sphere and an edge, not including its ends, up to a
+/// maximum time. The return values are the time of impact and the point on the edge that was hit.
+fn toi_unit_sphere_edge(center: Vector3<f32>, velocity: Vector3<f32>, p1: Vector3<f32>, p2: Vector3<f32>,
+    max: f32) -> Option<(f32, Vector3<f32>)>
+{
+    let edge = p2 - p1;
+    let center_to_vertex = p1 - center;
+
+    let velocity_magnitude2 = velocity.magnitude2();
+    let edge_magnitude2 = edge.magnitude2();
+    let edge_dot_velocity = edge.dot(velocity);
+    let edge_dot_center_to_vertex = edge.dot(center_to_vertex);
+
+    let a = edge_magnitude2 * -velocity_magnitude2
+        + edge_dot_velocity * edge_dot_velocity;
+    let b = edge_magnitude2 * (2.0 * velocity.dot(center_to_vertex))
+        - 2.0 * edge_dot_velocity * edge_dot_center_to_vertex;
+    let c = edge_magnitude2 * (1.0 - center_to_vertex.magnitude2())
+        + edge_dot_center_to_vertex * edge_dot_center_to_vertex;
+
+    if let Some(t) = lowest_root(a, b, c, max) {
+        // Check if intersection is within line segment
+        let f = (edge.dot(velocity) * t - edge.dot(center_to_vertex)) / edge.magnitude2();
+        if f >= 0.0 && f <= 1.0 {
+            return Some((t, p1 + f * edge));
+        }
+    }
+
+    None
+}
+
+/// Distance along a ray to where it hits a unit sphere, in units of the ray direction's length. A
+/// ray that starts inside the sphere hits it straight away.
+pub fn toi_ray_unit_sphere(origin: Vector3<f32>, direction: Vector3<f32>, center: Vector3<f32>) -> Option<f32> {
+    let offset = origin - center;
+    let c = offset.magnitude2() - 1.0;
+    if c <= 0.0 {
+        return Some(0.0);
+    }
+
+    let a = direction.magnitude2();
+    let b = 2.0 * direction.dot(offset);
+    lowest_root(a, b, c, f32::MAX)
+}
+
+/// Distance along a ray to where it hits a triangle, in units of the ray direction's length, using
+/// the Möller–Trumbore algorithm. Triangles are hit from either side.
+/// https://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm
+pub fn toi_ray_triangle(origin: Vector3<f32>, direction: Vector3<f32>, triangle: &Triangle) -> Option<f32> {
+    let ab = triangle.b - triangle.a;
+    let ac = triangle.c - triangle.a;
+
+    // If the ray is parallel to the triangle it can't hit it
+    let p = direction.cross(ac);
+    let det = ab.dot(p);
+    if f32::abs(det) < 1e-12 {
+        return None;
+    }
+
+    // Find the barycentric coordinates of where the ray hits the triangle's plane
+    let inv_det = 1.0 / det;
+    let s = origin - triangle.a;
+    let u = s.dot(p) * inv_det;
+    if u < 0.0 || u > 1.0 {
+        return None;
+    }
+
+    let q = s.cross(ab);
+    let v = direction.dot(q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = ac.dot(q) * inv_det;
+    if t >= 0.0 {
+        Some(t)
+    }
+    else {
+        None
+    }
+}
+
+/// Find the closest point on a line segment to a point
+pub fn closest_point_on_segment(point: Vector3<f32>, a: Vector3<f32>, b: Vector3<f32>) -> Vector3<f32> {
+    let ab = b - a;
+    let length2 = ab.magnitude2();
+    if length2 == 0.0 {
+        return a;
+    }
+
+    a + ab * f32::clamp((point - a).dot(ab) / length2, 0.0, 1.0)
+}
+
+/// Find the closest point on a triangle to a point, by working out which of the triangle's
+/// vertices, edges or face the point is nearest to from its barycentric coordinates
+/// Real-Time Collision Detection (Ericson), 5.1.5
+pub fn closest_point_on_triangle(point: Vector3<f32>, triangle: &Triangle) -> Vector3<f32> {
+    let (a, b, c) = (triangle.a, triangle.b, triangle.c);
+    let ab = b - a;
+    let ac = c - a;
+
+    // Check the vertex region outside a
+    let ap = point - a;
+    let d1 = ab.dot(ap);
+    let d2 = ac.dot(ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+
+    // Check the vertex region outside b
+    let bp = point - b;
+    let d3 = ab.dot(bp);
+    let d4 = ac.dot(bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+
+    // Check the edge region of ab
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+
+    // Check the vertex region outside c
+    let cp = point - c;
+    let d5 = ab.dot(cp);
+    let d6 = ac.dot(cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+
+    // Check the edge region of ac
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+
+    // Check the edge region of bc
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    // Otherwise the point is over the face
+    let denom = 1.0 / (va + vb + vc);
+    a + ab * (vb * denom) + ac * (vc * denom)
+}
+
+/// Test whether a line segment intersects an aabb, by clipping it against the aabb's slabs
+pub fn segment_intersects_aabb(a: Vector3<f32>, b: Vector3<f32>, aabb_min: &Vector3<f32>, aabb_max: &Vector3<f32>)
+    -> bool
+{
+    let direction = b - a;
+    let (mut t_min, mut t_max) = (0.0, 1.0);
+
+    for i in 0..3 {
+        if direction[i] == 0.0 {
+            // The segment is parallel to this slab, so it has to start inside it
+            if a[i] < aabb_min[i] || a[i] > aabb_max[i] {
+                return false;
+            }
+        }
+        else {
+            let mut t0 = (aabb_min[i] - a[i]) / direction[i];
+            let mut t1 = (aabb_max[i] - a[i]) / direction[i];
+            if t0 > t1 {
+                (t0, t1) = (t1, t0);
+            }
+
+            t_min = f32::max(t_min, t0);
+            t_max = f32::min(t_max, t1);
+            if t_min > t_max {
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+/// Test whether a triangle intersects an aabb, using the separating axis test
+/// https://gdbooks.gitbooks.io/3dcollisions/content/Chapter4/aabb-triangle.html
+pub fn triangle_intersects_aabb(aabb_min: &Vector3<f32>, aabb_max: &Vector3<f32>, triangle: &Triangle) -> bool {
+    // Convert AABB to center-extents form
+    let center = 0.5 * aabb_min + 0.5 * aabb_max;
+    let extents = 0.5 * aabb_max - 0.5 * aabb_min;
+
+    // Translate triangle as conceptually moving aabb to origin
+    let v0 = triangle.a - center;
+    let v1 = triangle.b - center;
+    let v2 = triangle.c - center;
+
+    // Compute the edge vectors of the triangle
+    let f0 = v1 - v0;
+    let f1 = v2 - v1;
+    let f2 = v0 - v2;
+
+    // Compute face normals of the aabb (they're just axis aligned)
+    let u0 = vec3(1.0, 0.0, 0.0);
+    let u1 = vec3(0.0, 1.0, 0.0);
+    let u2 = vec3(0.0, 0.0, 1.0);
+
+    // A helper function for doing SAT tests of a given axis against the triangle
+    let axis_separated = |axis: Vector3<f32>| -> bool {
+        let p0 = v0.dot(axis);
+        let p1 = v1.dot(axis);
+        let p2 = v2.dot(axis);
+
+        let r = extents.x * f32::abs(u0.dot(axis)) +
+                extents.y * f32::abs(u1.dot(axis)) +
+                extents.z * f32::abs(u2.dot(axis));
+
+        f32::max(-f32::max(p0, f32::max(p1, p2)), f32::min(p0, f32::min(p1, p2))) > r
+    };
+
+    // Do the sat tests for:
+    // * The 9 axes separating the edges of the aabb and the triangle's edges
+    // * The three face normals from the AABB
+    // * The axis of the face normal of the triangle
+    let any_separated =
+        axis_separated(u0.cross(f0)) ||
+        axis_separated(u0.cross(f1)) ||
+        axis_separated(u0.cross(f2)) ||
+        axis_separated(u1.cross(f0)) ||
+        axis_separated(u1.cross(f1)) ||
+        axis_separated(u1.cross(f2)) ||
+        axis_separated(u2.cross(f0)) ||
+        axis_separated(u2.cross(f1)) ||
+        axis_separated(u2.cross(f2)) ||
+        axis_separated(u0) ||
+        axis_separated(u1) ||
+        axis_separated(u2) ||
+        axis_separated(f0.cross(f1));
+
+    // If any axis was separated, the triangle did not intersect the aabb
+    !any_separated
+}
+
 // Solve a quadratic equation and find the lowest non-zero root
 fn lowest_root(a: f32, b: f32, c: f32, max: f32) -> Option<f32> {
     let determinant = b * b - 4.0 * a * c;
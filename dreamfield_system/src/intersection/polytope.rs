@@ -0,0 +1,269 @@
+use cgmath::{Vector3, Matrix3, InnerSpace, Zero};
+use super::{Triangle, box_corners};
+
+/// How close to parallel two directions can be and still both be tested as separating axes, as the
+/// sine of the angle between them
+const PARALLEL_EPSILON: f32 = 1e-3;
+
+/// A convex polytope grown by a radius, for separating axis tests between boxes, triangles, hulls
+/// and capsules. A capsule is the line between the centers of its ends grown by its radius, which
+/// the test treats as a little bigger than it is around its ends.
+#[derive(Debug, Clone)]
+pub struct Polytope {
+    points: Vec<Vector3<f32>>,
+    /// The directions of the normals of the polytope's faces, normalized and without any that are
+    /// parallel to each other
+    normals: Vec<Vector3<f32>>,
+    /// The directions of the polytope's edges, normalized and without any that are parallel to each
+    /// other
+    edges: Vec<Vector3<f32>>,
+    radius: f32,
+}
+
+impl Polytope {
+    /// Create an oriented box from its center, rotation and half extents
+    pub fn from_box(center: &Vector3<f32>, rot: &Matrix3<f32>, half_extents: &Vector3<f32>) -> Self {
+        let axes = vec![rot.x.normalize(), rot.y.normalize(), rot.z.normalize()];
+        Self {
+            points: box_corners(center, rot, half_extents).to_vec(),
+            normals: axes.clone(),
+            edges: axes,
+            radius: 0.0,
+        }
+    }
+
+    /// Create a polytope from a triangle, which is flat so has the same normal on either side
+    pub fn from_triangle(triangle: &Triangle) -> Self {
+        Self::from_triangles(std::slice::from_ref(triangle))
+    }
+
+    /// Create a polytope from the triangles of a convex shape's faces
+    pub fn from_triangles(triangles: &[Triangle]) -> Self {
+        let mut polytope = Self {
+            points: Vec::with_capacity(triangles.len() * 3),
+            normals: Vec::new(),
+            edges: Vec::new(),
+            radius: 0.0,
+        };
+
+        for triangle in triangles {
+            for point in [triangle.a, triangle.b, triangle.c] {
+                if !polytope.points.contains(&point) {
+                    polytope.points.push(point);
+                }
+            }
+
+            Self::add_direction(&mut polytope.normals, (triangle.b - triangle.a).cross(triangle.c - triangle.a));
+            for edge in [triangle.b - triangle.a, triangle.c - triangle.b, triangle.a - triangle.c] {
+                Self::add_direction(&mut polytope.edges, edge);
+            }
+        }
+
+        polytope
+    }
+
+    /// Create a capsule from the centers of its ends and its radius
+    pub fn from_capsule(a: Vector3<f32>, b: Vector3<f32>, radius: f32) -> Self {
+        let mut edges = Vec::new();
+        Self::add_direction(&mut edges, b - a);
+        Self {
+            points: vec![a, b],
+            normals: Vec::new(),
+            edges,
+            radius,
+        }
+    }
+
+    /// Get the point on the polytope furthest along a direction
+    pub fn support(&self, direction: Vector3<f32>) -> Vector3<f32> {
+        let furthest = self.points
+            .iter()
+            .max_by(|a, b| a.dot(direction).total_cmp(&b.dot(direction)))
+            .copied()
+            .unwrap_or_else(Vector3::zero);
+
+        match direction.magnitude2() > 0.0 {
+            true => furthest + direction.normalize() * self.radius,
+            false => furthest
+        }
+    }
+
+    /// Find the contact between this polytope and another, as the normal pointing from the other to
+    /// this one and how far they overlap along it, which is negative if they're apart. The normal is
+    /// the separating axis they overlap least along, which is the quickest way to push them apart.
+    pub fn contact(&self, other: &Polytope) -> Option<(Vector3<f32>, f32)> {
+        self.axes(other)
+            .map(|axis| self.overlap(other, axis))
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+    }
+
+    /// Sweep this polytope by a velocity against another that isn't moving, using the separating axis
+    /// test on the times each axis starts and stops separating them. Returns the time of impact from
+    /// 0..1 along the velocity and the normal of the contact, pointing from the other polytope to this
+    /// one. Polytopes that start out overlapping hit straight away with the normal that pushes them
+    /// apart quickest, unless this one is already moving away along it.
+    pub fn sweep(&self, velocity: Vector3<f32>, other: &Polytope) -> Option<(f32, Vector3<f32>)> {
+        let mut enter = (f32::NEG_INFINITY, Vector3::zero());
+        let mut exit = f32::INFINITY;
+        let mut least_overlap: Option<(Vector3<f32>, f32)> = None;
+
+        for axis in self.axes(other) {
+            let (min, max) = self.project(axis);
+            let (other_min, other_max) = other.project(axis);
+
+            let overlap = self.overlap(other, axis);
+            if least_overlap.map_or(true, |(_, least)| overlap.1 < least) {
+                least_overlap = Some(overlap);
+            }
+
+            // Find when the projections start and stop overlapping along the axis
+            let speed = velocity.dot(axis);
+            if speed == 0.0 {
+                if max < other_min || min > other_max {
+                    return None;
+                }
+                continue;
+            }
+
+            let (t0, t1) = ((other_min - max) / speed, (other_max - min) / speed);
+            let (axis_enter, axis_exit) = (f32::min(t0, t1), f32::max(t0, t1));
+            if axis_enter > enter.0 {
+                enter = (axis_enter, if speed > 0.0 { -axis } else { axis });
+            }
+            exit = f32::min(exit, axis_exit);
+
+            if enter.0 > exit || enter.0 > 1.0 || exit < 0.0 {
+                return None;
+            }
+        }
+
+        if enter.0 >= 0.0 {
+            return Some(enter);
+        }
+
+        // They're overlapping already
+        least_overlap
+            .filter(|(normal, _)| velocity.dot(*normal) < 0.0)
+            .map(|(normal, _)| (0.0, normal))
+    }
+
+    /// Get the separating axes to test against another polytope, which are the normals of both of
+    /// their faces, the cross products of each pair of their edges, and the axis between their
+    /// centers in case nothing else separates them, such as for parallel capsules
+    fn axes<'a>(&'a self, other: &'a Polytope) -> impl Iterator<Item=Vector3<f32>> + 'a {
+        let between = other.center() - self.center();
+        self.normals
+            .iter()
+            .chain(other.normals.iter())
+            .copied()
+            .chain(self.edges.iter().flat_map(move |a| other.edges.iter().map(move |b| a.cross(*b))))
+            .chain(std::iter::once(between))
+            .filter(|axis| axis.magnitude2() > PARALLEL_EPSILON * PARALLEL_EPSILON)
+            .map(|axis| axis.normalize())
+    }
+
+    /// Get how far this polytope overlaps another along an axis, along with the axis facing from the
+    /// other to this one
+    fn overlap(&self, other: &Polytope, axis: Vector3<f32>) -> (Vector3<f32>, f32) {
+        let (min, max) = self.project(axis);
+        let (other_min, other_max) = other.project(axis);
+
+        let (forwards, backwards) = (other_max - min, max - other_min);
+        match forwards < backwards {
+            true => (axis, forwards),
+            false => (-axis, backwards)
+        }
+    }
+
+    /// Project the polytope onto a normalized axis
+    fn project(&self, axis: Vector3<f32>) -> (f32, f32) {
+        let (min, max) = self.points
+            .iter()
+            .map(|point| point.dot(axis))
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), d| (f32::min(min, d), f32::max(max, d)));
+        (min - self.radius, max + self.radius)
+    }
+
+    /// Get the average of the polytope's points
+    fn center(&self) -> Vector3<f32> {
+        self.points.iter().fold(Vector3::zero(), |sum, point| sum + point) / f32::max(self.points.len() as f32, 1.0)
+    }
+
+    /// Add a direction to a list of directions, unless it's parallel to one that's already in it
+    fn add_direction(directions: &mut Vec<Vector3<f32>>, direction: Vector3<f32>) {
+        if direction.magnitude2() == 0.0 {
+            return;
+        }
+
+        let direction = direction.normalize();
+        if !directions.iter().any(|d| d.cross(direction).magnitude2() < PARALLEL_EPSILON * PARALLEL_EPSILON) {
+            directions.push(direction);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{vec3, SquareMatrix, MetricSpace, Rad, Matrix3};
+
+    fn unit_box(center: Vector3<f32>) -> Polytope {
+        Polytope::from_box(&center, &Matrix3::identity(), &vec3(0.5, 0.5, 0.5))
+    }
+
+    /// A big triangle of ground at y = 0
+    fn ground() -> Polytope {
+        Polytope::from_triangle(&Triangle::new(vec3(-10.0, 0.0, -10.0), vec3(0.0, 0.0, 10.0), vec3(10.0, 0.0, -10.0)))
+    }
+
+    #[test]
+    fn box_falls_onto_ground() {
+        let (toi, normal) = unit_box(vec3(0.0, 1.5, 0.0)).sweep(vec3(0.0, -2.0, 0.0), &ground()).unwrap();
+        assert!((toi - 0.5).abs() < 0.0001, "toi {toi}");
+        assert!(normal.distance(vec3(0.0, 1.0, 0.0)) < 0.0001, "normal {normal:?}");
+
+        assert!(unit_box(vec3(0.0, 1.5, 0.0)).sweep(vec3(0.0, -0.5, 0.0), &ground()).is_none());
+        assert!(unit_box(vec3(0.0, 1.5, 0.0)).sweep(vec3(2.0, 0.0, 0.0), &ground()).is_none());
+    }
+
+    #[test]
+    fn rotated_box_lands_on_its_corner() {
+        // A box turned 45 degrees about z reaches sqrt(0.5) below its center
+        let rot = Matrix3::from_angle_z(Rad(std::f32::consts::FRAC_PI_4));
+        let polytope = Polytope::from_box(&vec3(0.0, 2.0, 0.0), &rot, &vec3(0.5, 0.5, 0.5));
+        let (toi, _) = polytope.sweep(vec3(0.0, -2.0, 0.0), &ground()).unwrap();
+        assert!((toi - (2.0 - f32::sqrt(0.5)) / 2.0).abs() < 0.0001, "toi {toi}");
+    }
+
+    #[test]
+    fn overlapping_boxes_push_apart() {
+        let (a, b) = (unit_box(vec3(0.0, 0.9, 0.0)), unit_box(vec3(0.0, 0.0, 0.0)));
+        let (normal, depth) = a.contact(&b).unwrap();
+        assert!(normal.distance(vec3(0.0, 1.0, 0.0)) < 0.0001, "normal {normal:?}");
+        assert!((depth - 0.1).abs() < 0.0001, "depth {depth}");
+
+        // Moving into each other hits straight away, but moving apart doesn't
+        let (toi, normal) = a.sweep(vec3(0.0, -1.0, 0.0), &b).unwrap();
+        assert_eq!(toi, 0.0);
+        assert!(normal.distance(vec3(0.0, 1.0, 0.0)) < 0.0001, "normal {normal:?}");
+        assert!(a.sweep(vec3(0.0, 1.0, 0.0), &b).is_none());
+    }
+
+    #[test]
+    fn separated_boxes_have_a_negative_depth() {
+        let (normal, depth) = unit_box(vec3(1.2, 0.0, 0.0)).contact(&unit_box(vec3(0.0, 0.0, 0.0))).unwrap();
+        assert!(normal.distance(vec3(1.0, 0.0, 0.0)) < 0.0001, "normal {normal:?}");
+        assert!((depth + 0.2).abs() < 0.0001, "depth {depth}");
+    }
+
+    #[test]
+    fn capsule_hits_box() {
+        let capsule = Polytope::from_capsule(vec3(-2.0, 0.0, 0.0), vec3(-2.0, 1.0, 0.0), 0.5);
+        let (toi, normal) = capsule.sweep(vec3(2.0, 0.0, 0.0), &unit_box(vec3(0.0, 0.0, 0.0))).unwrap();
+        assert!((toi - 0.5).abs() < 0.0001, "toi {toi}");
+        assert!(normal.distance(vec3(-1.0, 0.0, 0.0)) < 0.0001, "normal {normal:?}");
+
+        let support = capsule.support(vec3(0.0, 1.0, 0.0));
+        assert!(support.distance(vec3(-2.0, 1.5, 0.0)) < 0.0001, "support {support:?}");
+    }
+}
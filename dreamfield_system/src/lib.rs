@@ -1,21 +1,28 @@
 pub mod input;
+pub mod input_map;
+pub mod replay;
 pub mod resources;
 pub mod world;
 pub mod components;
 pub mod systems;
 pub mod intersection;
+pub mod file_header;
+pub mod rng;
 mod fixed_timestep;
 mod glfw_system;
 mod game_host;
+mod headless_host;
 
 pub use fixed_timestep::*;
 pub use glfw_system::*;
 pub use game_host::*;
+pub use headless_host::*;
 
-use bevy_ecs::{schedule::SystemSet, world::World, prelude::Events};
+use bevy_ecs::{schedule::{SystemSet, ParallelSystemDescriptorCoercion}, world::World, prelude::Events};
 use input::InputState;
+use input_map::{InputMap, INPUT_MAP_FILENAME};
 use resources::{SimTime, Diagnostics};
-use systems::entity_spawner::EntitySpawnEvent;
+use systems::entity_spawner::{EntitySpawnEvent, EntityDespawnEvent, EntitySpawnResource};
 use world::world_collision::WorldCollision;
 
 /// Initialise resources etc
@@ -23,18 +30,30 @@ pub fn init(world: &mut World) {
     // Resources
     world.init_resource::<SimTime>();
     world.init_resource::<InputState>();
+    world.insert_resource(InputMap::load_or_default(INPUT_MAP_FILENAME));
     world.init_resource::<WindowSettings>();
     world.init_resource::<Diagnostics>();
     world.init_resource::<WorldCollision>();
+    world.init_resource::<EntitySpawnResource>();
 
     // Events
     world.init_resource::<Events::<EntitySpawnEvent>>();
+    world.init_resource::<Events::<EntityDespawnEvent>>();
 }
 
-/// The system systems
+/// The system systems, ordered so that updates are deterministic
 pub fn systems() -> SystemSet {
     SystemSet::new()
-        .with_system(systems::entity_spawner::entity_spawner_system)
-        .with_system(intersection::update_world_chunks_system)
+        .with_system(systems::entity_spawner::entity_spawner_system.label("entity_spawner_system"))
+        .with_system(intersection::update_world_chunks_system.label("update_world_chunks_system").after("entity_spawner_system"))
+        .with_system(systems::chunk_eviction::chunk_eviction_system.label("chunk_eviction_system").after("update_world_chunks_system"))
+        .with_system(systems::chunk_prefetch::chunk_prefetch_system.after("chunk_eviction_system"))
+}
+
+/// The system cleanup systems, which need to run in a stage after the main update so that they see
+/// entities that were despawned during it
+pub fn cleanup_systems() -> SystemSet {
+    SystemSet::new()
+        .with_system(intersection::remove_colliders_system)
 }
 
@@ -0,0 +1,282 @@
+use std::error::Error;
+use std::path::Path;
+use cgmath::{Vector3, Vector2};
+use speedy::{Readable, Writable};
+use crate::input::{InputState, InputName, InputAxis};
+use crate::file_header::FileHeader;
+
+/// The input recording format version, bump this whenever the format changes
+pub const INPUT_RECORDING_VERSION: u32 = 2;
+
+/// The header at the start of input recordings
+const INPUT_RECORDING_HEADER: FileHeader = FileHeader::new(*b"DFIR", INPUT_RECORDING_VERSION);
+
+/// The input for a single fixed update
+#[derive(Readable, Writable, Copy, Clone, Debug, PartialEq)]
+pub struct InputFrame {
+    /// The held inputs, one bit per InputName
+    inputs: u32,
+    axes: [f32; InputAxis::Last as usize],
+    mouse_diff: [f64; 2],
+    mouse_scroll: f64,
+    cursor_captured: bool,
+}
+
+impl InputFrame {
+    /// Capture the input for an update from the input state
+    pub fn from_input_state(input_state: &InputState) -> Self {
+        Self {
+            inputs: inputs_to_bits(&input_state.inputs),
+            axes: input_state.axes,
+            mouse_diff: [input_state.mouse_diff.0, input_state.mouse_diff.1],
+            mouse_scroll: input_state.mouse_scroll,
+            cursor_captured: input_state.cursor_captured,
+        }
+    }
+
+    /// Apply the input to the input state. last_inputs is left alone, as that's maintained by the
+    /// updates themselves.
+    pub fn apply(&self, input_state: &mut InputState) {
+        input_state.inputs = bits_to_inputs(self.inputs);
+        input_state.axes = self.axes;
+        input_state.mouse_diff = (self.mouse_diff[0], self.mouse_diff[1]);
+        input_state.mouse_scroll = self.mouse_scroll;
+        input_state.cursor_captured = self.cursor_captured;
+    }
+}
+
+/// The player state at the start or end of a recording, so that a replay can check it started and
+/// ended up in the same place
+#[derive(Readable, Writable, Copy, Clone, Debug, PartialEq)]
+pub struct RecordedPlayerState {
+    pub player_pos: [f32; 3],
+    pub player_pitch_yaw: [f32; 2],
+}
+
+impl RecordedPlayerState {
+    pub fn new(player_pos: Vector3<f32>, player_pitch_yaw: Vector2<f32>) -> Self {
+        Self {
+            player_pos: player_pos.into(),
+            player_pitch_yaw: player_pitch_yaw.into(),
+        }
+    }
+
+    /// Get whether another player state is the same as this one within a tolerance
+    pub fn approx_eq(&self, other: &RecordedPlayerState, epsilon: f32) -> bool {
+        let pos_eq = self.player_pos.iter().zip(other.player_pos.iter()).all(|(a, b)| (a - b).abs() <= epsilon);
+        let look_eq = self.player_pitch_yaw.iter().zip(other.player_pitch_yaw.iter()).all(|(a, b)| (a - b).abs() <= epsilon);
+        pos_eq && look_eq
+    }
+}
+
+/// What a recording started from. Replaying input from a different start wouldn't reproduce the
+/// session, so replays refuse to run unless they start from the same place in the same world, with
+/// the same save game.
+#[derive(Readable, Writable, Copy, Clone, Debug, PartialEq)]
+pub struct RecordingStart {
+    pub player: RecordedPlayerState,
+    /// The hash of the world build manifest, or None if the world had no manifest
+    pub world_hash: Option<u64>,
+    /// The hash of the save game the session was loaded from, or None if it started a new game
+    pub save_game_hash: Option<u64>,
+}
+
+impl RecordingStart {
+    /// Check that a replay is starting from the same state as the recording, returning what's
+    /// different if it isn't
+    pub fn check(&self, replay: &RecordingStart, epsilon: f32) -> Result<(), String> {
+        if self.world_hash != replay.world_hash {
+            Err(format!("Recorded in a different world: {:x?}, replaying in {:x?}", self.world_hash, replay.world_hash))
+        }
+        else if self.save_game_hash != replay.save_game_hash {
+            Err(format!("Recorded from a different save game: {:x?}, replaying from {:x?}",
+                self.save_game_hash, replay.save_game_hash))
+        }
+        else if !self.player.approx_eq(&replay.player, epsilon) {
+            Err(format!("Recorded with the player starting at {:?}, replaying from {:?}", self.player, replay.player))
+        }
+        else {
+            Ok(())
+        }
+    }
+}
+
+/// A recording of the input for every fixed update of a session, along with the state it started
+/// from. As the sim runs at a fixed timestep, replaying it should reproduce the session exactly.
+/// Runs of identical frames are stored once with a repeat count, as most frames don't change.
+#[derive(Readable, Writable, Clone, Debug)]
+pub struct InputRecording {
+    header: FileHeader,
+    update_timestep: f64,
+    start: RecordingStart,
+    start_sim_time: f64,
+    start_input: InputFrame,
+    start_last_inputs: u32,
+    frames: Vec<(u32, InputFrame)>,
+    end_state: Option<RecordedPlayerState>,
+}
+
+impl InputRecording {
+    /// Start a new recording from the current input state
+    pub fn new(update_timestep: f64, start: RecordingStart, start_sim_time: f64, input_state: &InputState) -> Self {
+        Self {
+            header: INPUT_RECORDING_HEADER,
+            update_timestep,
+            start,
+            start_sim_time,
+            start_input: InputFrame::from_input_state(input_state),
+            start_last_inputs: inputs_to_bits(&input_state.last_inputs),
+            frames: Vec::new(),
+            end_state: None,
+        }
+    }
+
+    /// Load a recording from a file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        Self::read(&std::fs::read(path)?)
+    }
+
+    /// Read a recording from its data
+    pub fn read(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        // Check the header first, so that old recordings give a useful error
+        INPUT_RECORDING_HEADER.check(data, "input recording")?;
+        Ok(Self::read_from_buffer(data)?)
+    }
+
+    /// Save the recording to a file
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        self.write_to_file(path)?;
+        Ok(())
+    }
+
+    /// Record the input for the next update
+    pub fn push_frame(&mut self, input_state: &InputState) {
+        let frame = InputFrame::from_input_state(input_state);
+        match self.frames.last_mut() {
+            Some((count, last_frame)) if *last_frame == frame => *count += 1,
+            _ => self.frames.push((1, frame)),
+        }
+    }
+
+    /// Set the state the recording ended in
+    pub fn set_end_state(&mut self, end_state: RecordedPlayerState) {
+        self.end_state = Some(end_state);
+    }
+
+    /// Restore the input state the recording started from
+    pub fn apply_start(&self, input_state: &mut InputState) {
+        self.start_input.apply(input_state);
+        input_state.last_inputs = bits_to_inputs(self.start_last_inputs);
+    }
+
+    /// Iterate over the input for every update
+    pub fn frames(&self) -> impl Iterator<Item=&InputFrame> + '_ {
+        self.frames
+            .iter()
+            .flat_map(|(count, frame)| std::iter::repeat(frame).take(*count as usize))
+    }
+
+    /// Get the number of updates in the recording
+    pub fn update_count(&self) -> u32 {
+        self.frames.iter().map(|(count, _)| count).sum()
+    }
+
+    pub fn update_timestep(&self) -> f64 {
+        self.update_timestep
+    }
+
+    pub fn start(&self) -> &RecordingStart {
+        &self.start
+    }
+
+    pub fn start_sim_time(&self) -> f64 {
+        self.start_sim_time
+    }
+
+    pub fn end_state(&self) -> Option<&RecordedPlayerState> {
+        self.end_state.as_ref()
+    }
+}
+
+/// Pack input flags into a bitmask
+fn inputs_to_bits(inputs: &[bool; InputName::Last as usize]) -> u32 {
+    inputs
+        .iter()
+        .enumerate()
+        .filter(|(_, held)| **held)
+        .fold(0, |bits, (i, _)| bits | (1 << i))
+}
+
+/// Unpack input flags from a bitmask
+fn bits_to_inputs(bits: u32) -> [bool; InputName::Last as usize] {
+    let mut inputs = [false; InputName::Last as usize];
+    for (i, held) in inputs.iter_mut().enumerate() {
+        *held = bits & (1 << i) != 0;
+    }
+    inputs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_header::test_util::{temp_path, assert_rejects_bad_headers};
+
+    fn test_start() -> RecordingStart {
+        RecordingStart {
+            player: RecordedPlayerState::new(Vector3::new(1.0, 2.0, 3.0), Vector2::new(0.5, -0.25)),
+            world_hash: Some(0x1234),
+            save_game_hash: None,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut input_state = InputState::new();
+        let mut recording = InputRecording::new(1.0 / 15.0, test_start(), 2.0, &input_state);
+
+        input_state.set_held(InputName::CamForwards, true);
+        recording.push_frame(&input_state);
+        recording.push_frame(&input_state);
+        input_state.set_axis(InputAxis::LookLeft, 0.5);
+        recording.push_frame(&input_state);
+        recording.set_end_state(RecordedPlayerState::new(Vector3::new(4.0, 5.0, 6.0), Vector2::new(0.0, 1.0)));
+
+        let path = temp_path("round_trip.dfir");
+        recording.save(&path).unwrap();
+        let loaded = InputRecording::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+
+        assert_eq!(loaded.update_count(), 3);
+        assert_eq!(loaded.frames().copied().collect::<Vec<_>>(), recording.frames().copied().collect::<Vec<_>>());
+        assert_eq!(loaded.start(), &test_start());
+        assert_eq!(loaded.start_sim_time(), 2.0);
+        assert_eq!(loaded.end_state(), recording.end_state());
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let recording = InputRecording::new(1.0 / 15.0, test_start(), 0.0, &InputState::new());
+        assert_rejects_bad_headers(&recording.write_to_vec().unwrap(), 0, InputRecording::read);
+    }
+
+    #[test]
+    fn start_mismatch() {
+        let start = test_start();
+        assert!(start.check(&start, 0.001).is_ok());
+
+        let mut moved = start;
+        moved.player.player_pos[0] += 0.01;
+        assert!(start.check(&moved, 0.001).is_err());
+        assert!(start.check(&moved, 0.1).is_ok());
+
+        let other_world = RecordingStart { world_hash: Some(0x5678), ..start };
+        assert!(start.check(&other_world, 0.001).unwrap_err().contains("different world"));
+
+        let no_world = RecordingStart { world_hash: None, ..start };
+        assert!(start.check(&no_world, 0.001).is_err());
+
+        let from_save = RecordingStart { save_game_hash: Some(0x9abc), ..start };
+        assert!(start.check(&from_save, 0.001).unwrap_err().contains("different save game"));
+    }
+}
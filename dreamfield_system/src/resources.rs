@@ -2,7 +2,7 @@ use std::time::Duration;
 
 use cgmath::{Vector3, Vector2, vec3, vec2};
 
-pub use crate::input::{InputState, InputName};
+pub use crate::input::{InputState, InputName, InputAxis};
 
 /// The SimTime resource
 pub struct SimTime {
@@ -0,0 +1,19 @@
+/// A tiny xorshift random number generator, for things that need the same random numbers every run,
+/// like tests and benchmarks
+pub struct XorShiftRng(u32);
+
+impl XorShiftRng {
+    /// Create a generator from a seed, which mustn't be zero
+    pub fn new(seed: u32) -> Self {
+        assert!(seed != 0, "An xorshift seed can't be zero");
+        Self(seed)
+    }
+
+    /// Get a random number from 0..1
+    pub fn next_f32(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as f32 / (1 << 24) as f32
+    }
+}
@@ -1 +1,3 @@
 pub mod entity_spawner;
+pub mod chunk_eviction;
+pub mod chunk_prefetch;
@@ -0,0 +1,22 @@
+use bevy_ecs::system::{Query, ResMut};
+
+use crate::world::{WorldChunkManager, ChunkAnchor, world_collision::WorldCollision};
+use crate::components::Transform;
+use super::entity_spawner::EntitySpawnRadius;
+
+/// The chunk eviction system. Evicts the least recently used world chunks when they go over the
+/// memory budget, keeping the chunks near cameras and entities with an EntitySpawnRadius loaded,
+/// and unloads the collision meshes for the evicted chunks.
+pub fn chunk_eviction_system(mut chunks: ResMut<WorldChunkManager>,
+                             mut collision: ResMut<WorldCollision>,
+                             query: Query<(&Transform, &EntitySpawnRadius)>)
+{
+    let anchors: Vec<ChunkAnchor> = query
+        .iter()
+        .map(|(transform, spawn_radius)| ChunkAnchor::new(transform.pos, spawn_radius.radius + spawn_radius.despawn_hysteresis))
+        .collect();
+
+    for chunk_index in chunks.evict_chunks(&anchors) {
+        collision.unload_chunk(chunk_index);
+    }
+}
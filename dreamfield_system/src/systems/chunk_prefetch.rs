@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+
+use bevy_ecs::prelude::Entity;
+use bevy_ecs::system::{Local, Query, ResMut};
+use cgmath::{Vector3, vec3, InnerSpace};
+
+use crate::world::{WorldChunkManager, CHUNK_SIZE, world_chunk::WorldChunk};
+use crate::components::Transform;
+use super::entity_spawner::EntitySpawnRadius;
+
+/// How far ahead of an entity with an EntitySpawnRadius to prefetch chunks, in the direction it's
+/// moving, so that they've been loaded in the background by the time it gets there
+pub const CHUNK_PREFETCH_DISTANCE: f32 = 2.0 * CHUNK_SIZE;
+
+/// The chunk prefetch system. Starts loading the chunks around entities with an EntitySpawnRadius,
+/// and the chunks ahead of them in their direction of travel, in the background. Anything that
+/// needs a chunk before it's ready still waits for it, so this only affects how long that takes.
+pub fn chunk_prefetch_system(mut chunks: ResMut<WorldChunkManager>,
+                             mut last_positions: Local<HashMap<Entity, Vector3<f32>>>,
+                             query: Query<(Entity, &Transform, &EntitySpawnRadius)>)
+{
+    let mut positions = HashMap::new();
+
+    for (entity, transform, spawn_radius) in query.iter() {
+        let pos = transform.pos;
+        positions.insert(entity, pos);
+
+        // Work out the direction of travel from how far the entity moved since the last update
+        let movement = last_positions
+            .get(&entity)
+            .map(|last_pos| pos - last_pos)
+            .unwrap_or(vec3(0.0, 0.0, 0.0));
+
+        let center = if movement.magnitude2() > f32::EPSILON {
+            pos + movement.normalize() * CHUNK_PREFETCH_DISTANCE
+        }
+        else {
+            pos
+        };
+
+        // Prefetch every chunk between the entity and the point ahead of it, within the spawn radius
+        let radius = spawn_radius.radius;
+        let min = vec3(f32::min(pos.x, center.x), f32::min(pos.y, center.y), f32::min(pos.z, center.z))
+            - vec3(radius, radius, radius);
+        let max = vec3(f32::max(pos.x, center.x), f32::max(pos.y, center.y), f32::max(pos.z, center.z))
+            + vec3(radius, radius, radius);
+
+        for chunk_index in WorldChunk::chunks_between(&min, &max, chunks.chunk_height()) {
+            chunks.prefetch_chunk(chunk_index);
+        }
+    }
+
+    *last_positions = positions;
+}
@@ -1,67 +1,222 @@
-use std::collections::HashSet;
+use std::collections::{HashSet, HashMap};
 
-use bevy_ecs::{prelude::{Component, EventWriter}, system::{Local, Query, ResMut}};
-use cgmath::vec3;
+use bevy_ecs::{prelude::{Component, Entity, EventWriter}, system::{Query, ResMut}};
+use cgmath::{vec3, Vector3, MetricSpace};
 
 use crate::world::{world_chunk::{WorldChunkEntity, EntityId, WorldChunk}, WorldChunkManager};
 use crate::components::Transform;
 
+/// The default distance beyond an EntitySpawnRadius that entities get despawned at, so that they
+/// don't get spawned and despawned repeatedly at the edge of the radius
+pub const DEFAULT_DESPAWN_HYSTERESIS: f32 = 5.0;
+
 /// An event sent by the system that instructs the game to spawn an entity
 pub struct EntitySpawnEvent {
     pub entity_info: WorldChunkEntity
 }
 
+/// An event sent by the system that instructs the game to despawn an entity that's no longer near
+/// any EntitySpawnRadius. The game can keep the entity's state so that it can be restored when the
+/// entity gets spawned again.
+pub struct EntityDespawnEvent {
+    pub entity: Entity,
+    pub entity_id: EntityId,
+}
+
 /// A component that can be used to tag the player, so that entities in nearby world chunks get
-/// spawned automatically when they come nearby
+/// spawned automatically when they come nearby, and despawned when they're far away
 #[derive(Component)]
 pub struct EntitySpawnRadius {
-    pub radius: f32
+    pub radius: f32,
+    pub despawn_hysteresis: f32,
 }
 
 impl EntitySpawnRadius {
     pub fn new(radius: f32) -> Self {
-        Self { radius }
+        Self {
+            radius,
+            despawn_hysteresis: DEFAULT_DESPAWN_HYSTERESIS,
+        }
+    }
+
+    /// Get whether a point is close enough for entities there to be spawned
+    fn in_spawn_range(&self, center: &Vector3<f32>, point: &Vector3<f32>) -> bool {
+        center.distance2(*point) <= self.radius * self.radius
+    }
+
+    /// Get whether a point is close enough for entities there to stay spawned
+    fn in_despawn_range(&self, center: &Vector3<f32>, point: &Vector3<f32>) -> bool {
+        let despawn_radius = self.radius + self.despawn_hysteresis;
+        center.distance2(*point) <= despawn_radius * despawn_radius
+    }
+}
+
+/// A component for entities that were spawned from a world chunk entity, so that the game can find
+/// them again, e.g. when saving
+#[derive(Component)]
+pub struct SpawnedWorldEntity {
+    pub entity_id: EntityId
+}
+
+impl SpawnedWorldEntity {
+    pub fn new(entity_id: EntityId) -> Self {
+        Self { entity_id }
     }
 }
 
-/// A local resource for the entity spawner system, so it can keep track of spawned entities
+/// A world chunk entity that was despawned, and where it was at the time
+struct StreamedOutEntity {
+    pos: Vector3<f32>,
+    entity_info: WorldChunkEntity,
+}
+
+/// The entity spawn resource, which keeps track of which world chunk entities have been spawned so
+/// that they only get spawned once, and of despawned entities so they can be spawned again where
+/// they were left
 #[derive(Default)]
 pub struct EntitySpawnResource {
-    spawned_entities: HashSet<EntityId>
+    spawned_entities: HashSet<EntityId>,
+    spawned_entity_info: HashMap<EntityId, WorldChunkEntity>,
+    streamed_out_entities: HashMap<EntityId, StreamedOutEntity>,
+}
+
+impl EntitySpawnResource {
+    /// Get whether a world chunk entity has been spawned
+    pub fn is_spawned(&self, entity_id: EntityId) -> bool {
+        self.spawned_entities.contains(&entity_id)
+    }
+
+    /// Mark a world chunk entity as spawned, so that it doesn't get spawned from the chunk data,
+    /// e.g. when restoring a save game
+    pub fn mark_spawned(&mut self, entity_id: EntityId) {
+        self.spawned_entities.insert(entity_id);
+    }
+
+    /// Get the world chunk entities that have been spawned
+    pub fn spawned_entities(&self) -> impl Iterator<Item=EntityId> + '_ {
+        self.spawned_entities.iter().copied()
+    }
+
+    /// Get the world chunk entities that were despawned and are waiting to be spawned again
+    pub fn streamed_out_entities(&self) -> impl Iterator<Item=EntityId> + '_ {
+        self.streamed_out_entities.keys().copied()
+    }
+
+    /// Get the chunk data of a world chunk entity that's spawned or waiting to be spawned again
+    pub fn entity_info(&self, entity_id: EntityId) -> Option<&WorldChunkEntity> {
+        self.spawned_entity_info
+            .get(&entity_id)
+            .or_else(|| self.streamed_out_entities.get(&entity_id).map(|streamed_out| &streamed_out.entity_info))
+    }
+
+    /// Mark a world chunk entity as despawned at a position, so that it gets spawned again when
+    /// something comes near that position rather than near its original chunk, e.g. when restoring
+    /// a save game
+    pub fn mark_streamed_out(&mut self, entity_info: WorldChunkEntity, pos: Vector3<f32>) {
+        let entity_id = entity_info.entity_id();
+        self.spawned_entities.remove(&entity_id);
+        self.spawned_entity_info.remove(&entity_id);
+        self.streamed_out_entities.insert(entity_id, StreamedOutEntity { pos, entity_info });
+    }
+
+    /// Forget all the spawned entities, so they'll be spawned again
+    pub fn clear(&mut self) {
+        self.spawned_entities.clear();
+        self.spawned_entity_info.clear();
+        self.streamed_out_entities.clear();
+    }
+
+    /// Spawn a world chunk entity
+    fn spawn(&mut self, entity_info: &WorldChunkEntity, writer: &mut EventWriter<EntitySpawnEvent>) {
+        log::info!("Spawning entity {} ({})", entity_info.object_id(), entity_info.entity_id());
+        writer.send(EntitySpawnEvent {
+            entity_info: entity_info.clone()
+        });
+        self.spawned_entities.insert(entity_info.entity_id());
+        self.spawned_entity_info.insert(entity_info.entity_id(), entity_info.clone());
+    }
+
+    /// Despawn a world chunk entity, remembering where it was left
+    fn despawn(&mut self, entity: Entity, entity_id: EntityId, pos: Vector3<f32>,
+        writer: &mut EventWriter<EntityDespawnEvent>)
+    {
+        log::info!("Despawning entity {}", entity_id);
+        writer.send(EntityDespawnEvent {
+            entity,
+            entity_id,
+        });
+        self.spawned_entities.remove(&entity_id);
+
+        if let Some(entity_info) = self.spawned_entity_info.remove(&entity_id) {
+            self.streamed_out_entities.insert(entity_id, StreamedOutEntity { pos, entity_info });
+        }
+    }
 }
 
 /// The entity spawner system. Watches for entities with an EntitySpawnRadius and a Transform (e.g.
-/// the player or camera entity), and spawns entities in world chunks when it comes nearby
-pub fn entity_spawner_system(mut local: Local<EntitySpawnResource>,
+/// the player or camera entity), and spawns entities in world chunks when it comes nearby. Spawned
+/// entities get despawned again when they're no longer near any EntitySpawnRadius.
+pub fn entity_spawner_system(mut spawned: ResMut<EntitySpawnResource>,
                              query: Query<(&Transform, &EntitySpawnRadius)>,
+                             spawned_query: Query<(Entity, &SpawnedWorldEntity, &Transform)>,
                              mut chunks: ResMut<WorldChunkManager>,
-                             mut writer: EventWriter<EntitySpawnEvent>)
+                             mut spawn_writer: EventWriter<EntitySpawnEvent>,
+                             mut despawn_writer: EventWriter<EntityDespawnEvent>)
 {
-    for (transform, radius) in query.iter() {
-        let radius = radius.radius;
+    for (transform, spawn_radius) in query.iter() {
+        // Spawn entities that were despawned nearby, where they were left
+        let respawn_ids: Vec<EntityId> = spawned.streamed_out_entities
+            .iter()
+            .filter(|(_, streamed_out)| spawn_radius.in_spawn_range(&transform.pos, &streamed_out.pos))
+            .map(|(entity_id, _)| *entity_id)
+            .collect();
+
+        for entity_id in respawn_ids {
+            if let Some(streamed_out) = spawned.streamed_out_entities.remove(&entity_id) {
+                spawned.spawn(&streamed_out.entity_info, &mut spawn_writer);
+            }
+        }
+
+        // Spawn entities in nearby world chunks
+        let radius = spawn_radius.radius;
         let min = transform.pos - vec3(radius, radius, radius);
         let max = transform.pos + vec3(radius, radius, radius);
 
-        let (min_chunk_x, min_chunk_y) = WorldChunk::point_to_chunk_index(&min);
-        let (max_chunk_x, max_chunk_y) = WorldChunk::point_to_chunk_index(&max);
-
-        for x in min_chunk_x..=max_chunk_x {
-            for y in min_chunk_y..=max_chunk_y {
-                if let Some(chunk) = chunks.get_or_load_chunk((x, y)) {
-                    for entity in chunk.entities().iter() {
-                        let entity_id = entity.entity_id();
-                        if local.spawned_entities.contains(&entity_id) {
-                            continue;
-                        }
-
-                        log::info!("Spawning entity {} ({})", entity.object_id(), entity.entity_id());
-                        writer.send(EntitySpawnEvent {
-                            entity_info: entity.clone()
-                        });
-                        local.spawned_entities.insert(entity_id);
+        for chunk_index in WorldChunk::chunks_between(&min, &max, chunks.chunk_height()) {
+            if let Some(chunk) = chunks.get_or_load_chunk(chunk_index) {
+                for entity in chunk.entities().iter() {
+                    let entity_id = entity.entity_id();
+                    if spawned.is_spawned(entity_id) || spawned.streamed_out_entities.contains_key(&entity_id) {
+                        continue;
+                    }
+
+                    let entity_pos = entity.world_transform().w.truncate();
+                    if spawn_radius.in_spawn_range(&transform.pos, &entity_pos) {
+                        spawned.spawn(entity, &mut spawn_writer);
                     }
                 }
             }
         }
     }
+
+    // Despawn entities that are out of range of every EntitySpawnRadius. If there aren't any, we
+    // leave everything alone, as there's nothing to measure against.
+    if query.is_empty() {
+        return;
+    }
+
+    for (entity, world_entity, transform) in spawned_query.iter() {
+        // The game might not have despawned the entity yet if we only just asked it to
+        if !spawned.is_spawned(world_entity.entity_id) {
+            continue;
+        }
+
+        let in_range = query
+            .iter()
+            .any(|(spawner_transform, spawn_radius)| spawn_radius.in_despawn_range(&spawner_transform.pos, &transform.pos));
+
+        if !in_range {
+            spawned.despawn(entity, world_entity.entity_id, transform.pos, &mut despawn_writer);
+        }
+    }
 }
@@ -1,68 +1,231 @@
 pub mod world_builder;
+pub mod world_build_manifest;
 pub mod world_chunk;
 pub mod world_texture;
 pub mod aabb;
 pub mod wrapped_vectors;
 pub mod world_collision;
+pub mod chunk_loader;
+pub mod chunk_source;
+pub mod chunk_file;
+pub mod gltf_accessor;
+pub mod texture_pipeline;
+pub mod texture_atlas;
+pub mod collision_bvh;
 
 use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 use bevy_ecs::prelude::Entity;
-use cgmath::Vector3;
-use speedy::Readable;
-use include_dir::Dir;
-use world_chunk::{WorldChunk, ChunkIndex};
+use cgmath::{Vector3, Matrix3, InnerSpace};
+use world_chunk::{WorldChunk, ChunkIndex, DEFAULT_CHUNK_HEIGHT};
+use world_build_manifest::{WorldBuildManifest, WORLD_BUILD_MANIFEST_FILENAME, hash_contents};
 use world_texture::{WorldTexture, TextureIndex};
+use world_collision::ChunkCollisionMeshes;
+use chunk_loader::{ChunkLoader, LoadedChunk};
+use chunk_source::ChunkSource;
+use chunk_file::ChunkFileError;
 
 /// The size of a world chunk in each dimension
 pub use world_chunk::CHUNK_SIZE;
 
 use crate::{components::{Transform, EntityName}, intersection::{Collider, Shape}};
 
+/// The default memory budget for loaded world chunks and textures, in bytes
+pub const DEFAULT_CHUNK_MEMORY_BUDGET: usize = 64 * 1024 * 1024;
+
+/// A point that world chunks are kept loaded around, such as a camera or an EntitySpawnRadius
+#[derive(Copy, Clone, Debug)]
+pub struct ChunkAnchor {
+    pub pos: Vector3<f32>,
+    pub radius: f32,
+}
+
+impl ChunkAnchor {
+    pub fn new(pos: Vector3<f32>, radius: f32) -> Self {
+        Self { pos, radius }
+    }
+
+    /// Get the distance from the anchor to the nearest point of a chunk
+    fn distance_to_chunk(&self, chunk_index: ChunkIndex, chunk_height: Option<f32>) -> f32 {
+        let (chunk_min, chunk_max) = WorldChunk::chunk_bounds(chunk_index, chunk_height);
+        let nearest = Vector3::new(
+            self.pos.x.clamp(chunk_min.x, chunk_max.x),
+            self.pos.y.clamp(chunk_min.y, chunk_max.y),
+            self.pos.z.clamp(chunk_min.z, chunk_max.z)
+        );
+        (self.pos - nearest).magnitude()
+    }
+}
+
+/// The state of a chunk that was requested without waiting for it to load
+pub enum ChunkState<'a> {
+    /// The chunk is being loaded in the background
+    Pending,
+    /// The chunk has been loaded, or doesn't exist
+    Loaded(&'a Option<WorldChunk>),
+}
+
+/// A world chunk that was evicted, along with the meshes and textures that went with it, so that
+/// anything cached from them elsewhere (e.g. the renderer's gl meshes) can be freed too
+pub struct EvictedChunk {
+    pub chunk_index: ChunkIndex,
+    pub mesh_indices: Vec<i32>,
+    pub texture_indices: Vec<TextureIndex>,
+}
+
 /// The world chunk manager
 pub struct WorldChunkManager {
-    world_chunks_dir: &'static Dir<'static>,
+    source: Arc<dyn ChunkSource>,
+    chunk_height: Option<f32>,
+    /// How far instance colliders reach outside of their chunks
+    instance_collider_reach: f32,
+    world_hash: Option<u64>,
     loaded_chunks: HashMap<ChunkIndex, Option<WorldChunk>>,
     loaded_textures: HashMap<TextureIndex, Option<WorldTexture>>,
     entity_locations: HashMap<Entity, EntityLocation>,
     chunk_entities: HashMap<ChunkIndex, HashSet<Entity>>,
     empty_entity_hashset: HashSet<Entity>,
+    memory_budget: usize,
+    /// The eviction pass each chunk was last used in
+    chunk_last_used: HashMap<ChunkIndex, u64>,
+    eviction_pass: u64,
+    /// The number of loaded chunks using each texture
+    texture_refs: HashMap<TextureIndex, usize>,
+    camera_anchors: Vec<ChunkAnchor>,
+    evicted_chunks: Vec<EvictedChunk>,
+    loader: ChunkLoader,
+    /// The collision meshes built by the loader threads, waiting for WorldCollision to take them
+    collision_meshes: HashMap<ChunkIndex, ChunkCollisionMeshes>,
 }
 
 struct EntityLocation {
     entity_id: Entity,
     pos: Vector3<f32>,
+    rot: Matrix3<f32>,
     shape: Shape
 }
 
 impl WorldChunkManager {
-    /// Create new WorldChunkManager
-    pub fn new(world_chunks_dir: &'static Dir<'static>) -> Self {
+    /// Create new WorldChunkManager, which loads chunks from the given source
+    pub fn new(source: Box<dyn ChunkSource>) -> Self {
+        log::info!("Loading world chunks from {}", source.describe());
+        let source: Arc<dyn ChunkSource> = Arc::from(source);
+        let (chunk_height, instance_collider_reach, world_hash) = Self::read_manifest(source.as_ref());
         Self {
-            world_chunks_dir,
+            source: source.clone(),
+            chunk_height,
+            instance_collider_reach,
+            world_hash,
             loaded_chunks: HashMap::new(),
             loaded_textures: HashMap::new(),
             entity_locations: HashMap::new(),
             chunk_entities: HashMap::new(),
             empty_entity_hashset: HashSet::new(),
+            memory_budget: DEFAULT_CHUNK_MEMORY_BUDGET,
+            chunk_last_used: HashMap::new(),
+            eviction_pass: 0,
+            texture_refs: HashMap::new(),
+            camera_anchors: Vec::new(),
+            evicted_chunks: Vec::new(),
+            loader: ChunkLoader::new(source, chunk_height),
+            collision_meshes: HashMap::new(),
         }
     }
 
-    /// Get the specified chunk, loading it if necessary
-    pub fn get_or_load_chunk(&mut self, (x, z): ChunkIndex) -> &Option<WorldChunk> {
-        self.loaded_chunks
-            .entry((x, z))
-            .or_insert_with(|| {
-                log::info!("Loading world chunk {}, {}", x, z);
-                let chunk_filename = WorldChunk::filename((x, z));
-                if let Some(file) = self.world_chunks_dir.get_file(&chunk_filename) {
-                    let chunk = WorldChunk::read_from_buffer(file.contents()).expect("Failed to load world chunk");
-                    Some(chunk)
-                }
-                else {
-                    log::info!("No such chunk {}, {}", x, z);
-                    None
-                }
-            })
+    /// Get the specified chunk, loading it if necessary. If the chunk is still being loaded in the
+    /// background, this waits for it.
+    pub fn get_or_load_chunk(&mut self, chunk_index: ChunkIndex) -> &Option<WorldChunk> {
+        self.chunk_last_used.insert(chunk_index, self.eviction_pass);
+        self.receive_loaded_chunks();
+
+        if !self.loaded_chunks.contains_key(&chunk_index) {
+            self.loader.request(chunk_index);
+            for loaded in self.loader.wait_for(chunk_index) {
+                self.insert_loaded_chunk(loaded);
+            }
+        }
+
+        &self.loaded_chunks[&chunk_index]
+    }
+
+    /// Get the specified chunk without waiting for it to load, starting to load it in the
+    /// background if necessary
+    pub fn chunk_state(&mut self, chunk_index: ChunkIndex) -> ChunkState<'_> {
+        self.chunk_last_used.insert(chunk_index, self.eviction_pass);
+        self.receive_loaded_chunks();
+
+        match self.loaded_chunks.get(&chunk_index) {
+            Some(chunk) => ChunkState::Loaded(chunk),
+            None => {
+                self.loader.request(chunk_index);
+                ChunkState::Pending
+            }
+        }
+    }
+
+    /// Start loading a chunk in the background if it isn't loaded, e.g. because something is
+    /// heading towards it
+    pub fn prefetch_chunk(&mut self, chunk_index: ChunkIndex) {
+        if !self.loaded_chunks.contains_key(&chunk_index) {
+            self.loader.request(chunk_index);
+        }
+    }
+
+    /// Mark a loaded chunk as used, so that it isn't evicted while something that keeps its own data
+    /// from it, like collision, is still using it
+    pub fn touch_chunk(&mut self, chunk_index: ChunkIndex) {
+        if self.loaded_chunks.contains_key(&chunk_index) {
+            self.chunk_last_used.insert(chunk_index, self.eviction_pass);
+        }
+    }
+
+    /// Get whether a chunk is being loaded in the background
+    pub fn is_chunk_pending(&self, chunk_index: ChunkIndex) -> bool {
+        self.loader.is_pending(chunk_index)
+    }
+
+    /// Take the collision meshes the loader threads built for a chunk, waiting for the chunk to
+    /// load if necessary. Returns None if the chunk doesn't exist or they've already been taken.
+    pub fn take_collision_meshes(&mut self, chunk_index: ChunkIndex) -> Option<ChunkCollisionMeshes> {
+        self.get_or_load_chunk(chunk_index);
+        self.collision_meshes.remove(&chunk_index)
+    }
+
+    /// Add the chunks that have finished loading in the background
+    fn receive_loaded_chunks(&mut self) {
+        while let Some(loaded) = self.loader.try_receive() {
+            self.insert_loaded_chunk(loaded);
+        }
+    }
+
+    /// Add a chunk that has finished loading
+    fn insert_loaded_chunk(&mut self, loaded: LoadedChunk) {
+        let chunk_index = loaded.chunk_index;
+
+        // Chunks that fail to load are treated as missing, so that a bad chunk doesn't take the game
+        // down with it
+        let chunk = loaded.chunk.unwrap_or_else(|err| {
+            log::error!("Failed to load world chunk {}, {}, {}: {err}", chunk_index.0, chunk_index.1, chunk_index.2);
+            None
+        });
+
+        // The chunk could have been requested twice, e.g. if it was evicted while loading
+        if self.loaded_chunks.contains_key(&chunk_index) {
+            return;
+        }
+
+        if let Some(chunk) = &chunk {
+            for texture_index in Self::chunk_textures(chunk) {
+                *self.texture_refs.entry(texture_index).or_insert(0) += 1;
+            }
+        }
+
+        if let Some(collision_meshes) = loaded.collision_meshes {
+            self.collision_meshes.insert(chunk_index, collision_meshes);
+        }
+
+        self.chunk_last_used.entry(chunk_index).or_insert(self.eviction_pass);
+        self.loaded_chunks.insert(chunk_index, chunk);
     }
 
     /// Get the specified texture, loading it if necessary
@@ -72,53 +235,237 @@ impl WorldChunkManager {
             .or_insert_with(|| {
                 log::info!("Loading world texture {}", idx);
                 let texture_filename = WorldTexture::filename(idx);
-                if let Some(file) = self.world_chunks_dir.get_file(&texture_filename) {
-                    let texture = WorldTexture::read_from_buffer(file.contents()).expect("Failed to load world texture");
-                    Some(texture)
-                }
-                else {
-                    log::info!("No such texture {}", idx);
+                let texture = match self.source.read_file(&texture_filename) {
+                    Ok(Some(file)) => WorldTexture::read_from_file(&file, idx).map(Some),
+                    Ok(None) => {
+                        log::info!("No such texture {}", idx);
+                        Ok(None)
+                    }
+                    Err(err) => Err(ChunkFileError::Read(err))
+                };
+
+                texture.unwrap_or_else(|err| {
+                    log::error!("Failed to load world texture {}: {err}", idx);
                     None
-                }
+                })
             })
     }
 
+    /// Read the chunk height the world was split with from its build manifest, which the world
+    /// builder writes along with the chunks, along with the hash of the manifest
+    fn read_manifest(source: &dyn ChunkSource) -> (Option<f32>, f32, Option<u64>) {
+        let manifest = match source.read_file(WORLD_BUILD_MANIFEST_FILENAME) {
+            Ok(Some(data)) => WorldBuildManifest::read(&data)
+                .map(|manifest| (manifest, hash_contents(&data)))
+                .map_err(|err| err.to_string()),
+            Ok(None) => Err("No world build manifest".to_string()),
+            Err(err) => Err(err.to_string())
+        };
+
+        match manifest {
+            Ok((manifest, hash)) => (manifest.chunk_height, manifest.instance_collider_reach, Some(hash)),
+            Err(err) => {
+                log::error!("Failed to read world build manifest, using the default chunk height: {err}");
+                (DEFAULT_CHUNK_HEIGHT, 0.0, None)
+            }
+        }
+    }
+
+    /// Get the height the world was split into chunks at, or None if it wasn't split vertically
+    pub fn chunk_height(&self) -> Option<f32> {
+        self.chunk_height
+    }
+
+    /// Get how far instance colliders reach outside of the chunks their instances are in
+    pub fn instance_collider_reach(&self) -> f32 {
+        self.instance_collider_reach
+    }
+
+    /// Get the hash of the world's build manifest, which changes whenever the world is built from
+    /// different models or differently, or None if the world has no manifest
+    pub fn world_hash(&self) -> Option<u64> {
+        self.world_hash
+    }
+
+    /// Get the memory budget for loaded chunks and textures, in bytes
+    pub fn memory_budget(&self) -> usize {
+        self.memory_budget
+    }
+
+    /// Set the memory budget for loaded chunks and textures, in bytes
+    pub fn set_memory_budget(&mut self, memory_budget: usize) {
+        self.memory_budget = memory_budget;
+    }
+
+    /// Get an estimate of the memory used by loaded chunks and textures, in bytes
+    pub fn memory_usage(&self) -> usize {
+        let chunks: usize = self.loaded_chunks.values().map(Self::chunk_memory_usage).sum();
+        let textures: usize = self.loaded_textures
+            .values()
+            .map(|texture| texture.as_ref().map(|texture| texture.size_in_bytes()).unwrap_or(0))
+            .sum();
+        let collision_meshes: usize = self.collision_meshes.values().map(Self::collision_memory_usage).sum();
+        chunks + textures + collision_meshes
+    }
+
+    /// Set the anchors for the active cameras, which chunks are kept loaded around along with the
+    /// anchors passed to evict_chunks
+    pub fn set_camera_anchors(&mut self, anchors: Vec<ChunkAnchor>) {
+        self.camera_anchors = anchors;
+    }
+
+    /// Evict chunks until the memory usage is within budget. Chunks are evicted least recently
+    /// used first, and then furthest from an anchor first. Chunks within range of an anchor, and
+    /// chunks used since the last call, are never evicted. Textures are evicted along with the last
+    /// chunk using them. Returns the indices of the evicted chunks.
+    pub fn evict_chunks(&mut self, anchors: &[ChunkAnchor]) -> Vec<ChunkIndex> {
+        let mut memory_usage = self.memory_usage();
+        let mut evicted = Vec::new();
+
+        if memory_usage > self.memory_budget {
+            let anchors: Vec<ChunkAnchor> = anchors.iter().chain(self.camera_anchors.iter()).copied().collect();
+            let anchor_distance = |chunk_index: ChunkIndex| anchors
+                .iter()
+                .map(|anchor| anchor.distance_to_chunk(chunk_index, self.chunk_height) - anchor.radius)
+                .fold(f32::INFINITY, f32::min);
+
+            let mut candidates: Vec<(u64, f32, ChunkIndex)> = self.chunk_last_used
+                .iter()
+                .filter(|(_, last_used)| **last_used < self.eviction_pass)
+                .map(|(chunk_index, last_used)| (*last_used, anchor_distance(*chunk_index), *chunk_index))
+                .filter(|(_, distance, _)| *distance > 0.0)
+                .collect();
+
+            candidates.sort_by(|(a_used, a_dist, a_idx), (b_used, b_dist, b_idx)| {
+                a_used.cmp(b_used)
+                    .then(b_dist.partial_cmp(a_dist).unwrap_or(std::cmp::Ordering::Equal))
+                    .then(a_idx.cmp(b_idx))
+            });
+
+            for (_, _, chunk_index) in candidates {
+                if memory_usage <= self.memory_budget {
+                    break;
+                }
+
+                memory_usage = memory_usage.saturating_sub(self.evict_chunk(chunk_index));
+                evicted.push(chunk_index);
+            }
+
+            if memory_usage > self.memory_budget {
+                log::warn!("World chunks use {} bytes after eviction, over the budget of {} bytes", memory_usage,
+                    self.memory_budget);
+            }
+        }
+
+        self.eviction_pass += 1;
+        evicted
+    }
+
+    /// Take the chunks that have been evicted since this was last called
+    pub fn take_evicted_chunks(&mut self) -> Vec<EvictedChunk> {
+        std::mem::take(&mut self.evicted_chunks)
+    }
+
+    /// Evict a chunk, and any textures no other loaded chunk uses, returning the memory freed
+    fn evict_chunk(&mut self, chunk_index: ChunkIndex) -> usize {
+        self.chunk_last_used.remove(&chunk_index);
+
+        let chunk = match self.loaded_chunks.remove(&chunk_index) {
+            Some(chunk) => chunk,
+            None => return 0
+        };
+
+        let mut memory_freed = Self::chunk_memory_usage(&chunk);
+        if let Some(collision_meshes) = self.collision_meshes.remove(&chunk_index) {
+            memory_freed += Self::collision_memory_usage(&collision_meshes);
+        }
+
+        if let Some(chunk) = chunk {
+            log::info!("Evicting world chunk {}, {}, {}", chunk_index.0, chunk_index.1, chunk_index.2);
+
+            let mut texture_indices = Vec::new();
+            for texture_index in Self::chunk_textures(&chunk) {
+                let refs = self.texture_refs.entry(texture_index).or_insert(1);
+                *refs -= 1;
+                if *refs == 0 {
+                    self.texture_refs.remove(&texture_index);
+                    if let Some(Some(texture)) = self.loaded_textures.remove(&texture_index) {
+                        memory_freed += texture.size_in_bytes();
+                    }
+                    texture_indices.push(texture_index);
+                }
+            }
+
+            self.evicted_chunks.push(EvictedChunk {
+                chunk_index,
+                mesh_indices: chunk.meshes().iter().map(|mesh| mesh.index()).collect(),
+                texture_indices,
+            });
+        }
+
+        memory_freed
+    }
+
+    /// Get the textures used by a chunk's meshes
+    fn chunk_textures(chunk: &WorldChunk) -> HashSet<TextureIndex> {
+        chunk.meshes()
+            .iter()
+            .filter_map(|mesh| mesh.material().as_ref())
+            .flat_map(|material| material.textures())
+            .collect()
+    }
+
+    /// Get an estimate of the memory used by a loaded chunk, including chunks that don't exist as
+    /// they still take up an entry
+    fn chunk_memory_usage(chunk: &Option<WorldChunk>) -> usize {
+        let mesh_data = chunk.as_ref().map(|chunk| {
+            let render_data: usize = chunk.meshes()
+                .iter()
+                .map(|mesh| std::mem::size_of_val(mesh.vertices()) + mesh.indices().size_in_bytes())
+                .sum();
+            let collision_data: usize = chunk.collision_meshes()
+                .iter()
+                .map(|mesh| std::mem::size_of_val(mesh.positions()) + mesh.indices().size_in_bytes())
+                .sum();
+            render_data + collision_data
+        }).unwrap_or(0);
+
+        std::mem::size_of::<(ChunkIndex, Option<WorldChunk>)>() + mesh_data
+    }
+
+    /// Get an estimate of the memory used by a chunk's collision meshes
+    fn collision_memory_usage(collision_meshes: &ChunkCollisionMeshes) -> usize {
+        collision_meshes.memory_usage()
+    }
+
     /// Update a live entity's location in the world, for collision purposes
     pub fn update_entity_location(&mut self, entity_id: Entity, transform: &Transform, collider: &mut Collider,
         entity_name: Option<&EntityName>)
     {
-        // Update entity location, adding it if we don't already have a record of it
-        let entity_location = self.entity_locations
-            .entry(entity_id)
-            .or_insert_with(|| {
-                EntityLocation {
-                    entity_id,
-                    pos: transform.pos,
-                    shape: collider.shape.clone(),
-                }
-            });
-        entity_location.pos = transform.pos;
+        // Update entity location, adding it if we don't already have a record of it. The shape is
+        // refreshed too, as colliders can change shape.
+        self.entity_locations.insert(entity_id, EntityLocation {
+            entity_id,
+            pos: transform.pos,
+            rot: transform.rot,
+            shape: collider.shape.clone(),
+        });
 
         // Get the aabb of the collider
-        let (pos_min, pos_max) = match collider.shape {
-            Shape::BoundingSpheroid(offset, radius) => {
-                let center = transform.pos + offset;
-                (center - radius, center + radius)
-            }
-            _ => panic!("update_world_chunks_system: Unsupported shape {:?}", collider.shape)
-        };
+        let (pos_min, pos_max) = collider.shape.world_aabb(&transform.pos, &transform.rot);
 
         // Get the min and max world chunk this entity can be intersecting
-        let (chunk_min_x, chunk_min_y) = WorldChunk::point_to_chunk_index(&pos_min);
-        let (chunk_max_x, chunk_max_y) = WorldChunk::point_to_chunk_index(&pos_max);
+        let (chunk_min_x, chunk_min_y, chunk_min_z) = WorldChunk::point_to_chunk_index(&pos_min, self.chunk_height);
+        let (chunk_max_x, chunk_max_y, chunk_max_z) = WorldChunk::point_to_chunk_index(&pos_max, self.chunk_height);
         
         // Remove entity from chunks it's no longer in
-        collider.chunks_in.retain(|(x, y)| {
-            let still_in_chunk = *x >= chunk_min_x && *x <= chunk_max_x && *y >= chunk_min_y && *y <= chunk_max_y;
+        collider.chunks_in.retain(|(x, y, z)| {
+            let still_in_chunk = *x >= chunk_min_x && *x <= chunk_max_x && *y >= chunk_min_y && *y <= chunk_max_y
+                && *z >= chunk_min_z && *z <= chunk_max_z;
             if !still_in_chunk {
-                self.remove_entity_from_chunk(entity_id, (*x, *y));
+                self.remove_entity_from_chunk(entity_id, (*x, *y, *z));
                 let entity_name = entity_name.as_ref().map(|n| n.name.as_str()).unwrap_or("no-name");
-                log::info!("Entity {entity_name} ({entity_id:?}) left chunk {x}, {y}");
+                log::info!("Entity {entity_name} ({entity_id:?}) left chunk {x}, {y}, {z}");
             }
             still_in_chunk
         });
@@ -126,16 +473,27 @@ impl WorldChunkManager {
         // Add any chunks that it's moved into
         for x in chunk_min_x..=chunk_max_x {
             for y in chunk_min_y..=chunk_max_y {
-                if !collider.chunks_in.contains(&(x, y)) {
-                    collider.chunks_in.insert((x, y));
-                    self.add_entity_to_chunk(entity_id, (x, y));
-                    let entity_name = entity_name.as_ref().map(|n| n.name.as_str()).unwrap_or("no-name");
-                    log::info!("Entity {entity_name} ({entity_id:?}) entered chunk {x}, {y}");
+                for z in chunk_min_z..=chunk_max_z {
+                    if !collider.chunks_in.contains(&(x, y, z)) {
+                        collider.chunks_in.insert((x, y, z));
+                        self.add_entity_to_chunk(entity_id, (x, y, z));
+                        let entity_name = entity_name.as_ref().map(|n| n.name.as_str()).unwrap_or("no-name");
+                        log::info!("Entity {entity_name} ({entity_id:?}) entered chunk {x}, {y}, {z}");
+                    }
                 }
             }
         }
     }
 
+    /// Remove an entity that no longer has a collider, e.g. because it was despawned
+    pub fn remove_entity(&mut self, entity_id: Entity) {
+        if self.entity_locations.remove(&entity_id).is_some() {
+            for chunk_entities in self.chunk_entities.values_mut() {
+                chunk_entities.remove(&entity_id);
+            }
+        }
+    }
+
     /// Add an entity to a chunk
     fn add_entity_to_chunk(&mut self, entity_id: Entity, chunk: ChunkIndex) {
         let chunk_entities = self.chunk_entities
@@ -24,6 +24,16 @@ impl Aabb {
         self.min_max = Some((WrappedVector3(*min), WrappedVector3(*max)));
     }
 
+    /// Get the aabb of the path of a unit sphere swept from start by velocity
+    pub fn from_unit_sphere_sweep(start: &Vector3<f32>, velocity: &Vector3<f32>) -> Self {
+        let end = start + velocity;
+        let radius = vec3(1.0, 1.0, 1.0);
+
+        let mut aabb = Aabb::new();
+        aabb.set_min_max(&(Self::vec_min(start, &end) - radius), &(Self::vec_max(start, &end) + radius));
+        aabb
+    }
+
     pub fn expand_with_point(&mut self, p: &Vector3<f32>) {
         if let Some((min, max)) = self.min_max() {
             let new_min = Self::vec_min(&min, p);
@@ -0,0 +1,321 @@
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use speedy::{Readable, Writable};
+use super::world_chunk::ChunkIndex;
+use super::world_texture::TextureIndex;
+use super::chunk_source::ChunkSourceError;
+use crate::file_header::{FileHeader, FileHeaderError, FILE_HEADER_LEN};
+
+/// The chunk file format version, bump this whenever the layout of a section changes, and add a
+/// migration to CHUNK_FILE_MIGRATIONS if old files should still load
+pub const CHUNK_FILE_VERSION: u32 = 11;
+
+/// The file header at the start of world chunk and texture files
+const CHUNK_FILE_HEADER: FileHeader = FileHeader::new(*b"DFWC", CHUNK_FILE_VERSION);
+
+/// The size of the file header and header length at the start of the file
+const CHUNK_FILE_PREFIX_LEN: usize = FILE_HEADER_LEN + 4;
+
+/// A migration that upgrades a whole chunk file from the version it's registered for to the next
+/// version
+pub type ChunkFileMigration = fn(data: &[u8], index: ChunkFileIndex) -> Result<Vec<u8>, ChunkFileError>;
+
+/// The migrations for older chunk file versions, by the version they upgrade from. Chunks are rebuilt
+/// along with the game, so old versions only need migrating when there's a world that can't be.
+///
+/// Version history:
+/// 1. The first version with a header
+/// 2. Mesh indices can be u16 or u32
+/// 3. Materials have emissive, alpha mode, double-sidedness, and samplers and transforms for textures
+/// 4. Textures can be stored as rgba5551 or palettized
+/// 5. Meshes are merged by material, and keep the aabbs of the parts they were merged from
+/// 6. Chunk indices have a y component, as the world can be split vertically
+/// 7. Chunks have their own collision meshes, each with a collision layer
+/// 8. Instances have a rotation and scale for each point, and a collider for each mesh
+/// 9. Merged meshes no longer keep the aabbs of their parts, as nothing culled them
+/// 10. Materials say whether they're lit by vertex colors
+/// 11. The header has the chunk height the world was split with
+const CHUNK_FILE_MIGRATIONS: &[(u32, ChunkFileMigration)] = &[];
+
+/// What a chunk file contains, and its index, so that a file that's been renamed or copied to the
+/// wrong place gets caught
+#[derive(Readable, Writable, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChunkFileIndex {
+    Chunk(ChunkIndex),
+    Texture(TextureIndex),
+}
+
+impl fmt::Display for ChunkFileIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChunkFileIndex::Chunk((x, y, z)) => write!(f, "chunk {x}, {y}, {z}"),
+            ChunkFileIndex::Texture(index) => write!(f, "texture {index}"),
+        }
+    }
+}
+
+/// The kinds of section in a chunk file
+#[derive(Readable, Writable, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChunkFileSection {
+    Aabb,
+    Meshes,
+    Instances,
+    Entities,
+    Texture,
+    Collision,
+}
+
+/// The chunk file header, which follows the magic, version and header length, and is followed by
+/// the data for each section
+#[derive(Readable, Writable, Clone, Debug)]
+struct ChunkFileHeader {
+    index: ChunkFileIndex,
+    /// The chunk height the world was split with, as chunks from a world split differently cover
+    /// different space
+    chunk_height: Option<f32>,
+    /// The hash of all the section data, so that corrupt files get caught rather than misparsed
+    content_hash: u64,
+    /// The kind, offset and length of each section, with offsets relative to the end of the header
+    sections: Vec<(ChunkFileSection, u64, u64)>,
+}
+
+/// An error loading a chunk file
+#[derive(Debug)]
+pub enum ChunkFileError {
+    /// The file couldn't be read from its chunk source
+    Read(ChunkSourceError),
+    /// The file doesn't start with the chunk file magic
+    NotAChunkFile,
+    /// The file has no header, as it was written before chunk files had one
+    Headerless,
+    /// The file is a version we can't load, and there's no migration for it
+    UnsupportedVersion(u32),
+    /// The file is for a different chunk or texture than the one requested
+    WrongIndex { expected: ChunkFileIndex, found: ChunkFileIndex },
+    /// The file is from a world split with a different chunk height
+    WrongChunkHeight { expected: Option<f32>, found: Option<f32> },
+    /// The section data doesn't match the hash in the header
+    ContentHashMismatch,
+    /// The file is missing a section it needs
+    MissingSection(ChunkFileSection),
+    /// The file is too short for its header or section table
+    Truncated,
+    /// A section or the header couldn't be decoded
+    Decode(speedy::Error),
+}
+
+impl fmt::Display for ChunkFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChunkFileError::Read(err) => write!(f, "Failed to read file: {err}"),
+            ChunkFileError::NotAChunkFile => write!(f, "Not a valid chunk file"),
+            ChunkFileError::Headerless => write!(f, "Chunk file has no header, the world needs rebuilding"),
+            ChunkFileError::UnsupportedVersion(version) =>
+                write!(f, "Unsupported chunk file version {version}, expected {CHUNK_FILE_VERSION}"),
+            ChunkFileError::WrongIndex { expected, found } =>
+                write!(f, "Chunk file is for {found}, expected {expected}"),
+            ChunkFileError::WrongChunkHeight { expected, found } =>
+                write!(f, "Chunk file is from a world with chunk height {found:?}, expected {expected:?}"),
+            ChunkFileError::ContentHashMismatch => write!(f, "Chunk file is corrupt, the content hash doesn't match"),
+            ChunkFileError::MissingSection(section) => write!(f, "Chunk file has no {section:?} section"),
+            ChunkFileError::Truncated => write!(f, "Chunk file is truncated"),
+            ChunkFileError::Decode(err) => write!(f, "Failed to decode chunk file: {err}"),
+        }
+    }
+}
+
+impl Error for ChunkFileError {}
+
+impl From<speedy::Error> for ChunkFileError {
+    fn from(err: speedy::Error) -> Self {
+        ChunkFileError::Decode(err)
+    }
+}
+
+/// A chunk file that's been read and checked, which sections can be decoded from
+pub struct ChunkFile<'a> {
+    data: Cow<'a, [u8]>,
+    data_offset: usize,
+    header: ChunkFileHeader,
+}
+
+impl<'a> ChunkFile<'a> {
+    /// Read a chunk file, checking its header and index, and migrating it if it's an older version
+    pub fn read(data: &'a [u8], expected_index: ChunkFileIndex) -> Result<Self, ChunkFileError> {
+        let mut data = Cow::Borrowed(data);
+
+        if data.len() < CHUNK_FILE_PREFIX_LEN {
+            return Err(ChunkFileError::Truncated);
+        }
+
+        // Files from before there was a header are just the raw chunk struct, which starts with the
+        // chunk aabb's option tag, so they can't start with the magic
+        let mut version = match CHUNK_FILE_HEADER.check(&data, "chunk file") {
+            Ok(()) => CHUNK_FILE_VERSION,
+            Err(FileHeaderError::UnsupportedVersion { version, .. }) => version,
+            Err(FileHeaderError::WrongFormat(_)) => return Err(match data[0] {
+                0 | 1 => ChunkFileError::Headerless,
+                _ => ChunkFileError::NotAChunkFile
+            }),
+        };
+
+        // Migrate the file up to the current version
+        while version != CHUNK_FILE_VERSION {
+            let migration = CHUNK_FILE_MIGRATIONS
+                .iter()
+                .find(|(from_version, _)| *from_version == version)
+                .map(|(_, migration)| migration)
+                .ok_or(ChunkFileError::UnsupportedVersion(version))?;
+
+            log::info!("Migrating {expected_index} from chunk file version {version}");
+            data = Cow::Owned(migration(&data, expected_index)?);
+            version = FileHeader::read(&data).ok_or(ChunkFileError::Truncated)?.version;
+        }
+
+        // Read the header
+        let header_len = read_u32(&data[FILE_HEADER_LEN..CHUNK_FILE_PREFIX_LEN]) as usize;
+        let data_offset = CHUNK_FILE_PREFIX_LEN + header_len;
+        if data.len() < data_offset {
+            return Err(ChunkFileError::Truncated);
+        }
+
+        let header = ChunkFileHeader::read_from_buffer(&data[CHUNK_FILE_PREFIX_LEN..data_offset])?;
+
+        if header.index != expected_index {
+            return Err(ChunkFileError::WrongIndex { expected: expected_index, found: header.index });
+        }
+
+        if content_hash(&data[data_offset..]) != header.content_hash {
+            return Err(ChunkFileError::ContentHashMismatch);
+        }
+
+        let sections_end = header.sections
+            .iter()
+            .map(|(_, offset, len)| offset.saturating_add(*len))
+            .max()
+            .unwrap_or(0);
+        if sections_end > (data.len() - data_offset) as u64 {
+            return Err(ChunkFileError::Truncated);
+        }
+
+        Ok(Self {
+            data,
+            data_offset,
+            header,
+        })
+    }
+
+    /// Get the chunk height of the world the file is from
+    pub fn chunk_height(&self) -> Option<f32> {
+        self.header.chunk_height
+    }
+
+    /// Get the raw data for a section, if the file has it
+    pub fn section_data(&self, section: ChunkFileSection) -> Option<&[u8]> {
+        self.header.sections
+            .iter()
+            .find(|(kind, _, _)| *kind == section)
+            .map(|(_, offset, len)| {
+                let start = self.data_offset + *offset as usize;
+                &self.data[start..start + *len as usize]
+            })
+    }
+
+    /// Decode a section
+    pub fn section<T: Readable<'static, speedy::LittleEndian>>(&self, section: ChunkFileSection)
+        -> Result<T, ChunkFileError>
+    {
+        let data = self.section_data(section).ok_or(ChunkFileError::MissingSection(section))?;
+        Ok(T::read_from_buffer_copying_data(data)?)
+    }
+
+    /// Write a chunk file from its sections
+    pub fn write(index: ChunkFileIndex, chunk_height: Option<f32>, sections: &[(ChunkFileSection, Vec<u8>)]) -> Vec<u8> {
+        let mut section_table = Vec::with_capacity(sections.len());
+        let mut section_data = Vec::new();
+        for (section, data) in sections {
+            section_table.push((*section, section_data.len() as u64, data.len() as u64));
+            section_data.extend_from_slice(data);
+        }
+
+        let header = ChunkFileHeader {
+            index,
+            chunk_height,
+            content_hash: content_hash(&section_data),
+            sections: section_table,
+        }.write_to_vec().expect("Failed to write chunk file header");
+
+        let mut data = Vec::with_capacity(CHUNK_FILE_PREFIX_LEN + header.len() + section_data.len());
+        data.extend_from_slice(&CHUNK_FILE_HEADER.to_bytes());
+        data.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        data.extend_from_slice(&header);
+        data.extend_from_slice(&section_data);
+        data
+    }
+}
+
+/// Read a little endian u32
+fn read_u32(data: &[u8]) -> u32 {
+    u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+}
+
+/// Hash the section data of a chunk file. This is FNV-1a rather than the std hasher, as it has to
+/// give the same result in every build of the game.
+fn content_hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_header::test_util::assert_rejects_bad_headers;
+
+    const INDEX: ChunkFileIndex = ChunkFileIndex::Chunk((1, 0, -2));
+
+    fn test_file() -> Vec<u8> {
+        ChunkFile::write(INDEX, Some(8.0), &[
+            (ChunkFileSection::Aabb, 5u32.write_to_vec().unwrap()),
+            (ChunkFileSection::Entities, vec![1u16, 2, 3].write_to_vec().unwrap()),
+        ])
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = test_file();
+        let file = ChunkFile::read(&data, INDEX).unwrap();
+        assert_eq!(file.chunk_height(), Some(8.0));
+
+        assert_eq!(file.section::<u32>(ChunkFileSection::Aabb).unwrap(), 5);
+        assert_eq!(file.section::<Vec<u16>>(ChunkFileSection::Entities).unwrap(), vec![1, 2, 3]);
+        assert!(matches!(file.section::<u32>(ChunkFileSection::Meshes),
+            Err(ChunkFileError::MissingSection(ChunkFileSection::Meshes))));
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        assert_rejects_bad_headers(&test_file(), 0, |data| ChunkFile::read(data, INDEX).map(|_| ()));
+
+        // Files from before the header start with the aabb's option tag instead
+        let mut data = test_file();
+        data[0] = 1;
+        assert!(matches!(ChunkFile::read(&data, INDEX), Err(ChunkFileError::Headerless)));
+    }
+
+    #[test]
+    fn wrong_index() {
+        let data = test_file();
+        assert!(matches!(ChunkFile::read(&data, ChunkFileIndex::Texture(0)), Err(ChunkFileError::WrongIndex { .. })));
+    }
+
+    #[test]
+    fn corrupt_or_truncated() {
+        let mut data = test_file();
+        *data.last_mut().unwrap() ^= 0xff;
+        assert!(matches!(ChunkFile::read(&data, INDEX), Err(ChunkFileError::ContentHashMismatch)));
+
+        let data = test_file();
+        assert!(matches!(ChunkFile::read(&data[..CHUNK_FILE_PREFIX_LEN + 2], INDEX), Err(ChunkFileError::Truncated)));
+        assert!(matches!(ChunkFile::read(&data[..6], INDEX), Err(ChunkFileError::Truncated)));
+    }
+}
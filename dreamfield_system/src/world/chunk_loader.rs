@@ -0,0 +1,154 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, mpsc::{self, Sender, Receiver}};
+use std::thread::JoinHandle;
+use super::world_chunk::{WorldChunk, ChunkIndex};
+use super::world_collision::{WorldCollision, ChunkCollisionMeshes};
+use super::chunk_source::ChunkSource;
+use super::chunk_file::ChunkFileError;
+
+/// The most loader threads to start, as loading is mostly limited by memory bandwidth
+const MAX_LOADER_THREADS: usize = 4;
+
+/// A chunk that was loaded by a loader thread. Errors are passed back rather than panicking on the
+/// loader thread, as anything waiting for the chunk would never hear about it otherwise.
+pub struct LoadedChunk {
+    pub chunk_index: ChunkIndex,
+    pub chunk: Result<Option<WorldChunk>, ChunkFileError>,
+    pub collision_meshes: Option<ChunkCollisionMeshes>,
+}
+
+/// A pool of threads that load and decode world chunks in the background, and build their
+/// collision meshes, so that crossing into a new chunk doesn't stall the frame
+pub struct ChunkLoader {
+    // These are only ever used through &mut self, the mutexes are just there to make the loader Sync
+    requests: Option<Mutex<Sender<ChunkIndex>>>,
+    results: Mutex<Receiver<LoadedChunk>>,
+    workers: Vec<JoinHandle<()>>,
+    pending: HashSet<ChunkIndex>,
+}
+
+impl ChunkLoader {
+    /// Create a new chunk loader for a world split with the given chunk height, with a thread per
+    /// available core up to a limit
+    pub fn new(source: Arc<dyn ChunkSource>, chunk_height: Option<f32>) -> Self {
+        let thread_count = std::thread::available_parallelism()
+            .map(|count| count.get().saturating_sub(1))
+            .unwrap_or(1)
+            .clamp(1, MAX_LOADER_THREADS);
+
+        Self::new_with_threads(source, chunk_height, thread_count)
+    }
+
+    /// Create a new chunk loader with a given number of threads
+    pub fn new_with_threads(source: Arc<dyn ChunkSource>, chunk_height: Option<f32>, thread_count: usize) -> Self {
+        let (request_sender, request_receiver) = mpsc::channel::<ChunkIndex>();
+        let (result_sender, result_receiver) = mpsc::channel();
+        let request_receiver = Arc::new(Mutex::new(request_receiver));
+
+        let workers = (0..thread_count)
+            .map(|i| {
+                let request_receiver = request_receiver.clone();
+                let source = source.clone();
+                let result_sender = result_sender.clone();
+                std::thread::Builder::new()
+                    .name(format!("chunk_loader_{i}"))
+                    .spawn(move || {
+                        loop {
+                            // The lock is released as soon as we have a request, so other threads
+                            // can wait for the next one while this one loads
+                            let request = request_receiver.lock().unwrap().recv();
+                            let chunk_index = match request {
+                                Ok(chunk_index) => chunk_index,
+                                Err(_) => break
+                            };
+
+                            if result_sender.send(load_chunk(source.as_ref(), chunk_index, chunk_height)).is_err() {
+                                break;
+                            }
+                        }
+                    })
+                    .expect("Failed to start chunk loader thread")
+            })
+            .collect();
+
+        Self {
+            requests: Some(Mutex::new(request_sender)),
+            results: Mutex::new(result_receiver),
+            workers,
+            pending: HashSet::new(),
+        }
+    }
+
+    /// Request a chunk be loaded in the background, if it isn't already being loaded
+    pub fn request(&mut self, chunk_index: ChunkIndex) {
+        if self.pending.insert(chunk_index) {
+            self.requests
+                .as_mut()
+                .expect("ChunkLoader: Internal error: No request channel")
+                .get_mut()
+                .unwrap()
+                .send(chunk_index)
+                .expect("Chunk loader threads have stopped");
+        }
+    }
+
+    /// Get whether a chunk is being loaded in the background
+    pub fn is_pending(&self, chunk_index: ChunkIndex) -> bool {
+        self.pending.contains(&chunk_index)
+    }
+
+    /// Get a chunk that has finished loading, if there are any
+    pub fn try_receive(&mut self) -> Option<LoadedChunk> {
+        let loaded = self.results.get_mut().unwrap().try_recv().ok()?;
+        self.pending.remove(&loaded.chunk_index);
+        Some(loaded)
+    }
+
+    /// Block until a chunk has finished loading, returning every chunk that finished loading in
+    /// the meantime
+    pub fn wait_for(&mut self, chunk_index: ChunkIndex) -> Vec<LoadedChunk> {
+        let mut loaded_chunks = Vec::new();
+        while self.pending.contains(&chunk_index) {
+            let loaded = self.results.get_mut().unwrap().recv().expect("Chunk loader threads have stopped");
+            self.pending.remove(&loaded.chunk_index);
+            loaded_chunks.push(loaded);
+        }
+        loaded_chunks
+    }
+}
+
+impl Drop for ChunkLoader {
+    fn drop(&mut self) {
+        // Closing the request channel stops the threads once they've finished what they're doing
+        self.requests = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Load and decode a chunk, and build its collision meshes
+fn load_chunk(source: &dyn ChunkSource, (x, y, z): ChunkIndex, chunk_height: Option<f32>) -> LoadedChunk {
+    log::info!("Loading world chunk {}, {}, {}", x, y, z);
+
+    let chunk_filename = WorldChunk::filename((x, y, z));
+    let chunk = match source.read_file(&chunk_filename) {
+        Ok(Some(file)) => WorldChunk::read_from_file(&file, (x, y, z), chunk_height).map(Some),
+        Ok(None) => {
+            log::info!("No such chunk {}, {}, {}", x, y, z);
+            Ok(None)
+        }
+        Err(err) => Err(ChunkFileError::Read(err))
+    };
+
+    let collision_meshes = match &chunk {
+        Ok(Some(chunk)) => Some(WorldCollision::build_chunk_meshes(chunk, (x, y, z))),
+        _ => None
+    };
+
+    LoadedChunk {
+        chunk_index: (x, y, z),
+        chunk,
+        collision_meshes,
+    }
+}
@@ -0,0 +1,283 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use speedy::{Readable, Writable};
+use include_dir::Dir;
+use super::world_build_manifest::WORLD_BUILD_MANIFEST_FILENAME;
+use crate::file_header::FileHeader;
+
+/// The error type for chunk sources, which has to be Send as chunks are loaded on other threads
+pub type ChunkSourceError = Box<dyn Error + Send + Sync>;
+
+/// The chunk pack format version, bump this whenever the format changes
+pub const CHUNK_PACK_VERSION: u32 = 1;
+
+/// The file header at the start of chunk pack headers
+const CHUNK_PACK_HEADER: FileHeader = FileHeader::new(*b"DFCP", CHUNK_PACK_VERSION);
+
+/// Somewhere world chunk and texture files can be read from
+pub trait ChunkSource: Send + Sync {
+    /// Read a file, returning None if there's no such file
+    fn read_file(&self, filename: &str) -> Result<Option<Cow<'_, [u8]>>, ChunkSourceError>;
+
+    /// Get a description of the source for logging
+    fn describe(&self) -> String;
+}
+
+/// Open a chunk source from a path, which can be either a directory of chunk files or a chunk pack
+pub fn open_chunk_source(path: impl AsRef<Path>) -> Result<Box<dyn ChunkSource>, Box<dyn Error>> {
+    let path = path.as_ref();
+    if path.is_dir() {
+        Ok(Box::new(DirectoryChunkSource::new(path)))
+    }
+    else {
+        Ok(Box::new(ChunkPack::open(path)?))
+    }
+}
+
+/// Chunk files embedded in the binary with include_dir!
+pub struct EmbeddedChunkSource {
+    dir: &'static Dir<'static>,
+}
+
+impl EmbeddedChunkSource {
+    pub fn new(dir: &'static Dir<'static>) -> Self {
+        Self { dir }
+    }
+}
+
+impl ChunkSource for EmbeddedChunkSource {
+    fn read_file(&self, filename: &str) -> Result<Option<Cow<'_, [u8]>>, ChunkSourceError> {
+        Ok(self.dir.get_file(filename).map(|file| Cow::Borrowed(file.contents())))
+    }
+
+    fn describe(&self) -> String {
+        "embedded world chunks".to_string()
+    }
+}
+
+/// Chunk files in a directory on disk, so that they can be rebuilt without recompiling the game
+pub struct DirectoryChunkSource {
+    path: PathBuf,
+}
+
+impl DirectoryChunkSource {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl ChunkSource for DirectoryChunkSource {
+    fn read_file(&self, filename: &str) -> Result<Option<Cow<'_, [u8]>>, ChunkSourceError> {
+        match std::fs::read(self.path.join(filename)) {
+            Ok(data) => Ok(Some(Cow::Owned(data))),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into())
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!("world chunk directory {}", self.path.display())
+    }
+}
+
+/// The header of a chunk pack, which is followed by the contents of each file
+#[derive(Readable, Writable)]
+struct ChunkPackHeader {
+    header: FileHeader,
+    /// The offset and length of each file, relative to the end of the header
+    files: Vec<(String, u64, u64)>,
+}
+
+/// A single archive containing all of a world's chunk files, so that a world can be shipped as one
+/// file. Only the header is read up front, the files are read from disk as they're needed.
+pub struct ChunkPack {
+    path: PathBuf,
+    file: Mutex<File>,
+    data_offset: u64,
+    files: HashMap<String, (u64, u64)>,
+}
+
+impl ChunkPack {
+    /// Open a chunk pack
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let mut file = File::open(path)?;
+
+        // The header is prefixed with its length so we know how much to read
+        let mut header_len = [0; 8];
+        file.read_exact(&mut header_len)?;
+        let header_len = u64::from_le_bytes(header_len);
+
+        // Don't trust the length before allocating for it, a corrupt pack could claim anything
+        let file_len = file.metadata()?.len();
+        if header_len > file_len.saturating_sub(8) {
+            return Err(format!("{} is not a chunk pack, its header is longer than the file", path.display()).into());
+        }
+
+        let mut header = vec![0; header_len as usize];
+        file.read_exact(&mut header)?;
+
+        // Check the magic and version first, so that old packs give a useful error
+        CHUNK_PACK_HEADER.check(&header, "chunk pack")?;
+        let header = ChunkPackHeader::read_from_buffer(&header)?;
+
+        // Check every file is inside the pack too, so that reading one can't allocate for or read
+        // past the end of a corrupt or truncated pack
+        let data_len = file_len - 8 - header_len;
+        let past_end = header.files
+            .iter()
+            .find(|(_, offset, len)| offset.checked_add(*len).map_or(true, |end| end > data_len));
+        if let Some((filename, _, _)) = past_end {
+            return Err(format!("{} is corrupt, {filename} runs past the end of the file", path.display()).into());
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+            data_offset: 8 + header_len,
+            files: header.files.into_iter().map(|(name, offset, len)| (name, (offset, len))).collect(),
+        })
+    }
+
+    /// Write all the chunk and texture files in a directory of chunk files to a chunk pack, along with
+    /// the world build manifest, which the game reads the world's chunk height from
+    pub fn write_from_dir(dir: impl AsRef<Path>, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        let mut filenames: Vec<String> = std::fs::read_dir(dir.as_ref())?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_file())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|filename| filename.ends_with(".chunk") || filename.ends_with(".texture")
+                || filename == WORLD_BUILD_MANIFEST_FILENAME)
+            .collect();
+
+        // Sort the files so that packing the same chunks always gives the same pack
+        filenames.sort();
+
+        let mut files = Vec::with_capacity(filenames.len());
+        let mut contents = Vec::with_capacity(filenames.len());
+        let mut offset = 0;
+        for filename in filenames {
+            let data = std::fs::read(dir.as_ref().join(&filename))?;
+            files.push((filename, offset, data.len() as u64));
+            offset += data.len() as u64;
+            contents.push(data);
+        }
+
+        let header = ChunkPackHeader {
+            header: CHUNK_PACK_HEADER,
+            files,
+        }.write_to_vec()?;
+
+        let mut file = std::io::BufWriter::new(File::create(path)?);
+        file.write_all(&(header.len() as u64).to_le_bytes())?;
+        file.write_all(&header)?;
+        for data in contents {
+            file.write_all(&data)?;
+        }
+        file.flush()?;
+
+        Ok(())
+    }
+}
+
+impl ChunkSource for ChunkPack {
+    fn read_file(&self, filename: &str) -> Result<Option<Cow<'_, [u8]>>, ChunkSourceError> {
+        let (offset, len) = match self.files.get(filename) {
+            Some(entry) => *entry,
+            None => return Ok(None)
+        };
+
+        let mut data = vec![0; len as usize];
+        let mut file = self.file.lock().unwrap();
+        file.seek(SeekFrom::Start(self.data_offset + offset))?;
+        file.read_exact(&mut data)?;
+
+        Ok(Some(Cow::Owned(data)))
+    }
+
+    fn describe(&self) -> String {
+        format!("world chunk pack {}", self.path.display())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_header::test_util::{temp_path, assert_rejects_bad_headers};
+
+    /// Get the data for a pack with the given files, which are all filled with zeroes
+    fn pack_data(files: &[(&str, u64, u64)], data_len: usize) -> Vec<u8> {
+        let files = files.iter().map(|(filename, offset, len)| (filename.to_string(), *offset, *len)).collect();
+        let header = ChunkPackHeader { header: CHUNK_PACK_HEADER, files }.write_to_vec().unwrap();
+
+        let mut data = (header.len() as u64).to_le_bytes().to_vec();
+        data.extend_from_slice(&header);
+        data.resize(data.len() + data_len, 0);
+        data
+    }
+
+    /// Write a pack to a file and open it
+    fn open_pack(name: &str, data: &[u8]) -> Result<ChunkPack, Box<dyn Error>> {
+        let path = temp_path(name);
+        std::fs::write(&path, data).unwrap();
+        let result = ChunkPack::open(&path);
+        std::fs::remove_file(&path).ok();
+        result
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = temp_path("pack_dir");
+        let path = temp_path("round_trip.pack");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("world_0_0_0.chunk"), b"chunk").unwrap();
+        std::fs::write(dir.join("world_0.texture"), b"texture data").unwrap();
+        std::fs::write(dir.join(WORLD_BUILD_MANIFEST_FILENAME), b"manifest").unwrap();
+        std::fs::write(dir.join("village.glb.model"), b"not packed").unwrap();
+
+        ChunkPack::write_from_dir(&dir, &path).unwrap();
+        let pack = ChunkPack::open(&path).unwrap();
+        let chunk = pack.read_file("world_0_0_0.chunk").unwrap().map(|data| data.into_owned());
+        let texture = pack.read_file("world_0.texture").unwrap().map(|data| data.into_owned());
+        let manifest = pack.read_file(WORLD_BUILD_MANIFEST_FILENAME).unwrap().map(|data| data.into_owned());
+        let model_cache = pack.read_file("village.glb.model").unwrap().map(|data| data.into_owned());
+        drop(pack);
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(chunk.as_deref(), Some(&b"chunk"[..]));
+        assert_eq!(texture.as_deref(), Some(&b"texture data"[..]));
+        assert_eq!(manifest.as_deref(), Some(&b"manifest"[..]));
+        assert_eq!(model_cache, None);
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let data = pack_data(&[("world_0_0_0.chunk", 0, 4)], 4);
+        assert_rejects_bad_headers(&data, 8, |data| open_pack("bad_header.pack", data));
+    }
+
+    #[test]
+    fn header_longer_than_the_file() {
+        let err = open_pack("header_len.pack", &u64::MAX.to_le_bytes()).err().unwrap();
+        assert!(err.to_string().contains("header is longer than the file"));
+    }
+
+    #[test]
+    fn files_past_the_end() {
+        let truncated = pack_data(&[("world_0_0_0.chunk", 0, 4), ("world_0_0_1.chunk", 4, 1 << 40)], 8);
+        let err = open_pack("truncated.pack", &truncated).err().unwrap();
+        assert!(err.to_string().contains("world_0_0_1.chunk runs past the end of the file"), "{err}");
+
+        let overflowing = pack_data(&[("world_0_0_0.chunk", u64::MAX, 2)], 8);
+        let err = open_pack("overflowing.pack", &overflowing).err().unwrap();
+        assert!(err.to_string().contains("world_0_0_0.chunk runs past the end of the file"), "{err}");
+
+        assert!(open_pack("exact.pack", &pack_data(&[("world_0_0_0.chunk", 4, 4)], 8)).is_ok());
+    }
+}
@@ -0,0 +1,285 @@
+use cgmath::{Vector3, ElementWise};
+use crate::intersection::Triangle;
+use super::aabb::Aabb;
+use super::world_chunk::CollisionLayer;
+
+/// The most triangles a bvh leaf can have before it gets split
+const BVH_MAX_LEAF_TRIANGLES: usize = 4;
+
+/// The deepest a bvh query can go. Nodes are split at the median so the depth is log2 of the
+/// number of leaves, and this is far more than a chunk will ever need.
+const BVH_MAX_DEPTH: usize = 64;
+
+/// A collision triangle, along with the layer of the surface it's part of
+#[derive(Clone, Debug)]
+pub struct CollisionTriangle {
+    pub triangle: Triangle,
+    pub layer: CollisionLayer,
+}
+
+/// A node in a bvh. Leaves have `count` triangles starting at `first`, and other nodes have a
+/// count of 0, with their first child at the next index and their second child at `first`.
+#[derive(Clone, Debug)]
+struct BvhNode {
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+    first: u32,
+    count: u32,
+}
+
+/// A bounding volume hierarchy of a chunk's collision triangles, so that queries only have to test
+/// the triangles near them rather than every triangle in the chunk
+pub struct CollisionBvh {
+    nodes: Vec<BvhNode>,
+    triangles: Vec<CollisionTriangle>,
+}
+
+impl CollisionBvh {
+    /// Build a bvh, splitting each node at the median of its triangles' centers along the axis
+    /// they're most spread out on
+    pub fn build(triangles: Vec<CollisionTriangle>) -> Self {
+        let centers: Vec<Vector3<f32>> = triangles
+            .iter()
+            .map(|t| (t.triangle.a + t.triangle.b + t.triangle.c) / 3.0)
+            .collect();
+
+        let mut order: Vec<usize> = (0..triangles.len()).collect();
+        let mut nodes = Vec::new();
+        if !triangles.is_empty() {
+            Self::build_node(&triangles, &centers, &mut order, 0, &mut nodes);
+        }
+
+        // Store the triangles in the order the leaves use them, so each leaf is a contiguous range
+        let triangles = order.iter().map(|i| triangles[*i].clone()).collect();
+
+        Self {
+            nodes,
+            triangles,
+        }
+    }
+
+    /// Build the node for the triangles in `order`, which start at `first` in the final order,
+    /// returning its index
+    fn build_node(triangles: &[CollisionTriangle], centers: &[Vector3<f32>], order: &mut [usize], first: usize,
+        nodes: &mut Vec<BvhNode>) -> usize
+    {
+        let mut aabb = Aabb::new();
+        let mut center_aabb = Aabb::new();
+        for i in order.iter() {
+            let triangle = &triangles[*i].triangle;
+            aabb.expand_with_point(&triangle.a);
+            aabb.expand_with_point(&triangle.b);
+            aabb.expand_with_point(&triangle.c);
+            center_aabb.expand_with_point(&centers[*i]);
+        }
+        let (min, max) = aabb.min_max().unwrap();
+
+        let index = nodes.len();
+        nodes.push(BvhNode {
+            min: *min,
+            max: *max,
+            first: first as u32,
+            count: order.len() as u32,
+        });
+
+        if order.len() <= BVH_MAX_LEAF_TRIANGLES {
+            return index;
+        }
+
+        // Split at the median along the axis with the widest spread of centers
+        let (center_min, center_max) = center_aabb.min_max().unwrap();
+        let extent = center_max - center_min;
+        let axis = match (extent.x >= extent.y && extent.x >= extent.z, extent.y >= extent.z) {
+            (true, _) => 0,
+            (false, true) => 1,
+            (false, false) => 2
+        };
+
+        let mid = order.len() / 2;
+        order.select_nth_unstable_by(mid, |a, b| centers[*a][axis].total_cmp(&centers[*b][axis]));
+
+        let (left, right) = order.split_at_mut(mid);
+        Self::build_node(triangles, centers, left, first, nodes);
+        let right_index = Self::build_node(triangles, centers, right, first + mid, nodes);
+
+        nodes[index].first = right_index as u32;
+        nodes[index].count = 0;
+        index
+    }
+
+    /// Call a function for each triangle in the leaves that overlap an aabb
+    pub fn query(&self, min: &Vector3<f32>, max: &Vector3<f32>, mut f: impl FnMut(&CollisionTriangle)) {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let mut stack = [0u32; BVH_MAX_DEPTH];
+        let mut stack_len = 1;
+
+        while stack_len > 0 {
+            stack_len -= 1;
+            let index = stack[stack_len] as usize;
+            let node = &self.nodes[index];
+
+            let overlaps = node.min.x <= max.x && node.max.x >= min.x &&
+                           node.min.y <= max.y && node.max.y >= min.y &&
+                           node.min.z <= max.z && node.max.z >= min.z;
+            if !overlaps {
+                continue;
+            }
+
+            if node.count > 0 {
+                let first = node.first as usize;
+                for triangle in self.triangles[first..first + node.count as usize].iter() {
+                    f(triangle);
+                }
+            }
+            else {
+                stack[stack_len] = node.first;
+                stack[stack_len + 1] = index as u32 + 1;
+                stack_len += 2;
+            }
+        }
+    }
+
+    /// Call a function for each triangle in the leaves that overlap an aabb in ellipsoid space,
+    /// given the change of basis matrix for that space. The triangles are still in world space.
+    pub fn query_ellipsoid_space(&self, min: &Vector3<f32>, max: &Vector3<f32>, cbm: Vector3<f32>,
+        f: impl FnMut(&CollisionTriangle))
+    {
+        // It's cheaper to take the query out of ellipsoid space once than to put every node in it
+        self.query(&min.div_element_wise(cbm), &max.div_element_wise(cbm), f)
+    }
+
+    /// Get all of the triangles, in no particular order
+    pub fn triangles(&self) -> &[CollisionTriangle] {
+        &self.triangles
+    }
+
+    /// Get an estimate of the memory used by the bvh
+    pub fn memory_usage(&self) -> usize {
+        std::mem::size_of_val(self.nodes.as_slice()) + std::mem::size_of_val(self.triangles.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::vec3;
+    use crate::intersection;
+    use crate::rng::XorShiftRng;
+
+    /// Get a random vector with each component from 0..scale
+    fn random_vec(rng: &mut XorShiftRng, scale: f32) -> Vector3<f32> {
+        vec3(rng.next_f32(), rng.next_f32(), rng.next_f32()) * scale
+    }
+
+    /// Scatter small triangles of all orientations around a 16m cube
+    fn random_triangles(rng: &mut XorShiftRng, count: usize) -> Vec<CollisionTriangle> {
+        (0..count)
+            .map(|_| {
+                let a = random_vec(rng, 16.0);
+                let triangle = Triangle::new(a, a + random_vec(rng, 2.0), a + random_vec(rng, 2.0));
+                CollisionTriangle { triangle, layer: CollisionLayer::Solid }
+            })
+            .collect()
+    }
+
+    fn triangle_overlaps(triangle: &Triangle, min: &Vector3<f32>, max: &Vector3<f32>) -> bool {
+        let mut aabb = Aabb::new();
+        aabb.expand_with_point(&triangle.a);
+        aabb.expand_with_point(&triangle.b);
+        aabb.expand_with_point(&triangle.c);
+        let (tri_min, tri_max) = aabb.min_max().unwrap();
+        tri_min.x <= max.x && tri_max.x >= min.x &&
+        tri_min.y <= max.y && tri_max.y >= min.y &&
+        tri_min.z <= max.z && tri_max.z >= min.z
+    }
+
+    #[test]
+    fn empty() {
+        let bvh = CollisionBvh::build(Vec::new());
+        bvh.query(&vec3(-100.0, -100.0, -100.0), &vec3(100.0, 100.0, 100.0), |_| panic!("Found a triangle"));
+        assert!(bvh.triangles().is_empty());
+    }
+
+    #[test]
+    fn keeps_every_triangle() {
+        let mut rng = XorShiftRng::new(0x2545f491);
+        let triangles = random_triangles(&mut rng, 500);
+        let bvh = CollisionBvh::build(triangles.clone());
+
+        let mut before: Vec<[f32; 3]> = triangles.iter().map(|t| t.triangle.a.into()).collect();
+        let mut after: Vec<[f32; 3]> = bvh.triangles().iter().map(|t| t.triangle.a.into()).collect();
+        before.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        after.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn query_finds_what_the_linear_scan_finds() {
+        let mut rng = XorShiftRng::new(0x9e3779b9);
+        let bvh = CollisionBvh::build(random_triangles(&mut rng, 1000));
+
+        for _ in 0..200 {
+            let min = random_vec(&mut rng, 18.0) - vec3(1.0, 1.0, 1.0);
+            let max = min + random_vec(&mut rng, 4.0);
+
+            let mut linear: Vec<[f32; 3]> = bvh.triangles()
+                .iter()
+                .filter(|t| triangle_overlaps(&t.triangle, &min, &max))
+                .map(|t| t.triangle.a.into())
+                .collect();
+
+            // The bvh returns whole leaves, so filter it the same way
+            let mut found = Vec::new();
+            bvh.query(&min, &max, |t| {
+                if triangle_overlaps(&t.triangle, &min, &max) {
+                    found.push(t.triangle.a.into());
+                }
+            });
+
+            linear.sort_by(|a: &[f32; 3], b| a.partial_cmp(b).unwrap());
+            found.sort_by(|a: &[f32; 3], b| a.partial_cmp(b).unwrap());
+            assert_eq!(linear, found, "query {min:?} to {max:?}");
+        }
+    }
+
+    #[test]
+    fn sweeps_hit_what_the_linear_scan_hits() {
+        let mut rng = XorShiftRng::new(0x1234567);
+        let bvh = CollisionBvh::build(random_triangles(&mut rng, 1000));
+
+        // The player's collider, as in the collision benchmark
+        let cbm = vec3(1.0 / 0.5, 1.0 / 0.9, 1.0 / 0.5);
+        let mut hits = 0;
+
+        for _ in 0..200 {
+            let start = random_vec(&mut rng, 16.0).mul_element_wise(cbm);
+            let velocity = (random_vec(&mut rng, 4.0) - vec3(2.0, 2.0, 2.0)).mul_element_wise(cbm);
+            let sweep = |t: &CollisionTriangle| {
+                intersection::toi_unit_sphere_triangle(start, velocity, &t.triangle.apply_cbm(cbm))
+                    .map(|(toi, _, _)| toi)
+                    .filter(|toi| *toi >= 0.0)
+            };
+
+            let linear = bvh.triangles().iter().filter_map(sweep).min_by(f32::total_cmp);
+
+            let path = Aabb::from_unit_sphere_sweep(&start, &velocity);
+            let (min, max) = path.min_max().unwrap();
+
+            let mut closest: Option<f32> = None;
+            bvh.query_ellipsoid_space(min, max, cbm, |t| {
+                if let Some(toi) = sweep(t) {
+                    closest = Some(closest.map_or(toi, |closest| closest.min(toi)));
+                }
+            });
+
+            assert_eq!(linear, closest, "sweep from {start:?} by {velocity:?}");
+            hits += linear.is_some() as usize;
+        }
+
+        // Make sure the test actually exercises some hits
+        assert!(hits > 20, "only {hits} sweeps hit anything");
+    }
+}
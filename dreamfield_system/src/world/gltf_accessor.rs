@@ -0,0 +1,138 @@
+use gltf::{buffer, Accessor};
+use gltf::accessor::{DataType, sparse::IndexType};
+
+/// Read every component of an accessor as f32, e.g. for vertex attributes. Normalized integer
+/// components are converted to the 0..1 or -1..1 range as the gltf spec says.
+pub fn read_f32(accessor: &Accessor, buffers: &[buffer::Data]) -> Result<Vec<f32>, String> {
+    read_components(accessor, buffers, |data, data_type, normalized| {
+        let value = read_component(data, data_type);
+        match (normalized, data_type) {
+            (_, DataType::F32) => f32::from_bits(value as u32),
+            (true, DataType::U8) => value as f32 / u8::MAX as f32,
+            (true, DataType::U16) => value as f32 / u16::MAX as f32,
+            (true, DataType::I8) => f32::max(value as i8 as f32 / i8::MAX as f32, -1.0),
+            (true, DataType::I16) => f32::max(value as i16 as f32 / i16::MAX as f32, -1.0),
+            (_, DataType::I8) => value as i8 as f32,
+            (_, DataType::I16) => value as i16 as f32,
+            (_, _) => value as f32,
+        }
+    })
+}
+
+/// Read every component of an accessor as u32, e.g. for indices, which can be u8, u16 or u32
+pub fn read_u32(accessor: &Accessor, buffers: &[buffer::Data]) -> Result<Vec<u32>, String> {
+    read_components(accessor, buffers, |data, data_type, _| read_component(data, data_type) as u32)
+}
+
+/// Read every component of an accessor, handling strided (interleaved) buffer views and sparse
+/// accessors. Accessors with no buffer view are all zeros apart from their sparse values. Returns an
+/// error if the accessor reads outside of its buffers.
+fn read_components<T: Copy + Default>(accessor: &Accessor, buffers: &[buffer::Data],
+    convert: impl Fn(&[u8], DataType, bool) -> T) -> Result<Vec<T>, String>
+{
+    let data_type = accessor.data_type();
+    let normalized = accessor.normalized();
+    let components = accessor.dimensions().multiplicity();
+    let component_size = data_type_size(data_type);
+    let element_size = components * component_size;
+
+    let mut values = vec![T::default(); accessor.count() * components];
+
+    if let Some(view) = accessor.view() {
+        let buffer = get_buffer(buffers, view.buffer().index(), accessor)?;
+        let stride = view.stride().unwrap_or(element_size);
+        let start = view.offset() + accessor.offset();
+        check_range(buffer, start, accessor.count(), stride, element_size, accessor)?;
+
+        for (i, element) in values.chunks_exact_mut(components).enumerate() {
+            let element_offset = start + i * stride;
+            for (c, value) in element.iter_mut().enumerate() {
+                let offset = element_offset + c * component_size;
+                *value = convert(&buffer[offset..offset + component_size], data_type, normalized);
+            }
+        }
+    }
+
+    // Sparse accessors replace some of the elements with values from a separate, tightly packed view
+    if let Some(sparse) = accessor.sparse() {
+        let indices = sparse.indices();
+        let index_view = indices.view();
+        let index_buffer = get_buffer(buffers, index_view.buffer().index(), accessor)?;
+        let index_start = index_view.offset() + indices.offset() as usize;
+        let index_type = match indices.index_type() {
+            IndexType::U8 => DataType::U8,
+            IndexType::U16 => DataType::U16,
+            IndexType::U32 => DataType::U32,
+        };
+        let index_size = data_type_size(index_type);
+        check_range(index_buffer, index_start, sparse.count() as usize, index_size, index_size, accessor)?;
+
+        let sparse_values = sparse.values();
+        let value_view = sparse_values.view();
+        let value_buffer = get_buffer(buffers, value_view.buffer().index(), accessor)?;
+        let value_start = value_view.offset() + sparse_values.offset() as usize;
+        check_range(value_buffer, value_start, sparse.count() as usize, element_size, element_size, accessor)?;
+
+        for i in 0..sparse.count() as usize {
+            let index_offset = index_start + i * index_size;
+            let index = read_component(&index_buffer[index_offset..index_offset + index_size], index_type) as usize;
+            if index >= accessor.count() {
+                return Err(format!("Accessor {} has sparse index {index}, but only {} elements", accessor.index(),
+                    accessor.count()));
+            }
+
+            for c in 0..components {
+                let offset = value_start + i * element_size + c * component_size;
+                values[index * components + c] =
+                    convert(&value_buffer[offset..offset + component_size], data_type, normalized);
+            }
+        }
+    }
+
+    Ok(values)
+}
+
+/// Get a buffer that an accessor uses
+fn get_buffer<'a>(buffers: &'a [buffer::Data], index: usize, accessor: &Accessor) -> Result<&'a [u8], String> {
+    buffers
+        .get(index)
+        .map(|buffer| &buffer[..])
+        .ok_or_else(|| format!("Accessor {} uses buffer {index}, which doesn't exist", accessor.index()))
+}
+
+/// Check that count elements of a given size, a stride apart from the start, are all inside a buffer
+fn check_range(buffer: &[u8], start: usize, count: usize, stride: usize, element_size: usize, accessor: &Accessor)
+    -> Result<(), String>
+{
+    let end = match count {
+        0 => start,
+        _ => (count - 1).checked_mul(stride)
+            .and_then(|offset| offset.checked_add(start))
+            .and_then(|offset| offset.checked_add(element_size))
+            .unwrap_or(usize::MAX)
+    };
+
+    match end <= buffer.len() {
+        true => Ok(()),
+        false => Err(format!("Accessor {} reads up to byte {end} of a buffer that's only {} bytes long",
+            accessor.index(), buffer.len()))
+    }
+}
+
+/// Read a single little endian component as raw bits
+fn read_component(data: &[u8], data_type: DataType) -> u64 {
+    match data_type {
+        DataType::I8 | DataType::U8 => data[0] as u64,
+        DataType::I16 | DataType::U16 => u16::from_le_bytes([data[0], data[1]]) as u64,
+        DataType::U32 | DataType::F32 => u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as u64,
+    }
+}
+
+/// Get the size of a component in bytes
+fn data_type_size(data_type: DataType) -> usize {
+    match data_type {
+        DataType::I8 | DataType::U8 => 1,
+        DataType::I16 | DataType::U16 => 2,
+        DataType::U32 | DataType::F32 => 4,
+    }
+}
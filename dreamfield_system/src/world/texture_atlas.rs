@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use super::world_texture::{WorldTexture, WorldTextureData, TextureIndex};
+
+/// The biggest width or height a texture can have to be put in an atlas
+pub const ATLAS_MAX_TEXTURE_SIZE: u32 = 64;
+
+/// The width and height of texture atlases
+pub const ATLAS_SIZE: u32 = 256;
+
+/// The border around each texture in an atlas, which repeats the texture's edge pixels so that
+/// filtering doesn't bleed into its neighbours
+const ATLAS_PADDING: u32 = 1;
+
+/// Where a texture was placed in an atlas, in pixels, not including its padding
+#[derive(Copy, Clone, Debug)]
+pub struct AtlasRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl AtlasRect {
+    /// Map a texture coordinate within the original texture to the atlas
+    pub fn map_uv(&self, u: f32, v: f32) -> (f32, f32) {
+        ((self.x as f32 + u * self.width as f32) / ATLAS_SIZE as f32,
+         (self.y as f32 + v * self.height as f32) / ATLAS_SIZE as f32)
+    }
+}
+
+/// A texture atlas, which is a single rgba8 texture containing several small textures
+pub struct TextureAtlas {
+    pub pixels: Vec<u8>,
+    pub rects: HashMap<TextureIndex, AtlasRect>,
+}
+
+/// Get whether a texture can be put in an atlas. Only small rgba8 textures are, as atlases are
+/// rgba8, and the compressed formats are already small.
+pub fn can_atlas(texture: &WorldTexture) -> bool {
+    matches!(texture.data(), WorldTextureData::Rgba8(_))
+        && texture.width() <= ATLAS_MAX_TEXTURE_SIZE
+        && texture.height() <= ATLAS_MAX_TEXTURE_SIZE
+}
+
+/// Pack textures into as few atlases as possible, using shelf packing with the tallest textures
+/// first. The textures should all satisfy can_atlas.
+pub fn build_atlases(textures: &[&WorldTexture]) -> Vec<TextureAtlas> {
+    let mut sorted: Vec<&WorldTexture> = textures.to_vec();
+    sorted.sort_by_key(|texture| (std::cmp::Reverse(texture.height()), texture.index()));
+
+    let mut atlases: Vec<TextureAtlas> = Vec::new();
+    let (mut shelf_x, mut shelf_y, mut shelf_height) = (0, 0, 0);
+
+    for texture in sorted {
+        let padded_width = texture.width() + ATLAS_PADDING * 2;
+        let padded_height = texture.height() + ATLAS_PADDING * 2;
+
+        // Start a new shelf if this one's full, and a new atlas if there's no room for another shelf
+        if shelf_x + padded_width > ATLAS_SIZE {
+            shelf_x = 0;
+            shelf_y += shelf_height;
+            shelf_height = 0;
+        }
+        if atlases.is_empty() || shelf_y + padded_height > ATLAS_SIZE {
+            atlases.push(TextureAtlas {
+                pixels: vec![0; (ATLAS_SIZE * ATLAS_SIZE * 4) as usize],
+                rects: HashMap::new(),
+            });
+            (shelf_x, shelf_y, shelf_height) = (0, 0, 0);
+        }
+
+        let rect = AtlasRect {
+            x: shelf_x + ATLAS_PADDING,
+            y: shelf_y + ATLAS_PADDING,
+            width: texture.width(),
+            height: texture.height(),
+        };
+
+        let atlas = atlases.last_mut().unwrap();
+        blit_padded(&mut atlas.pixels, &texture.pixels(), &rect);
+        atlas.rects.insert(texture.index(), rect);
+
+        shelf_x += padded_width;
+        shelf_height = u32::max(shelf_height, padded_height);
+    }
+
+    atlases
+}
+
+/// Copy a texture's pixels into an atlas, along with its padding
+fn blit_padded(atlas: &mut [u8], pixels: &[u8], rect: &AtlasRect) {
+    let padding = ATLAS_PADDING as i32;
+    for y in -padding..rect.height as i32 + padding {
+        for x in -padding..rect.width as i32 + padding {
+            // The padding repeats the nearest edge pixel
+            let src_x = i32::clamp(x, 0, rect.width as i32 - 1) as u32;
+            let src_y = i32::clamp(y, 0, rect.height as i32 - 1) as u32;
+            let src = ((src_y * rect.width + src_x) * 4) as usize;
+
+            let dst_x = (rect.x as i32 + x) as u32;
+            let dst_y = (rect.y as i32 + y) as u32;
+            let dst = ((dst_y * ATLAS_SIZE + dst_x) * 4) as usize;
+
+            atlas[dst..dst + 4].copy_from_slice(&pixels[src..src + 4]);
+        }
+    }
+}
@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+use gltf::image::{self, Format};
+use speedy::{Readable, Writable};
+use super::world_texture::WorldTextureData;
+
+/// The formats world textures can be stored in
+#[derive(Copy, Clone, Readable, Writable, Debug, Default, PartialEq, Eq, Hash)]
+pub enum WorldTextureFormat {
+    #[default]
+    Rgba8,
+    Rgba5551,
+    Clut8,
+    Clut4,
+}
+
+impl WorldTextureFormat {
+    /// Parse a texture format from its name in the material extras
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "rgba8" => Some(WorldTextureFormat::Rgba8),
+            "rgba5551" => Some(WorldTextureFormat::Rgba5551),
+            "clut8" => Some(WorldTextureFormat::Clut8),
+            "clut4" => Some(WorldTextureFormat::Clut4),
+            _ => None
+        }
+    }
+}
+
+/// How the world builder should process a texture, which comes from the extras of the material
+/// using it. The same image used with different settings becomes two world textures.
+#[derive(Copy, Clone, Readable, Writable, Debug, Default, PartialEq, Eq, Hash)]
+pub struct WorldTextureSettings {
+    pub format: WorldTextureFormat,
+    /// The maximum width or height, textures bigger than this are downscaled to fit
+    pub max_size: Option<u32>,
+}
+
+/// Process a gltf image into world texture data, returning the data along with its width and height
+pub fn process_texture(image: &image::Data, settings: &WorldTextureSettings) -> (WorldTextureData, u32, u32) {
+    let mut pixels = to_rgba8(image);
+    let (mut width, mut height) = (image.width, image.height);
+
+    if let Some(max_size) = settings.max_size {
+        (pixels, width, height) = downscale(&pixels, width, height, max_size);
+    }
+
+    let data = match settings.format {
+        WorldTextureFormat::Rgba8 => WorldTextureData::Rgba8(pixels),
+        WorldTextureFormat::Rgba5551 => WorldTextureData::Rgba5551(convert_rgba8_to_rgba5551(&pixels)),
+        WorldTextureFormat::Clut8 => {
+            let (palette, indices) = palettize(&pixels, 256);
+            WorldTextureData::Clut8 { palette, indices }
+        }
+        WorldTextureFormat::Clut4 => {
+            let (palette, indices) = palettize(&pixels, 16);
+            let indices = indices
+                .chunks(2)
+                .map(|pair| pair[0] | pair.get(1).unwrap_or(&0) << 4)
+                .collect();
+            WorldTextureData::Clut4 { palette, indices }
+        }
+    };
+
+    (data, width, height)
+}
+
+/// Convert any gltf image format to rgba8. Single channel images are treated as greyscale, and two
+/// channel ones as greyscale with alpha, as that's what they are when they come from a png.
+fn to_rgba8(image: &image::Data) -> Vec<u8> {
+    let pixels = &image.pixels;
+    let pixel_count = (image.width * image.height) as usize;
+
+    // 16 bit components are in native endianness, and we just keep the top 8 bits
+    let c16 = |i: usize| (u16::from_ne_bytes([pixels[i * 2], pixels[i * 2 + 1]]) >> 8) as u8;
+
+    (0..pixel_count)
+        .flat_map(|i| match image.format {
+            Format::R8 => [pixels[i], pixels[i], pixels[i], 255],
+            Format::R8G8 => [pixels[i * 2], pixels[i * 2], pixels[i * 2], pixels[i * 2 + 1]],
+            Format::R8G8B8 => [pixels[i * 3], pixels[i * 3 + 1], pixels[i * 3 + 2], 255],
+            Format::R8G8B8A8 => [pixels[i * 4], pixels[i * 4 + 1], pixels[i * 4 + 2], pixels[i * 4 + 3]],
+            Format::B8G8R8 => [pixels[i * 3 + 2], pixels[i * 3 + 1], pixels[i * 3], 255],
+            Format::B8G8R8A8 => [pixels[i * 4 + 2], pixels[i * 4 + 1], pixels[i * 4], pixels[i * 4 + 3]],
+            Format::R16 => [c16(i), c16(i), c16(i), 255],
+            Format::R16G16 => [c16(i * 2), c16(i * 2), c16(i * 2), c16(i * 2 + 1)],
+            Format::R16G16B16 => [c16(i * 3), c16(i * 3 + 1), c16(i * 3 + 2), 255],
+            Format::R16G16B16A16 => [c16(i * 4), c16(i * 4 + 1), c16(i * 4 + 2), c16(i * 4 + 3)],
+        })
+        .collect()
+}
+
+/// Downscale an rgba8 image with a box filter so that neither dimension is bigger than max_size,
+/// keeping the aspect ratio. Colors are weighted by alpha so that transparent pixels don't bleed
+/// into the edges of cutouts.
+fn downscale(pixels: &[u8], width: u32, height: u32, max_size: u32) -> (Vec<u8>, u32, u32) {
+    if width <= max_size && height <= max_size {
+        return (pixels.to_vec(), width, height);
+    }
+
+    let scale = max_size as f32 / u32::max(width, height) as f32;
+    let new_width = u32::max(1, f32::round(width as f32 * scale) as u32);
+    let new_height = u32::max(1, f32::round(height as f32 * scale) as u32);
+
+    let mut out = Vec::with_capacity((new_width * new_height * 4) as usize);
+    for y in 0..new_height {
+        let (y0, y1) = source_range(y, height, new_height);
+        for x in 0..new_width {
+            let (x0, x1) = source_range(x, width, new_width);
+
+            let mut color_sum = [0.0; 3];
+            let mut alpha_sum = 0.0;
+            for sy in y0..y1 {
+                for sx in x0..x1 {
+                    let p = &pixels[((sy * width + sx) * 4) as usize..][..4];
+                    let alpha = p[3] as f32;
+                    for c in 0..3 {
+                        color_sum[c] += p[c] as f32 * alpha;
+                    }
+                    alpha_sum += alpha;
+                }
+            }
+
+            let count = ((x1 - x0) * (y1 - y0)) as f32;
+            let color = color_sum.map(|c| if alpha_sum > 0.0 { c / alpha_sum } else { 0.0 });
+            out.extend_from_slice(&[color[0] as u8, color[1] as u8, color[2] as u8, (alpha_sum / count) as u8]);
+        }
+    }
+
+    (out, new_width, new_height)
+}
+
+/// Get the range of source pixels that a downscaled pixel covers, which is always at least one
+fn source_range(i: u32, size: u32, new_size: u32) -> (u32, u32) {
+    let start = i * size / new_size;
+    let end = u32::max(start + 1, (i + 1) * size / new_size);
+    (start, end)
+}
+
+/// Convert rgba8 pixels to rgba5551, where alpha maps 0 to 0 and any other value to 1
+pub fn convert_rgba8_to_rgba5551(pixels: &[u8]) -> Vec<u16> {
+    pixels
+        .chunks_exact(4)
+        .map(|p| {
+            let r = p[0] as u16 * 31 / 255;
+            let g = p[1] as u16 * 31 / 255;
+            let b = p[2] as u16 * 31 / 255;
+            let a = (p[3] != 0) as u16;
+            r << 11 | g << 6 | b << 1 | a
+        })
+        .collect()
+}
+
+/// Quantize rgba8 pixels to a given number of bits per component, leaving them as rgba8
+pub fn quantize_to_bit_depth(pixels: &mut [u8], bits: u8) {
+    assert!((1..=8).contains(&bits), "quantize_to_bit_depth: bits should be between 1 and 8");
+    assert!(pixels.len() % 4 == 0, "quantize_to_bit_depth: Pixels need to be a multiple of 4 bytes");
+
+    let multiplier = (1 << bits) as f32 / 255.0;
+    for c in pixels.iter_mut() {
+        *c = (f32::floor(*c as f32 * multiplier) / multiplier) as u8;
+    }
+}
+
+/// Reduce rgba8 pixels to a palette of at most max_colors using median cut, returning the palette
+/// and the palette index of each pixel
+fn palettize(pixels: &[u8], max_colors: usize) -> (Vec<[u8; 4]>, Vec<u8>) {
+    // Count the unique colors, sorted so that the same image always gives the same palette
+    let mut counts: HashMap<[u8; 4], usize> = HashMap::new();
+    for p in pixels.chunks_exact(4) {
+        *counts.entry([p[0], p[1], p[2], p[3]]).or_insert(0) += 1;
+    }
+    let mut colors: Vec<([u8; 4], usize)> = counts.into_iter().collect();
+    colors.sort();
+
+    let palette = match colors.len() <= max_colors {
+        true => colors.iter().map(|(color, _)| *color).collect(),
+        false => median_cut(colors, max_colors)
+    };
+
+    // Map each pixel to the nearest color in the palette
+    let mut nearest: HashMap<[u8; 4], u8> = HashMap::new();
+    let indices = pixels
+        .chunks_exact(4)
+        .map(|p| {
+            let color = [p[0], p[1], p[2], p[3]];
+            *nearest.entry(color).or_insert_with(|| nearest_color(&palette, &color))
+        })
+        .collect();
+
+    (palette, indices)
+}
+
+/// Split the colors into max_colors boxes by repeatedly splitting the box with the widest range in
+/// any component at its median, and return the average color of each box
+fn median_cut(colors: Vec<([u8; 4], usize)>, max_colors: usize) -> Vec<[u8; 4]> {
+    let mut boxes = vec![colors];
+
+    while boxes.len() < max_colors {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .map(|(i, colors)| {
+                let (component, range) = widest_component(colors);
+                (i, component, range)
+            })
+            .max_by_key(|(_, _, range)| *range);
+
+        let (i, component) = match widest {
+            Some((i, component, _)) => (i, component),
+            None => break
+        };
+
+        // Split at the median, weighted by how many pixels have each color
+        let mut colors = boxes.swap_remove(i);
+        colors.sort_by_key(|(color, _)| color[component]);
+
+        let total: usize = colors.iter().map(|(_, count)| count).sum();
+        let mut split = colors.len() - 1;
+        let mut sum = 0;
+        for (j, (_, count)) in colors.iter().enumerate() {
+            sum += count;
+            if sum * 2 >= total {
+                split = j + 1;
+                break;
+            }
+        }
+
+        let rest = colors.split_off(usize::clamp(split, 1, colors.len() - 1));
+        boxes.push(colors);
+        boxes.push(rest);
+    }
+
+    boxes.iter().map(|colors| average_color(colors)).collect()
+}
+
+/// Get the component with the widest range of values, and its range
+fn widest_component(colors: &[([u8; 4], usize)]) -> (usize, u8) {
+    (0..4)
+        .map(|c| {
+            let min = colors.iter().map(|(color, _)| color[c]).min().unwrap_or(0);
+            let max = colors.iter().map(|(color, _)| color[c]).max().unwrap_or(0);
+            (c, max - min)
+        })
+        .max_by_key(|(_, range)| *range)
+        .unwrap()
+}
+
+/// Get the average of some colors, weighted by how many pixels have each
+fn average_color(colors: &[([u8; 4], usize)]) -> [u8; 4] {
+    let total: usize = colors.iter().map(|(_, count)| count).sum();
+    let mut sum = [0; 4];
+    for (color, count) in colors {
+        for c in 0..4 {
+            sum[c] += color[c] as usize * count;
+        }
+    }
+    sum.map(|c| ((c + total / 2) / total) as u8)
+}
+
+/// Find the index of the closest color in a palette
+fn nearest_color(palette: &[[u8; 4]], color: &[u8; 4]) -> u8 {
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, entry)| {
+            (0..4).map(|c| (entry[c] as i32 - color[c] as i32).pow(2)).sum::<i32>()
+        })
+        .map(|(i, _)| i as u8)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgba5551() {
+        let pixels = [255, 0, 0, 255, 0, 255, 0, 1, 0, 0, 255, 0, 128, 128, 128, 255];
+        assert_eq!(convert_rgba8_to_rgba5551(&pixels), vec![0xf801, 0x07c1, 0x003e, 0x7bdf]);
+    }
+
+    #[test]
+    fn quantize() {
+        let mut pixels = [0, 31, 32, 200];
+        quantize_to_bit_depth(&mut pixels, 3);
+        assert_eq!(pixels, [0, 0, 31, 191]);
+    }
+
+    #[test]
+    fn palettize_keeps_exact_colors_when_they_fit() {
+        let pixels = [10, 20, 30, 255, 200, 100, 0, 255, 10, 20, 30, 255, 0, 0, 0, 0];
+        let (palette, indices) = palettize(&pixels, 16);
+
+        assert_eq!(palette.len(), 3);
+        let colors: Vec<[u8; 4]> = indices.iter().map(|i| palette[*i as usize]).collect();
+        assert_eq!(colors, vec![[10, 20, 30, 255], [200, 100, 0, 255], [10, 20, 30, 255], [0, 0, 0, 0]]);
+    }
+
+    #[test]
+    fn palettize_reduces_to_the_nearest_colors() {
+        // Two clusters of reds and blues, which should end up as one color each
+        let pixels: Vec<u8> = (0..8u8)
+            .flat_map(|i| [[250 - i, 0, 0, 255], [0, 0, 250 - i, 255]])
+            .flatten()
+            .collect();
+        let (palette, indices) = palettize(&pixels, 2);
+
+        assert_eq!(palette.len(), 2);
+        for (pixel, index) in pixels.chunks_exact(4).zip(indices.iter()) {
+            let color = palette[*index as usize];
+            let is_red = pixel[0] > 0;
+            assert_eq!(color[0] > 0, is_red);
+            assert_eq!(color[2] > 0, !is_red);
+        }
+    }
+}
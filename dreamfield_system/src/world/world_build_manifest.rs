@@ -0,0 +1,220 @@
+use std::collections::hash_map::DefaultHasher;
+use std::error::Error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use speedy::{Readable, Writable};
+use super::chunk_file::CHUNK_FILE_VERSION;
+use super::world_texture::TextureIndex;
+use crate::file_header::FileHeader;
+
+/// The filename of the manifest the world builder writes to its output directory
+pub const WORLD_BUILD_MANIFEST_FILENAME: &str = "world_build.manifest";
+
+/// The world build manifest format version, bump this whenever the format changes
+pub const WORLD_BUILD_MANIFEST_VERSION: u32 = 6;
+
+/// The header at the start of world build manifests
+const WORLD_BUILD_MANIFEST_HEADER: FileHeader = FileHeader::new(*b"DFWM", WORLD_BUILD_MANIFEST_VERSION);
+
+/// A file written by the world builder, along with the hash of its contents and the models that
+/// contributed to it
+#[derive(Readable, Writable, Clone, Debug, PartialEq)]
+pub struct WorldBuildFile {
+    pub filename: String,
+    pub content_hash: u64,
+    pub models: Vec<String>,
+}
+
+/// A model the world was built from, along with the hash of its contents and of the cache of what
+/// it output, and where its meshes, entities and textures were numbered from in the world
+#[derive(Readable, Writable, Clone, Debug, PartialEq)]
+pub struct WorldBuildModel {
+    pub filename: String,
+    pub hash: u64,
+    pub cache_hash: u64,
+    pub first_mesh: i32,
+    pub first_entity: i32,
+    /// The world texture index of each of the model's textures
+    pub textures: Vec<TextureIndex>,
+}
+
+impl WorldBuildModel {
+    /// Get the filename of the cache of what a model output, which is kept with the chunks
+    pub fn cache_filename(model_filename: &str) -> String {
+        let name: String = model_filename
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+            .collect();
+        format!("{name}.model")
+    }
+
+    /// Get whether the model's meshes, entities and textures are numbered the same as in another
+    /// build, so that the files it contributed to in that build can be kept
+    pub fn same_numbering(&self, other: &WorldBuildModel) -> bool {
+        self.first_mesh == other.first_mesh && self.first_entity == other.first_entity
+            && self.textures == other.textures
+    }
+}
+
+/// A summary of what the world builder built
+#[derive(Readable, Writable, Clone, Default, Debug)]
+pub struct WorldBuildSummary {
+    pub chunks: usize,
+    pub chunks_written: usize,
+    pub meshes: usize,
+    pub batches: usize,
+    pub textures: usize,
+    pub textures_written: usize,
+    pub entities: usize,
+    pub files_removed: usize,
+}
+
+impl fmt::Display for WorldBuildSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} chunks ({} written), {} meshes in {} batches, {} textures ({} written), {} entities, \
+            {} stale files removed", self.chunks, self.chunks_written, self.meshes, self.batches, self.textures,
+            self.textures_written, self.entities, self.files_removed)
+    }
+}
+
+/// The world build manifest, which records the models the world was built from and the files
+/// written, so that the next build only has to import the models that changed and rewrite the files
+/// they contribute to
+#[derive(Readable, Writable, Clone, Debug)]
+pub struct WorldBuildManifest {
+    header: FileHeader,
+    /// The models, in build order
+    pub models: Vec<WorldBuildModel>,
+    /// The filename and content hash of each instance model, as changing one can change any chunk
+    pub instance_models: Vec<(String, u64)>,
+    /// Whether textures were atlased, as changing it means everything needs rebuilding
+    pub atlas: bool,
+    /// The chunk height the world was split with, which the game needs to know which chunk anything is
+    /// in, and as changing it moves everything between chunks
+    pub chunk_height: Option<f32>,
+    /// How far instance colliders reach outside of the chunks their instances are in, which the game
+    /// needs to know how far into neighbouring chunks collision queries have to look
+    pub instance_collider_reach: f32,
+    /// The chunk file version the chunks were written with, as old chunks can't be loaded
+    pub chunk_file_version: u32,
+    pub files: Vec<WorldBuildFile>,
+    pub summary: WorldBuildSummary,
+}
+
+impl WorldBuildManifest {
+    pub fn new(models: Vec<WorldBuildModel>, instance_models: Vec<(String, u64)>, atlas: bool,
+        chunk_height: Option<f32>, instance_collider_reach: f32, files: Vec<WorldBuildFile>,
+        summary: WorldBuildSummary) -> Self
+    {
+        Self {
+            header: WORLD_BUILD_MANIFEST_HEADER,
+            models,
+            instance_models,
+            atlas,
+            chunk_height,
+            instance_collider_reach,
+            chunk_file_version: CHUNK_FILE_VERSION,
+            files,
+            summary,
+        }
+    }
+
+    /// Load a manifest from a file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        Self::read(&std::fs::read(path)?)
+    }
+
+    /// Read a manifest from its data, e.g. from a chunk source
+    pub fn read(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        // Check the header first, so that old manifests give a useful error
+        WORLD_BUILD_MANIFEST_HEADER.check(data, "world build manifest")?;
+        Ok(Self::read_from_buffer(data)?)
+    }
+
+    /// Save the manifest to a file
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        self.write_to_file(path)?;
+        Ok(())
+    }
+
+    /// Get the record of a file from the last build
+    pub fn file(&self, filename: &str) -> Option<&WorldBuildFile> {
+        self.files.iter().find(|file| file.filename == filename)
+    }
+
+    /// Get the record of a model from the last build
+    pub fn model(&self, filename: &str) -> Option<&WorldBuildModel> {
+        self.models.iter().find(|model| model.filename == filename)
+    }
+
+    /// Get whether what each model output in the last build can be reused, which it can't be if
+    /// chunks were split or written differently, or the instance models colliders are fitted to
+    /// have changed
+    pub fn model_outputs_reusable(&self, chunk_height: Option<f32>, instance_models: &[(String, u64)]) -> bool {
+        self.chunk_height == chunk_height && self.chunk_file_version == CHUNK_FILE_VERSION
+            && self.instance_models == instance_models
+    }
+}
+
+/// Hash the contents of a model or output file. This uses the std hasher, which isn't guaranteed to
+/// be stable between rust versions, but the worst that happens is that everything gets rewritten.
+pub fn hash_contents(data: &[u8]) -> u64 {
+    let mut s = DefaultHasher::new();
+    data.hash(&mut s);
+    s.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_header::test_util::{temp_path, assert_rejects_bad_headers};
+
+    fn test_manifest() -> WorldBuildManifest {
+        let model = WorldBuildModel {
+            filename: "models/village.glb".to_string(),
+            hash: 1,
+            cache_hash: 2,
+            first_mesh: 0,
+            first_entity: 0,
+            textures: vec![0, 1],
+        };
+        let file = WorldBuildFile {
+            filename: "world_0_0_0.chunk".to_string(),
+            content_hash: 3,
+            models: vec![model.filename.clone()],
+        };
+        WorldBuildManifest::new(vec![model], vec![("tree.glb".to_string(), 4)], false, Some(8.0), 0.5, vec![file],
+            WorldBuildSummary { chunks: 1, ..WorldBuildSummary::default() })
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = temp_path("round_trip.manifest");
+        test_manifest().save(&path).unwrap();
+        let loaded = WorldBuildManifest::load(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(loaded.models, test_manifest().models);
+        assert_eq!(loaded.files, test_manifest().files);
+        assert_eq!(loaded.summary.chunks, 1);
+        assert_eq!(loaded.chunk_height, Some(8.0));
+        assert_eq!(loaded.instance_collider_reach, 0.5);
+        assert!(loaded.model_outputs_reusable(Some(8.0), &[("tree.glb".to_string(), 4)]));
+        assert!(!loaded.model_outputs_reusable(Some(8.0), &[("tree.glb".to_string(), 5)]));
+        assert!(!loaded.model_outputs_reusable(None, &[("tree.glb".to_string(), 4)]));
+        assert_eq!(loaded.model("models/village.glb").map(|model| model.cache_hash), Some(2));
+        assert!(loaded.file("world_0_0_0.chunk").is_some());
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        assert_rejects_bad_headers(&test_manifest().write_to_vec().unwrap(), 0, WorldBuildManifest::read);
+    }
+
+    #[test]
+    fn cache_filenames_stay_in_the_output_dir() {
+        assert_eq!(WorldBuildModel::cache_filename("resources/models/village.glb"), "resources_models_village.glb.model");
+        assert_eq!(WorldBuildModel::cache_filename("C:\\models\\a b.glb"), "C__models_a_b.glb.model");
+    }
+}
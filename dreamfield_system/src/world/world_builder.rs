@@ -1,17 +1,24 @@
-use super::world_chunk::{WorldChunk, WorldChunkMesh, ChunkIndex, CHUNK_SIZE, VERTEX_STRIDE, INDEX_STRIDE,
-    WorldChunkMaterial, WorldChunkInstance, WorldChunkEntity};
+use super::world_chunk::{WorldChunk, WorldChunkMesh, ChunkIndex, VERTEX_STRIDE, INDEX_STRIDE, DEFAULT_CHUNK_HEIGHT,
+    WorldChunkMaterial, WorldChunkInstance, WorldChunkEntity, WorldChunkIndices, WorldChunkAlphaMode, WorldChunkTextureRef,
+    WorldChunkSampler, WorldChunkTextureTransform, WorldChunkCollisionMesh, CollisionLayer, WorldChunkInstancePoint,
+    WorldChunkInstanceCollider};
+use super::gltf_accessor;
 use super::aabb::Aabb;
-use super::world_texture::{WorldTexture, TextureIndex};
+use super::world_texture::{WorldTexture, WorldTextureData, TextureIndex};
+use super::texture_pipeline::{self, WorldTextureSettings, WorldTextureFormat};
+use super::texture_atlas::{self, AtlasRect, ATLAS_SIZE};
 use super::wrapped_vectors::{WrappedVector3, WrappedVector4};
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
-use std::{collections::HashMap, path::Path};
-use gltf::image::Format;
+use super::world_build_manifest::{WorldBuildManifest, WorldBuildFile, WorldBuildModel, WorldBuildSummary,
+    WORLD_BUILD_MANIFEST_FILENAME, hash_contents};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
 use gltf::{import_slice, buffer, image, Semantic, Node};
-use cgmath::{Matrix4, SquareMatrix, Vector3, vec4, vec3, vec2, InnerSpace};
-use byteorder::{ReadBytesExt, LittleEndian};
+use cgmath::{Matrix3, Matrix4, SquareMatrix, Vector3, Quaternion, vec4, vec3, vec2, InnerSpace, ElementWise};
 use serde_json::value::RawValue;
-use speedy::Writable;
+use speedy::{Readable, Writable};
 use crate::build_log;
 use serde::{Deserialize, Serialize};
 
@@ -26,10 +33,16 @@ macro_rules! include_world_model {
 
 /// Some bullshit log thing
 /// https://github.com/rust-lang/cargo/issues/985#issuecomment-1071667472
+/// Cargo only sets OUT_DIR when running a build script, so anywhere else we just print normally.
 #[macro_export]
 macro_rules! build_log {
     ($($tokens: tt)*) => {
-        println!("cargo:warning={}", format!($($tokens)*))
+        if std::env::var_os("OUT_DIR").is_some() {
+            println!("cargo:warning={}", format!($($tokens)*))
+        }
+        else {
+            println!($($tokens)*)
+        }
     }
 }
 
@@ -42,94 +55,793 @@ pub struct WorldNodeExtras {
     #[serde(default)]
     pub instance_mesh: Option<String>,
 
+    /// The collision shape of each instance of an instances node, one of box (the default),
+    /// spheroid, capsule or none
+    #[serde(default)]
+    pub instance_collision: Option<String>,
+
+    /// The bounds to fit the instance collision shape to, relative to an unscaled instance. They
+    /// must be given together, and the bounds of the instance mesh's model are used if they aren't.
+    #[serde(default)]
+    pub instance_collision_min: Option<[f32; 3]>,
+
+    #[serde(default)]
+    pub instance_collision_max: Option<[f32; 3]>,
+
     #[serde(default)]
     pub object_id: Option<String>,
+
+    /// What a mesh is used for, one of both (the default), render or collision
+    #[serde(default)]
+    pub collision: Option<String>,
+
+    /// The layer of a mesh's collision surfaces, such as water or ladder, solid by default
+    #[serde(default)]
+    pub collision_layer: Option<String>,
 }
 
+/// The material extras we support
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct WorldMaterialExtras {
+    /// The format to store the material's textures in, one of rgba8, rgba5551, clut8 or clut4
+    #[serde(default)]
+    pub texture_format: Option<String>,
+
+    /// The maximum width or height of the material's textures, bigger ones are downscaled
+    #[serde(default)]
+    pub texture_max_size: Option<u32>,
+
+    /// Whether the mesh's vertex colors are used to light the material, true by default. Turning
+    /// it off shows the material at full brightness, for meshes whose vertex colors aren't lighting.
+    #[serde(default)]
+    pub vertex_colors: Option<bool>,
+}
 
-/// A world model - i.e. a gltf model embedded in a build script that we should build into the game world
+/// The index in the model's output of the texture for each image in the model being built, by the
+/// image's index and the settings it was processed with
+type ModelTextures = HashMap<(usize, WorldTextureSettings), i32>;
+
+/// What a single model contributed to the world. Its meshes, entities and textures are numbered from
+/// 0, so that it can be cached and added to the world again without importing the model, wherever
+/// it ends up in the build order. The chunks are kept in order so that the cache is the same for the
+/// same model.
+#[derive(Readable, Writable, Default)]
+struct WorldModelOutput {
+    chunks: BTreeMap<ChunkIndex, WorldChunk>,
+    /// Each texture along with the hash of its image and the settings it was processed with, which
+    /// it's deduplicated with the other models' textures by
+    textures: Vec<(u64, WorldTextureSettings, WorldTexture)>,
+    mesh_count: i32,
+    entity_count: i32,
+}
+
+/// The data of a single vertex while clipping meshes to chunks
+type ClipVertex = [f32; VERTEX_STRIDE];
+
+
+/// An error building a world model, with the model and node it came from. Errors reading or writing
+/// the output directory give the file instead of the model.
+#[derive(Clone, Debug)]
+pub struct WorldBuildError {
+    pub model: String,
+    pub node: Option<String>,
+    pub reason: String,
+}
+
+impl fmt::Display for WorldBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.node {
+            Some(node) => write!(f, "{}: node {}: {}", self.model, node, self.reason),
+            None => write!(f, "{}: {}", self.model, self.reason),
+        }
+    }
+}
+
+impl Error for WorldBuildError {}
+
+/// All the errors from building the world, which are collected in one pass so that they can all be
+/// fixed at once
+#[derive(Clone, Debug)]
+pub struct WorldBuildErrors(pub Vec<WorldBuildError>);
+
+impl fmt::Display for WorldBuildErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} errors building world models", self.0.len())?;
+        for err in self.0.iter() {
+            write!(f, "\n  {err}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for WorldBuildErrors {}
+
+impl WorldBuildErrors {
+    /// An error reading or writing a file in the output directory, which stops the build
+    fn file(path: &Path, reason: impl fmt::Display) -> Self {
+        Self(vec![WorldBuildError {
+            model: path.display().to_string(),
+            node: None,
+            reason: reason.to_string(),
+        }])
+    }
+}
+
+/// A world model - i.e. a gltf model embedded in a build script, or loaded from disk by the world
+/// builder tool, that we should build into the game world
 pub struct WorldModel {
-    filename: &'static str,
-    data: &'static [u8]
+    filename: Cow<'static, str>,
+    data: Cow<'static, [u8]>
 }
 
 impl WorldModel {
     /// Create a new world model, use include_world_model! instead of calling this directly
     pub const fn new(filename: &'static str, data: &'static [u8]) -> Self {
         Self {
-            filename,
-            data
+            filename: Cow::Borrowed(filename),
+            data: Cow::Borrowed(data)
         }
     }
+
+    /// Load a world model from a file
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        Ok(Self {
+            filename: Cow::Owned(path.to_string_lossy().into_owned()),
+            data: Cow::Owned(std::fs::read(path)?)
+        })
+    }
+
+    /// Get the model's filename
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+}
+
+/// A model that instances nodes place copies of, by the instance_mesh name they use for it, which
+/// the world builder fits the instances' colliders to
+pub struct InstanceModel {
+    name: Cow<'static, str>,
+    model: WorldModel,
+}
+
+impl InstanceModel {
+    /// Create a new instance model, use include_world_model! for the model
+    pub const fn new(name: &'static str, model: WorldModel) -> Self {
+        Self {
+            name: Cow::Borrowed(name),
+            model
+        }
+    }
+
+    /// Load an instance model from a file
+    pub fn load(name: impl Into<String>, path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self {
+            name: Cow::Owned(name.into()),
+            model: WorldModel::load(path)?
+        })
+    }
+
+    /// Get the instance_mesh name of the model
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the model
+    pub fn model(&self) -> &WorldModel {
+        &self.model
+    }
 }
 
 /// World builder
-pub struct WorldBuilder {
-    out_dir: &'static str,
-    models: &'static [WorldModel],
+pub struct WorldBuilder<'a> {
+    out_dir: PathBuf,
+    models: &'a [WorldModel],
+    instance_models: &'a [InstanceModel],
+    /// The bounds of each instance model that's been used so far, so each is only loaded once
+    instance_bounds: HashMap<String, (Vector3<f32>, Vector3<f32>)>,
+    force: bool,
+    atlas: bool,
+    chunk_height: Option<f32>,
     chunks: HashMap<ChunkIndex, WorldChunk>,
     textures: Vec<WorldTexture>,
-    texture_hashes: HashMap<u64, usize>,
+    texture_hashes: HashMap<(u64, WorldTextureSettings), usize>,
+    atlas_hashes: HashMap<u64, usize>,
+    mesh_count: i32,
     entity_count: i32,
+    /// The model currently being processed and what it's output so far, and the models that
+    /// contributed to each chunk and texture, so that we know which files a changed model affects
+    current_model: usize,
+    output: WorldModelOutput,
+    chunk_models: HashMap<ChunkIndex, BTreeSet<usize>>,
+    texture_models: Vec<BTreeSet<usize>>,
+    errors: Vec<WorldBuildError>,
 }
 
-impl WorldBuilder {
+impl<'a> WorldBuilder<'a> {
     /// Create a new world builder
-    pub fn new(out_dir: &'static str, models: &'static [WorldModel]) -> Self {
+    pub fn new(out_dir: impl Into<PathBuf>, models: &'a [WorldModel]) -> Self {
         Self {
-            out_dir,
+            out_dir: out_dir.into(),
             models,
+            instance_models: &[],
+            instance_bounds: HashMap::new(),
+            force: false,
+            atlas: false,
+            chunk_height: DEFAULT_CHUNK_HEIGHT,
             chunks: HashMap::new(),
             textures: Vec::new(),
             texture_hashes: HashMap::new(),
-            entity_count: 0
+            atlas_hashes: HashMap::new(),
+            mesh_count: 0,
+            entity_count: 0,
+            current_model: 0,
+            output: WorldModelOutput::default(),
+            chunk_models: HashMap::new(),
+            texture_models: Vec::new(),
+            errors: Vec::new(),
         }
     }
 
-    // Build world models
-    pub fn build_world_models(&mut self) {
-        std::fs::remove_dir_all(self.out_dir).unwrap();
-        std::fs::create_dir_all(self.out_dir).unwrap();
+    /// Set whether to rewrite every file, even if the models they were built from haven't changed
+    pub fn with_force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Set whether to put the small textures used by each chunk into atlases, so that more of its
+    /// meshes can be merged
+    pub fn with_atlas(mut self, atlas: bool) -> Self {
+        self.atlas = atlas;
+        self
+    }
+
+    /// Set the height to split the world into chunks at, or None to not split it vertically. The
+    /// game reads it from the manifest, so it can be changed without rebuilding the game.
+    pub fn with_chunk_height(mut self, chunk_height: Option<f32>) -> Self {
+        self.chunk_height = chunk_height;
+        self
+    }
 
-        // Tell cargo to rerun build.rs if any of the models change
-        for model in self.models {
-            println!("cargo:rerun-if-changed=./{}", model.filename);
+    /// Set the models that instances nodes use, so that their colliders can be fitted to them
+    pub fn with_instance_models(mut self, instance_models: &'a [InstanceModel]) -> Self {
+        self.instance_models = instance_models;
+        self
+    }
+
+    /// Build world models. Models that haven't changed since the last build aren't imported again,
+    /// and what they output is loaded from the cache kept with the chunks instead. Only the chunks
+    /// and textures that changed models contribute to are rewritten, and if no models have changed
+    /// nothing is rebuilt at all. Atlases are built across the whole world, so atlasing textures
+    /// rewrites every file whose contents changed. If any models have errors, they're all returned
+    /// together and nothing is written.
+    pub fn build_world_models(&mut self) -> Result<WorldBuildSummary, WorldBuildErrors> {
+        if let Some(chunk_height) = self.chunk_height.filter(|height| !(height.is_finite() && *height > 0.0)) {
+            return Err(WorldBuildErrors::file(&self.out_dir, format!("Invalid chunk height {chunk_height}")));
         }
 
-        let mut world_mesh_count: i32 = 0;
+        std::fs::create_dir_all(&self.out_dir)
+            .map_err(|err| WorldBuildErrors::file(&self.out_dir, format!("Failed to create output directory: {err}")))?;
+
+        // Hash the models and compare them to the last build
+        let manifest_path = self.out_dir.join(WORLD_BUILD_MANIFEST_FILENAME);
+        let model_hashes: Vec<u64> = self.models.iter().map(|model| hash_contents(&model.data)).collect();
+
+        // Instance models don't contribute to any particular file, but the colliders fitted to them
+        // can change any chunk, so a change to one means no model's cached output can be used
+        let instance_model_hashes: Vec<(String, u64)> = self.instance_models
+            .iter()
+            .map(|instance| (instance.model.filename.to_string(), hash_contents(&instance.model.data)))
+            .collect();
+
+        let old_manifest = match self.force {
+            true => None,
+            false => WorldBuildManifest::load(&manifest_path)
+                .ok()
+                .filter(|manifest| manifest.model_outputs_reusable(self.chunk_height, &instance_model_hashes))
+        };
+
+        if let Some(manifest) = &old_manifest {
+            let same_models = manifest.models.len() == self.models.len() && manifest.models
+                .iter()
+                .zip(self.models.iter().zip(model_hashes.iter()))
+                .all(|(old, (model, hash))| old.filename == model.filename() && old.hash == *hash);
+            let files_exist = manifest.files.iter().all(|file| self.out_dir.join(&file.filename).is_file());
+            if same_models && manifest.atlas == self.atlas && files_exist {
+                build_log!("World models unchanged since the last build");
+                return Ok(WorldBuildSummary {
+                    chunks_written: 0,
+                    textures_written: 0,
+                    files_removed: 0,
+                    ..manifest.summary.clone()
+                });
+            }
+        }
 
-        // Iterate all models and add their meshes to the world chunks
-        for model in self.models.iter() {
-            build_log!("Processing model {}", model.filename);
-            let (doc, buffer_data, image_data) = import_slice(model.data).unwrap();
-            let mut model_textures = HashMap::new();
-            for scene in doc.scenes() {
-                for n in scene.nodes() {
-                    self.walk_nodes(&Matrix4::identity(), &n, &buffer_data, &image_data, &mut world_mesh_count,
-                        &mut model_textures, None);
+        // Add what each model output to the world, importing the models that changed and loading
+        // the rest from their caches. A model's files need rewriting if it was imported, or if its
+        // meshes, entities or textures are numbered differently because of the models before it.
+        let models = self.models;
+        let mut model_records = Vec::with_capacity(models.len());
+        let mut cache_writes = Vec::new();
+        let mut affected_models = HashSet::new();
+        for (model_index, model) in models.iter().enumerate() {
+            self.current_model = model_index;
+            let old_record = old_manifest.as_ref()
+                .and_then(|manifest| manifest.model(model.filename()))
+                .filter(|old| old.hash == model_hashes[model_index]);
+
+            let cached = old_record.and_then(|old| self.load_model_output(old).map(|output| (output, old.cache_hash)));
+            let reused = cached.is_some();
+            let (output, cache_hash) = match cached {
+                Some(cached) => cached,
+                None => {
+                    build_log!("Processing model {}", model.filename);
+                    let output = self.process_model(model);
+                    let data = output.write_to_vec().map_err(|err| WorldBuildErrors::file(
+                        &self.out_dir.join(WorldBuildModel::cache_filename(model.filename())),
+                        format!("Failed to serialize model output: {err}")))?;
+                    let cache_hash = hash_contents(&data);
+                    cache_writes.push((WorldBuildModel::cache_filename(model.filename()), data));
+                    (output, cache_hash)
                 }
+            };
+
+            let record = self.add_model_output(model_index, output, model_hashes[model_index], cache_hash);
+            if !reused || !old_record.map(|old| old.same_numbering(&record)).unwrap_or(false) {
+                affected_models.insert(model.filename().to_string());
             }
+            model_records.push(record);
         }
 
-        // Write chunks
-        for ((x, z), chunk) in self.chunks.iter() {
-            let chunk_filename = WorldChunk::filename((*x, *z));
-            let chunk_path = Path::new(self.out_dir).join(chunk_filename);
-            chunk.write_to_file(chunk_path).unwrap();
+        // Models that were removed affect the files they used to contribute to
+        if let Some(manifest) = &old_manifest {
+            affected_models.extend(manifest.models
+                .iter()
+                .filter(|old| !models.iter().any(|model| model.filename() == old.filename))
+                .map(|old| old.filename.clone()));
         }
 
-        // Write textures
+        // Don't write a partial world if anything went wrong
+        if !self.errors.is_empty() {
+            return Err(WorldBuildErrors(std::mem::take(&mut self.errors)));
+        }
+
+        for (filename, data) in cache_writes {
+            let path = self.out_dir.join(filename);
+            std::fs::write(&path, data).map_err(|err| WorldBuildErrors::file(&path, format!("Failed to write: {err}")))?;
+        }
+
+        // Merge the meshes in each chunk by material, atlasing their textures first if we're asked
+        // to so that more of them share a material
+        if self.atlas {
+            self.atlas_textures();
+            self.remove_unused_textures();
+        }
+        self.batch_meshes();
+
+        let mut summary = WorldBuildSummary {
+            chunks: self.chunks.len(),
+            meshes: self.mesh_count as usize,
+            batches: self.chunks.values().map(|chunk| chunk.meshes().len()).sum(),
+            textures: self.textures.len(),
+            entities: self.entity_count as usize,
+            ..WorldBuildSummary::default()
+        };
+        let mut files = Vec::new();
+
+        // Write the chunks that affected models contribute to, in order so that the manifest is the
+        // same for the same models
+        let mut chunk_indices: Vec<ChunkIndex> = self.chunks.keys().copied().collect();
+        chunk_indices.sort();
+        for chunk_index in chunk_indices {
+            let filename = WorldChunk::filename(chunk_index);
+            let models = &self.chunk_models[&chunk_index];
+            let file = match self.unaffected_file(&filename, models, old_manifest.as_ref(), &affected_models) {
+                Some(file) => file,
+                None => {
+                    let data = self.chunks[&chunk_index].write_to_file_data(chunk_index, self.chunk_height)
+                        .map_err(|err| WorldBuildErrors::file(&self.out_dir.join(&filename), format!("Failed to serialize: {err}")))?;
+                    let file = self.build_file(filename, &data, models);
+                    if self.write_if_changed(&file, &data, old_manifest.as_ref())? {
+                        summary.chunks_written += 1;
+                    }
+                    file
+                }
+            };
+            files.push(file);
+        }
+
+        // Write the textures that affected models contribute to
         for (i, tex) in self.textures.iter().enumerate() {
-            let texture_filename = WorldTexture::filename(i as TextureIndex);
-            let texture_path = Path::new(self.out_dir).join(texture_filename);
-            tex.write_to_file(texture_path).unwrap();
+            let filename = WorldTexture::filename(i as TextureIndex);
+            let models = &self.texture_models[i];
+            let file = match self.unaffected_file(&filename, models, old_manifest.as_ref(), &affected_models) {
+                Some(file) => file,
+                None => {
+                    let data = tex.write_to_file_data(self.chunk_height)
+                        .map_err(|err| WorldBuildErrors::file(&self.out_dir.join(&filename), format!("Failed to serialize: {err}")))?;
+                    let file = self.build_file(filename, &data, models);
+                    if self.write_if_changed(&file, &data, old_manifest.as_ref())? {
+                        summary.textures_written += 1;
+                    }
+                    file
+                }
+            };
+            files.push(file);
+        }
+
+        // Remove chunks, textures and model caches that are no longer built from any model
+        let caches: Vec<String> = model_records.iter().map(|model| WorldBuildModel::cache_filename(&model.filename)).collect();
+        summary.files_removed = self.remove_stale_files(&files, &caches)?;
+
+        let instance_collider_reach = self.chunks
+            .iter()
+            .map(|(chunk_index, chunk)| chunk.instance_collider_reach(*chunk_index, self.chunk_height))
+            .fold(0.0, f32::max);
+
+        WorldBuildManifest::new(model_records, instance_model_hashes, self.atlas, self.chunk_height,
+            instance_collider_reach, files, summary.clone())
+            .save(&manifest_path)
+            .map_err(|err| WorldBuildErrors::file(&manifest_path, format!("Failed to write: {err}")))?;
+
+        Ok(summary)
+    }
+
+    /// Put the small textures used by each chunk into atlases, and point the meshes that use them at
+    /// the atlases instead, remapping their texture coordinates
+    fn atlas_textures(&mut self) {
+        // Go through the chunks in order, so that the atlases are numbered the same for the same models
+        let mut chunk_indices: Vec<ChunkIndex> = self.chunks.keys().copied().collect();
+        chunk_indices.sort();
+
+        for chunk_index in chunk_indices {
+            let mut chunk = match self.chunks.remove(&chunk_index) {
+                Some(chunk) => chunk,
+                None => continue
+            };
+
+            let mut texture_indices: Vec<TextureIndex> = chunk.meshes()
+                .iter()
+                .filter(|mesh| self.can_atlas_mesh(mesh))
+                .filter_map(|mesh| mesh.material().as_ref().and_then(|material| material.base_color_tex()))
+                .map(|tex| tex.texture())
+                .collect();
+            texture_indices.sort();
+            texture_indices.dedup();
+
+            // An atlas of one texture wouldn't let any more meshes be merged
+            if texture_indices.len() > 1 {
+                let textures: Vec<&WorldTexture> = texture_indices.iter().map(|i| &self.textures[*i as usize]).collect();
+                for atlas in texture_atlas::build_atlases(&textures) {
+                    let atlas_index = self.add_atlas_texture(atlas.pixels, atlas.rects.keys());
+
+                    for mesh in chunk.meshes_mut() {
+                        let rect = mesh.material().as_ref()
+                            .and_then(|material| material.base_color_tex())
+                            .and_then(|tex| atlas.rects.get(&tex.texture()));
+
+                        if let Some(rect) = rect.copied() {
+                            if self.can_atlas_mesh(mesh) {
+                                Self::move_mesh_to_atlas(mesh, atlas_index, &rect);
+                            }
+                        }
+                    }
+                }
+            }
+
+            self.chunks.insert(chunk_index, chunk);
+        }
+    }
+
+    /// Get whether a mesh's texture can be put in an atlas, which it can be if it's the mesh's only
+    /// texture, isn't mipmapped or transformed, and the mesh doesn't need it to repeat
+    fn can_atlas_mesh(&self, mesh: &WorldChunkMesh) -> bool {
+        let material = match mesh.material() {
+            Some(material) => material,
+            None => return false
+        };
+
+        let tex = match material.base_color_tex() {
+            Some(tex) => tex,
+            None => return false
+        };
+
+        let unfiltered_or_linear = |filter: u32| filter == gl::NEAREST || filter == gl::LINEAR;
+
+        // Allow a little slack for texture coordinates that are meant to be exactly on the edge
+        const UV_EPSILON: f32 = 0.001;
+        let uvs_in_range = mesh.vertices()
+            .chunks_exact(VERTEX_STRIDE)
+            .all(|v| (-UV_EPSILON..=1.0 + UV_EPSILON).contains(&v[6]) && (-UV_EPSILON..=1.0 + UV_EPSILON).contains(&v[7]));
+
+        material.emissive_tex().is_none()
+            && material.alpha_mode() != WorldChunkAlphaMode::Blend
+            && *tex.transform() == WorldChunkTextureTransform::default()
+            && unfiltered_or_linear(tex.sampler().min_filter)
+            && unfiltered_or_linear(tex.sampler().mag_filter)
+            && self.textures.get(tex.texture() as usize).map(texture_atlas::can_atlas).unwrap_or(false)
+            && uvs_in_range
+    }
+
+    /// Add an atlas to the world textures, unless an identical one's already been added, and return
+    /// its index. The atlas is built from the models that contributed to each of its textures.
+    fn add_atlas_texture<'b>(&mut self, pixels: Vec<u8>, textures: impl Iterator<Item=&'b TextureIndex>) -> TextureIndex {
+        let models: BTreeSet<usize> = textures
+            .flat_map(|i| self.texture_models[*i as usize].iter().copied())
+            .collect();
+
+        let atlas_hash = hash_contents(&pixels);
+        let index = *self.atlas_hashes
+            .entry(atlas_hash)
+            .or_insert_with(|| {
+                let idx = self.textures.len();
+                self.textures.push(WorldTexture::new(WorldTextureData::Rgba8(pixels), ATLAS_SIZE, ATLAS_SIZE,
+                    idx as TextureIndex));
+                self.texture_models.push(BTreeSet::new());
+                idx
+            });
+
+        self.texture_models[index].extend(models);
+        index as TextureIndex
+    }
+
+    /// Point a mesh's material at an atlas and remap its texture coordinates to the texture's place
+    /// in it. The atlas is clamped, as textures that need repeating can't be atlased anyway.
+    fn move_mesh_to_atlas(mesh: &mut WorldChunkMesh, atlas_index: TextureIndex, rect: &AtlasRect) {
+        for v in mesh.vertices_mut().chunks_exact_mut(VERTEX_STRIDE) {
+            let (u, v_coord) = rect.map_uv(f32::clamp(v[6], 0.0, 1.0), f32::clamp(v[7], 0.0, 1.0));
+            v[6] = u;
+            v[7] = v_coord;
+        }
+
+        if let Some(material) = mesh.material_mut() {
+            if let Some(sampler) = material.base_color_tex().map(|tex| *tex.sampler()) {
+                let sampler = WorldChunkSampler {
+                    wrap_s: gl::CLAMP_TO_EDGE,
+                    wrap_t: gl::CLAMP_TO_EDGE,
+                    ..sampler
+                };
+                material.set_base_color_tex(Some(WorldChunkTextureRef::new(atlas_index, sampler,
+                    WorldChunkTextureTransform::default())));
+            }
         }
     }
 
+    /// Remove the textures that no chunk uses any more because they were all put in atlases, and
+    /// renumber the rest
+    fn remove_unused_textures(&mut self) {
+        let used: HashSet<TextureIndex> = self.chunks
+            .values()
+            .flat_map(|chunk| chunk.meshes().iter())
+            .filter_map(|mesh| mesh.material().as_ref())
+            .flat_map(|material| material.textures())
+            .collect();
+
+        let textures = std::mem::take(&mut self.textures);
+        let texture_models = std::mem::take(&mut self.texture_models);
+
+        let mut remap = HashMap::new();
+        for (mut texture, models) in textures.into_iter().zip(texture_models) {
+            if used.contains(&texture.index()) {
+                let new_index = self.textures.len() as TextureIndex;
+                remap.insert(texture.index(), new_index);
+                texture.set_index(new_index);
+                self.textures.push(texture);
+                self.texture_models.push(models);
+            }
+        }
+
+        for chunk in self.chunks.values_mut() {
+            for mesh in chunk.meshes_mut() {
+                if let Some(material) = mesh.material_mut() {
+                    material.remap_textures(|index| remap[&index]);
+                }
+            }
+        }
+    }
+
+    /// Merge the meshes in each chunk that have the same material, so that each chunk can be drawn
+    /// in a handful of draw calls. Alpha blended meshes aren't merged, so that they can still be
+    /// sorted back to front.
+    fn batch_meshes(&mut self) {
+        for chunk in self.chunks.values_mut() {
+            let mut batches: Vec<Vec<WorldChunkMesh>> = Vec::new();
+
+            for mesh in chunk.take_meshes() {
+                let blended = mesh.material()
+                    .as_ref()
+                    .map(|material| material.alpha_mode() == WorldChunkAlphaMode::Blend)
+                    .unwrap_or(false);
+
+                let batch = match blended {
+                    true => None,
+                    false => batches.iter_mut().find(|batch| batch[0].material() == mesh.material())
+                };
+
+                match batch {
+                    Some(batch) => batch.push(mesh),
+                    None => batches.push(vec![mesh])
+                }
+            }
+
+            for batch in batches {
+                chunk.add_mesh(WorldChunkMesh::merge(batch));
+            }
+        }
+    }
+
+    /// Record an error in the current model
+    fn add_error(&mut self, node: Option<&Node>, reason: String) {
+        self.errors.push(WorldBuildError {
+            model: self.models[self.current_model].filename.to_string(),
+            node: node.map(|node| node.name().unwrap_or("no-name").to_string()),
+            reason,
+        });
+    }
+
+    /// Import a model and walk its nodes, returning what it output
+    fn process_model(&mut self, model: &WorldModel) -> WorldModelOutput {
+        match import_slice(&model.data) {
+            Ok((doc, buffer_data, image_data)) => {
+                let mut model_textures = HashMap::new();
+                for scene in doc.scenes() {
+                    for n in scene.nodes() {
+                        self.walk_nodes(&Matrix4::identity(), &n, &buffer_data, &image_data, &mut model_textures, None);
+                    }
+                }
+            }
+            Err(err) => self.add_error(None, format!("Failed to import model: {err}"))
+        }
+
+        std::mem::take(&mut self.output)
+    }
+
+    /// Load what a model output in the last build from its cache, if it's there and intact
+    fn load_model_output(&self, model: &WorldBuildModel) -> Option<WorldModelOutput> {
+        let data = std::fs::read(self.out_dir.join(WorldBuildModel::cache_filename(&model.filename))).ok()?;
+        if hash_contents(&data) != model.cache_hash {
+            return None;
+        }
+        WorldModelOutput::read_from_buffer(&data).ok()
+    }
+
+    /// Add what a model output to the world, numbering its meshes, entities and textures after those
+    /// of the models before it, and return the manifest record for the model
+    fn add_model_output(&mut self, model_index: usize, output: WorldModelOutput, hash: u64, cache_hash: u64)
+        -> WorldBuildModel
+    {
+        let first_mesh = self.mesh_count;
+        let first_entity = self.entity_count;
+        self.mesh_count += output.mesh_count;
+        self.entity_count += output.entity_count;
+
+        // Add the textures that aren't already in the world with the same settings
+        let textures: Vec<TextureIndex> = output.textures
+            .into_iter()
+            .map(|(image_hash, settings, mut texture)| {
+                let index = *self.texture_hashes
+                    .entry((image_hash, settings))
+                    .or_insert_with(|| {
+                        let idx = self.textures.len();
+                        texture.set_index(idx as TextureIndex);
+                        self.textures.push(texture);
+                        self.texture_models.push(BTreeSet::new());
+                        idx
+                    });
+
+                self.texture_models[index].insert(model_index);
+                index as TextureIndex
+            })
+            .collect();
+
+        for (chunk_index, mut chunk) in output.chunks {
+            for mesh in chunk.meshes_mut() {
+                mesh.set_index(first_mesh + mesh.index());
+                if let Some(material) = mesh.material_mut() {
+                    material.remap_textures(|index| textures[index as usize]);
+                }
+            }
+            for entity in chunk.entities_mut() {
+                entity.set_entity_id(first_entity + entity.entity_id());
+            }
+
+            self.chunk_models
+                .entry(chunk_index)
+                .or_insert_with(BTreeSet::new)
+                .insert(model_index);
+
+            self.chunks
+                .entry(chunk_index)
+                .or_insert_with(WorldChunk::new)
+                .append(chunk);
+        }
+
+        WorldBuildModel {
+            filename: self.models[model_index].filename().to_string(),
+            hash,
+            cache_hash,
+            first_mesh,
+            first_entity,
+            textures,
+        }
+    }
+
+    /// Create the manifest record for an output file
+    fn build_file(&self, filename: String, data: &[u8], models: &BTreeSet<usize>) -> WorldBuildFile {
+        WorldBuildFile {
+            filename,
+            content_hash: hash_contents(data),
+            models: models.iter().map(|i| self.models[*i].filename.to_string()).collect(),
+        }
+    }
+
+    /// Get the record of an output file from the last build if it can be kept as it is, which it can
+    /// be if it's still there, textures weren't atlased, and it's built from the same models as last
+    /// time, none of which are affected by this build
+    fn unaffected_file(&self, filename: &str, models: &BTreeSet<usize>, old_manifest: Option<&WorldBuildManifest>,
+        affected_models: &HashSet<String>) -> Option<WorldBuildFile>
+    {
+        let old_file = old_manifest
+            .filter(|manifest| !manifest.atlas && !self.atlas)
+            .and_then(|manifest| manifest.file(filename))?;
+
+        let same_models = old_file.models.iter().map(String::as_str).eq(models.iter().map(|i| self.models[*i].filename()));
+        let unaffected = same_models
+            && old_file.models.iter().all(|model| !affected_models.contains(model))
+            && self.out_dir.join(filename).is_file();
+
+        unaffected.then(|| old_file.clone())
+    }
+
+    /// Write an output file, unless it already exists and its contents haven't changed since the
+    /// last build. Returns whether it was written.
+    fn write_if_changed(&self, file: &WorldBuildFile, data: &[u8], old_manifest: Option<&WorldBuildManifest>)
+        -> Result<bool, WorldBuildErrors>
+    {
+        let path = self.out_dir.join(&file.filename);
+
+        let unchanged = path.is_file() && old_manifest
+            .and_then(|manifest| manifest.file(&file.filename))
+            .map(|old_file| old_file == file)
+            .unwrap_or(false);
+
+        if !unchanged {
+            std::fs::write(&path, data).map_err(|err| WorldBuildErrors::file(&path, format!("Failed to write: {err}")))?;
+        }
+
+        Ok(!unchanged)
+    }
+
+    /// Remove any chunk, texture and model cache files in the output directory that weren't part of
+    /// this build, returning the number removed
+    fn remove_stale_files(&self, files: &[WorldBuildFile], caches: &[String]) -> Result<usize, WorldBuildErrors> {
+        let built: HashSet<&str> = files
+            .iter()
+            .map(|file| file.filename.as_str())
+            .chain(caches.iter().map(String::as_str))
+            .collect();
+        let mut removed = 0;
+
+        let entries = std::fs::read_dir(&self.out_dir)
+            .map_err(|err| WorldBuildErrors::file(&self.out_dir, format!("Failed to read output directory: {err}")))?;
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let filename = entry.file_name().to_string_lossy().into_owned();
+            let is_output = filename.ends_with(".chunk") || filename.ends_with(".texture") || filename.ends_with(".model");
+            if is_output && !built.contains(filename.as_str()) {
+                build_log!("Removing stale file {}", filename);
+                std::fs::remove_file(entry.path())
+                    .map_err(|err| WorldBuildErrors::file(&entry.path(), format!("Failed to remove: {err}")))?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+
     /// Walk model hierarchy, adding geometry to chunks
     fn walk_nodes(&mut self, parent_world_transform: &Matrix4<f32>, node: &Node, buffers: &[buffer::Data],
-        image_data: &[image::Data], world_mesh_count: &mut i32, model_textures: &mut HashMap<usize, i32>,
-        parent_node_extras: Option<&Box<RawValue>>)
+        image_data: &[image::Data], model_textures: &mut ModelTextures, parent_node_extras: Option<&Box<RawValue>>)
     {
         let local_transform = cgmath::Matrix4::from(node.transform().matrix());
         let world_transform = parent_world_transform * local_transform;
@@ -137,303 +849,466 @@ impl WorldBuilder {
         // Pass down extras until they're replaced so they inherit.. This allows us to read a
         // blender node's custom properties when we're on the mesh node.
         let node_extras = node.extras().as_ref().or(parent_node_extras);
-        let node_extras_parsed: Option<WorldNodeExtras> = node_extras.as_ref().map(|extras| {
-            serde_json::from_str(extras.get()).unwrap()
-        });
+        let node_extras_parsed: Option<WorldNodeExtras> = match node_extras.map(|extras| serde_json::from_str(extras.get())) {
+            Some(Ok(extras)) => Some(extras),
+            Some(Err(err)) => {
+                self.add_error(Some(node), format!("Invalid extras: {err}"));
+                None
+            }
+            None => None
+        };
 
         if let Some(mesh) = node.mesh() {
             for prim in mesh.primitives() {
                 // Either load the primitive as point instances, or as a regular mesh
-                let node_type = node_extras_parsed.as_ref().map(|e| e.node_type.clone()).flatten();
-                if let Some(node_type) = node_type.as_ref() {
-                    if node_type == "instances" {
-                        let instance_mesh = node_extras_parsed.as_ref()
-                            .map(|e| e.instance_mesh.clone())
-                            .flatten()
-                            .expect(&format!("Node {} with node_type = instances must have instance_mesh",
-                                node.name().unwrap_or("no-name")));
-
-                        self.add_instances(&prim, &world_transform, instance_mesh, &buffers);
+                let node_type = node_extras_parsed.as_ref().and_then(|e| e.node_type.clone());
+                let result = match node_type.as_deref() {
+                    Some("instances") => {
+                        match node_extras_parsed.as_ref().and_then(|e| e.instance_mesh.clone()) {
+                            Some(instance_mesh) => self.instance_collider(&instance_mesh, node_extras_parsed.as_ref())
+                                .and_then(|collider| {
+                                    self.add_instances(&prim, &world_transform, instance_mesh, collider, &buffers)
+                                }),
+                            None => Err("node_type = instances must have instance_mesh".to_string())
+                        }
                     }
-                    else if node_type == "entity" {
-                        let object_id = node_extras_parsed.as_ref()
-                            .map(|e| e.object_id.clone())
-                            .flatten()
-                            .expect(&format!("Node {} with node_type = entity must have object_id",
-                                node.name().unwrap_or("no-name")));
-
-                        self.add_entity(&prim, &world_transform, object_id, &buffers, node_extras);
+                    Some("entity") => {
+                        match node_extras_parsed.as_ref().and_then(|e| e.object_id.clone()) {
+                            Some(object_id) => self.add_entity(&prim, &world_transform, object_id, &buffers, node_extras),
+                            None => Err("node_type = entity must have object_id".to_string())
+                        }
                     }
-                }
-                else {
-                    let material = self.load_material(&prim.material(), model_textures, image_data);
-                    self.add_mesh(&node, &prim, &buffers, &world_transform, world_mesh_count, material);
+                    Some(node_type) => {
+                        // Blender nodes can have custom properties for other tools, so skip ones
+                        // we don't know about rather than failing the whole build
+                        build_log!("{}: node {}: Unknown node_type {node_type}, skipping it",
+                            self.models[self.current_model].filename, node.name().unwrap_or("no-name"));
+                        Ok(())
+                    }
+                    None => {
+                        Self::mesh_usage(node_extras_parsed.as_ref())
+                            .and_then(|(render, collision_layer)| {
+                                let material = match render {
+                                    true => Some(self.load_material(&prim.material(), model_textures, image_data)?),
+                                    false => None
+                                };
+                                self.add_mesh(&node, &prim, &buffers, &world_transform, material, collision_layer)
+                            })
+                    }
+                };
+
+                if let Err(reason) = result {
+                    self.add_error(Some(node), reason);
                 }
             }
         }
 
         for child in node.children() {
-            self.walk_nodes(&world_transform, &child, &buffers, &image_data, world_mesh_count, model_textures,
-                node_extras);
+            self.walk_nodes(&world_transform, &child, &buffers, &image_data, model_textures, node_extras);
         }
     }
 
-    /// Load a gltf material to a WorldChunkMaterial, and load any texture data, deduplicating it if possible
-    fn load_material(&mut self, material: &gltf::Material, model_textures: &mut HashMap<usize, i32>,
-        image_data: &[image::Data]) -> WorldChunkMaterial
-    {
-        let pbr = material.pbr_metallic_roughness();
-        let base_color = pbr.base_color_factor();
+    /// Get whether a mesh should be rendered, and the layer of its collision surfaces if it should
+    /// have any, from its node extras
+    fn mesh_usage(extras: Option<&WorldNodeExtras>) -> Result<(bool, Option<CollisionLayer>), String> {
+        let collision_layer = match extras.and_then(|e| e.collision_layer.as_deref()) {
+            Some(name) => CollisionLayer::from_name(name).ok_or_else(|| format!("Unknown collision_layer {name}"))?,
+            None => CollisionLayer::default()
+        };
+
+        match extras.and_then(|e| e.collision.as_deref()) {
+            Some("both") | None => Ok((true, Some(collision_layer))),
+            Some("render") => Ok((true, None)),
+            Some("collision") => Ok((false, Some(collision_layer))),
+            Some(collision) => Err(format!("Unknown collision {collision}, expected both, render or collision"))
+        }
+    }
 
-        // Get texture index, inserting it into the texture map if it's not already there
-        let texture = pbr.base_color_texture()
-            .map(|tex_info| {
-                let image = tex_info.texture().source();
-                let data = &image_data[image.index()];
+    /// Get the collider for the instances of an instances node from its extras, fitting it to the
+    /// bounds of the instance mesh's model if the extras don't give any
+    fn instance_collider(&mut self, instance_mesh: &str, extras: Option<&WorldNodeExtras>)
+        -> Result<Option<WorldChunkInstanceCollider>, String>
+    {
+        let kind = extras.and_then(|e| e.instance_collision.as_deref()).unwrap_or("box");
+        if kind == "none" {
+            return Ok(None);
+        }
 
-                *model_textures
-                    .entry(image.index())
-                    .or_insert_with(|| {
-                        // Hash image data to check if it's already in the world textures
-                        let mut s = DefaultHasher::new();
-                        data.pixels.hash(&mut s);
-                        let image_hash = s.finish();
-
-                        let texture_index = *self.texture_hashes
-                            .entry(image_hash)
-                            .or_insert_with(|| {
-                                let idx = self.textures.len();
-                                let pixels = data.pixels.clone();
-                                let gl_format = Self::format_to_gl_enum(data.format);
-                                let texture = WorldTexture::new(pixels, gl_format, data.width, data.height, idx as TextureIndex);
-                                self.textures.push(texture);
-                                idx
-                            });
-
-                        texture_index as i32
-                    })
-            });
+        let (min, max) = match (extras.and_then(|e| e.instance_collision_min), extras.and_then(|e| e.instance_collision_max)) {
+            (Some(min), Some(max)) => (Vector3::from(min), Vector3::from(max)),
+            (None, None) => self.instance_model_bounds(instance_mesh)?,
+            _ => return Err("instance_collision_min and instance_collision_max must be given together".to_string())
+        };
 
-        WorldChunkMaterial::new(WrappedVector4(vec4(base_color[0], base_color[1], base_color[2], base_color[3])), texture)
+        WorldChunkInstanceCollider::fit(kind, &min, &max)
+            .map(Some)
+            .ok_or_else(|| format!("Unknown instance_collision {kind}, expected box, spheroid, capsule or none"))
     }
 
-    /// Convert gltf image format to a gl enum
-    fn format_to_gl_enum(format: Format) -> u32 {
-        match format {
-            Format::R8 => gl::RED,
-            Format::R8G8 => gl::RG,
-            Format::R8G8B8 => gl::RGB,
-            Format::R8G8B8A8 => gl::RGBA,
-            Format::R16 => gl::R16,
-            Format::R16G16 => gl::RG16,
-            Format::R16G16B16 => gl::RGB16,
-            Format::R16G16B16A16 => gl::RGBA16,
-            Format::B8G8R8 => gl::BGR,
-            Format::B8G8R8A8 => gl::BGRA
+    /// Get the bounds of the meshes in an instance model, loading it if it hasn't been used yet
+    fn instance_model_bounds(&mut self, instance_mesh: &str) -> Result<(Vector3<f32>, Vector3<f32>), String> {
+        if let Some(bounds) = self.instance_bounds.get(instance_mesh) {
+            return Ok(*bounds);
         }
+
+        let instance_model = self.instance_models
+            .iter()
+            .find(|instance| instance.name() == instance_mesh)
+            .ok_or_else(|| format!("No instance model for {instance_mesh} to fit a collider to, give \
+                instance_collision_min and instance_collision_max or set instance_collision = none"))?;
+
+        let (doc, buffer_data, _) = import_slice(&instance_model.model.data)
+            .map_err(|err| format!("Failed to import instance model {}: {err}", instance_model.model.filename))?;
+
+        let mut aabb = Aabb::new();
+        for scene in doc.scenes() {
+            for n in scene.nodes() {
+                Self::expand_aabb_with_node(&mut aabb, &Matrix4::identity(), &n, &buffer_data)
+                    .map_err(|err| format!("Failed to read instance model {}: node {}: {err}",
+                        instance_model.model.filename, n.name().unwrap_or("no-name")))?;
+            }
+        }
+
+        let bounds = aabb.min_max()
+            .map(|(min, max)| (*min, *max))
+            .ok_or_else(|| format!("Instance model {} has no meshes to fit a collider to", instance_model.model.filename))?;
+
+        self.instance_bounds.insert(instance_mesh.to_string(), bounds);
+        Ok(bounds)
     }
 
-    /// Add a gltf primitive to the world as a WorldChunkMesh
-    fn add_mesh(&mut self, node: &gltf::Node, prim: &gltf::Primitive, buffers: &[buffer::Data],
-        world_transform: &Matrix4<f32>, world_mesh_count: &mut i32, material: WorldChunkMaterial)
+    /// Expand an aabb with the positions of a node's meshes and its children's meshes
+    fn expand_aabb_with_node(aabb: &mut Aabb, parent_transform: &Matrix4<f32>, node: &Node, buffers: &[buffer::Data])
+        -> Result<(), String>
     {
-        // Read indices for mesh
-        let indices = prim.indices().map(|accessor| {
-            let buffer_view = accessor.view().unwrap();
-            let buffer_index = buffer_view.buffer().index();
+        let transform = parent_transform * Matrix4::from(node.transform().matrix());
 
-            let buffer = &buffers[buffer_index];
-
-            if accessor.data_type() != gltf::accessor::DataType::U16 {
-                panic!("not u16 mesh indices: {:?}", accessor.data_type());
+        if let Some(mesh) = node.mesh() {
+            for prim in mesh.primitives() {
+                let positions = prim.attributes()
+                    .find(|attrib| attrib.0 == Semantic::Positions)
+                    .map(|(_, accessor)| gltf_accessor::read_f32(&accessor, buffers))
+                    .transpose()?
+                    .unwrap_or_default();
+
+                for v in positions.chunks_exact(3) {
+                    aabb.expand_with_point(&(transform * vec4(v[0], v[1], v[2], 1.0)).truncate());
+                }
             }
-            let data_type_size = std::mem::size_of::<u16>();
+        }
 
-            let offset = buffer_view.offset();
-            let length_bytes = buffer_view.length();
-            let length_elements = length_bytes / data_type_size;
+        for child in node.children() {
+            Self::expand_aabb_with_node(aabb, &transform, &child, buffers)?;
+        }
 
-            let mut indices = vec![0; length_elements];
-            let mut slice = &buffer[offset..offset+length_bytes];
-            slice.read_u16_into::<LittleEndian>(&mut indices).expect("Failed!");
+        Ok(())
+    }
 
-            indices
-        });
+    /// Load a gltf material to a WorldChunkMaterial, and load any texture data, deduplicating it if possible
+    fn load_material(&mut self, material: &gltf::Material, model_textures: &mut ModelTextures,
+        image_data: &[image::Data]) -> Result<WorldChunkMaterial, String>
+    {
+        let pbr = material.pbr_metallic_roughness();
+        let base_color = pbr.base_color_factor();
+        let emissive = material.emissive_factor();
+
+        let extras = Self::material_extras(material)?;
+        let texture_settings = Self::texture_settings(&extras)?;
+        let base_color_texture = pbr.base_color_texture()
+            .map(|tex_info| self.load_texture(&tex_info, &texture_settings, model_textures, image_data));
+        let emissive_texture = material.emissive_texture()
+            .map(|tex_info| self.load_texture(&tex_info, &texture_settings, model_textures, image_data));
+
+        let alpha_mode = match material.alpha_mode() {
+            gltf::material::AlphaMode::Opaque => WorldChunkAlphaMode::Opaque,
+            gltf::material::AlphaMode::Mask => WorldChunkAlphaMode::Mask,
+            gltf::material::AlphaMode::Blend => WorldChunkAlphaMode::Blend,
+        };
 
-        // Read vertex attributes for mesh
-        let attribs = prim.attributes()
-            .map(|(semantic, accessor)| {
-                let buffer_view  = accessor.view().unwrap();
-                let buffer_index = buffer_view.buffer().index();
+        // The gltf default cutoff is 0.5
+        let alpha_cutoff = material.alpha_cutoff().unwrap_or(0.5);
 
-                let buffer = &buffers[buffer_index];
+        Ok(WorldChunkMaterial::new(WrappedVector4(vec4(base_color[0], base_color[1], base_color[2], base_color[3])),
+                base_color_texture)
+            .with_emissive(WrappedVector3(vec3(emissive[0], emissive[1], emissive[2])), emissive_texture)
+            .with_alpha_mode(alpha_mode, alpha_cutoff)
+            .with_double_sided(material.double_sided())
+            .with_vertex_colors(extras.vertex_colors.unwrap_or(true)))
+    }
 
-                let attrib_stride = buffer_view.stride().unwrap_or(0) as i32;
-                if attrib_stride != 0 {
-                    panic!("unhandled");
-                }
+    /// Parse a material's extras
+    fn material_extras(material: &gltf::Material) -> Result<WorldMaterialExtras, String> {
+        match material.extras() {
+            Some(extras) => serde_json::from_str(extras.get())
+                .map_err(|err| format!("Invalid extras for material {}: {err}", material.name().unwrap_or("unnamed"))),
+            None => Ok(Default::default())
+        }
+    }
 
-                // Assuming that it's always gl::FLOAT but I might be wrong
-                let data_size_type = std::mem::size_of::<f32>();
+    /// Get the texture settings for a material from its extras
+    fn texture_settings(extras: &WorldMaterialExtras) -> Result<WorldTextureSettings, String> {
+        let format = match extras.texture_format.as_deref() {
+            Some(name) => WorldTextureFormat::from_name(name)
+                .ok_or_else(|| format!("Unknown texture_format {name}"))?,
+            None => WorldTextureFormat::default()
+        };
 
-                let offset = buffer_view.offset();
-                let length_bytes = buffer_view.length();
-                let length_elements = length_bytes / data_size_type;
+        if extras.texture_max_size == Some(0) {
+            return Err("texture_max_size must be at least 1".to_string());
+        }
 
-                let mut vertices = vec![0.0; length_elements];
-                let mut slice = &buffer[offset..offset+length_bytes];
-                slice.read_f32_into::<LittleEndian>(&mut vertices).expect("Failed!");
+        Ok(WorldTextureSettings {
+            format,
+            max_size: extras.texture_max_size,
+        })
+    }
 
-                (semantic, vertices)
-            })
-        .collect::<HashMap<Semantic, Vec<f32>>>();
+    /// Load a texture referenced by a material, along with its sampler and transform, processing its
+    /// image and inserting it into the world textures if it's not already there
+    fn load_texture(&mut self, tex_info: &gltf::texture::Info, settings: &WorldTextureSettings,
+        model_textures: &mut ModelTextures, image_data: &[image::Data]) -> WorldChunkTextureRef
+    {
+        let image = tex_info.texture().source();
+        let data = &image_data[image.index()];
+
+        let texture_index = *model_textures
+            .entry((image.index(), *settings))
+            .or_insert_with(|| {
+                // Hash image data to check if the model already has it with these settings, and so
+                // that it can be deduplicated with the other models' textures
+                let image_hash = hash_contents(&data.pixels);
+
+                let textures = &mut self.output.textures;
+                let existing = textures.iter().position(|(hash, s, _)| *hash == image_hash && s == settings);
+                let texture_index = existing.unwrap_or_else(|| {
+                    let idx = textures.len();
+                    let (pixels, width, height) = texture_pipeline::process_texture(data, settings);
+                    textures.push((image_hash, *settings, WorldTexture::new(pixels, width, height, idx as TextureIndex)));
+                    idx
+                });
+
+                texture_index as i32
+            });
 
-        // Enforce that we now have indices and vertices, if we want to support non-indexed
-        // meshes (which are uncommon and blender's gltf exporter doesn't output them),
-        // we'll have to change this
-        let indices = indices.expect(&format!("Mesh {} must have indices", node.name().unwrap_or("no-name")));
-        let (vertices, aabb) = Self::build_mesh_vertices(&attribs, &world_transform);
+        // Unspecified filters are left unfiltered, for that crunchy look
+        let sampler = tex_info.texture().sampler();
+        let default_sampler = WorldChunkSampler::default();
+        let sampler = WorldChunkSampler {
+            wrap_s: sampler.wrap_s().as_gl_enum(),
+            wrap_t: sampler.wrap_t().as_gl_enum(),
+            min_filter: sampler.min_filter().map(|filter| filter.as_gl_enum()).unwrap_or(default_sampler.min_filter),
+            mag_filter: sampler.mag_filter().map(|filter| filter.as_gl_enum()).unwrap_or(default_sampler.mag_filter),
+        };
 
-        // Add the mesh to each chunk that the mesh overlaps
-        if let Some((min, max)) = aabb.min_max().map(|(a, b)| (a.clone(), b.clone())) {
-            // Get the min and max
-            let (chunk_x_min, chunk_z_min) = WorldChunk::point_to_chunk_index(&min);
-            let (chunk_x_max, chunk_z_max) = WorldChunk::point_to_chunk_index(&max);
+        let transform = tex_info.texture_transform()
+            .map(|transform| WorldChunkTextureTransform {
+                offset: transform.offset(),
+                rotation: transform.rotation(),
+                scale: transform.scale(),
+            })
+            .unwrap_or_default();
+
+        WorldChunkTextureRef::new(texture_index, sampler, transform)
+    }
 
-            for x in chunk_x_min..=chunk_x_max {
-                for z in chunk_z_min..=chunk_z_max {
-                    let chunk = self.get_chunk((x, z));
+    /// Add a gltf primitive to the world as a WorldChunkMesh if it has a material, and as a
+    /// WorldChunkCollisionMesh if it has a collision layer
+    fn add_mesh(&mut self, node: &gltf::Node, prim: &gltf::Primitive, buffers: &[buffer::Data],
+        world_transform: &Matrix4<f32>, material: Option<WorldChunkMaterial>, collision_layer: Option<CollisionLayer>)
+        -> Result<(), String>
+    {
+        // Read indices for mesh, which can be u8, u16 or u32
+        let indices = prim.indices().map(|accessor| gltf_accessor::read_u32(&accessor, buffers)).transpose()?;
 
-                    let chunk_bounds_min = vec3(x as f32 * CHUNK_SIZE, -1000.0, z as f32 * CHUNK_SIZE);
-                    let chunk_bounds_max = vec3(chunk_bounds_min.x + CHUNK_SIZE, 1000.0, chunk_bounds_min.z + CHUNK_SIZE);
+        // Read vertex attributes for mesh, converting them all to f32
+        let attribs = prim.attributes()
+            .map(|(semantic, accessor)| Ok((semantic, gltf_accessor::read_f32(&accessor, buffers)?)))
+            .collect::<Result<HashMap<Semantic, Vec<f32>>, String>>()?;
+
+        // Non-indexed meshes are uncommon, and blender's gltf exporter doesn't output them, but
+        // they're easy enough to support by just indexing every vertex in order
+        let positions = attribs.get(&Semantic::Positions).ok_or("Mesh has no positions")?;
+        let indices = indices.unwrap_or_else(|| (0..(positions.len() / 3) as u32).collect());
+        if indices.len() % INDEX_STRIDE != 0 {
+            return Err(format!("Mesh has {} indices, which isn't a multiple of {INDEX_STRIDE}", indices.len()));
+        }
+        if let Some(index) = indices.iter().find(|i| **i as usize >= positions.len() / 3) {
+            return Err(format!("Mesh index {index} is out of range"));
+        }
 
-                    let chunk_mesh = Self::clip_mesh_to_aabb(&vertices, &indices, &chunk_bounds_min, &chunk_bounds_max,
-                        *world_mesh_count, &material);
+        let (vertices, aabb) = Self::build_mesh_vertices(node, &attribs, &indices, &world_transform)?;
 
-                    if let Some(mesh) = chunk_mesh {
-                        *world_mesh_count += 1;
-                        chunk.add_mesh(mesh.clone());
+        // Add the mesh to each chunk that the mesh overlaps
+        if let Some((min, max)) = aabb.min_max().map(|(a, b)| (a.clone(), b.clone())) {
+            let chunk_height = self.chunk_height;
+            for chunk_index in WorldChunk::chunks_between(&min, &max, chunk_height) {
+                let (chunk_bounds_min, chunk_bounds_max) = WorldChunk::chunk_bounds(chunk_index, chunk_height);
+                let clipped = Self::clip_mesh_to_aabb(&vertices, &indices, &chunk_bounds_min, &chunk_bounds_max)?;
+
+                if let Some((chunk_mesh_aabb, chunk_mesh_vertices, chunk_mesh_indices)) = clipped {
+                    let collision_mesh = collision_layer.and_then(|layer| {
+                        Self::build_collision_mesh(&chunk_mesh_vertices, &chunk_mesh_indices, layer)
+                    });
+
+                    let mesh_index = self.output.mesh_count;
+                    let chunk = self.get_chunk(chunk_index);
+                    if let Some(collision_mesh) = collision_mesh {
+                        chunk.add_collision_mesh(collision_mesh);
+                    }
+                    if let Some(material) = &material {
+                        chunk.add_mesh(WorldChunkMesh::new(chunk_mesh_aabb, mesh_index, chunk_mesh_vertices,
+                            WorldChunkIndices::new(chunk_mesh_indices), Some(material.clone())));
+                        self.output.mesh_count += 1;
                     }
                 }
             }
         }
-    }
 
-    /// Add instances
-    fn add_instances(&mut self, prim: &gltf::Primitive, world_transform: &Matrix4<f32>, mesh: String, buffers: &[buffer::Data]) {
-        let points: Vec<WrappedVector3> = prim.attributes()
-            .find(|attrib| attrib.0 == Semantic::Positions)
-            .map(|(_, accessor)| {
-                let buffer_view  = accessor.view().unwrap();
-                let buffer_index = buffer_view.buffer().index();
+        Ok(())
+    }
 
-                let buffer = &buffers[buffer_index];
+    /// Add instances. Each point can have a rotation and scale in the _ROTATION (a quaternion) and
+    /// _SCALE (a vec3, or a scalar for a uniform scale) attributes.
+    fn add_instances(&mut self, prim: &gltf::Primitive, world_transform: &Matrix4<f32>, mesh: String,
+        collider: Option<WorldChunkInstanceCollider>, buffers: &[buffer::Data]) -> Result<(), String>
+    {
+        let attribs = prim.attributes()
+            .map(|(semantic, accessor)| Ok((semantic, gltf_accessor::read_f32(&accessor, buffers)?)))
+            .collect::<Result<HashMap<Semantic, Vec<f32>>, String>>()?;
 
-                let attrib_stride = buffer_view.stride().unwrap_or(0) as i32;
-                if attrib_stride != 0 {
-                    panic!("unhandled");
-                }
+        let positions = attribs.get(&Semantic::Positions).ok_or("Instance mesh must have points")?;
+        let point_count = positions.len() / 3;
 
-                // Assuming that it's always gl::FLOAT but I might be wrong
-                let data_size_type = std::mem::size_of::<f32>();
+        let rotations = attribs.get(&Semantic::Extras("ROTATION".to_string()));
+        if let Some(rotations) = rotations {
+            if rotations.len() != point_count * 4 {
+                return Err(format!("Instance mesh has {} _ROTATION values, expected a quaternion for each of its \
+                    {point_count} points", rotations.len()));
+            }
+        }
 
-                let offset = buffer_view.offset();
-                let length_bytes = buffer_view.length();
-                let length_elements = length_bytes / data_size_type;
+        let scales = attribs.get(&Semantic::Extras("SCALE".to_string()));
+        let scale_stride = match scales.map(|scales| scales.len()) {
+            None => 0,
+            Some(len) if len == point_count * 3 => 3,
+            Some(len) if len == point_count => 1,
+            Some(len) => return Err(format!("Instance mesh has {len} _SCALE values, expected a vec3 or a scalar for \
+                each of its {point_count} points"))
+        };
 
-                let mut vertices = vec![0.0; length_elements];
-                let mut slice = &buffer[offset..offset+length_bytes];
-                slice.read_f32_into::<LittleEndian>(&mut vertices).expect("Failed!");
+        // Split the node's rotation and scale out of its transform so they can be combined with each
+        // point's. This is only exact if the node is scaled uniformly, which it nearly always is.
+        let node_scale = vec3(world_transform.x.truncate().magnitude(), world_transform.y.truncate().magnitude(),
+            world_transform.z.truncate().magnitude());
+        let node_rot = Quaternion::from(Matrix3::from_cols(
+            world_transform.x.truncate() / node_scale.x,
+            world_transform.y.truncate() / node_scale.y,
+            world_transform.z.truncate() / node_scale.z)).normalize();
 
-                vertices
-            })
-            .expect("Instance mesh must have points")
+        let points: Vec<WorldChunkInstancePoint> = positions
             .chunks_exact(3)
-            .map(|v| WrappedVector3((world_transform * vec4(v[0], v[1], v[2], 1.0)).truncate()))
+            .enumerate()
+            .map(|(i, v)| {
+                let pos = (world_transform * vec4(v[0], v[1], v[2], 1.0)).truncate();
+                let rot = match rotations {
+                    Some(r) => node_rot * Quaternion::new(r[i * 4 + 3], r[i * 4], r[i * 4 + 1], r[i * 4 + 2]).normalize(),
+                    None => node_rot
+                };
+                let scale = match (scales, scale_stride) {
+                    (Some(s), 3) => node_scale.mul_element_wise(vec3(s[i * 3], s[i * 3 + 1], s[i * 3 + 2])),
+                    (Some(s), _) => node_scale * s[i],
+                    (None, _) => node_scale
+                };
+                WorldChunkInstancePoint::new(pos, rot, scale)
+            })
             .collect();
 
         // Split points by chunk
-        let mut chunk_points = HashMap::<ChunkIndex, Vec<WrappedVector3>>::new();
+        let mut chunk_points = HashMap::<ChunkIndex, Vec<WorldChunkInstancePoint>>::new();
 
-        for point in points.iter() {
+        for point in points.into_iter() {
             chunk_points
-                .entry(WorldChunk::point_to_chunk_index(point.as_vec()))
+                .entry(WorldChunk::point_to_chunk_index(point.pos(), self.chunk_height))
                 .or_insert_with(Vec::new)
-                .push(point.clone());
+                .push(point);
         }
 
         // Add points to chunks
-        for (chunk_index, points) in chunk_points.iter() {
-            self.get_chunk(*chunk_index)
-                .add_instances(WorldChunkInstance::new(mesh.clone(), points.to_vec()));
+        for (chunk_index, points) in chunk_points.into_iter() {
+            self.get_chunk(chunk_index)
+                .add_instances(WorldChunkInstance::new(mesh.clone(), points, collider.clone()));
         }
+
+        Ok(())
     }
 
     /// Add an entity
     fn add_entity(&mut self, prim: &gltf::Primitive, world_transform: &Matrix4<f32>, object_id: String,
-        buffers: &[buffer::Data], raw_extras: Option<&Box<RawValue>>)
+        buffers: &[buffer::Data], raw_extras: Option<&Box<RawValue>>) -> Result<(), String>
     {
-        let entity_id = self.entity_count;
-        self.entity_count += 1;
-
-        // Add this entity to exactly the chunk it's supposed to be in based on its transform
-        let chunk = {
-            let entity_pos = world_transform.w.truncate();
-            let chunk_index = WorldChunk::point_to_chunk_index(&entity_pos);
-            self.get_chunk(chunk_index)
-        };
-
         // Might as well add some of the mesh data (the positions at least)... might be useful! I'm
         // not including any indexes for now since isolated points and edges are probably more
         // useful anyway.
         let points = prim.attributes()
             .find(|attrib| attrib.0 == Semantic::Positions)
-            .map(|(_, accessor)| {
-                let buffer_view  = accessor.view().unwrap();
-                let buffer_index = buffer_view.buffer().index();
-
-                let buffer = &buffers[buffer_index];
-
-                let attrib_stride = buffer_view.stride().unwrap_or(0) as i32;
-                if attrib_stride != 0 {
-                    panic!("unhandled");
-                }
-
-                // Assuming that it's always gl::FLOAT but I might be wrong
-                let data_size_type = std::mem::size_of::<f32>();
-
-                let offset = buffer_view.offset();
-                let length_bytes = buffer_view.length();
-                let length_elements = length_bytes / data_size_type;
-
-                let mut vertices = vec![0.0; length_elements];
-                let mut slice = &buffer[offset..offset+length_bytes];
-                slice.read_f32_into::<LittleEndian>(&mut vertices).expect("Failed!");
-
-                vertices
-            })
+            .map(|(_, accessor)| gltf_accessor::read_f32(&accessor, buffers))
+            .transpose()?
             .map(|points| {
                 points.chunks_exact(3)
                 .map(|v| WrappedVector3((world_transform * vec4(v[0], v[1], v[2], 1.0)).truncate()))
                 .collect()
             });
 
+        let entity_id = self.output.entity_count;
+        self.output.entity_count += 1;
+
+        // Add this entity to exactly the chunk it's supposed to be in based on its transform
+        let chunk = {
+            let entity_pos = world_transform.w.truncate();
+            let chunk_index = WorldChunk::point_to_chunk_index(&entity_pos, self.chunk_height);
+            self.get_chunk(chunk_index)
+        };
+
         chunk.add_entity(WorldChunkEntity::new(entity_id, object_id, *world_transform, points, raw_extras.map(|e| e.get().to_string())));
+        Ok(())
     }
 
     /// Build the vertices for a single mesh from a gltf::Primitive
-    fn build_mesh_vertices(attribs: &HashMap<Semantic, Vec<f32>>, world_transform: &Matrix4<f32>) -> (Vec<f32>, Aabb) {
-        let positions = attribs.get(&Semantic::Positions).expect("Need positions");
-        let normals = attribs.get(&Semantic::Normals).expect("Need normals");
+    fn build_mesh_vertices(node: &gltf::Node, attribs: &HashMap<Semantic, Vec<f32>>, indices: &[u32],
+        world_transform: &Matrix4<f32>) -> Result<(Vec<f32>, Aabb), String>
+    {
+        let positions = attribs.get(&Semantic::Positions).ok_or("Mesh has no positions")?;
         let uvs = attribs.get(&Semantic::TexCoords(0));
         let colors = attribs.get(&Semantic::Colors(0));
 
         let vertex_count = positions.len() / 3;
 
-        assert!(positions.len() % 3 == 0);
-        assert!(normals.len() / 3 == vertex_count);
-        assert!(uvs.map(|uvs| uvs.len() / 2).unwrap_or(vertex_count) == vertex_count);
-        assert!(colors.map(|colors| colors.len() / 4).unwrap_or(vertex_count) == vertex_count);
+        // Recompute normals if the exporter didn't include them
+        let computed_normals;
+        let normals = match attribs.get(&Semantic::Normals) {
+            Some(normals) => normals,
+            None => {
+                build_log!("Mesh {} has no normals, recomputing them", node.name().unwrap_or("no-name"));
+                computed_normals = Self::compute_normals(positions, indices);
+                &computed_normals
+            }
+        };
+
+        if normals.len() != vertex_count * 3 {
+            return Err(format!("Mesh has {} normals for {vertex_count} vertices", normals.len() / 3));
+        }
+        if let Some(uvs) = uvs.filter(|uvs| uvs.len() != vertex_count * 2) {
+            return Err(format!("Mesh has {} uvs for {vertex_count} vertices", uvs.len() / 2));
+        }
+
+        // Colors can be rgb or rgba
+        let color_components = colors.map(|colors| colors.len() / vertex_count.max(1)).unwrap_or(4);
+        if colors.map(|colors| colors.len() != vertex_count * color_components).unwrap_or(false)
+            || (color_components != 3 && color_components != 4)
+        {
+            return Err(format!("Mesh has {} color components for {vertex_count} vertices",
+                colors.map(|colors| colors.len()).unwrap_or(0)));
+        }
 
         // Transform vertices to world space, and calculate bounding box
         let mut vertices = Vec::with_capacity(vertex_count * VERTEX_STRIDE);
@@ -458,6 +1333,7 @@ impl WorldBuilder {
 
             // Get color
             let color = match colors {
+                Some(colors) if color_components == 3 => vec4(colors[i*3], colors[i*3+1], colors[i*3+2], 1.0),
                 Some(colors) => vec4(colors[i*4], colors[i*4+1], colors[i*4+2], colors[i*4+3]),
                 None => vec4(1.0, 1.0, 1.0, 1.0)
             };
@@ -477,160 +1353,438 @@ impl WorldBuilder {
             vertices.push(color.w);
         };
 
-        (vertices, aabb)
+        Ok((vertices, aabb))
+    }
+
+    /// Compute smooth vertex normals for a mesh, weighting each triangle's normal by its area
+    fn compute_normals(positions: &[f32], indices: &[u32]) -> Vec<f32> {
+        let position = |i: u32| vec3(positions[i as usize * 3], positions[i as usize * 3 + 1], positions[i as usize * 3 + 2]);
+
+        let mut normals = vec![vec3(0.0, 0.0, 0.0); positions.len() / 3];
+        for tri in indices.chunks_exact(INDEX_STRIDE) {
+            // The cross product's length is twice the triangle's area, which gives us the weighting
+            let face_normal = (position(tri[1]) - position(tri[0])).cross(position(tri[2]) - position(tri[0]));
+            for i in tri {
+                normals[*i as usize] += face_normal;
+            }
+        }
+
+        normals
+            .into_iter()
+            .flat_map(|normal| {
+                let normal = if normal.magnitude2() > 0.0 { normal.normalize() } else { vec3(0.0, 1.0, 0.0) };
+                [normal.x, normal.y, normal.z]
+            })
+            .collect()
     }
 
-    /// Clip a mesh to an aabb, discarding any triangles that aren't at least touching the aabb.
-    /// The resulting mesh can be a little bigger than the aabb as triangles on the border are
-    /// retianed to avoid any seams. If no triangles from the original mesh were in the aabb,
-    /// returns None.
-    fn clip_mesh_to_aabb(vertices: &[f32], indices: &[u16], clip_min: &Vector3<f32>, clip_max: &Vector3<f32>,
-        next_mesh_index: i32, material: &WorldChunkMaterial) -> Option<WorldChunkMesh>
+    /// Clip a mesh to an aabb, splitting the triangles that cross its faces and discarding the
+    /// parts outside of it. Each part of a triangle ends up in exactly one chunk, including ones
+    /// lying on the border between two chunks. Returns the aabb, vertices and indices of the clipped
+    /// mesh, or None if no part of the original mesh was in the aabb, or an error if the indices
+    /// don't fit the vertices.
+    fn clip_mesh_to_aabb(vertices: &[f32], indices: &[u32], clip_min: &Vector3<f32>, clip_max: &Vector3<f32>)
+        -> Result<Option<(Aabb, Vec<f32>, Vec<u32>)>, String>
     {
+        if vertices.len() % VERTEX_STRIDE != 0 {
+            return Err(format!("Mesh has {} vertex components, which isn't a multiple of {VERTEX_STRIDE}", vertices.len()));
+        }
+        if indices.len() % INDEX_STRIDE != 0 {
+            return Err(format!("Mesh has {} indices, which isn't a multiple of {INDEX_STRIDE}", indices.len()));
+        }
+        if let Some(index) = indices.iter().find(|i| **i as usize >= vertices.len() / VERTEX_STRIDE) {
+            return Err(format!("Mesh index {index} is out of range"));
+        }
+
         // Build vertex and index buffer for this chunk
         let mut chunk_mesh_aabb = Aabb::new();
         let mut chunk_mesh_vertices = Vec::new();
         let mut chunk_mesh_indices = Vec::new();
 
-        // A map of original mesh indices to new mesh indices, since we're
-        // going to be filtering some of them out and need to remap them
-        let mut chunk_index_map: HashMap<u16, u16> = HashMap::new();
+        // A map of vertex data to new mesh indices, so that vertices shared between triangles are
+        // only added once, including the ones created where neighbouring triangles were split
+        let mut chunk_vertex_map: HashMap<[u32; VERTEX_STRIDE], u32> = HashMap::new();
 
         // Insert a vertex into the new mesh, returning the index of the vertex
-        let mut insert_chunk_mesh_vertex = |data: &[f32]| -> u16 {
-            let index = (chunk_mesh_vertices.len() / VERTEX_STRIDE) as u16;
+        let mut insert_chunk_mesh_vertex = |data: &ClipVertex| -> u32 {
+            *chunk_vertex_map
+                .entry(data.map(f32::to_bits))
+                .or_insert_with(|| {
+                    let index = (chunk_mesh_vertices.len() / VERTEX_STRIDE) as u32;
+                    chunk_mesh_vertices.extend_from_slice(data);
+                    index
+                })
+        };
 
-            for i in 0..VERTEX_STRIDE {
-                chunk_mesh_vertices.push(data[i]);
+        // Clip each triangle in the original mesh against each face of the aabb in turn, which
+        // leaves a convex polygon that we can split back into triangles
+        for tri in indices.chunks_exact(INDEX_STRIDE) {
+            let mut polygon: Vec<ClipVertex> = tri
+                .iter()
+                .map(|i| {
+                    let offset = *i as usize * VERTEX_STRIDE;
+                    let mut vertex = [0.0; VERTEX_STRIDE];
+                    vertex.copy_from_slice(&vertices[offset .. offset + VERTEX_STRIDE]);
+                    vertex
+                })
+                .collect();
+
+            for axis in 0..3 {
+                polygon = Self::clip_polygon_to_plane(&polygon, axis, clip_min[axis], false);
+                polygon = Self::clip_polygon_to_plane(&polygon, axis, clip_max[axis], true);
             }
 
-            index
-        };
+            if polygon.len() < 3 {
+                continue;
+            }
 
-        // Iterate through each triangle in the original mesh and build a mesh of just triangles
-        // that intersect or are contained within the chunk's aabb
-        assert!(vertices.len() % VERTEX_STRIDE == 0);
-        assert!(indices.len() % INDEX_STRIDE == 0);
-        for tri in indices.chunks_exact(INDEX_STRIDE) {
-            let i1 = tri[0];
-            let i2 = tri[1];
-            let i3 = tri[2];
-
-            let i1_offset = i1 as usize * VERTEX_STRIDE;
-            let i2_offset = i2 as usize * VERTEX_STRIDE;
-            let i3_offset = i3 as usize * VERTEX_STRIDE;
-
-            let v1_data = &vertices[i1_offset .. i1_offset + VERTEX_STRIDE];
-            let v2_data = &vertices[i2_offset .. i2_offset + VERTEX_STRIDE];
-            let v3_data = &vertices[i3_offset .. i3_offset + VERTEX_STRIDE];
-
-            let v1 = vec3(v1_data[0], v1_data[1], v1_data[2]);
-            let v2 = vec3(v2_data[0], v2_data[1], v2_data[2]);
-            let v3 = vec3(v3_data[0], v3_data[1], v3_data[2]);
-
-            if Self::triangle_intersects_aabb(&clip_min, &clip_max, &v1, &v2, &v3) {
-                // And then remap each index into a new place in the vertex buffer.
-                let i1 = *chunk_index_map
-                    .entry(i1)
-                    .or_insert_with(|| { insert_chunk_mesh_vertex(v1_data) });
-
-                let i2 = *chunk_index_map
-                    .entry(i2)
-                    .or_insert_with(|| { insert_chunk_mesh_vertex(v2_data) });
-
-                let i3 = *chunk_index_map
-                    .entry(i3)
-                    .or_insert_with(|| { insert_chunk_mesh_vertex(v3_data) });
-
-                chunk_mesh_indices.push(i1);
-                chunk_mesh_indices.push(i2);
-                chunk_mesh_indices.push(i3);
-
-                chunk_mesh_aabb.expand_with_point(&v1);
-                chunk_mesh_aabb.expand_with_point(&v2);
-                chunk_mesh_aabb.expand_with_point(&v3);
+            let polygon_indices: Vec<u32> = polygon.iter().map(|v| insert_chunk_mesh_vertex(v)).collect();
+            for i in 1..polygon_indices.len() - 1 {
+                let (i1, i2, i3) = (polygon_indices[0], polygon_indices[i], polygon_indices[i + 1]);
+
+                // Splitting a triangle right next to one of its vertices can leave slivers where
+                // two vertices are the same, which aren't worth keeping
+                if i1 == i2 || i2 == i3 || i3 == i1 {
+                    continue;
+                }
+
+                chunk_mesh_indices.extend_from_slice(&[i1, i2, i3]);
+                for v in [&polygon[0], &polygon[i], &polygon[i + 1]] {
+                    chunk_mesh_aabb.expand_with_point(&vec3(v[0], v[1], v[2]));
+                }
             }
         }
 
-        // Create the mesh if any triangles remain
+        // Return the mesh if any triangles remain
         if chunk_mesh_indices.len() > 0 {
-            assert!(chunk_mesh_indices.len() % INDEX_STRIDE == 0);
-            assert!(chunk_mesh_vertices.len() % VERTEX_STRIDE == 0);
+            Ok(Some((chunk_mesh_aabb, chunk_mesh_vertices, chunk_mesh_indices)))
+        }
+        else {
+            Ok(None)
+        }
+    }
+
+    /// Build a collision mesh from a chunk mesh's vertices and indices. Only the positions are
+    /// kept, vertices with the same position are welded together, and triangles with no area are
+    /// removed, as they can't be collided with. Returns None if there are no triangles left.
+    fn build_collision_mesh(vertices: &[f32], indices: &[u32], layer: CollisionLayer) -> Option<WorldChunkCollisionMesh> {
+        let mut aabb = Aabb::new();
+        let mut positions = Vec::new();
+        let mut collision_indices = Vec::new();
 
-            let mesh = WorldChunkMesh::new(chunk_mesh_aabb.clone(), next_mesh_index, chunk_mesh_vertices,
-                chunk_mesh_indices, Some(material.clone()));
+        // A map of positions to new mesh indices, for welding vertices
+        let mut position_map: HashMap<[u32; 3], u32> = HashMap::new();
 
-            Some(mesh)
+        let position = |i: u32| {
+            let offset = i as usize * VERTEX_STRIDE;
+            vec3(vertices[offset], vertices[offset + 1], vertices[offset + 2])
+        };
+
+        for tri in indices.chunks_exact(INDEX_STRIDE) {
+            let (v1, v2, v3) = (position(tri[0]), position(tri[1]), position(tri[2]));
+
+            // This also catches triangles that had two vertices welded together
+            if (v2 - v1).cross(v3 - v1).magnitude2() == 0.0 {
+                continue;
+            }
+
+            for v in [v1, v2, v3] {
+                let index = *position_map
+                    .entry([v.x.to_bits(), v.y.to_bits(), v.z.to_bits()])
+                    .or_insert_with(|| {
+                        let index = (positions.len() / 3) as u32;
+                        positions.extend_from_slice(&[v.x, v.y, v.z]);
+                        index
+                    });
+
+                collision_indices.push(index);
+                aabb.expand_with_point(&v);
+            }
         }
-        else {
-            None
+
+        match collision_indices.is_empty() {
+            true => None,
+            false => Some(WorldChunkCollisionMesh::new(aabb, layer, positions, WorldChunkIndices::new(collision_indices)))
         }
     }
 
-    /// Check whether a triangle intersects an aabb
-    /// https://gdbooks.gitbooks.io/3dcollisions/content/Chapter4/aabb-triangle.html
-    fn triangle_intersects_aabb(aabb_min: &Vector3<f32>, aabb_max: &Vector3<f32>,
-        a: &Vector3<f32>, b: &Vector3<f32>, c: &Vector3<f32>) -> bool
-    {
-        // Convert AABB to center-extents form
-        let center = 0.5 * aabb_min + 0.5 * aabb_max;
-        let extents = 0.5 * aabb_max - 0.5 * aabb_min;
-
-        // Translate triangle as conceptually moving aabb to origin
-        let v0 = a - center;
-        let v1 = b - center;
-        let v2 = c - center;
-
-        // Compute the edge vectors of the triangle
-        let f0 = v1 - v0;
-        let f1 = v2 - v1;
-        let f2 = v0 - v2;
-
-        // Compute face normals of the aabb (they're just axis aligned)
-        let u0 = Vector3::new(1.0, 0.0, 0.0);
-        let u1 = Vector3::new(0.0, 1.0, 0.0);
-        let u2 = Vector3::new(0.0, 0.0, 1.0);
-
-        // A helper function for doing SAT tests of a given axis against the triangle
-        let axis_separated = |axis: Vector3<f32>| -> bool {
-            let p0 = v0.dot(axis);
-            let p1 = v1.dot(axis);
-            let p2 = v2.dot(axis);
-
-            let r = extents.x * f32::abs(u0.dot(axis)) +
-                    extents.y * f32::abs(u1.dot(axis)) +
-                    extents.z * f32::abs(u2.dot(axis));
-
-            f32::max(-f32::max(p0, f32::max(p1, p2)), f32::min(p0, f32::min(p1, p2))) > r
+    /// Clip a convex polygon to one side of an axis aligned plane, keeping the part on or above the
+    /// plane, or if `below` is set, the part strictly below it. This way a polygon lying exactly on
+    /// the border between two chunks only goes in the one above it.
+    fn clip_polygon_to_plane(polygon: &[ClipVertex], axis: usize, plane: f32, below: bool) -> Vec<ClipVertex> {
+        let inside = |v: &ClipVertex| match below {
+            true => v[axis] < plane,
+            false => v[axis] >= plane
         };
 
-        // Do the sat tests for:
-        // * The 9 axes separating the edges of the aabb and the triangle's edges
-        // * The three face normals from the AABB
-        // * The axis of the face normal of the triangle
-        let any_separated =
-            axis_separated(u0.cross(f0)) ||
-            axis_separated(u0.cross(f1)) ||
-            axis_separated(u0.cross(f2)) ||
-            axis_separated(u1.cross(f0)) ||
-            axis_separated(u1.cross(f1)) ||
-            axis_separated(u1.cross(f2)) ||
-            axis_separated(u2.cross(f0)) ||
-            axis_separated(u2.cross(f1)) ||
-            axis_separated(u2.cross(f2)) ||
-            axis_separated(u0) ||
-            axis_separated(u1) ||
-            axis_separated(u2) ||
-            axis_separated(f0.cross(f1));
-
-        // If any axis was separated, the triangle did not intersect the aabb
-        !any_separated
-    }
-
-    /// Get the chunk for a given chunk index
+        let mut clipped = Vec::with_capacity(polygon.len() + 1);
+        for (i, cur) in polygon.iter().enumerate() {
+            let prev = &polygon[(i + polygon.len() - 1) % polygon.len()];
+            if inside(cur) != inside(prev) {
+                clipped.push(Self::split_edge(prev, cur, axis, plane));
+            }
+            if inside(cur) {
+                clipped.push(*cur);
+            }
+        }
+
+        clipped
+    }
+
+    /// Get the vertex where an edge crosses an axis aligned plane, interpolating all of its
+    /// attributes. The ends of the edge are put in a consistent order and the new vertex is snapped
+    /// to the plane, so that the chunks on either side get exactly the same vertex and there's no
+    /// seam between them.
+    fn split_edge(a: &ClipVertex, b: &ClipVertex, axis: usize, plane: f32) -> ClipVertex {
+        let (a, b) = if a[axis] < b[axis] { (a, b) } else { (b, a) };
+        let t = (plane - a[axis]) / (b[axis] - a[axis]);
+
+        let mut vertex = [0.0; VERTEX_STRIDE];
+        for i in 0..VERTEX_STRIDE {
+            vertex[i] = a[i] + (b[i] - a[i]) * t;
+        }
+        vertex[axis] = plane;
+
+        // Interpolated normals get shorter, so they need normalizing again
+        let normal = vec3(vertex[3], vertex[4], vertex[5]);
+        if normal.magnitude2() > 0.0 {
+            let normal = normal.normalize();
+            vertex[3..6].copy_from_slice(&[normal.x, normal.y, normal.z]);
+        }
+
+        vertex
+    }
+
+    /// Get the current model's output for a given chunk index
     fn get_chunk(&mut self, idx: ChunkIndex) -> &mut WorldChunk {
-        self.chunks
+        self.output.chunks
             .entry(idx)
             .or_insert(WorldChunk::new())
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::chunk_file::ChunkFileError;
+    use crate::file_header::test_util::temp_path;
+
+    /// Make a glb model with a node for each of the given names and extras, all using the same
+    /// triangle mesh, whose positions accessor claims to have position_count positions
+    fn triangle_model(nodes: &[(&str, &str)], position_count: usize) -> WorldModel {
+        let positions: Vec<u8> = [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0]
+            .iter()
+            .flat_map(|f| f.to_le_bytes())
+            .collect();
+
+        let node_json: Vec<String> = nodes
+            .iter()
+            .map(|(name, extras)| format!(r#"{{"name":"{name}","mesh":0,"extras":{extras}}}"#))
+            .collect();
+        let scene_nodes: Vec<String> = (0..nodes.len()).map(|i| i.to_string()).collect();
+
+        let mut json = format!(r#"{{"asset":{{"version":"2.0"}},"scene":0,"scenes":[{{"nodes":[{}]}}],"nodes":[{}],
+            "meshes":[{{"primitives":[{{"attributes":{{"POSITION":0}}}}]}}],
+            "accessors":[{{"bufferView":0,"componentType":5126,"count":{position_count},"type":"VEC3",
+                "min":[0,0,0],"max":[1,0,1]}}],
+            "bufferViews":[{{"buffer":0,"byteLength":{len}}}],"buffers":[{{"byteLength":{len}}}]}}"#,
+            scene_nodes.join(","), node_json.join(","), len = positions.len()).into_bytes();
+        while json.len() % 4 != 0 {
+            json.push(b' ');
+        }
+
+        let mut data = Vec::new();
+        data.extend_from_slice(b"glTF");
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.extend_from_slice(&((12 + 8 + json.len() + 8 + positions.len()) as u32).to_le_bytes());
+        data.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        data.extend_from_slice(b"JSON");
+        data.extend_from_slice(&json);
+        data.extend_from_slice(&(positions.len() as u32).to_le_bytes());
+        data.extend_from_slice(b"BIN\0");
+        data.extend_from_slice(&positions);
+
+        WorldModel {
+            filename: Cow::Owned("test.glb".to_string()),
+            data: Cow::Owned(data)
+        }
+    }
+
+    /// Build a model into a fresh directory
+    fn build(name: &str, model: WorldModel) -> Result<WorldBuildSummary, WorldBuildErrors> {
+        let out_dir = temp_path(name);
+        let models = [model];
+        let result = WorldBuilder::new(&out_dir, &models).build_world_models();
+        std::fs::remove_dir_all(&out_dir).ok();
+        result
+    }
+
+    #[test]
+    fn builds_a_triangle() {
+        let summary = build("builds_a_triangle", triangle_model(&[("Floor", "{}")], 3)).unwrap();
+
+        assert_eq!(summary.chunks, 1);
+        assert_eq!(summary.meshes, 1);
+    }
+
+    #[test]
+    fn skips_unknown_node_types() {
+        let model = triangle_model(&[("Floor", "{}"), ("Thing", r#"{"node_type":"something_else"}"#)], 3);
+        let summary = build("skips_unknown_node_types", model).unwrap();
+
+        assert_eq!(summary.meshes, 1);
+    }
+
+    #[test]
+    fn collects_every_error_with_its_model_and_node() {
+        let model = triangle_model(&[
+            ("Spawner", r#"{"node_type":"entity"}"#),
+            ("Trees", r#"{"node_type":"instances"}"#),
+            ("Floor", r#"{"collision":"sometimes"}"#),
+        ], 3);
+        let errors = build("collects_every_error", model).unwrap_err();
+
+        let nodes: Vec<Option<&str>> = errors.0.iter().map(|err| err.node.as_deref()).collect();
+        assert_eq!(nodes, vec![Some("Spawner"), Some("Trees"), Some("Floor")]);
+        assert!(errors.0.iter().all(|err| err.model == "test.glb"));
+        assert!(errors.0[0].reason.contains("object_id"));
+        assert!(errors.0[1].reason.contains("instance_mesh"));
+    }
+
+    #[test]
+    fn accessors_past_the_end_of_their_buffer_are_errors() {
+        let errors = build("accessors_past_the_end", triangle_model(&[("Floor", "{}")], 4)).unwrap_err();
+
+        assert_eq!(errors.0.len(), 1);
+        assert_eq!(errors.0[0].node.as_deref(), Some("Floor"));
+        assert!(errors.0[0].reason.contains("Accessor 0"), "{}", errors.0[0].reason);
+    }
+
+    #[test]
+    fn chunk_height_is_recorded_in_the_manifest_and_chunks() {
+        let out_dir = temp_path("chunk_height");
+        let models = [triangle_model(&[("Floor", "{}")], 3)];
+        let build = |chunk_height| WorldBuilder::new(&out_dir, &models).with_chunk_height(chunk_height).build_world_models();
+
+        build(Some(4.0)).unwrap();
+        let manifest = WorldBuildManifest::load(out_dir.join(WORLD_BUILD_MANIFEST_FILENAME)).unwrap();
+        let chunk_data = std::fs::read(out_dir.join(WorldChunk::filename((0, 0, 0)))).unwrap();
+
+        // Changing the chunk height rewrites everything, even though the model hasn't changed
+        let rebuilt = build(None).unwrap();
+        let invalid = build(Some(0.0));
+        std::fs::remove_dir_all(&out_dir).ok();
+
+        assert_eq!(manifest.chunk_height, Some(4.0));
+        assert!(WorldChunk::read_from_file(&chunk_data, (0, 0, 0), Some(4.0)).is_ok());
+        assert!(matches!(WorldChunk::read_from_file(&chunk_data, (0, 0, 0), None),
+            Err(ChunkFileError::WrongChunkHeight { expected: None, found: Some(_) })));
+        assert_eq!(rebuilt.chunks_written, 1);
+        assert!(invalid.unwrap_err().to_string().contains("Invalid chunk height"));
+    }
+
+    /// A clip vertex at a position, with a uv that matches its x and z so that interpolation can be
+    /// checked
+    fn clip_vertex(x: f32, y: f32, z: f32) -> ClipVertex {
+        [x, y, z, 0.0, 1.0, 0.0, x / 32.0, z / 32.0, 1.0, 1.0, 1.0, 1.0]
+    }
+
+    fn clip_mesh(vertices: &[ClipVertex]) -> Vec<f32> {
+        vertices.iter().flatten().copied().collect()
+    }
+
+    /// Get the area of a clipped mesh
+    fn mesh_area(vertices: &[f32], indices: &[u32]) -> f32 {
+        let position = |i: u32| {
+            let offset = i as usize * VERTEX_STRIDE;
+            vec3(vertices[offset], vertices[offset + 1], vertices[offset + 2])
+        };
+
+        indices
+            .chunks_exact(INDEX_STRIDE)
+            .map(|tri| (position(tri[1]) - position(tri[0])).cross(position(tri[2]) - position(tri[0])).magnitude() / 2.0)
+            .sum()
+    }
+
+    #[test]
+    fn clipping_keeps_triangles_inside() {
+        let vertices = clip_mesh(&[clip_vertex(1.0, 1.0, 1.0), clip_vertex(4.0, 1.0, 1.0), clip_vertex(1.0, 1.0, 4.0)]);
+        let (aabb, clipped_vertices, clipped_indices) = WorldBuilder::clip_mesh_to_aabb(&vertices, &[0, 1, 2],
+            &vec3(0.0, 0.0, 0.0), &vec3(16.0, 16.0, 16.0)).unwrap().unwrap();
+
+        assert_eq!(clipped_vertices, vertices);
+        assert_eq!(clipped_indices, vec![0, 1, 2]);
+        assert_eq!(aabb.min_max(), Some((&vec3(1.0, 1.0, 1.0), &vec3(4.0, 1.0, 4.0))));
+    }
+
+    #[test]
+    fn clipping_discards_triangles_outside() {
+        let vertices = clip_mesh(&[clip_vertex(20.0, 1.0, 1.0), clip_vertex(24.0, 1.0, 1.0), clip_vertex(20.0, 1.0, 4.0)]);
+        let clipped = WorldBuilder::clip_mesh_to_aabb(&vertices, &[0, 1, 2], &vec3(0.0, 0.0, 0.0),
+            &vec3(16.0, 16.0, 16.0)).unwrap();
+
+        assert!(clipped.is_none());
+    }
+
+    #[test]
+    fn clipping_splits_triangles_across_chunks() {
+        // A triangle crossing x = 16 and z = 16, the corner of four chunks
+        let vertices = clip_mesh(&[clip_vertex(10.0, 1.0, 10.0), clip_vertex(24.0, 3.0, 12.0),
+            clip_vertex(12.0, 5.0, 22.0)]);
+        let indices = [0, 1, 2];
+        let total_area = mesh_area(&vertices, &indices);
+
+        let mut area = 0.0;
+        let mut border_vertices = Vec::new();
+        for (x, z) in [(0.0, 0.0), (16.0, 0.0), (0.0, 16.0), (16.0, 16.0)] {
+            let (min, max) = (vec3(x, 0.0, z), vec3(x + 16.0, 16.0, z + 16.0));
+            let (_, clipped_vertices, clipped_indices) = WorldBuilder::clip_mesh_to_aabb(&vertices, &indices, &min, &max)
+                .unwrap()
+                .unwrap();
+            area += mesh_area(&clipped_vertices, &clipped_indices);
+
+            for vertex in clipped_vertices.chunks_exact(VERTEX_STRIDE) {
+                assert!(vertex[0] >= min.x && vertex[0] <= max.x && vertex[2] >= min.z && vertex[2] <= max.z,
+                    "vertex {vertex:?} is outside of {min:?} to {max:?}");
+
+                // Attributes are interpolated along with the position
+                assert!((vertex[6] - vertex[0] / 32.0).abs() < 0.0001 && (vertex[7] - vertex[2] / 32.0).abs() < 0.0001,
+                    "vertex {vertex:?} has the wrong uv");
+                if vertex[0] == 16.0 || vertex[2] == 16.0 {
+                    border_vertices.push(vertex.iter().map(|f| f.to_bits()).collect::<Vec<_>>());
+                }
+            }
+        }
+
+        assert!((area - total_area).abs() < 0.001, "clipped area {area}, original area {total_area}");
+
+        // Every vertex on a border is shared exactly with the chunk on the other side, so there
+        // are no seams
+        for vertex in border_vertices.iter() {
+            assert!(border_vertices.iter().filter(|v| *v == vertex).count() >= 2, "vertex {vertex:?} isn't shared");
+        }
+    }
+
+    #[test]
+    fn clipping_puts_triangles_on_a_border_in_one_chunk() {
+        let vertices = clip_mesh(&[clip_vertex(1.0, 16.0, 1.0), clip_vertex(4.0, 16.0, 1.0), clip_vertex(1.0, 16.0, 4.0)]);
+        let below = WorldBuilder::clip_mesh_to_aabb(&vertices, &[0, 1, 2], &vec3(0.0, 0.0, 0.0),
+            &vec3(16.0, 16.0, 16.0)).unwrap();
+        let above = WorldBuilder::clip_mesh_to_aabb(&vertices, &[0, 1, 2], &vec3(0.0, 16.0, 0.0),
+            &vec3(16.0, 32.0, 16.0)).unwrap();
+
+        assert!(below.is_none());
+        assert!(above.is_some());
+    }
+
+    #[test]
+    fn clipping_bad_meshes_is_an_error() {
+        let vertices = clip_mesh(&[clip_vertex(1.0, 1.0, 1.0), clip_vertex(4.0, 1.0, 1.0), clip_vertex(1.0, 1.0, 4.0)]);
+        let (min, max) = (vec3(0.0, 0.0, 0.0), vec3(16.0, 16.0, 16.0));
+
+        assert!(WorldBuilder::clip_mesh_to_aabb(&vertices, &[0, 1, 3], &min, &max).is_err());
+        assert!(WorldBuilder::clip_mesh_to_aabb(&vertices, &[0, 1], &min, &max).is_err());
+        assert!(WorldBuilder::clip_mesh_to_aabb(&vertices[1..], &[0, 1, 2], &min, &max).is_err());
+    }
+}
@@ -1,10 +1,22 @@
-use cgmath::{Vector3, Vector2, Matrix4};
+use cgmath::{Vector3, Matrix3, Matrix4, Quaternion, vec3};
 use speedy::{Readable, Writable};
 use super::{aabb::Aabb, wrapped_vectors::{WrappedVector4, WrappedVector3, WrappedMatrix4}};
+use super::chunk_file::{ChunkFile, ChunkFileIndex, ChunkFileSection, ChunkFileError};
+use super::world_texture::TextureIndex;
+use crate::intersection::Shape;
 
 /// World chunk size
 pub const CHUNK_SIZE: f32 = 16.0;
 
+/// The chunk height worlds are built with unless the world builder is given another. A chunk height
+/// of None doesn't split the world vertically, in which case every chunk has a y index of 0 and
+/// covers the whole height of the world. Splitting it means that levels above or below each other,
+/// such as a dungeon under a village, are loaded and drawn separately.
+pub const DEFAULT_CHUNK_HEIGHT: Option<f32> = Some(CHUNK_SIZE);
+
+/// How far up and down chunks extend when the world isn't split vertically
+pub const UNSPLIT_CHUNK_HEIGHT_LIMIT: f32 = 1000.0;
+
 // Stride for world meshes is pos (3) + normals (3) + uv (2) + color (4)
 // We could split these into separate buffers since this part only needs positions
 pub const VERTEX_STRIDE: usize = 3 + 3 + 2 + 4;
@@ -12,8 +24,8 @@ pub const VERTEX_STRIDE: usize = 3 + 3 + 2 + 4;
 // For indices it's just 3 because they're triangles
 pub const INDEX_STRIDE: usize = 3;
 
-/// Type for chunk indexes
-pub type ChunkIndex = (i32, i32);
+/// Type for chunk indexes, as x, y, z
+pub type ChunkIndex = (i32, i32, i32);
 
 /// Type for entity IDs
 pub type EntityId = i32;
@@ -23,6 +35,7 @@ pub type EntityId = i32;
 pub struct WorldChunk {
     aabb: Aabb,
     meshes: Vec<WorldChunkMesh>,
+    collision_meshes: Vec<WorldChunkCollisionMesh>,
     instances: Vec<WorldChunkInstance>,
     entities: Vec<WorldChunkEntity>,
 }
@@ -33,6 +46,7 @@ impl WorldChunk {
         Self {
             aabb: Aabb::new(),
             meshes: Vec::new(),
+            collision_meshes: Vec::new(),
             instances: Vec::new(),
             entities: Vec::new(),
         }
@@ -48,6 +62,11 @@ impl WorldChunk {
         &self.meshes
     }
 
+    /// Get the chunk's collision meshes
+    pub fn collision_meshes(&self) -> &[WorldChunkCollisionMesh] {
+        &self.collision_meshes
+    }
+
     /// Get the chunk's instances
     pub fn instances(&self) -> &[WorldChunkInstance] {
         &self.instances
@@ -64,64 +83,171 @@ impl WorldChunk {
         self.meshes.push(mesh);
     }
 
+    /// Add a collision mesh to a world chunk
+    pub fn add_collision_mesh(&mut self, mesh: WorldChunkCollisionMesh) {
+        self.aabb.expand_with_aabb(mesh.aabb());
+        self.collision_meshes.push(mesh);
+    }
+
     /// Add an instance to a world chunk
     pub fn add_instances(&mut self, instance: WorldChunkInstance) {
-        for WrappedVector3(point) in instance.points().iter() {
-            self.aabb.expand_with_point(point)
+        for point in instance.points().iter() {
+            self.aabb.expand_with_point(point.pos());
+
+            // Colliders can reach outside of the chunk, so make sure queries of its aabb find them
+            if let Some(collider) = instance.collider() {
+                let (min, max) = collider.shape(point.scale()).world_aabb(point.pos(), &Matrix3::from(point.rot()));
+                self.aabb.expand_with_point(&min);
+                self.aabb.expand_with_point(&max);
+            }
         }
         self.instances.push(instance);
     }
 
+    /// Get how far the colliders of the chunk's instances reach outside of the chunk's bounds
+    pub fn instance_collider_reach(&self, chunk_index: ChunkIndex, chunk_height: Option<f32>) -> f32 {
+        let (chunk_min, chunk_max) = Self::chunk_bounds(chunk_index, chunk_height);
+
+        let mut reach: f32 = 0.0;
+        for instance in self.instances.iter() {
+            if let Some(collider) = instance.collider() {
+                for point in instance.points().iter() {
+                    let (min, max) = collider.shape(point.scale()).world_aabb(point.pos(), &Matrix3::from(point.rot()));
+                    let outside = Aabb::vec_max(&(chunk_min - min), &(max - chunk_max));
+                    reach = reach.max(outside.x).max(outside.y).max(outside.z);
+                }
+            }
+        }
+
+        reach
+    }
+
     /// Add an entity to a world chunk
     pub fn add_entity(&mut self, entity: WorldChunkEntity) {
         self.entities.push(entity);
     }
 
+    /// Add everything in another chunk to this one
+    pub fn append(&mut self, other: WorldChunk) {
+        self.aabb.expand_with_aabb(&other.aabb);
+        self.meshes.extend(other.meshes);
+        self.collision_meshes.extend(other.collision_meshes);
+        self.instances.extend(other.instances);
+        self.entities.extend(other.entities);
+    }
+
+    /// Get the chunk's meshes mutably
+    pub fn meshes_mut(&mut self) -> &mut [WorldChunkMesh] {
+        &mut self.meshes
+    }
+
+    /// Get the chunk's entities mutably
+    pub fn entities_mut(&mut self) -> &mut [WorldChunkEntity] {
+        &mut self.entities
+    }
+
+    /// Take the chunk's meshes out of it, e.g. so that they can be merged and added back
+    pub fn take_meshes(&mut self) -> Vec<WorldChunkMesh> {
+        std::mem::take(&mut self.meshes)
+    }
+
+    /// Read a chunk from a chunk file, checking that it's the chunk we expected, from a world split
+    /// with the given chunk height
+    pub fn read_from_file(data: &[u8], chunk_index: ChunkIndex, chunk_height: Option<f32>) -> Result<Self, ChunkFileError> {
+        let file = ChunkFile::read(data, ChunkFileIndex::Chunk(chunk_index))?;
+        if file.chunk_height() != chunk_height {
+            return Err(ChunkFileError::WrongChunkHeight { expected: chunk_height, found: file.chunk_height() });
+        }
+
+        Ok(Self {
+            aabb: file.section(ChunkFileSection::Aabb)?,
+            meshes: file.section(ChunkFileSection::Meshes)?,
+            collision_meshes: file.section(ChunkFileSection::Collision)?,
+            instances: file.section(ChunkFileSection::Instances)?,
+            entities: file.section(ChunkFileSection::Entities)?,
+        })
+    }
+
+    /// Write the chunk to a chunk file
+    pub fn write_to_file_data(&self, chunk_index: ChunkIndex, chunk_height: Option<f32>) -> Result<Vec<u8>, speedy::Error> {
+        Ok(ChunkFile::write(ChunkFileIndex::Chunk(chunk_index), chunk_height, &[
+            (ChunkFileSection::Aabb, self.aabb.write_to_vec()?),
+            (ChunkFileSection::Meshes, self.meshes.write_to_vec()?),
+            (ChunkFileSection::Collision, self.collision_meshes.write_to_vec()?),
+            (ChunkFileSection::Instances, self.instances.write_to_vec()?),
+            (ChunkFileSection::Entities, self.entities.write_to_vec()?),
+        ]))
+    }
+
     /// Get the chunk filename for a given chunk index
-    pub fn filename((x, z): ChunkIndex) -> String {
-        format!("world_{}_{}.chunk", x, z)
+    pub fn filename((x, y, z): ChunkIndex) -> String {
+        format!("world_{}_{}_{}.chunk", x, y, z)
     }
 
     /// Parse a chunk's filename back to a chunk index
     pub fn parse_filename(filename: &str) -> Option<ChunkIndex> {
         if filename.starts_with("world_") && filename.ends_with(".chunk") {
             let idx: Vec<i32> = filename[6..filename.len()-6].split("_").map(|s| s.parse::<i32>().unwrap()).collect();
-            if idx.len() != 2 {
-                panic!("Chunk filename split into more than two parts");
+            if idx.len() != 3 {
+                panic!("Chunk filename didn't split into three parts");
             }
-            Some((idx[0], idx[1]))
+            Some((idx[0], idx[1], idx[2]))
         }
         else {
             None
         }
     }
 
-    /// Get the chunk index for a 2d point
-    pub fn point_to_chunk_index_2d(point: &Vector2<f32>) -> ChunkIndex {
-        (f32::floor(point.x / CHUNK_SIZE as f32) as i32,
-         f32::floor(point.y / CHUNK_SIZE as f32) as i32)
-    }
+    /// Get the chunk index for a 3d point, in a world split with the given chunk height
+    pub fn point_to_chunk_index(point: &Vector3<f32>, chunk_height: Option<f32>) -> ChunkIndex {
+        let y = match chunk_height {
+            Some(chunk_height) => f32::floor(point.y / chunk_height) as i32,
+            None => 0
+        };
 
-    /// Get the chunk index for a 3d point
-    pub fn point_to_chunk_index(point: &Vector3<f32>) -> ChunkIndex {
         (f32::floor(point.x / CHUNK_SIZE as f32) as i32,
+         y,
          f32::floor(point.z / CHUNK_SIZE as f32) as i32)
     }
+
+    /// Get the index of every chunk that the space between two points overlaps
+    pub fn chunks_between(min: &Vector3<f32>, max: &Vector3<f32>, chunk_height: Option<f32>)
+        -> impl Iterator<Item=ChunkIndex>
+    {
+        let (min_x, min_y, min_z) = Self::point_to_chunk_index(min, chunk_height);
+        let (max_x, max_y, max_z) = Self::point_to_chunk_index(max, chunk_height);
+
+        (min_x..=max_x).flat_map(move |x| {
+            (min_y..=max_y).flat_map(move |y| (min_z..=max_z).map(move |z| (x, y, z)))
+        })
+    }
+
+    /// Get the min and max corners of the space a chunk covers
+    pub fn chunk_bounds((x, y, z): ChunkIndex, chunk_height: Option<f32>) -> (Vector3<f32>, Vector3<f32>) {
+        let (min_y, max_y) = match chunk_height {
+            Some(chunk_height) => (y as f32 * chunk_height, (y + 1) as f32 * chunk_height),
+            None => (-UNSPLIT_CHUNK_HEIGHT_LIMIT, UNSPLIT_CHUNK_HEIGHT_LIMIT)
+        };
+
+        (Vector3::new(x as f32 * CHUNK_SIZE, min_y, z as f32 * CHUNK_SIZE),
+         Vector3::new((x + 1) as f32 * CHUNK_SIZE, max_y, (z + 1) as f32 * CHUNK_SIZE))
+    }
 }
 
-/// A mesh within a world chunk
+/// A mesh within a world chunk. Meshes with the same material are merged by the world builder so
+/// that they can be drawn together.
 #[derive(Clone, Readable, Writable, Debug)]
 pub struct WorldChunkMesh {
     aabb: Aabb,
     index: i32,
     vertices: Vec<f32>,
-    indices: Vec<u16>,
+    indices: WorldChunkIndices,
     material: Option<WorldChunkMaterial>
 }
 
 impl WorldChunkMesh {
     /// Create a new mesh
-    pub fn new(aabb: Aabb, index: i32, vertices: Vec<f32>, indices: Vec<u16>, material: Option<WorldChunkMaterial>)
+    pub fn new(aabb: Aabb, index: i32, vertices: Vec<f32>, indices: WorldChunkIndices, material: Option<WorldChunkMaterial>)
         -> Self
     {
         Self {
@@ -133,6 +259,32 @@ impl WorldChunkMesh {
         }
     }
 
+    /// Merge meshes into a single mesh with the material and index of the first one
+    pub fn merge(meshes: Vec<WorldChunkMesh>) -> Self {
+        assert!(!meshes.is_empty(), "WorldChunkMesh::merge: no meshes to merge");
+
+        let mut aabb = Aabb::new();
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+
+        for mesh in meshes.iter() {
+            let first_vertex = (vertices.len() / VERTEX_STRIDE) as u32;
+
+            aabb.expand_with_aabb(&mesh.aabb);
+            vertices.extend_from_slice(&mesh.vertices);
+            indices.extend(mesh.indices.iter().map(|i| first_vertex + i));
+        }
+
+        let first = meshes.into_iter().next().unwrap();
+        Self {
+            aabb,
+            index: first.index,
+            vertices,
+            indices: WorldChunkIndices::new(indices),
+            material: first.material
+        }
+    }
+
     /// Get the aabb for this mesh
     pub fn aabb(&self) -> &Aabb {
         &self.aabb
@@ -143,43 +295,375 @@ impl WorldChunkMesh {
         self.index
     }
 
+    /// Set the index of this mesh
+    pub fn set_index(&mut self, index: i32) {
+        self.index = index;
+    }
+
     /// Get the vertices of this mesh
     pub fn vertices(&self) -> &[f32] {
         &self.vertices
     }
 
     /// Get the indices of this mesh
-    pub fn indices(&self) -> &[u16] {
+    pub fn indices(&self) -> &WorldChunkIndices {
         &self.indices
     }
 
+    /// Get the vertices of this mesh mutably, e.g. to remap their texture coordinates
+    pub fn vertices_mut(&mut self) -> &mut [f32] {
+        &mut self.vertices
+    }
+
     /// Get the material of this mesh
     pub fn material(&self) -> &Option<WorldChunkMaterial> {
         &self.material
     }
+
+    /// Get the material of this mesh mutably
+    pub fn material_mut(&mut self) -> Option<&mut WorldChunkMaterial> {
+        self.material.as_mut()
+    }
 }
 
-/// A material within a world chunk
+/// A collision mesh within a world chunk. These are built separately from the render meshes by the
+/// world builder, and only have positions, with duplicate vertices welded together and degenerate
+/// triangles removed.
+#[derive(Clone, Readable, Writable, Debug)]
+pub struct WorldChunkCollisionMesh {
+    aabb: Aabb,
+    layer: CollisionLayer,
+    positions: Vec<f32>,
+    indices: WorldChunkIndices,
+}
+
+impl WorldChunkCollisionMesh {
+    /// Create a new collision mesh
+    pub fn new(aabb: Aabb, layer: CollisionLayer, positions: Vec<f32>, indices: WorldChunkIndices) -> Self {
+        Self {
+            aabb,
+            layer,
+            positions,
+            indices,
+        }
+    }
+
+    /// Get the mesh's aabb
+    pub fn aabb(&self) -> &Aabb {
+        &self.aabb
+    }
+
+    /// Get the collision layer the mesh's surfaces are on
+    pub fn layer(&self) -> CollisionLayer {
+        self.layer
+    }
+
+    /// Get the mesh's positions, 3 floats per vertex
+    pub fn positions(&self) -> &[f32] {
+        &self.positions
+    }
+
+    /// Get the mesh's indices
+    pub fn indices(&self) -> &WorldChunkIndices {
+        &self.indices
+    }
+}
+
+/// The layer a collision surface is on, which says what kind of surface it is, so that the sim can
+/// react to it and collision queries can pick what they hit
+#[derive(Copy, Clone, Readable, Writable, Debug, Default, PartialEq, Eq, Hash)]
+pub enum CollisionLayer {
+    /// Regular solid ground and walls
+    #[default]
+    Solid,
+    /// The surface of water
+    Water,
+    /// Something that can be climbed
+    Ladder,
+    /// Ground with very little friction, like ice
+    Slippery,
+    /// A wall that only blocks things other than the player, such as an npc boundary
+    NoPlayer,
+}
+
+impl CollisionLayer {
+    /// Parse a collision layer from its name in the node extras
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "solid" => Some(CollisionLayer::Solid),
+            "water" => Some(CollisionLayer::Water),
+            "ladder" => Some(CollisionLayer::Ladder),
+            "slippery" => Some(CollisionLayer::Slippery),
+            "no-player" => Some(CollisionLayer::NoPlayer),
+            _ => None
+        }
+    }
+}
+
+/// The index buffer of a world chunk mesh, which is only u32 if the mesh has too many vertices for
+/// u16 indices
 #[derive(Clone, Readable, Writable, Debug)]
+pub enum WorldChunkIndices {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl WorldChunkIndices {
+    /// Create an index buffer, using u16 indices if they're big enough
+    pub fn new(indices: Vec<u32>) -> Self {
+        if indices.iter().all(|i| *i <= u16::MAX as u32) {
+            WorldChunkIndices::U16(indices.into_iter().map(|i| i as u16).collect())
+        }
+        else {
+            WorldChunkIndices::U32(indices)
+        }
+    }
+
+    /// Get the number of indices
+    pub fn len(&self) -> usize {
+        match self {
+            WorldChunkIndices::U16(indices) => indices.len(),
+            WorldChunkIndices::U32(indices) => indices.len(),
+        }
+    }
+
+    /// Get whether there are no indices
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get an index
+    pub fn get(&self, i: usize) -> u32 {
+        match self {
+            WorldChunkIndices::U16(indices) => indices[i] as u32,
+            WorldChunkIndices::U32(indices) => indices[i],
+        }
+    }
+
+    /// Iterate over the indices
+    pub fn iter(&self) -> impl Iterator<Item=u32> + '_ {
+        (0..self.len()).map(|i| self.get(i))
+    }
+
+    /// Iterate over the triangles
+    pub fn triangles(&self) -> impl Iterator<Item=[u32; INDEX_STRIDE]> + '_ {
+        (0..self.len() / INDEX_STRIDE).map(|i| [self.get(i * 3), self.get(i * 3 + 1), self.get(i * 3 + 2)])
+    }
+
+    /// Get the size of the indices in bytes
+    pub fn size_in_bytes(&self) -> usize {
+        match self {
+            WorldChunkIndices::U16(indices) => std::mem::size_of_val(indices.as_slice()),
+            WorldChunkIndices::U32(indices) => std::mem::size_of_val(indices.as_slice()),
+        }
+    }
+}
+
+/// A material within a world chunk
+#[derive(Clone, Readable, Writable, Debug, PartialEq)]
 pub struct WorldChunkMaterial {
     base_color: WrappedVector4,
-    base_color_tex: Option<i32>
+    base_color_tex: Option<WorldChunkTextureRef>,
+    emissive: WrappedVector3,
+    emissive_tex: Option<WorldChunkTextureRef>,
+    alpha_mode: WorldChunkAlphaMode,
+    alpha_cutoff: f32,
+    double_sided: bool,
+    vertex_colors: bool
 }
 
 impl WorldChunkMaterial {
-    pub fn new(base_color: WrappedVector4, base_color_tex: Option<i32>) -> Self {
+    pub fn new(base_color: WrappedVector4, base_color_tex: Option<WorldChunkTextureRef>) -> Self {
         Self {
             base_color,
-            base_color_tex
+            base_color_tex,
+            emissive: WrappedVector3(Vector3::new(0.0, 0.0, 0.0)),
+            emissive_tex: None,
+            alpha_mode: WorldChunkAlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+            double_sided: false,
+            vertex_colors: true
         }
     }
 
+    /// Set the emissive color and texture
+    pub fn with_emissive(mut self, emissive: WrappedVector3, emissive_tex: Option<WorldChunkTextureRef>) -> Self {
+        self.emissive = emissive;
+        self.emissive_tex = emissive_tex;
+        self
+    }
+
+    /// Set the alpha mode, and the cutoff below which fragments are discarded for masked materials
+    pub fn with_alpha_mode(mut self, alpha_mode: WorldChunkAlphaMode, alpha_cutoff: f32) -> Self {
+        self.alpha_mode = alpha_mode;
+        self.alpha_cutoff = alpha_cutoff;
+        self
+    }
+
+    /// Set whether back faces should be drawn
+    pub fn with_double_sided(mut self, double_sided: bool) -> Self {
+        self.double_sided = double_sided;
+        self
+    }
+
+    /// Set whether the mesh's vertex colors, which hold its baked lighting, are used to light it
+    pub fn with_vertex_colors(mut self, vertex_colors: bool) -> Self {
+        self.vertex_colors = vertex_colors;
+        self
+    }
+
     pub fn base_color(&self) -> &WrappedVector4 {
         &self.base_color
     }
 
-    pub fn base_color_tex(&self) -> &Option<i32> {
-        &self.base_color_tex
+    pub fn base_color_tex(&self) -> Option<&WorldChunkTextureRef> {
+        self.base_color_tex.as_ref()
+    }
+
+    pub fn set_base_color_tex(&mut self, base_color_tex: Option<WorldChunkTextureRef>) {
+        self.base_color_tex = base_color_tex;
+    }
+
+    pub fn emissive(&self) -> &WrappedVector3 {
+        &self.emissive
+    }
+
+    pub fn emissive_tex(&self) -> Option<&WorldChunkTextureRef> {
+        self.emissive_tex.as_ref()
+    }
+
+    pub fn alpha_mode(&self) -> WorldChunkAlphaMode {
+        self.alpha_mode
+    }
+
+    pub fn alpha_cutoff(&self) -> f32 {
+        self.alpha_cutoff
+    }
+
+    pub fn double_sided(&self) -> bool {
+        self.double_sided
+    }
+
+    pub fn vertex_colors(&self) -> bool {
+        self.vertex_colors
+    }
+
+    /// Get all the textures the material uses
+    pub fn textures(&self) -> impl Iterator<Item=TextureIndex> + '_ {
+        self.base_color_tex.iter().chain(self.emissive_tex.iter()).map(|tex| tex.texture())
+    }
+
+    /// Change the textures the material uses, e.g. when the world builder renumbers them
+    pub fn remap_textures(&mut self, remap: impl Fn(TextureIndex) -> TextureIndex) {
+        for tex in self.base_color_tex.iter_mut().chain(self.emissive_tex.iter_mut()) {
+            tex.texture = remap(tex.texture);
+        }
+    }
+}
+
+/// How a material's alpha is used, as in gltf
+#[derive(Copy, Clone, Readable, Writable, Debug, PartialEq, Eq)]
+pub enum WorldChunkAlphaMode {
+    /// Alpha is ignored
+    Opaque,
+    /// Fragments with an alpha below the material's alpha cutoff are discarded
+    Mask,
+    /// The material is alpha blended, and drawn after the opaque parts of the world
+    Blend
+}
+
+/// A reference from a material to a world texture, along with how it should be sampled
+#[derive(Clone, Readable, Writable, Debug, PartialEq)]
+pub struct WorldChunkTextureRef {
+    texture: TextureIndex,
+    sampler: WorldChunkSampler,
+    transform: WorldChunkTextureTransform
+}
+
+impl WorldChunkTextureRef {
+    pub fn new(texture: TextureIndex, sampler: WorldChunkSampler, transform: WorldChunkTextureTransform) -> Self {
+        Self {
+            texture,
+            sampler,
+            transform
+        }
+    }
+
+    pub fn texture(&self) -> TextureIndex {
+        self.texture
+    }
+
+    pub fn sampler(&self) -> &WorldChunkSampler {
+        &self.sampler
+    }
+
+    pub fn transform(&self) -> &WorldChunkTextureTransform {
+        &self.transform
+    }
+}
+
+/// The sampler settings for a texture, as gl enums. These are stored with the reference to the
+/// texture rather than the texture itself, as the same image can be used with different samplers.
+#[derive(Copy, Clone, Readable, Writable, Debug, PartialEq, Eq)]
+pub struct WorldChunkSampler {
+    pub wrap_s: u32,
+    pub wrap_t: u32,
+    pub min_filter: u32,
+    pub mag_filter: u32
+}
+
+impl Default for WorldChunkSampler {
+    /// Repeating and unfiltered, which is what world textures used before samplers were imported
+    fn default() -> Self {
+        Self {
+            wrap_s: gl::REPEAT,
+            wrap_t: gl::REPEAT,
+            min_filter: gl::NEAREST,
+            mag_filter: gl::NEAREST
+        }
+    }
+}
+
+/// A texture coordinate transform, as in KHR_texture_transform
+#[derive(Copy, Clone, Readable, Writable, Debug, PartialEq)]
+pub struct WorldChunkTextureTransform {
+    pub offset: [f32; 2],
+    pub rotation: f32,
+    pub scale: [f32; 2]
+}
+
+impl WorldChunkTextureTransform {
+    /// Get the transform as a matrix for 2d homogeneous texture coordinates, which applies the
+    /// scale, then the rotation, then the offset
+    pub fn matrix(&self) -> Matrix3<f32> {
+        let (sin, cos) = f32::sin_cos(self.rotation);
+
+        let translation = Matrix3::new(
+            1.0, 0.0, 0.0,
+            0.0, 1.0, 0.0,
+            self.offset[0], self.offset[1], 1.0);
+
+        let rotation = Matrix3::new(
+            cos, -sin, 0.0,
+            sin, cos, 0.0,
+            0.0, 0.0, 1.0);
+
+        let scale = Matrix3::new(
+            self.scale[0], 0.0, 0.0,
+            0.0, self.scale[1], 0.0,
+            0.0, 0.0, 1.0);
+
+        translation * rotation * scale
+    }
+}
+
+impl Default for WorldChunkTextureTransform {
+    fn default() -> Self {
+        Self {
+            offset: [0.0, 0.0],
+            rotation: 0.0,
+            scale: [1.0, 1.0]
+        }
     }
 }
 
@@ -187,14 +671,20 @@ impl WorldChunkMaterial {
 #[derive(Clone, Readable, Writable, Debug)]
 pub struct WorldChunkInstance {
     mesh_name: String,
-    points: Vec<WrappedVector3>
+    points: Vec<WorldChunkInstancePoint>,
+    /// The collider of each instance before it's scaled, or None if instances of the mesh don't
+    /// collide with anything
+    collider: Option<WorldChunkInstanceCollider>,
 }
 
 impl WorldChunkInstance {
-    pub fn new(mesh_name: String, points: Vec<WrappedVector3>) -> Self {
+    pub fn new(mesh_name: String, points: Vec<WorldChunkInstancePoint>, collider: Option<WorldChunkInstanceCollider>)
+        -> Self
+    {
         Self {
             mesh_name,
-            points
+            points,
+            collider,
         }
     }
 
@@ -202,9 +692,99 @@ impl WorldChunkInstance {
         &self.mesh_name
     }
 
-    pub fn points(&self) -> &Vec<WrappedVector3> {
+    pub fn points(&self) -> &Vec<WorldChunkInstancePoint> {
         &self.points
     }
+
+    pub fn collider(&self) -> Option<&WorldChunkInstanceCollider> {
+        self.collider.as_ref()
+    }
+}
+
+/// A single instance of an instanced mesh, with its world space position, rotation and scale
+#[derive(Clone, Readable, Writable, Debug)]
+pub struct WorldChunkInstancePoint {
+    pos: WrappedVector3,
+    /// The rotation as a quaternion, in the same x, y, z, w order as gltf
+    rot: WrappedVector4,
+    scale: WrappedVector3,
+}
+
+impl WorldChunkInstancePoint {
+    pub fn new(pos: Vector3<f32>, rot: Quaternion<f32>, scale: Vector3<f32>) -> Self {
+        Self {
+            pos: WrappedVector3(pos),
+            rot: WrappedVector4(rot.v.extend(rot.s)),
+            scale: WrappedVector3(scale),
+        }
+    }
+
+    pub fn pos(&self) -> &Vector3<f32> {
+        self.pos.as_vec()
+    }
+
+    pub fn rot(&self) -> Quaternion<f32> {
+        let rot = self.rot.as_vec();
+        Quaternion::new(rot.w, rot.x, rot.y, rot.z)
+    }
+
+    pub fn scale(&self) -> &Vector3<f32> {
+        self.scale.as_vec()
+    }
+
+    /// Get the instance's model matrix
+    pub fn transform(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(*self.pos()) *
+            Matrix4::from(self.rot()) *
+            Matrix4::from_nonuniform_scale(self.scale().x, self.scale().y, self.scale().z)
+    }
+}
+
+/// The collider shared by all the instances of an instanced mesh, relative to an unscaled instance
+#[derive(Clone, Readable, Writable, Debug)]
+pub enum WorldChunkInstanceCollider {
+    /// A spheroid with an offset and radiuses
+    Spheroid(WrappedVector3, WrappedVector3),
+    /// A box with an offset and half extents
+    Box(WrappedVector3, WrappedVector3),
+    /// An upright capsule with an offset, the half height of the line between the centers of its
+    /// ends, and its radius
+    Capsule(WrappedVector3, f32, f32),
+}
+
+impl WorldChunkInstanceCollider {
+    /// Fit a collider of a given kind (one of box, spheroid or capsule) to some bounds, returning
+    /// None if the kind isn't known
+    pub fn fit(kind: &str, min: &Vector3<f32>, max: &Vector3<f32>) -> Option<Self> {
+        let center = 0.5 * min + 0.5 * max;
+        let half_extents = 0.5 * max - 0.5 * min;
+        match kind {
+            "box" => Some(WorldChunkInstanceCollider::Box(WrappedVector3(center), WrappedVector3(half_extents))),
+            "spheroid" => Some(WorldChunkInstanceCollider::Spheroid(WrappedVector3(center), WrappedVector3(half_extents))),
+            "capsule" => {
+                // The capsule is as wide as the bounds, and its rounded ends fit inside their height
+                let radius = f32::max(half_extents.x, half_extents.z);
+                let half_height = f32::max(half_extents.y - radius, 0.0);
+                Some(WorldChunkInstanceCollider::Capsule(WrappedVector3(center), half_height, radius))
+            },
+            _ => None
+        }
+    }
+
+    /// Get the collision shape for an instance with a given scale. Capsules stay round, so their
+    /// radius scales with the larger of the horizontal scales.
+    pub fn shape(&self, scale: &Vector3<f32>) -> Shape {
+        let scale_vec = |WrappedVector3(v): &WrappedVector3| vec3(v.x * scale.x, v.y * scale.y, v.z * scale.z);
+        match self {
+            WorldChunkInstanceCollider::Spheroid(offset, radius) =>
+                Shape::BoundingSpheroid(scale_vec(offset), scale_vec(radius).map(f32::abs)),
+            WorldChunkInstanceCollider::Box(offset, half_extents) =>
+                Shape::BoundingBox(scale_vec(offset), scale_vec(half_extents).map(f32::abs)),
+            WorldChunkInstanceCollider::Capsule(offset, half_height, radius) =>
+                Shape::Capsule(scale_vec(offset), f32::abs(half_height * scale.y),
+                    f32::abs(radius * f32::max(scale.x.abs(), scale.z.abs()))),
+        }
+    }
 }
 
 /// An entity to be spawned in if the player is near this chunk
@@ -239,6 +819,10 @@ impl WorldChunkEntity {
         self.entity_id
     }
 
+    pub fn set_entity_id(&mut self, entity_id: EntityId) {
+        self.entity_id = entity_id;
+    }
+
     pub fn object_id(&self) -> &str {
         &self.object_id
     }
@@ -1,25 +1,29 @@
 use std::collections::HashMap;
 
-use crate::{world::{world_chunk::{ChunkIndex, VERTEX_STRIDE, INDEX_STRIDE, WorldChunk}, WorldChunkManager, aabb::Aabb}, intersection::Shape};
+use crate::{world::{world_chunk::{ChunkIndex, WorldChunk, CollisionLayer}, WorldChunkManager, aabb::Aabb}, intersection::Shape};
+use crate::world::collision_bvh::{CollisionBvh, CollisionTriangle};
 use bevy_ecs::prelude::Entity;
-use cgmath::{Vector3, vec3, ElementWise, InnerSpace};
+use cgmath::{Vector3, Matrix3, vec3, ElementWise, InnerSpace};
 
-use crate::intersection::{self, Triangle};
+use crate::intersection::{self, Triangle, Polytope};
 
-/// A struct for storing spherecast hits
+/// A struct for storing spherecast and collider sweep hits
 pub struct SpherecastResult {
     /// The time of impact from 0..1 along the velocity
     hit_toi: f32,
     hit_point: Vector3<f32>,
-    hit_normal: Vector3<f32>
+    hit_normal: Vector3<f32>,
+    /// The layer of the surface that was hit, entities and instances are always solid
+    hit_layer: CollisionLayer,
 }
 
 impl SpherecastResult {
-    pub fn new(hit_toi: f32, hit_point: Vector3<f32>, hit_normal: Vector3<f32>) -> Self {
+    pub fn new(hit_toi: f32, hit_point: Vector3<f32>, hit_normal: Vector3<f32>, hit_layer: CollisionLayer) -> Self {
         Self {
             hit_toi,
             hit_point,
-            hit_normal
+            hit_normal,
+            hit_layer
         }
     }
 
@@ -34,158 +38,242 @@ impl SpherecastResult {
     pub fn normal(&self) -> &Vector3<f32> {
         &self.hit_normal
     }
+
+    pub fn layer(&self) -> CollisionLayer {
+        self.hit_layer
+    }
 }
 
-/// The level collision service
-pub struct WorldCollision {
-    chunk_meshes: HashMap<ChunkIndex, Option<(Aabb, Vec<(Aabb, Vec<Triangle>)>)>>,
+/// A struct for storing raycast hits
+pub struct RaycastResult {
+    /// The distance along the ray
+    hit_distance: f32,
+    hit_point: Vector3<f32>,
+    hit_normal: Vector3<f32>,
+    /// The layer of the surface that was hit, entities and instances are always solid
+    hit_layer: CollisionLayer,
+    /// The entity that was hit, if it wasn't the world or an instance
+    hit_entity: Option<Entity>,
 }
 
-impl Default for WorldCollision {
-    fn default() -> Self {
+impl RaycastResult {
+    pub fn new(hit_distance: f32, hit_point: Vector3<f32>, hit_normal: Vector3<f32>, hit_layer: CollisionLayer,
+        hit_entity: Option<Entity>) -> Self
+    {
         Self {
-            chunk_meshes: HashMap::new()
+            hit_distance,
+            hit_point,
+            hit_normal,
+            hit_layer,
+            hit_entity
         }
     }
+
+    pub fn distance(&self) -> f32 {
+        self.hit_distance
+    }
+
+    pub fn point(&self) -> &Vector3<f32> {
+        &self.hit_point
+    }
+
+    pub fn normal(&self) -> &Vector3<f32> {
+        &self.hit_normal
+    }
+
+    pub fn layer(&self) -> CollisionLayer {
+        self.hit_layer
+    }
+
+    pub fn entity(&self) -> Option<Entity> {
+        self.hit_entity
+    }
 }
 
-impl WorldCollision {
-    /// Sweep a sphere out from start to end, returning an intersection result along with a time of impact
-    pub fn sweep_sphere(&mut self, world: &mut WorldChunkManager, start: Vector3<f32>, velocity: Vector3<f32>,
-        radius: Vector3<f32>, ignore_entity: Option<Entity>) -> Option<SpherecastResult>
-    {
-        // Apply change of basis from R3 to ellipsoid space
-        let cbm = vec3(1.0 / radius.x, 1.0 / radius.y, 1.0 / radius.z);
+/// A struct for storing everything an overlap query touched
+#[derive(Default)]
+pub struct OverlapResult {
+    triangles: Vec<CollisionTriangle>,
+    /// Each entity only appears once, even if it's in several of the chunks the query walked
+    entities: Vec<Entity>,
+    /// The positions of the instances
+    instances: Vec<Vector3<f32>>,
+}
 
-        let start = start.mul_element_wise(cbm);
-        let velocity = velocity.mul_element_wise(cbm);
+impl OverlapResult {
+    pub fn triangles(&self) -> &[CollisionTriangle] {
+        &self.triangles
+    }
 
-        // Perform intersection
-        let mut result = self.sweep_unit_sphere(world, start, velocity, cbm, ignore_entity);
+    pub fn entities(&self) -> &[Entity] {
+        &self.entities
+    }
 
-        // Convert results back to R3
-        if let Some(result) = &mut result {
-            // Normal doesn't need to be transformed for a sphere
-            result.hit_point = result.hit_point.div_element_wise(cbm);
+    pub fn instances(&self) -> &[Vector3<f32>] {
+        &self.instances
+    }
+
+    /// Add an entity, or an instance if there's no entity
+    fn add_collider(&mut self, entity: Option<Entity>, pos: &Vector3<f32>) {
+        match entity {
+            Some(entity) => if !self.entities.contains(&entity) {
+                self.entities.push(entity);
+            },
+            None => self.instances.push(*pos)
         }
+    }
+}
 
-        result
+/// A struct for storing the results of closest point queries
+pub struct ClosestPointResult {
+    distance: f32,
+    point: Vector3<f32>,
+    /// The layer of the surface the point is on, entities and instances are always solid
+    layer: CollisionLayer,
+    /// The entity the point is on, if it isn't on the world or an instance
+    entity: Option<Entity>,
+}
+
+impl ClosestPointResult {
+    pub fn new(distance: f32, point: Vector3<f32>, layer: CollisionLayer, entity: Option<Entity>) -> Self {
+        Self {
+            distance,
+            point,
+            layer,
+            entity
+        }
     }
 
-    /// Sweep a unit sphere with a given change of basis matrix
-    pub fn sweep_unit_sphere(&mut self, world: &mut WorldChunkManager, start: Vector3<f32>, velocity: Vector3<f32>,
-        cbm: Vector3<f32>, ignore_entity: Option<Entity>) -> Option<SpherecastResult>
-    {
-        let end = start + velocity;
+    pub fn distance(&self) -> f32 {
+        self.distance
+    }
 
-        // Construct an aabb for the sphere's path
-        let mut sphere_path_aabb = Aabb::new();
-        sphere_path_aabb.expand_with_point(&(start - vec3(1.0, 1.0, 1.0)));
-        sphere_path_aabb.expand_with_point(&(start + vec3(1.0, 1.0, 1.0)));
-        sphere_path_aabb.expand_with_point(&(end - vec3(1.0, 1.0, 1.0)));
-        sphere_path_aabb.expand_with_point(&(end + vec3(1.0, 1.0, 1.0)));
+    pub fn point(&self) -> &Vector3<f32> {
+        &self.point
+    }
 
-        // Walk aabb bounds and find all chunks that intersect the spherecast
-        let (min, max) = sphere_path_aabb.min_max().unwrap();
-        let (chunk_min_x, chunk_min_z) = WorldChunk::point_to_chunk_index(&min.div_element_wise(cbm));
-        let (chunk_max_x, chunk_max_z) = WorldChunk::point_to_chunk_index(&max.div_element_wise(cbm));
+    pub fn layer(&self) -> CollisionLayer {
+        self.layer
+    }
 
-        //// We clip this toi by each intersection until we end up with no more intersections
-        let mut closest_intersection: Option<(f32, Vector3<f32>, Vector3<f32>)> = None;
-
-        for x in chunk_min_x..=chunk_max_x {
-            for z in chunk_min_z..=chunk_max_z {
-                if let Some((chunk_aabb, meshes)) = self.get_chunk_meshes(world, (x, z)) {
-                    let chunk_aabb = chunk_aabb.apply_cbm(cbm);
-                    if !sphere_path_aabb.intersects_aabb(&chunk_aabb) {
-                        continue;
-                    }
+    pub fn entity(&self) -> Option<Entity> {
+        self.entity
+    }
+}
 
-                    // Check each mesh in the chunk for intersections
-                    for (mesh_aabb, mesh) in meshes.iter() {
-                        let mesh_aabb = mesh_aabb.apply_cbm(cbm);
-                        if !sphere_path_aabb.intersects_aabb(&mesh_aabb) {
-                            continue;
-                        }
-
-                        for triangle in mesh.iter() {
-                            let triangle = triangle.apply_cbm(cbm);
-
-                            let res = intersection::toi_unit_sphere_triangle(start, velocity, &triangle);
-                            if let Some((toi, _, _)) = res {
-                                if let Some((closest_toi, _, _)) = closest_intersection {
-                                    if toi >= 0.0 && toi < closest_toi {
-                                        closest_intersection = res;
-                                    }
-                                }
-                                else if toi >= 0.0 {
-                                    closest_intersection = res;
-                                }
-                            }
-                        }
-                    }
+/// Something near a query that it needs to test against
+enum QueryCandidate<'a> {
+    /// A collision triangle on one of the query's layers
+    Triangle(&'a CollisionTriangle),
+    /// The position and collider of an entity, or an instance if there's no entity
+    Collider(Option<Entity>, &'a Vector3<f32>, &'a ColliderGeometry),
+}
 
-                    // Check each entity in the chunk for intersections
-                    for entity_location in world.get_entities_in_chunk((x, z)) {
-                        if let Some(entity_id) = ignore_entity {
-                            if entity_location.entity_id == entity_id {
-                                continue;
-                            }
-                            
-                        }
-
-                        let result = Self::sweep_unit_sphere_entity(start, velocity, cbm, &entity_location.pos,
-                            &entity_location.shape);
-                        if let Some((toi, _, _)) = result {
-                            if let Some((old_toi, _, _)) = closest_intersection {
-                                if toi < old_toi {
-                                    closest_intersection = result;
-                                }
-                            }
-                            else {
-                                closest_intersection = result;
-                            }
-                        }
-                    }
+/// An entity or instance's collider shape placed in the world
+enum ColliderGeometry {
+    /// The center and radius of a spheroid, which is always upright
+    Spheroid(Vector3<f32>, Vector3<f32>),
+    /// The centers of the ends of a capsule, and its radius
+    Capsule(Vector3<f32>, Vector3<f32>, f32),
+    /// The outward facing triangles of a box or convex hull, and the polytope for sweeping boxes,
+    /// capsules and hulls against it
+    Convex(Vec<Triangle>, Polytope),
+}
 
-                    // Intersect instances in the chunk
-                    if let Some(chunk) = world.get_or_load_chunk((x, z)) {
-                        let shape = Shape::BoundingSpheroid(vec3(0.0, 1.0, 0.0), vec3(1.0, 2.0, 1.0));
-                        for instance in chunk.instances().iter() {
-                            for point in instance.points().iter() {
-                                let result = Self::sweep_unit_sphere_entity(start, velocity, cbm, point.as_vec(),
-                                    &shape);
-                                if let Some((toi, _, _)) = result {
-                                    if let Some((old_toi, _, _)) = closest_intersection {
-                                        if toi < old_toi {
-                                            closest_intersection = result;
-                                        }
-                                    }
-                                    else {
-                                        closest_intersection = result;
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+impl ColliderGeometry {
+    /// Place a collider shape in the world with a given position and rotation
+    fn new(pos: &Vector3<f32>, rot: &Matrix3<f32>, shape: &Shape) -> Self {
+        match shape {
+            Shape::BoundingSpheroid(offset, radius) => ColliderGeometry::Spheroid(pos + offset, *radius),
+            Shape::BoundingBox(offset, half_extents) => {
+                let center = pos + rot * offset;
+                let corners = intersection::box_corners(&center, rot, half_extents);
+                let triangles = intersection::BOX_FACES
+                    .iter()
+                    .map(|[i0, i1, i2]| Triangle::new(corners[*i0], corners[*i1], corners[*i2]))
+                    .collect();
+
+                ColliderGeometry::Convex(triangles, Polytope::from_box(&center, rot, half_extents))
+            },
+            Shape::Capsule(offset, half_height, radius) => {
+                let (a, b) = Shape::capsule_ends(pos, rot, offset, *half_height);
+                ColliderGeometry::Capsule(a, b, *radius)
+            },
+            Shape::ConvexHull(hull) => {
+                let point = |i: usize| pos + rot * hull.points()[i];
+                let triangles: Vec<Triangle> = hull.triangles()
+                    .map(|[i0, i1, i2]| Triangle::new(point(i0), point(i1), point(i2)))
+                    .collect();
+                let polytope = Polytope::from_triangles(&triangles);
+                ColliderGeometry::Convex(triangles, polytope)
             }
         }
+    }
 
-        // If we have a closest_intersection that means there was at least one intersection, otherwise there was none
-        closest_intersection.map(|(toi, point, normal)| SpherecastResult::new(toi, point, normal))
+    /// Test whether a point is inside the collider
+    fn contains(&self, point: Vector3<f32>) -> bool {
+        match self {
+            ColliderGeometry::Spheroid(center, radius) => (point - center).div_element_wise(*radius).magnitude2() <= 1.0,
+            ColliderGeometry::Capsule(a, b, radius) => {
+                (point - intersection::closest_point_on_segment(point, *a, *b)).magnitude2() <= radius * radius
+            },
+            ColliderGeometry::Convex(triangles, _) => {
+                !triangles.is_empty() && triangles.iter().all(|t| t.normal().dot(point - t.a) <= 0.0)
+            }
+        }
     }
 
-    /// Test whether a unit sphere intersects an entity
-    fn sweep_unit_sphere_entity(start: Vector3<f32>, velocity: Vector3<f32>, cbm: Vector3<f32>, pos: &Vector3<f32>, shape: &Shape)
+    /// Sweep a unit sphere with a given change of basis matrix against the collider, returning the
+    /// time of impact along with the point and normal of the hit in ellipsoid space. A sweep that
+    /// starts out touching a box, capsule or hull hits it straight away with the normal that pushes
+    /// it back out, unless it's already moving away, so that nothing gets stuck in an entity that's
+    /// moved into it but nothing passes through it either.
+    fn sweep_unit_sphere(&self, start: Vector3<f32>, velocity: Vector3<f32>, cbm: Vector3<f32>)
         -> Option<(f32, Vector3<f32>, Vector3<f32>)>
     {
-        // TODO: take rotation into account
-        let (other_pos, other_radius) = match shape {
-            Shape::BoundingSpheroid(offset, center) => (pos + offset, center),
-            _ => panic!("sweep_unit_sphere_entity: Shape not implemented {:?}", shape)
-        };
+        match self {
+            ColliderGeometry::Spheroid(center, radius) =>
+                Self::sweep_unit_sphere_spheroid(start, velocity, cbm, *center, *radius),
+            ColliderGeometry::Capsule(a, b, radius) =>
+                Self::sweep_unit_sphere_capsule(start, velocity, cbm, *a, *b, *radius),
+            ColliderGeometry::Convex(triangles, _) => {
+                // The faces are swept against like the world's triangles
+                let triangles_es: Vec<Triangle> = triangles.iter().map(|t| t.apply_cbm(cbm)).collect();
+
+                if triangles_es.is_empty() {
+                    return None;
+                }
+
+                if self.contains(start.div_element_wise(cbm)) {
+                    // Push out through the face whose plane is closest
+                    let (t, _) = triangles_es
+                        .iter()
+                        .map(|t| (t, t.normal().dot(start - t.a)))
+                        .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+                    return Self::starting_hit(velocity, start - t.normal(), t.normal());
+                }
+
+                let closest = triangles_es
+                    .iter()
+                    .map(|t| intersection::closest_point_on_triangle(start, t))
+                    .min_by(|a, b| (a - start).magnitude2().total_cmp(&(b - start).magnitude2()))?;
+                if (closest - start).magnitude2() < 1.0 {
+                    return Self::starting_hit(velocity, closest, (start - closest).normalize());
+                }
 
+                triangles_es
+                    .iter()
+                    .filter_map(|t| intersection::toi_unit_sphere_triangle(start, velocity, t))
+                    .filter(|(toi, _, _)| *toi >= 0.0)
+                    .min_by(|(a, _, _), (b, _, _)| a.total_cmp(b))
+            }
+        }
+    }
+
+    /// Sweep a unit sphere against a spheroid
+    fn sweep_unit_sphere_spheroid(start: Vector3<f32>, velocity: Vector3<f32>, cbm: Vector3<f32>,
+        other_pos: Vector3<f32>, other_radius: Vector3<f32>) -> Option<(f32, Vector3<f32>, Vector3<f32>)>
+    {
         // Convert coordinate spaces to the space where the spheroid with combined radius = (radius1 + radius2)
         // is a unit sphere, and then we can just raycast it to find out the intersection point between the two
         // spheroids (I think this is valid, and in pratice it seems to work fine.
@@ -221,51 +309,979 @@ impl WorldCollision {
         None
     }
 
-    /// Get the meshes for a chunk, loading them if necessary from the world chunk manager
+    /// Sweep a unit sphere against a capsule. This uses the same combined radius space as spheroids,
+    /// where the capsule's radius is added to the sphere's, and the capsule becomes a line.
+    fn sweep_unit_sphere_capsule(start: Vector3<f32>, velocity: Vector3<f32>, cbm: Vector3<f32>, a: Vector3<f32>,
+        b: Vector3<f32>, radius: f32) -> Option<(f32, Vector3<f32>, Vector3<f32>)>
+    {
+        let self_to_combined_cbm = vec3(
+            1.0 / (1.0 + radius * cbm.x),
+            1.0 / (1.0 + radius * cbm.y),
+            1.0 / (1.0 + radius * cbm.z)
+            );
+
+        let world_to_combined_cbm = cbm.mul_element_wise(self_to_combined_cbm);
+        let a_combined_es = a.mul_element_wise(world_to_combined_cbm);
+        let b_combined_es = b.mul_element_wise(world_to_combined_cbm);
+
+        let start_combined_es = start.mul_element_wise(self_to_combined_cbm);
+        let vel_combined_es = velocity.mul_element_wise(self_to_combined_cbm);
+
+        let closest = intersection::closest_point_on_segment(start_combined_es, a_combined_es, b_combined_es);
+        let offset_combined_es = start_combined_es - closest;
+        if offset_combined_es.magnitude2() < 1.0 {
+            // If the sphere is right on the capsule's line there's no way out that's better than
+            // another, so it gets pushed back the way it came
+            let hit_normal_combined_es = if offset_combined_es.magnitude2() > f32::EPSILON {
+                offset_combined_es.normalize()
+            }
+            else {
+                -vel_combined_es.normalize()
+            };
+            let hit_normal = hit_normal_combined_es.div_element_wise(self_to_combined_cbm).normalize();
+            return Self::starting_hit(velocity, start - hit_normal, hit_normal);
+        }
+
+        intersection::toi_unit_sphere_segment(start_combined_es, vel_combined_es, a_combined_es, b_combined_es, 1.0)
+            .map(|(hit, hit_point_combined_es)| {
+                // As with spheroids, the normal has to be converted back to the original e-space
+                let hit_normal_combined_es = (start_combined_es + vel_combined_es * hit - hit_point_combined_es).normalize();
+                let hit_normal = hit_normal_combined_es.div_element_wise(self_to_combined_cbm).normalize();
+                let hit_point = start + velocity * hit - hit_normal;
+
+                (hit, hit_point, hit_normal)
+            })
+    }
+
+    /// The hit for a sweep that starts out touching the collider, which is ignored if the sweep is
+    /// already moving away from it
+    fn starting_hit(velocity: Vector3<f32>, hit_point: Vector3<f32>, hit_normal: Vector3<f32>)
+        -> Option<(f32, Vector3<f32>, Vector3<f32>)>
+    {
+        if velocity.dot(hit_normal) >= 0.0 || hit_normal.x.is_nan() {
+            return None;
+        }
+
+        Some((0.0, hit_point, hit_normal))
+    }
+
+    /// Cast a ray against the collider, returning the distance along it and the normal that was hit.
+    /// A ray that starts inside the collider hits it where it starts, and faces straight back.
+    fn raycast(&self, origin: Vector3<f32>, direction: Vector3<f32>) -> Option<(f32, Vector3<f32>)> {
+        if self.contains(origin) {
+            return Some((0.0, -direction));
+        }
+
+        match self {
+            ColliderGeometry::Spheroid(center, radius) => {
+                // Cast the ray in the space where the spheroid is a unit sphere, which doesn't change
+                // the distance along it. The spheroid's normal is the unit sphere's normal scaled by
+                // the inverse radius.
+                let origin_es = (origin - center).div_element_wise(*radius);
+                let direction_es = direction.div_element_wise(*radius);
+
+                intersection::toi_ray_unit_sphere(origin_es, direction_es, vec3(0.0, 0.0, 0.0)).map(|distance| {
+                    (distance, (origin_es + direction_es * distance).div_element_wise(*radius).normalize())
+                })
+            },
+            ColliderGeometry::Capsule(a, b, radius) => {
+                // Scaling by the radius turns this into sweeping a unit sphere against the capsule's line
+                let scale = 1.0 / radius;
+                let hit = intersection::toi_unit_sphere_segment(origin * scale, direction * scale, *a * scale, *b * scale,
+                    f32::MAX);
+                hit.map(|(distance, hit_point)| {
+                    (distance, (origin + direction * distance - hit_point * *radius).normalize())
+                })
+            },
+            ColliderGeometry::Convex(triangles, _) => {
+                triangles
+                    .iter()
+                    .filter_map(|t| intersection::toi_ray_triangle(origin, direction, t).map(|distance| (distance, t.normal())))
+                    .min_by(|(a, _), (b, _)| a.total_cmp(b))
+            }
+        }
+    }
+
+    /// Find the closest point on the collider to a point, which is the point itself if it's inside
+    fn closest_point(&self, point: Vector3<f32>) -> Option<Vector3<f32>> {
+        if self.contains(point) {
+            return Some(point);
+        }
+
+        match self {
+            ColliderGeometry::Spheroid(center, radius) => {
+                // This projects the point onto the unit sphere in the spheroid's space, which is exact
+                // for spheres and close enough for the slightly squashed spheroids that entities use
+                let offset_es = (point - center).div_element_wise(*radius);
+                Some(center + offset_es.normalize().mul_element_wise(*radius))
+            },
+            ColliderGeometry::Capsule(a, b, radius) => {
+                let closest = intersection::closest_point_on_segment(point, *a, *b);
+                Some(closest + (point - closest).normalize() * *radius)
+            },
+            ColliderGeometry::Convex(triangles, _) => {
+                triangles
+                    .iter()
+                    .map(|t| intersection::closest_point_on_triangle(point, t))
+                    .min_by(|a, b| (a - point).magnitude2().total_cmp(&(b - point).magnitude2()))
+            }
+        }
+    }
+
+    /// Test whether the collider touches a sphere
+    fn overlaps_sphere(&self, center: Vector3<f32>, radius: f32) -> bool {
+        match self {
+            ColliderGeometry::Spheroid(other_center, other_radius) => {
+                // As with sweeps, the sphere touches the spheroid if its center is inside the spheroid
+                // with their combined radius
+                let combined_radius = other_radius + vec3(radius, radius, radius);
+                (center - other_center).div_element_wise(combined_radius).magnitude2() <= 1.0
+            },
+            _ => self.closest_point(center).map_or(false, |closest| (closest - center).magnitude2() <= radius * radius)
+        }
+    }
+
+    /// Test whether the collider touches an aabb
+    fn overlaps_aabb(&self, min: &Vector3<f32>, max: &Vector3<f32>) -> bool {
+        match self {
+            ColliderGeometry::Spheroid(center, radius) => {
+                // Scaling by the spheroid's radius turns it into a unit sphere, and the aabb stays an aabb
+                let mut aabb_es = Aabb::new();
+                aabb_es.set_min_max(&(min - center).div_element_wise(*radius), &(max - center).div_element_wise(*radius));
+                aabb_es.intersects_sphere(&vec3(0.0, 0.0, 0.0), 1.0)
+            },
+            ColliderGeometry::Capsule(a, b, radius) => {
+                // Growing the aabb by the radius is slightly generous at its edges and corners, which
+                // should be rounded
+                let radius = vec3(*radius, *radius, *radius);
+                intersection::segment_intersects_aabb(*a, *b, &(min - radius), &(max + radius))
+            },
+            ColliderGeometry::Convex(triangles, _) => {
+                self.contains(0.5 * min + 0.5 * max)
+                    || triangles.iter().any(|t| intersection::triangle_intersects_aabb(min, max, t))
+            }
+        }
+    }
+
+    /// Sweep a box, capsule or hull's collider by a velocity against this collider, given the moving
+    /// collider's polytope, returning the time of impact along with the point and normal of the hit
+    fn sweep_collider(&self, moving: &ColliderGeometry, moving_polytope: &Polytope, velocity: Vector3<f32>)
+        -> Option<(f32, Vector3<f32>, Vector3<f32>)>
+    {
+        match self {
+            ColliderGeometry::Spheroid(center, radius) => {
+                // Sweeping the spheroid backwards against the moving collider hits at the same time, from
+                // the other side
+                let cbm = radius.map(|r| 1.0 / r);
+                moving.sweep_unit_sphere(center.mul_element_wise(cbm), -velocity.mul_element_wise(cbm), cbm)
+                    .map(|(toi, point_es, normal_es)| {
+                        (toi, point_es.div_element_wise(cbm) + velocity * toi, -normal_es.mul_element_wise(cbm).normalize())
+                    })
+            },
+            ColliderGeometry::Capsule(a, b, radius) =>
+                Self::sweep_polytope(moving_polytope, velocity, &Polytope::from_capsule(*a, *b, *radius)),
+            ColliderGeometry::Convex(_, polytope) => Self::sweep_polytope(moving_polytope, velocity, polytope),
+        }
+    }
+
+    /// Sweep a polytope by a velocity against another, returning the time of impact along with the
+    /// point and normal of the hit, where the point is the moving polytope's furthest point into the hit
+    fn sweep_polytope(moving: &Polytope, velocity: Vector3<f32>, other: &Polytope)
+        -> Option<(f32, Vector3<f32>, Vector3<f32>)>
+    {
+        moving.sweep(velocity, other).map(|(toi, normal)| (toi, moving.support(-normal) + velocity * toi, normal))
+    }
+
+    /// Get an estimate of the memory used by the collider's triangles
+    fn memory_usage(&self) -> usize {
+        match self {
+            ColliderGeometry::Convex(triangles, _) => std::mem::size_of_val(triangles.as_slice()),
+            _ => 0
+        }
+    }
+}
+
+/// A set of collision layers, for picking which surfaces a collision query hits
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CollisionLayers(u32);
+
+impl CollisionLayers {
+    /// Every layer
+    pub const ALL: CollisionLayers = CollisionLayers(u32::MAX);
+
+    /// The layers the player collides with. The player wades through water rather than standing on
+    /// it, and passes through walls that are only there to block everything else.
+    pub const PLAYER: CollisionLayers = CollisionLayers::ALL
+        .without(CollisionLayer::Water)
+        .without(CollisionLayer::NoPlayer);
+
+    /// The layers rigid bodies collide with. They sink through water, but are blocked by walls that
+    /// are only there to block everything but the player.
+    pub const RIGID_BODY: CollisionLayers = CollisionLayers::ALL
+        .without(CollisionLayer::Water);
+
+    /// Get this set of layers without the given layer
+    pub const fn without(self, layer: CollisionLayer) -> Self {
+        CollisionLayers(self.0 & !(1 << layer as u32))
+    }
+
+    /// Get whether this set contains a layer
+    pub fn contains(&self, layer: CollisionLayer) -> bool {
+        self.0 & (1 << layer as u32) != 0
+    }
+}
+
+/// An instance's collider placed in the world, along with the instance's position and the collider's
+/// aabb
+struct InstanceCollider {
+    pos: Vector3<f32>,
+    aabb: Aabb,
+    geometry: ColliderGeometry,
+}
+
+/// The collision meshes for a chunk, as the chunk's aabb along with a bvh of all of their triangles,
+/// and the colliders of the chunk's instances
+pub struct ChunkCollisionMeshes {
+    aabb: Aabb,
+    bvh: CollisionBvh,
+    instances: Vec<InstanceCollider>,
+}
+
+impl ChunkCollisionMeshes {
+    /// Get an estimate of the memory used by the chunk's collision meshes
+    pub fn memory_usage(&self) -> usize {
+        let instance_triangles: usize = self.instances.iter().map(|instance| instance.geometry.memory_usage()).sum();
+        self.bvh.memory_usage() + std::mem::size_of_val(self.instances.as_slice()) + instance_triangles
+    }
+}
+
+/// The level collision service
+pub struct WorldCollision {
+    chunk_meshes: HashMap<ChunkIndex, Option<ChunkCollisionMeshes>>,
+}
+
+impl Default for WorldCollision {
+    fn default() -> Self {
+        Self {
+            chunk_meshes: HashMap::new()
+        }
+    }
+}
+
+impl WorldCollision {
+    /// Sweep a sphere out from start to end, returning an intersection result along with a time of impact
+    pub fn sweep_sphere(&mut self, world: &mut WorldChunkManager, start: Vector3<f32>, velocity: Vector3<f32>,
+        radius: Vector3<f32>, layers: CollisionLayers, ignore_entity: Option<Entity>) -> Option<SpherecastResult>
+    {
+        // Apply change of basis from R3 to ellipsoid space
+        let cbm = vec3(1.0 / radius.x, 1.0 / radius.y, 1.0 / radius.z);
+
+        let start = start.mul_element_wise(cbm);
+        let velocity = velocity.mul_element_wise(cbm);
+
+        // Perform intersection
+        let mut result = self.sweep_unit_sphere(world, start, velocity, cbm, layers, ignore_entity);
+
+        // Convert results back to R3
+        if let Some(result) = &mut result {
+            // Normal doesn't need to be transformed for a sphere
+            result.hit_point = result.hit_point.div_element_wise(cbm);
+        }
+
+        result
+    }
+
+    /// Sweep a unit sphere with a given change of basis matrix, hitting only the surfaces on the
+    /// given layers. Entities and instances are solid.
+    pub fn sweep_unit_sphere(&mut self, world: &mut WorldChunkManager, start: Vector3<f32>, velocity: Vector3<f32>,
+        cbm: Vector3<f32>, layers: CollisionLayers, ignore_entity: Option<Entity>) -> Option<SpherecastResult>
+    {
+        // Construct an aabb for the sphere's path
+        let sphere_path_aabb = Aabb::from_unit_sphere_sweep(&start, &velocity);
+
+        // Take the path's aabb back out of ellipsoid space to find what's near it
+        let (min, max) = sphere_path_aabb.min_max().unwrap();
+        let (world_min, world_max) = (min.div_element_wise(cbm), max.div_element_wise(cbm));
+
+        //// We clip this toi by each intersection until we end up with no more intersections
+        let mut closest_intersection: Option<(f32, Vector3<f32>, Vector3<f32>, CollisionLayer)> = None;
+
+        self.walk_chunks(world, &world_min, &world_max, layers, ignore_entity, |candidate| {
+            let result = match candidate {
+                QueryCandidate::Triangle(collision_triangle) => {
+                    let triangle = collision_triangle.triangle.apply_cbm(cbm);
+                    intersection::toi_unit_sphere_triangle(start, velocity, &triangle)
+                        .map(|(toi, point, normal)| (toi, point, normal, collision_triangle.layer))
+                },
+                QueryCandidate::Collider(_, _, geometry) => geometry.sweep_unit_sphere(start, velocity, cbm)
+                    .map(|(toi, point, normal)| (toi, point, normal, CollisionLayer::Solid))
+            };
+
+            if let Some((toi, _, _, _)) = result {
+                let closer = closest_intersection.map_or(true, |(closest_toi, _, _, _)| toi < closest_toi);
+                if toi >= 0.0 && closer {
+                    closest_intersection = result;
+                }
+            }
+        });
+
+        // If we have a closest_intersection that means there was at least one intersection, otherwise there was none
+        closest_intersection.map(|(toi, point, normal, layer)| SpherecastResult::new(toi, point, normal, layer))
+    }
+
+    /// Sweep a collider shape with a given position and rotation, hitting only the surfaces on the
+    /// given layers. Entities and instances are solid. Unlike the sphere sweeps, the hit point and
+    /// normal are in world space. Boxes, capsules and hulls are swept with the separating axis test.
+    pub fn sweep_collider(&mut self, world: &mut WorldChunkManager, pos: &Vector3<f32>, rot: &Matrix3<f32>,
+        shape: &Shape, velocity: Vector3<f32>, layers: CollisionLayers, ignore_entity: Option<Entity>)
+        -> Option<SpherecastResult>
+    {
+        let geometry = ColliderGeometry::new(pos, rot, shape);
+        let polytope = match &geometry {
+            ColliderGeometry::Spheroid(center, radius) => {
+                // The normal is from the hit point to the spheroid's center in ellipsoid space, which
+                // is right for edges and corners too, and scales the opposite way to points out of it
+                let cbm = radius.map(|r| 1.0 / r);
+                return self.sweep_sphere(world, *center, velocity, *radius, layers, ignore_entity)
+                    .map(|hit| {
+                        let normal_es = (center + velocity * hit.toi() - hit.point()).mul_element_wise(cbm);
+                        let normal_es = match normal_es.magnitude2() > 0.0 {
+                            true => normal_es.normalize(),
+                            false => *hit.normal()
+                        };
+                        SpherecastResult::new(hit.toi(), *hit.point(), normal_es.mul_element_wise(cbm).normalize(),
+                            hit.layer())
+                    });
+            },
+            ColliderGeometry::Capsule(a, b, radius) => Polytope::from_capsule(*a, *b, *radius),
+            ColliderGeometry::Convex(_, polytope) => polytope.clone(),
+        };
+
+        // Construct an aabb for the collider's path
+        let (min, max) = shape.world_aabb(pos, rot);
+        let (path_min, path_max) = (Aabb::vec_min(&min, &(min + velocity)), Aabb::vec_max(&max, &(max + velocity)));
+
+        let mut closest_intersection: Option<(f32, Vector3<f32>, Vector3<f32>, CollisionLayer)> = None;
+
+        self.walk_chunks(world, &path_min, &path_max, layers, ignore_entity, |candidate| {
+            let result = match candidate {
+                QueryCandidate::Triangle(collision_triangle) => {
+                    let triangle = Polytope::from_triangle(&collision_triangle.triangle);
+                    ColliderGeometry::sweep_polytope(&polytope, velocity, &triangle)
+                        .map(|(toi, point, normal)| (toi, point, normal, collision_triangle.layer))
+                },
+                QueryCandidate::Collider(_, _, collider) => collider.sweep_collider(&geometry, &polytope, velocity)
+                    .map(|(toi, point, normal)| (toi, point, normal, CollisionLayer::Solid))
+            };
+
+            if let Some((toi, _, _, _)) = result {
+                let closer = closest_intersection.map_or(true, |(closest_toi, _, _, _)| toi < closest_toi);
+                if toi >= 0.0 && closer {
+                    closest_intersection = result;
+                }
+            }
+        });
+
+        closest_intersection.map(|(toi, point, normal, layer)| SpherecastResult::new(toi, point, normal, layer))
+    }
+
+    /// Cast a ray, returning the closest surface, entity or instance on the given layers that it hits
+    /// within max_distance. Entities and instances are solid.
+    pub fn raycast(&mut self, world: &mut WorldChunkManager, origin: Vector3<f32>, direction: Vector3<f32>,
+        max_distance: f32, layers: CollisionLayers, ignore_entity: Option<Entity>) -> Option<RaycastResult>
+    {
+        if direction.magnitude2() == 0.0 {
+            return None;
+        }
+
+        let direction = direction.normalize();
+        let end = origin + direction * max_distance;
+
+        let mut closest_hit: Option<RaycastResult> = None;
+
+        self.walk_chunks(world, &Aabb::vec_min(&origin, &end), &Aabb::vec_max(&origin, &end), layers, ignore_entity,
+            |candidate| {
+                let hit = match candidate {
+                    QueryCandidate::Triangle(collision_triangle) => {
+                        let triangle = &collision_triangle.triangle;
+                        intersection::toi_ray_triangle(origin, direction, triangle).map(|distance| {
+                            // Triangles are hit from either side, so face the normal back along the ray
+                            let normal = triangle.normal();
+                            let normal = if normal.dot(direction) > 0.0 { -normal } else { normal };
+                            RaycastResult::new(distance, origin + direction * distance, normal, collision_triangle.layer,
+                                None)
+                        })
+                    },
+                    QueryCandidate::Collider(entity, _, geometry) => {
+                        geometry.raycast(origin, direction).map(|(distance, normal)| {
+                            RaycastResult::new(distance, origin + direction * distance, normal, CollisionLayer::Solid,
+                                entity)
+                        })
+                    }
+                };
+
+                if let Some(hit) = hit {
+                    let closer = closest_hit.as_ref().map_or(true, |closest| hit.distance() < closest.distance());
+                    if hit.distance() <= max_distance && closer {
+                        closest_hit = Some(hit);
+                    }
+                }
+            });
+
+        closest_hit
+    }
+
+    /// Find all of the triangles on the given layers, entities and instances that touch a sphere.
+    /// Entities and instances are solid.
+    pub fn overlap_sphere(&mut self, world: &mut WorldChunkManager, center: Vector3<f32>, radius: f32,
+        layers: CollisionLayers, ignore_entity: Option<Entity>) -> OverlapResult
+    {
+        let extents = vec3(radius, radius, radius);
+        let mut result = OverlapResult::default();
+
+        self.walk_chunks(world, &(center - extents), &(center + extents), layers, ignore_entity, |candidate| {
+            match candidate {
+                QueryCandidate::Triangle(collision_triangle) => {
+                    let closest = intersection::closest_point_on_triangle(center, &collision_triangle.triangle);
+                    if (closest - center).magnitude2() <= radius * radius {
+                        result.triangles.push(collision_triangle.clone());
+                    }
+                },
+                QueryCandidate::Collider(entity, pos, geometry) => {
+                    if geometry.overlaps_sphere(center, radius) {
+                        result.add_collider(entity, pos);
+                    }
+                }
+            }
+        });
+
+        result
+    }
+
+    /// Find all of the triangles on the given layers, entities and instances that touch an aabb.
+    /// Entities and instances are solid.
+    pub fn overlap_aabb(&mut self, world: &mut WorldChunkManager, min: Vector3<f32>, max: Vector3<f32>,
+        layers: CollisionLayers, ignore_entity: Option<Entity>) -> OverlapResult
+    {
+        let mut result = OverlapResult::default();
+
+        self.walk_chunks(world, &min, &max, layers, ignore_entity, |candidate| {
+            match candidate {
+                QueryCandidate::Triangle(collision_triangle) => {
+                    if intersection::triangle_intersects_aabb(&min, &max, &collision_triangle.triangle) {
+                        result.triangles.push(collision_triangle.clone());
+                    }
+                },
+                QueryCandidate::Collider(entity, pos, geometry) => {
+                    if geometry.overlaps_aabb(&min, &max) {
+                        result.add_collider(entity, pos);
+                    }
+                }
+            }
+        });
+
+        result
+    }
+
+    /// Find the closest point to a point within max_distance on the surfaces on the given layers, and
+    /// on entities and instances, which are solid. A point inside an entity is its own closest point.
+    pub fn closest_point(&mut self, world: &mut WorldChunkManager, point: Vector3<f32>, max_distance: f32,
+        layers: CollisionLayers, ignore_entity: Option<Entity>) -> Option<ClosestPointResult>
+    {
+        let extents = vec3(max_distance, max_distance, max_distance);
+        let mut closest: Option<ClosestPointResult> = None;
+
+        self.walk_chunks(world, &(point - extents), &(point + extents), layers, ignore_entity, |candidate| {
+            let (closest_point, layer, entity) = match candidate {
+                QueryCandidate::Triangle(collision_triangle) => {
+                    let closest_point = intersection::closest_point_on_triangle(point, &collision_triangle.triangle);
+                    (closest_point, collision_triangle.layer, None)
+                },
+                QueryCandidate::Collider(entity, _, geometry) => match geometry.closest_point(point) {
+                    Some(closest_point) => (closest_point, CollisionLayer::Solid, entity),
+                    None => return
+                }
+            };
+
+            let distance = (closest_point - point).magnitude();
+            let closer = closest.as_ref().map_or(true, |closest| distance < closest.distance());
+            if distance <= max_distance && closer {
+                closest = Some(ClosestPointResult::new(distance, closest_point, layer, entity));
+            }
+        });
+
+        closest
+    }
+
+    /// Walk the chunks overlapping an aabb, calling a function for the collision triangles on the
+    /// given layers near it, and for the entities and instances in those chunks if the layers
+    /// include solid surfaces. Entities can be in several chunks, so they may be visited more than
+    /// once.
+    fn walk_chunks(&mut self, world: &mut WorldChunkManager, min: &Vector3<f32>, max: &Vector3<f32>,
+        layers: CollisionLayers, ignore_entity: Option<Entity>, mut f: impl FnMut(QueryCandidate))
+    {
+        let mut query_aabb = Aabb::new();
+        query_aabb.set_min_max(min, max);
+
+        // Entities and instances are solid, so they're only hit if solid surfaces are
+        let hit_solid = layers.contains(CollisionLayer::Solid);
+
+        // Instance colliders can reach out of their instance's chunk, so look that far into the
+        // chunks around the query for them
+        let reach = if hit_solid { world.instance_collider_reach() } else { 0.0 };
+        let padding = vec3(reach, reach, reach);
+
+        for chunk_index in WorldChunk::chunks_between(&(min - padding), &(max + padding), world.chunk_height()) {
+            let meshes = self.get_chunk_meshes(world, chunk_index).as_ref();
+
+            // Check the triangles in the chunk near the query
+            if let Some(meshes) = meshes.filter(|meshes| query_aabb.intersects_aabb(&meshes.aabb)) {
+                meshes.bvh.query(min, max, |collision_triangle| {
+                    if layers.contains(collision_triangle.layer) {
+                        f(QueryCandidate::Triangle(collision_triangle));
+                    }
+                });
+            }
+
+            if !hit_solid {
+                continue;
+            }
+
+            // Check each entity in the chunk. Entities are tracked separately from the chunk's contents,
+            // so this is done even if the chunk has nothing else in it.
+            for entity_location in world.get_entities_in_chunk(chunk_index) {
+                if Some(entity_location.entity_id) != ignore_entity {
+                    let geometry = ColliderGeometry::new(&entity_location.pos, &entity_location.rot,
+                        &entity_location.shape);
+                    f(QueryCandidate::Collider(Some(entity_location.entity_id), &entity_location.pos, &geometry));
+                }
+            }
+
+            // Check the colliders of the instances in the chunk near the query
+            for instance in meshes.iter().flat_map(|meshes| meshes.instances.iter()) {
+                if query_aabb.intersects_aabb(&instance.aabb) {
+                    f(QueryCandidate::Collider(None, &instance.pos, &instance.geometry));
+                }
+            }
+        }
+    }
+
+    /// Unload the meshes for a chunk, e.g. because the world chunk manager evicted it
+    pub fn unload_chunk(&mut self, chunk_index: ChunkIndex) {
+        self.chunk_meshes.remove(&chunk_index);
+    }
+
+    /// Get the meshes for a chunk, loading them if necessary from the world chunk manager. The
+    /// collision meshes are normally built by the chunk loader threads along with the chunk. If
+    /// the chunk is still loading we wait for it, as the sim has to see the same world every time
+    /// for it to be deterministic.
     fn get_chunk_meshes(&mut self, world: &mut WorldChunkManager, chunk_index: ChunkIndex)
-        -> &Option<(Aabb, Vec<(Aabb, Vec<Triangle>)>)>
+        -> &Option<ChunkCollisionMeshes>
     {
+        // The chunk manager doesn't know we're using the chunk when the meshes are already cached, so
+        // mark it as used, otherwise it could be evicted and have to be reloaded in the middle of a
+        // query
+        world.touch_chunk(chunk_index);
+
         self.chunk_meshes
             .entry(chunk_index)
-            .or_insert_with(|| { Self::load_chunk_meshes(world, chunk_index) })
+            .or_insert_with(|| {
+                let meshes = world.take_collision_meshes(chunk_index);
+                meshes.or_else(|| {
+                    world.get_or_load_chunk(chunk_index)
+                        .as_ref()
+                        .map(|chunk| Self::build_chunk_meshes(chunk, chunk_index))
+                })
+            })
     }
 
-    /// Load the meshes for a chunk from the world chunk manager
-    fn load_chunk_meshes(world: &mut WorldChunkManager, chunk_index: ChunkIndex)
-        -> Option<(Aabb, Vec<(Aabb, Vec<Triangle>)>)>
-    {
-        world.get_or_load_chunk(chunk_index)
-            .as_ref()
-            .map(|chunk| {
-                log::info!("Loading {} chunk meshes for chunk {}, {}", chunk.meshes().len(), chunk_index.0, chunk_index.1);
-
-                let meshes = chunk.meshes().iter().map(|mesh| {
-                    let vertices = mesh.vertices();
-
-                    let mut triangles = Vec::with_capacity(mesh.indices().len() / INDEX_STRIDE);
-
-                    assert!(mesh.indices().len() % INDEX_STRIDE == 0);
-                    for i in mesh.indices().chunks(INDEX_STRIDE) {
-                        let i0 = i[0] as usize * VERTEX_STRIDE;
-                        let i1 = i[1] as usize * VERTEX_STRIDE;
-                        let i2 = i[2] as usize * VERTEX_STRIDE;
-
-                        let v1 = &vertices[i0..i0+3];
-                        let v2 = &vertices[i1..i1+3];
-                        let v3 = &vertices[i2..i2+3];
-
-                        triangles.push(Triangle::new(
-                            vec3(v1[0], v1[1], v1[2]),
-                            vec3(v2[0], v2[1], v2[2]),
-                            vec3(v3[0], v3[1], v3[2])
-                        ));
-                    }
+    /// Build the collision meshes for a chunk, putting all of their triangles in one bvh and placing
+    /// its instances' colliders in the world. This is normally done by the chunk loader threads, so
+    /// it doesn't hold up the sim.
+    pub fn build_chunk_meshes(chunk: &WorldChunk, chunk_index: ChunkIndex) -> ChunkCollisionMeshes {
+        log::info!("Loading {} collision meshes for chunk {}, {}, {}", chunk.collision_meshes().len(), chunk_index.0,
+            chunk_index.1, chunk_index.2);
 
-                    // TODO: we could make the builder generate collision specific meshes
-                    (mesh.aabb().clone(), triangles)
-                }).collect();
+        let triangles = chunk.collision_meshes().iter().flat_map(|mesh| {
+            let positions = mesh.positions();
+            let position = move |i: u32| {
+                let offset = i as usize * 3;
+                vec3(positions[offset], positions[offset + 1], positions[offset + 2])
+            };
 
-                (chunk.aabb().clone(), meshes)
+            mesh.indices()
+                .triangles()
+                .map(move |[i0, i1, i2]| CollisionTriangle {
+                    triangle: Triangle::new(position(i0), position(i1), position(i2)),
+                    layer: mesh.layer(),
+                })
+        }).collect();
+
+        let instances = chunk.instances()
+            .iter()
+            .filter_map(|instance| instance.collider().map(|collider| (instance, collider)))
+            .flat_map(|(instance, collider)| instance.points().iter().map(move |point| {
+                let rot = Matrix3::from(point.rot());
+                let shape = collider.shape(point.scale());
+                let (min, max) = shape.world_aabb(point.pos(), &rot);
+                let mut aabb = Aabb::new();
+                aabb.set_min_max(&min, &max);
+
+                InstanceCollider {
+                    pos: *point.pos(),
+                    aabb,
+                    geometry: ColliderGeometry::new(point.pos(), &rot, &shape),
+                }
+            }))
+            .collect();
+
+        ChunkCollisionMeshes {
+            aabb: chunk.aabb().clone(),
+            bvh: CollisionBvh::build(triangles),
+            instances,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+    use cgmath::{SquareMatrix, MetricSpace};
+    use crate::world::chunk_source::{ChunkSource, ChunkSourceError};
+    use crate::components::Transform;
+    use crate::intersection::{Collider, ConvexHull};
+    use crate::world::world_build_manifest::{WorldBuildManifest, WORLD_BUILD_MANIFEST_FILENAME};
+    use crate::world::world_chunk::{WorldChunkInstance, WorldChunkInstanceCollider, WorldChunkInstancePoint,
+        DEFAULT_CHUNK_HEIGHT};
+    use cgmath::Quaternion;
+    use speedy::Writable;
+
+    /// A chunk source with no chunks, so the only triangles are the ones the tests add
+    struct NoChunks;
+
+    impl ChunkSource for NoChunks {
+        fn read_file(&self, _filename: &str) -> Result<Option<Cow<'_, [u8]>>, ChunkSourceError> {
+            Ok(None)
+        }
+
+        fn describe(&self) -> String {
+            "no chunks".to_string()
+        }
+    }
+
+    /// A horizontal square covering chunk 0, 0, 0 at a given height
+    fn square(y: f32, layer: CollisionLayer) -> [CollisionTriangle; 2] {
+        let (a, b, c, d) = (vec3(0.0, y, 0.0), vec3(16.0, y, 0.0), vec3(16.0, y, 16.0), vec3(0.0, y, 16.0));
+        [
+            CollisionTriangle { triangle: Triangle::new(a, c, b), layer },
+            CollisionTriangle { triangle: Triangle::new(a, d, c), layer },
+        ]
+    }
+
+    /// A chunk source holding a world's files in memory
+    struct MemoryChunks(HashMap<String, Vec<u8>>);
+
+    impl ChunkSource for MemoryChunks {
+        fn read_file(&self, filename: &str) -> Result<Option<Cow<'_, [u8]>>, ChunkSourceError> {
+            Ok(self.0.get(filename).map(|data| Cow::Borrowed(data.as_slice())))
+        }
+
+        fn describe(&self) -> String {
+            "world chunks in memory".to_string()
+        }
+    }
+
+    /// A world with solid ground at y = 2 and the surface of water at y = 6 in chunk 0, 0, 0
+    fn ground_and_water() -> (WorldChunkManager, WorldCollision) {
+        let world = WorldChunkManager::new(Box::new(NoChunks));
+
+        let triangles = square(2.0, CollisionLayer::Solid).into_iter()
+            .chain(square(6.0, CollisionLayer::Water))
+            .collect();
+        let mut chunk_aabb = Aabb::new();
+        chunk_aabb.set_min_max(&vec3(0.0, 0.0, 0.0), &vec3(16.0, 16.0, 16.0));
+
+        let mut collision = WorldCollision::default();
+        collision.chunk_meshes.insert((0, 0, 0), Some(ChunkCollisionMeshes {
+            aabb: chunk_aabb,
+            bvh: CollisionBvh::build(triangles),
+            instances: Vec::new(),
+        }));
+        (world, collision)
+    }
+
+    #[test]
+    fn raycast_hits_the_closest_surface_on_its_layers() {
+        let (mut world, mut collision) = ground_and_water();
+        let (origin, down) = (vec3(8.0, 10.0, 8.0), vec3(0.0, -2.0, 0.0));
+
+        let hit = collision.raycast(&mut world, origin, down, 10.0, CollisionLayers::ALL, None).unwrap();
+        assert!((hit.distance() - 4.0).abs() < 0.001, "distance {}", hit.distance());
+        assert!(hit.point().distance(vec3(8.0, 6.0, 8.0)) < 0.001, "point {:?}", hit.point());
+        assert!(hit.normal().distance(vec3(0.0, 1.0, 0.0)) < 0.001, "normal {:?}", hit.normal());
+        assert_eq!(hit.layer(), CollisionLayer::Water);
+        assert_eq!(hit.entity(), None);
+
+        let hit = collision.raycast(&mut world, origin, down, 10.0, CollisionLayers::PLAYER, None).unwrap();
+        assert!((hit.distance() - 8.0).abs() < 0.001, "distance {}", hit.distance());
+        assert_eq!(hit.layer(), CollisionLayer::Solid);
+    }
+
+    #[test]
+    fn raycast_faces_the_normal_back_along_the_ray() {
+        let (mut world, mut collision) = ground_and_water();
+        let hit = collision.raycast(&mut world, vec3(8.0, 4.0, 8.0), vec3(0.0, 1.0, 0.0), 10.0, CollisionLayers::ALL,
+            None).unwrap();
+
+        assert!((hit.distance() - 2.0).abs() < 0.001, "distance {}", hit.distance());
+        assert!(hit.normal().distance(vec3(0.0, -1.0, 0.0)) < 0.001, "normal {:?}", hit.normal());
+    }
+
+    #[test]
+    fn raycast_misses() {
+        let (mut world, mut collision) = ground_and_water();
+        let down = vec3(0.0, -1.0, 0.0);
+
+        // Too short, sideways, pointing away and without a direction
+        assert!(collision.raycast(&mut world, vec3(8.0, 10.0, 8.0), down, 3.0, CollisionLayers::ALL, None).is_none());
+        assert!(collision.raycast(&mut world, vec3(8.0, 4.0, 8.0), vec3(1.0, 0.0, 0.0), 6.0, CollisionLayers::ALL,
+            None).is_none());
+        assert!(collision.raycast(&mut world, vec3(8.0, 10.0, 8.0), -down, 4.0, CollisionLayers::ALL, None).is_none());
+        assert!(collision.raycast(&mut world, vec3(8.0, 10.0, 8.0), vec3(0.0, 0.0, 0.0), 10.0, CollisionLayers::ALL,
+            None).is_none());
+    }
+
+    #[test]
+    fn sweep_collider_lands_a_box_on_the_ground() {
+        let (mut world, mut collision) = ground_and_water();
+        let shape = Shape::BoundingBox(vec3(0.0, 0.5, 0.0), vec3(0.5, 0.5, 0.5));
+
+        // The box's base is at its position, so it lands flat on the solid ground at y = 2
+        let hit = collision.sweep_collider(&mut world, &vec3(8.0, 4.0, 8.0), &Matrix3::identity(), &shape,
+            vec3(0.0, -4.0, 0.0), CollisionLayers::PLAYER, None).unwrap();
+        assert!((hit.toi() - 0.5).abs() < 0.001, "toi {}", hit.toi());
+        assert!((hit.point().y - 2.0).abs() < 0.001, "point {:?}", hit.point());
+        assert!(hit.normal().distance(vec3(0.0, 1.0, 0.0)) < 0.001, "normal {:?}", hit.normal());
+        assert_eq!(hit.layer(), CollisionLayer::Solid);
+
+        // Spheroids get their normal in world space too
+        let spheroid = Shape::BoundingSpheroid(vec3(0.0, 0.0, 0.0), vec3(0.5, 1.0, 0.5));
+        let hit = collision.sweep_collider(&mut world, &vec3(8.0, 4.0, 8.0), &Matrix3::identity(), &spheroid,
+            vec3(0.0, -4.0, 0.0), CollisionLayers::PLAYER, None).unwrap();
+        assert!((hit.toi() - 0.25).abs() < 0.001, "toi {}", hit.toi());
+        assert!(hit.normal().distance(vec3(0.0, 1.0, 0.0)) < 0.001, "normal {:?}", hit.normal());
+    }
+
+    #[test]
+    fn closest_point_on_its_layers() {
+        let (mut world, mut collision) = ground_and_water();
+
+        let closest = collision.closest_point(&mut world, vec3(8.0, 5.0, 8.0), 4.0, CollisionLayers::ALL, None)
+            .unwrap();
+        assert!((closest.distance() - 1.0).abs() < 0.001, "distance {}", closest.distance());
+        assert!(closest.point().distance(vec3(8.0, 6.0, 8.0)) < 0.001, "point {:?}", closest.point());
+        assert_eq!(closest.layer(), CollisionLayer::Water);
+
+        let closest = collision.closest_point(&mut world, vec3(8.0, 5.0, 8.0), 4.0, CollisionLayers::PLAYER, None)
+            .unwrap();
+        assert!((closest.distance() - 3.0).abs() < 0.001, "distance {}", closest.distance());
+        assert!(closest.point().distance(vec3(8.0, 2.0, 8.0)) < 0.001, "point {:?}", closest.point());
+        assert_eq!(closest.layer(), CollisionLayer::Solid);
+    }
+
+    #[test]
+    fn raycast_and_closest_point_on_colliders() {
+        let (distance, normal) = unit_box().raycast(vec3(4.0, 0.5, 0.0), vec3(-1.0, 0.0, 0.0)).unwrap();
+        assert!((distance - 3.0).abs() < 0.001, "distance {distance}");
+        assert!(normal.distance(vec3(1.0, 0.0, 0.0)) < 0.001, "normal {normal:?}");
+        assert!(unit_box().raycast(vec3(4.0, 2.0, 0.0), vec3(-1.0, 0.0, 0.0)).is_none());
+
+        let closest = capsule().closest_point(vec3(3.0, 0.5, 0.0)).unwrap();
+        assert!(closest.distance(vec3(0.5, 0.5, 0.0)) < 0.001, "closest {closest:?}");
+
+        // A point inside a collider is its own closest point
+        let closest = unit_box().closest_point(vec3(0.5, 0.5, 0.5)).unwrap();
+        assert!(closest.distance(vec3(0.5, 0.5, 0.5)) < 0.001, "closest {closest:?}");
+    }
+
+    #[test]
+    fn raycast_hits_entities_that_change_shape() {
+        let mut world = WorldChunkManager::new(Box::new(NoChunks));
+        let mut collision = WorldCollision::default();
+        let entity = Entity::from_raw(1);
+        let transform = Transform::new(vec3(8.0, 4.0, 8.0), Matrix3::identity());
+
+        let mut collider = Collider::new(Shape::BoundingBox(vec3(0.0, 0.0, 0.0), vec3(1.0, 1.0, 1.0)));
+        world.update_entity_location(entity, &transform, &mut collider, None);
+        let hit = collision.raycast(&mut world, vec3(12.0, 4.0, 8.0), vec3(-1.0, 0.0, 0.0), 10.0, CollisionLayers::ALL, None)
+            .unwrap();
+        assert!((hit.distance() - 3.0).abs() < 0.001, "distance {}", hit.distance());
+
+        // The hull around a bigger box
+        let corners = (0..8)
+            .map(|i| {
+                let sign = |bit: usize| if i & bit != 0 { 2.0 } else { -2.0 };
+                vec3(sign(1), sign(2), sign(4))
             })
+            .collect();
+        collider.shape = Shape::ConvexHull(ConvexHull::new(corners));
+        world.update_entity_location(entity, &transform, &mut collider, None);
+        let hit = collision.raycast(&mut world, vec3(12.0, 4.0, 8.0), vec3(-1.0, 0.0, 0.0), 10.0, CollisionLayers::ALL, None)
+            .unwrap();
+        assert!((hit.distance() - 2.0).abs() < 0.001, "distance {}", hit.distance());
+        assert_eq!(hit.entity(), Some(entity));
+    }
+
+    #[test]
+    fn instance_colliders_reaching_into_neighbouring_chunks() {
+        // A crate at the edge of chunk 0, 0, 0, which reaches 1.5m into chunk 1, 0, 0
+        let collider = WorldChunkInstanceCollider::fit("box", &vec3(-2.0, 0.0, -2.0), &vec3(2.0, 4.0, 2.0));
+        let point = WorldChunkInstancePoint::new(vec3(15.5, 2.0, 8.0), Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            vec3(1.0, 1.0, 1.0));
+        let mut chunk = WorldChunk::new();
+        chunk.add_instances(WorldChunkInstance::new("crate".to_string(), vec![point], collider));
+        assert_eq!(chunk.instance_collider_reach((0, 0, 0), DEFAULT_CHUNK_HEIGHT), 1.5);
+
+        let manifest = WorldBuildManifest::new(Vec::new(), Vec::new(), false, DEFAULT_CHUNK_HEIGHT, 1.5, Vec::new(),
+            Default::default());
+        let files = HashMap::from([
+            (WorldChunk::filename((0, 0, 0)), chunk.write_to_file_data((0, 0, 0), DEFAULT_CHUNK_HEIGHT).unwrap()),
+            (WORLD_BUILD_MANIFEST_FILENAME.to_string(), manifest.write_to_vec().unwrap()),
+        ]);
+        let mut world = WorldChunkManager::new(Box::new(MemoryChunks(files)));
+        let mut collision = WorldCollision::default();
+
+        // Queries entirely inside chunk 1, 0, 0 still find it
+        let hit = collision.raycast(&mut world, vec3(17.0, 10.0, 8.0), vec3(0.0, -1.0, 0.0), 20.0, CollisionLayers::ALL,
+            None).unwrap();
+        assert!((hit.distance() - 4.0).abs() < 0.001, "distance {}", hit.distance());
+        assert_eq!(hit.entity(), None);
+
+        let overlap = collision.overlap_sphere(&mut world, vec3(17.0, 4.0, 8.0), 0.5, CollisionLayers::ALL, None);
+        assert_eq!(overlap.instances(), &[vec3(15.5, 2.0, 8.0)]);
+
+        // But not once they're past it
+        let overlap = collision.overlap_sphere(&mut world, vec3(18.5, 4.0, 8.0), 0.5, CollisionLayers::ALL, None);
+        assert!(overlap.instances().is_empty());
+    }
+
+    #[test]
+    fn closest_point_within_max_distance() {
+        let (mut world, mut collision) = ground_and_water();
+        assert!(collision.closest_point(&mut world, vec3(8.0, 4.0, 8.0), 1.5, CollisionLayers::ALL, None).is_none());
+
+        // Off the edge of the ground, the closest point is on its edge
+        let closest = collision.closest_point(&mut world, vec3(-1.0, 2.0, 8.0), 1.5, CollisionLayers::ALL, None)
+            .unwrap();
+        assert!(closest.point().distance(vec3(0.0, 2.0, 8.0)) < 0.001, "point {:?}", closest.point());
+    }
+
+    fn unit_box() -> ColliderGeometry {
+        ColliderGeometry::new(&vec3(0.0, 0.0, 0.0), &Matrix3::identity(), &Shape::BoundingBox(vec3(0.0, 0.0, 0.0),
+            vec3(1.0, 1.0, 1.0)))
+    }
+
+    fn capsule() -> ColliderGeometry {
+        ColliderGeometry::new(&vec3(0.0, 0.0, 0.0), &Matrix3::identity(), &Shape::Capsule(vec3(0.0, 0.0, 0.0), 1.0, 0.5))
+    }
+
+    const UNIT: Vector3<f32> = vec3(1.0, 1.0, 1.0);
+
+    #[test]
+    fn sweep_box_from_outside() {
+        let (toi, point, normal) = unit_box().sweep_unit_sphere(vec3(4.0, 0.0, 0.0), vec3(-4.0, 0.0, 0.0), UNIT)
+            .unwrap();
+
+        assert!((toi - 0.5).abs() < 0.001, "toi {toi}");
+        assert!(point.distance(vec3(1.0, 0.0, 0.0)) < 0.001, "point {point:?}");
+        assert!(normal.distance(vec3(1.0, 0.0, 0.0)) < 0.001, "normal {normal:?}");
+    }
+
+    #[test]
+    fn sweep_box_misses() {
+        assert!(unit_box().sweep_unit_sphere(vec3(4.0, 3.0, 0.0), vec3(-8.0, 0.0, 0.0), UNIT).is_none());
+    }
+
+    #[test]
+    fn sweep_box_starting_inside_pushes_out() {
+        // Just inside the top face, moving further in
+        let (toi, _, normal) = unit_box().sweep_unit_sphere(vec3(0.0, 0.8, 0.0), vec3(0.0, -1.0, 0.0), UNIT)
+            .unwrap();
+
+        assert_eq!(toi, 0.0);
+        assert!(normal.distance(vec3(0.0, 1.0, 0.0)) < 0.001, "normal {normal:?}");
+    }
+
+    #[test]
+    fn sweep_box_starting_touching_pushes_out() {
+        let (toi, _, normal) = unit_box().sweep_unit_sphere(vec3(1.5, 0.0, 0.0), vec3(-1.0, 0.0, 0.0), UNIT)
+            .unwrap();
+
+        assert_eq!(toi, 0.0);
+        assert!(normal.distance(vec3(1.0, 0.0, 0.0)) < 0.001, "normal {normal:?}");
+    }
+
+    #[test]
+    fn sweep_box_starting_touching_moving_away() {
+        assert!(unit_box().sweep_unit_sphere(vec3(1.5, 0.0, 0.0), vec3(1.0, 0.0, 0.0), UNIT).is_none());
+        assert!(unit_box().sweep_unit_sphere(vec3(0.0, 0.8, 0.0), vec3(0.0, 1.0, 0.0), UNIT).is_none());
+    }
+
+    #[test]
+    fn sweep_capsule_from_outside() {
+        let (toi, _, normal) = capsule().sweep_unit_sphere(vec3(4.0, 0.5, 0.0), vec3(-4.0, 0.0, 0.0), UNIT).unwrap();
+
+        // The sphere touches the capsule's side when its center is 1.5 from the axis
+        assert!((toi - 2.5 / 4.0).abs() < 0.001, "toi {toi}");
+        assert!(normal.distance(vec3(1.0, 0.0, 0.0)) < 0.001, "normal {normal:?}");
+    }
+
+    #[test]
+    fn sweep_capsule_starting_touching() {
+        let (toi, _, normal) = capsule().sweep_unit_sphere(vec3(1.2, 0.0, 0.0), vec3(-1.0, 0.0, 0.0), UNIT).unwrap();
+        assert_eq!(toi, 0.0);
+        assert!(normal.distance(vec3(1.0, 0.0, 0.0)) < 0.001, "normal {normal:?}");
+
+        assert!(capsule().sweep_unit_sphere(vec3(1.2, 0.0, 0.0), vec3(1.0, 0.0, 0.0), UNIT).is_none());
+    }
+
+    #[test]
+    fn sweep_box_collider_against_colliders() {
+        let moving = ColliderGeometry::new(&vec3(4.0, 0.0, 0.0), &Matrix3::identity(),
+            &Shape::BoundingBox(vec3(0.0, 0.0, 0.0), vec3(0.5, 0.5, 0.5)));
+        let polytope = match &moving {
+            ColliderGeometry::Convex(_, polytope) => polytope.clone(),
+            _ => unreachable!()
+        };
+        let velocity = vec3(-4.0, 0.0, 0.0);
+
+        // The box's face meets the other box's face when it's moved 2.5
+        let (toi, point, normal) = unit_box().sweep_collider(&moving, &polytope, velocity).unwrap();
+        assert!((toi - 2.5 / 4.0).abs() < 0.001, "toi {toi}");
+        assert!((point.x - 1.0).abs() < 0.001, "point {point:?}");
+        assert!(normal.distance(vec3(1.0, 0.0, 0.0)) < 0.001, "normal {normal:?}");
+
+        // And the side of a capsule or a spheroid when it's moved 3
+        let (toi, _, normal) = capsule().sweep_collider(&moving, &polytope, velocity).unwrap();
+        assert!((toi - 3.0 / 4.0).abs() < 0.001, "toi {toi}");
+        assert!(normal.distance(vec3(1.0, 0.0, 0.0)) < 0.001, "normal {normal:?}");
+
+        let spheroid = ColliderGeometry::new(&vec3(0.0, 0.0, 0.0), &Matrix3::identity(),
+            &Shape::BoundingSpheroid(vec3(0.0, 0.0, 0.0), vec3(0.5, 2.0, 0.5)));
+        let (toi, point, normal) = spheroid.sweep_collider(&moving, &polytope, velocity).unwrap();
+        assert!((toi - 3.0 / 4.0).abs() < 0.001, "toi {toi}");
+        assert!(point.distance(vec3(0.5, 0.0, 0.0)) < 0.001, "point {point:?}");
+        assert!(normal.distance(vec3(1.0, 0.0, 0.0)) < 0.001, "normal {normal:?}");
+    }
+
+    #[test]
+    fn sweep_spheroid() {
+        let spheroid = ColliderGeometry::new(&vec3(0.0, 0.0, 0.0), &Matrix3::identity(),
+            &Shape::BoundingSpheroid(vec3(0.0, 0.0, 0.0), vec3(1.0, 1.0, 1.0)));
+        let (toi, _, normal) = spheroid.sweep_unit_sphere(vec3(4.0, 0.0, 0.0), vec3(-4.0, 0.0, 0.0), UNIT).unwrap();
+
+        assert!((toi - 0.5).abs() < 0.001, "toi {toi}");
+        assert!(normal.distance(vec3(1.0, 0.0, 0.0)) < 0.001, "normal {normal:?}");
     }
 }
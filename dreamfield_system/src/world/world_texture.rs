@@ -1,36 +1,90 @@
+use std::borrow::Cow;
 use speedy::{Readable, Writable};
+use super::chunk_file::{ChunkFile, ChunkFileIndex, ChunkFileSection, ChunkFileError};
 
 pub type TextureIndex = i32;
 
+/// The pixel data of a world texture, in whichever format the world builder was asked to store it
+#[derive(Readable, Writable, Clone, Debug)]
+pub enum WorldTextureData {
+    /// 8 bits per component rgba
+    Rgba8(Vec<u8>),
+    /// 16 bit pixels with 5 bits per color component and a 1 bit alpha, packed as r << 11 | g << 6
+    /// | b << 1 | a, like gl's UNSIGNED_SHORT_5_5_5_1
+    Rgba5551(Vec<u16>),
+    /// An 8 bit index per pixel into an rgba8 palette of up to 256 colors
+    Clut8 { palette: Vec<[u8; 4]>, indices: Vec<u8> },
+    /// A 4 bit index per pixel into an rgba8 palette of up to 16 colors, with two pixels per byte,
+    /// the first in the low nibble
+    Clut4 { palette: Vec<[u8; 4]>, indices: Vec<u8> },
+}
+
 /// A single world texture
 #[derive(Readable, Writable)]
 pub struct WorldTexture {
-    data: Vec<u8>,
-    format: u32,
+    data: WorldTextureData,
     width: u32,
     height: u32,
     index: TextureIndex
 }
 
 impl WorldTexture {
-    pub fn new(pixels: Vec<u8>, format: u32, width: u32, height: u32, index: TextureIndex) -> Self {
+    pub fn new(data: WorldTextureData, width: u32, height: u32, index: TextureIndex) -> Self {
         Self {
-            data: pixels,
-            format,
+            data,
             width,
             height,
             index
         }
     }
 
-    /// Get the pixels in rgba8 format
-    pub fn pixels(&self) -> &[u8] {
+    /// Get the stored pixel data
+    pub fn data(&self) -> &WorldTextureData {
         &self.data
     }
 
-    /// Get the gl format
+    /// Get the pixels for uploading to gl, in format() and pixel_type(). Palettized textures are
+    /// expanded to rgba8.
+    pub fn pixels(&self) -> Cow<'_, [u8]> {
+        match &self.data {
+            WorldTextureData::Rgba8(pixels) => Cow::Borrowed(pixels),
+            WorldTextureData::Rgba5551(pixels) => Cow::Owned(pixels.iter().flat_map(|p| p.to_ne_bytes()).collect()),
+            WorldTextureData::Clut8 { palette, indices } => {
+                Cow::Owned(indices.iter().flat_map(|i| palette[*i as usize]).collect())
+            }
+            WorldTextureData::Clut4 { palette, indices } => {
+                let pixel_count = (self.width * self.height) as usize;
+                Cow::Owned(indices
+                    .iter()
+                    .flat_map(|i| [i & 0xf, i >> 4])
+                    .take(pixel_count)
+                    .flat_map(|i| palette[i as usize])
+                    .collect())
+            }
+        }
+    }
+
+    /// Get the gl format of pixels()
     pub fn format(&self) -> u32 {
-        self.format
+        gl::RGBA
+    }
+
+    /// Get the gl type of pixels()
+    pub fn pixel_type(&self) -> u32 {
+        match &self.data {
+            WorldTextureData::Rgba5551(_) => gl::UNSIGNED_SHORT_5_5_5_1,
+            _ => gl::UNSIGNED_BYTE
+        }
+    }
+
+    /// Get the size of the stored pixel data in bytes
+    pub fn size_in_bytes(&self) -> usize {
+        match &self.data {
+            WorldTextureData::Rgba8(pixels) => pixels.len(),
+            WorldTextureData::Rgba5551(pixels) => std::mem::size_of_val(pixels.as_slice()),
+            WorldTextureData::Clut8 { palette, indices } | WorldTextureData::Clut4 { palette, indices } =>
+                std::mem::size_of_val(palette.as_slice()) + indices.len(),
+        }
     }
 
     /// Get the width
@@ -48,6 +102,23 @@ impl WorldTexture {
         self.index
     }
 
+    /// Set the index, e.g. when the world builder renumbers textures
+    pub fn set_index(&mut self, index: TextureIndex) {
+        self.index = index;
+    }
+
+    /// Read a texture from a chunk file, checking that it's the texture we expected
+    pub fn read_from_file(data: &[u8], texture_index: TextureIndex) -> Result<Self, ChunkFileError> {
+        ChunkFile::read(data, ChunkFileIndex::Texture(texture_index))?.section(ChunkFileSection::Texture)
+    }
+
+    /// Write the texture to a chunk file, for a world split with the given chunk height
+    pub fn write_to_file_data(&self, chunk_height: Option<f32>) -> Result<Vec<u8>, speedy::Error> {
+        Ok(ChunkFile::write(ChunkFileIndex::Texture(self.index), chunk_height, &[
+            (ChunkFileSection::Texture, self.write_to_vec()?),
+        ]))
+    }
+
     /// Get the chunk filename for a given texture index
     pub fn filename(texture_index: TextureIndex) -> String {
         format!("texture_{}.texture", texture_index)
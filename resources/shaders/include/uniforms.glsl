@@ -26,10 +26,24 @@ layout (std140) uniform GlobalParams
 };
 
 // Materials
+#define ALPHA_MODE_OPAQUE 0
+#define ALPHA_MODE_MASK 1
+#define ALPHA_MODE_BLEND 2
+
 layout (std140) uniform MaterialParams
 {
     bool has_base_color_texture;
     vec4 base_color;
+    mat3 base_color_uv_transform;
+
+    bool has_emissive_texture;
+    vec3 emissive;
+    mat3 emissive_uv_transform;
+
+    int alpha_mode;
+    float alpha_cutoff;
+
+    bool use_vertex_color;
 };
 
 // Lights
@@ -158,6 +158,7 @@ void main() {
 #ifdef BUILDING_FRAGMENT_SHADER
 
 uniform sampler2D tex_base_color;
+uniform sampler2D tex_emissive;
 
 noperspective in float frag_dist;
 noperspective in vec3 frag_world_pos;
@@ -169,17 +170,27 @@ out vec4 out_frag_color;
 
 void main() {
     // Sample base color texture and calculate base color and alpha
-    vec4 base_color_tex = has_base_color_texture ? texture(tex_base_color, frag_uv) : vec4(1.0);
+    vec2 base_color_uv = (base_color_uv_transform * vec3(frag_uv, 1.0)).xy;
+    vec4 base_color_tex = has_base_color_texture ? texture(tex_base_color, base_color_uv) : vec4(1.0);
     vec3 albedo = base_color.rgb * base_color_tex.rgb;
     float alpha = base_color.a * base_color_tex.a;
 
-    // Alpha clip low opacity fragments
-    if (alpha < 0.1)
+    // Ignore alpha for opaque materials, and alpha clip fragments below the cutoff for masked ones
+    if (alpha_mode == ALPHA_MODE_OPAQUE)
+        alpha = 1.0;
+    else if (alpha_mode == ALPHA_MODE_MASK && alpha < alpha_cutoff)
         discard;
 
-    // Calculate vertex lighting for fragment
+    // Sample emissive texture and calculate emitted light, which isn't affected by the lighting
+    vec2 emissive_uv = (emissive_uv_transform * vec3(frag_uv, 1.0)).xy;
+    vec3 emissive_tex = has_emissive_texture ? texture(tex_emissive, emissive_uv).rgb : vec3(1.0);
+    vec3 emitted = emissive * emissive_tex;
+
+    // Calculate vertex lighting for fragment, or light it fully if the material doesn't use the
+    // vertex colors
     const vec3 AMBIENT_LIGHT = vec3(0.00);
-    vec3 light = frag_light * lighting_strength + AMBIENT_LIGHT;
+    vec3 vertex_light = use_vertex_color ? frag_light : vec3(1.0);
+    vec3 light = vertex_light * lighting_strength + AMBIENT_LIGHT;
 
     // Calculate foggedness of fragment
     float fog_factor = fog_dist.y > 0.0 && fog_dist.y > fog_dist.x ?
@@ -187,7 +198,7 @@ void main() {
         : 0.0;
 
     // Multiply the light by the albedo and get the fragment's color and value
-    vec3 pre_dither_color = light * albedo;
+    vec3 pre_dither_color = light * albedo + emitted;
     float pre_dither_value = luma(pre_dither_color);
 
     // Calculate the dithering strength using the value, skewing it exponentially towards 1
@@ -4,4 +4,5 @@ pub enum AppState {
     TitleScreen,
     MainGame,
     Paused,
+    ControlsMenu,
 }
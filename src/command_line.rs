@@ -0,0 +1,84 @@
+use std::path::PathBuf;
+
+/// The number of updates to run in headless mode if no count is given (one minute at 15hz)
+const DEFAULT_HEADLESS_UPDATES: u32 = 15 * 60;
+
+/// Command line options
+#[derive(Default)]
+pub struct CommandLine {
+    /// Run the sim headless for this many updates, instead of creating a window
+    pub headless_updates: Option<u32>,
+    /// Record the input to this file, starting straight in the main game
+    pub record_path: Option<PathBuf>,
+    /// Replay an input recording headless, and check it ends up in the same state
+    pub replay_path: Option<PathBuf>,
+    /// Load the world chunks from this directory or chunk pack, instead of the built in ones
+    pub world_path: Option<PathBuf>,
+}
+
+impl CommandLine {
+    /// Parse the process's command line arguments
+    pub fn parse() -> Self {
+        Self::parse_args(std::env::args().skip(1))
+    }
+
+    /// Parse command line arguments, not including the program name
+    fn parse_args(args: impl Iterator<Item=String>) -> Self {
+        let mut options = Self::default();
+        let mut args = args.peekable();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--headless" => {
+                    // The count is optional, so only take the next argument if it is one, otherwise
+                    // it's another option
+                    let count = args.peek().and_then(|count| count.parse().ok());
+                    if count.is_some() {
+                        args.next();
+                    }
+                    else {
+                        log::warn!("--headless expects an update count, defaulting to {DEFAULT_HEADLESS_UPDATES}");
+                    }
+                    options.headless_updates = Some(count.unwrap_or(DEFAULT_HEADLESS_UPDATES));
+                }
+                "--record" | "--replay" => {
+                    match (arg.as_str(), args.next()) {
+                        ("--record", Some(path)) => options.record_path = Some(path.into()),
+                        (_, Some(path)) => options.replay_path = Some(path.into()),
+                        (_, None) => log::warn!("{arg} expects a filename"),
+                    }
+                }
+                "--world" => {
+                    match args.next() {
+                        Some(path) => options.world_path = Some(path.into()),
+                        None => log::warn!("--world expects a directory or chunk pack"),
+                    }
+                }
+                _ => {
+                    log::warn!("Ignoring unknown command line argument {arg}");
+                }
+            }
+        }
+
+        options
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> CommandLine {
+        CommandLine::parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn headless_count_is_optional() {
+        assert_eq!(parse(&["--headless", "50"]).headless_updates, Some(50));
+        assert_eq!(parse(&["--headless"]).headless_updates, Some(DEFAULT_HEADLESS_UPDATES));
+
+        let options = parse(&["--headless", "--world", "world.pack"]);
+        assert_eq!(options.headless_updates, Some(DEFAULT_HEADLESS_UPDATES));
+        assert_eq!(options.world_path, Some(PathBuf::from("world.pack")));
+    }
+}
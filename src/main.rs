@@ -2,11 +2,19 @@ mod sim;
 mod resources;
 mod states;
 mod app_state;
+mod command_line;
+mod save_game;
 
+use std::path::Path;
 use bevy_ecs::prelude::*;
 use bevy_ecs::world::World;
-use dreamfield_system::GameHost;
+use dreamfield_system::{GameHost, HeadlessGameHost};
+use dreamfield_system::resources::{InputState, InputName, Diagnostics};
+use dreamfield_system::replay::{InputRecording, RecordedPlayerState, RecordingStart};
+use dreamfield_system::world::WorldChunkManager;
 use app_state::AppState;
+use command_line::CommandLine;
+use save_game::{SaveGameSettings, SavedEntityStates};
 
 /// The initial app state
 const INITIAL_STATE: AppState = AppState::SplashScreen;
@@ -17,10 +25,17 @@ const FIXED_UPDATE: i32 = 15;
 /// The fixed update target time
 const FIXED_UPDATE_TIME: f64 = 1.0 / (FIXED_UPDATE as f64);
 
+/// How far the end state of a replay can be from the recorded end state before it counts as diverged
+const REPLAY_TOLERANCE: f32 = 0.001;
+
 // Create update schedule
-fn create_update_schedule(world: &mut World) -> Schedule {
-    // Add app state with initial value SplashScreen
-    world.insert_resource(State::new(INITIAL_STATE));
+fn create_update_schedule(world: &mut World, initial_state: AppState) -> Schedule {
+    // Add app state with initial value
+    world.insert_resource(State::new(initial_state));
+
+    // Add save game resources, the caller can insert its own settings first to disable saving
+    world.init_resource::<SaveGameSettings>();
+    world.init_resource::<SavedEntityStates>();
 
     // Create main update stage, right now this has to be one big stage, because the app state
     // can't be shared between stages
@@ -31,8 +46,15 @@ fn create_update_schedule(world: &mut World) -> Schedule {
     states::title_screen::init_title_screen(&mut update_stage);
     states::main_game::init_main_game(&mut update_stage);
     states::pause_menu::init_pause_menu(&mut update_stage);
+    states::controls_menu::init_controls_menu(&mut update_stage);
 
-    Schedule::default().with_stage("main_update", update_stage)
+    // Create cleanup stage, which runs after the main update stage has applied its commands
+    let cleanup_stage = SystemStage::parallel()
+        .with_system_set(dreamfield_system::cleanup_systems());
+
+    Schedule::default()
+        .with_stage("main_update", update_stage)
+        .with_stage_after("main_update", "cleanup", cleanup_stage)
 }
 
 /// Entry point
@@ -41,9 +63,28 @@ fn main() {
     env_logger::init();
     log::info!("Welcome to Dreamfield!");
 
+    // Parse command line
+    let command_line = CommandLine::parse();
+
+    if let Some(update_count) = command_line.headless_updates {
+        run_headless(update_count, command_line.world_path.as_deref());
+        return;
+    }
+
+    if let Some(path) = &command_line.replay_path {
+        let succeeded = run_replay(path, command_line.world_path.as_deref());
+        std::process::exit(if succeeded { 0 } else { 1 });
+    }
+
     // Create game host
     let mut host = GameHost::new(None, FIXED_UPDATE_TIME);
 
+    // Recordings start straight in the main game, so that replays start from the same state
+    let initial_state = match &command_line.record_path {
+        Some(_) => AppState::MainGame,
+        None => INITIAL_STATE
+    };
+
     // Create bevy world
     let mut world = World::default();
 
@@ -54,16 +95,120 @@ fn main() {
         resources::create_shader_manager(),
         resources::create_texture_manager(),
         resources::create_font_manager(),
-        resources::create_world_chunk_manager());
+        resources::create_world_chunk_manager(command_line.world_path.as_deref()));
+
+    // Don't load or save while recording, so that replays start from the same state
+    world.insert_resource(SaveGameSettings::new(command_line.record_path.is_none()));
+
+    if let Some(path) = &command_line.record_path {
+        log::info!("Recording input to {}", path.display());
+        host.record_input(path, recording_start(&world));
+    }
 
     // Create update schedule
-    let update_schedule = create_update_schedule(&mut world);
+    let update_schedule = create_update_schedule(&mut world, initial_state);
 
     // Create render schedule
     let render_schedule = Schedule::default()
         .with_stage("render", SystemStage::single_threaded()
             .with_system_set(dreamfield_renderer::systems()));
 
-    // Run game
-    host.run(world, update_schedule, render_schedule);
+    // Run game, and save when the window is closed during the main game
+    let mut world = host.run(world, update_schedule, render_schedule);
+    save_game::save_game_on_exit(&mut world);
+}
+
+/// Get what the main game will start from in this world, for input recordings to record and for
+/// replays to check against
+fn recording_start(world: &World) -> RecordingStart {
+    let saved = save_game::saved_player_and_hash(world.resource::<SaveGameSettings>());
+    let (player_pos, player_pitch_yaw) = states::main_game::player_start(saved.as_ref().map(|(player, _)| player));
+
+    RecordingStart {
+        player: RecordedPlayerState::new(player_pos, player_pitch_yaw),
+        world_hash: world.resource::<WorldChunkManager>().world_hash(),
+        save_game_hash: saved.map(|(_, hash)| hash),
+    }
+}
+
+/// Run the sim headless for a number of updates, starting straight in the main game. The player
+/// walks forwards while turning back and forth, so that collision, entity spawning and the
+/// minecart all get exercised without needing a display.
+fn run_headless(update_count: u32, world_path: Option<&Path>) {
+    log::info!("Running headless for {update_count} updates");
+
+    // Create bevy world, we only need the system resources and the world chunks as nothing gets
+    // rendered
+    let mut world = World::default();
+    dreamfield_system::init(&mut world);
+    world.insert_resource(resources::create_world_chunk_manager(world_path));
+    world.insert_resource(SaveGameSettings::new(false));
+
+    // Create update schedule, skipping the splash screen and title screen
+    let mut update_schedule = create_update_schedule(&mut world, AppState::MainGame);
+
+    // Create input script
+    let frames = (0..update_count).map(|i| {
+        let mut input = InputState::new();
+        input.set_held(InputName::CamForwards, true);
+        input.set_held(InputName::CamLookLeft, (i / 45) % 2 == 0);
+        input.set_held(InputName::CamLookRight, (i / 45) % 2 == 1);
+        input
+    });
+
+    // Advance the virtual clock by one timestep per frame
+    let mut host = HeadlessGameHost::new(FIXED_UPDATE_TIME, FIXED_UPDATE_TIME);
+    let updates_run = host.run(&mut world, &mut update_schedule, frames);
+
+    let diagnostics = world.resource::<Diagnostics>();
+    log::info!("Headless run finished after {} updates ({:.1}s sim time), player at {:.1}, {:.1}, {:.1}",
+        updates_run, host.sim_time(), diagnostics.player_pos.x, diagnostics.player_pos.y, diagnostics.player_pos.z);
+}
+
+/// Replay an input recording headless, returning whether it ended up in the same state as when it
+/// was recorded
+fn run_replay(path: &Path, world_path: Option<&Path>) -> bool {
+    let recording = match InputRecording::load(path) {
+        Ok(recording) => recording,
+        Err(err) => {
+            log::error!("Failed to load input recording {}: {err}", path.display());
+            return false;
+        }
+    };
+
+    log::info!("Replaying {} updates from {}", recording.update_count(), path.display());
+
+    // Set up the world the same way as recording did, but without the renderer
+    let mut world = World::default();
+    dreamfield_system::init(&mut world);
+    world.insert_resource(resources::create_world_chunk_manager(world_path));
+    world.insert_resource(SaveGameSettings::new(false));
+    let mut update_schedule = create_update_schedule(&mut world, AppState::MainGame);
+
+    // Only replay from the same start as the recording, otherwise it's bound to diverge
+    if let Err(err) = recording.start().check(&recording_start(&world), REPLAY_TOLERANCE) {
+        log::error!("Refusing to replay {}: {err}", path.display());
+        return false;
+    }
+
+    let mut host = HeadlessGameHost::new(FIXED_UPDATE_TIME, FIXED_UPDATE_TIME);
+    host.run_recording(&mut world, &mut update_schedule, &recording);
+
+    // Compare the end state
+    let diagnostics = world.resource::<Diagnostics>();
+    let end_state = RecordedPlayerState::new(diagnostics.player_pos, diagnostics.player_pitch_yaw);
+    match recording.end_state() {
+        Some(expected) if expected.approx_eq(&end_state, REPLAY_TOLERANCE) => {
+            log::info!("Replay matched the recording, player at {:?}", end_state.player_pos);
+            true
+        }
+        Some(expected) => {
+            log::error!("Replay diverged from the recording: expected {:?}, got {:?}", expected, end_state);
+            false
+        }
+        None => {
+            log::warn!("Recording has no end state to compare against, player at {:?}", end_state.player_pos);
+            true
+        }
+    }
 }
@@ -1,16 +1,28 @@
+use std::path::Path;
 use include_dir::{include_dir, Dir};
 
 use dreamfield_macros::*;
 use dreamfield_renderer::resources::{ShaderManager, TextureManager, ModelManager, FontManager};
 use dreamfield_renderer::gl_backend::TextureParams;
 use dreamfield_system::world::WorldChunkManager;
+use dreamfield_system::world::chunk_source::{ChunkSource, EmbeddedChunkSource, open_chunk_source};
 
-/// The world chunks
-const WORLD_CHUNKS: Dir<'_> = include_dir!("target/world_chunks");
+/// The world chunks built into the binary, used unless another world is given on the command line
+static WORLD_CHUNKS: Dir<'_> = include_dir!("target/world_chunks");
 
-/// Create the world chunk manager
-pub fn create_world_chunk_manager() -> WorldChunkManager {
-    WorldChunkManager::new(&WORLD_CHUNKS)
+/// Create the world chunk manager, loading chunks from a directory or chunk pack if a path is given,
+/// and from the world chunks built into the binary otherwise
+pub fn create_world_chunk_manager(world_path: Option<&Path>) -> WorldChunkManager {
+    let source: Box<dyn ChunkSource> = match world_path.map(open_chunk_source) {
+        Some(Ok(source)) => source,
+        Some(Err(err)) => {
+            log::error!("Failed to open world {}, using the built in world: {err}", world_path.unwrap().display());
+            Box::new(EmbeddedChunkSource::new(&WORLD_CHUNKS))
+        }
+        None => Box::new(EmbeddedChunkSource::new(&WORLD_CHUNKS))
+    };
+
+    WorldChunkManager::new(source)
 }
 
 /// Create the shader manager
@@ -44,6 +56,7 @@ pub fn create_model_manager() -> ModelManager {
         ("elf", include_bytes!("../resources/models/elf.glb")),
         ("minecart", include_bytes!("../resources/models/minecart.glb")),
         ("capsule", include_bytes!("../resources/models/capsule.glb")),
+        ("crate", include_bytes!("../resources/models/crate.glb")),
     ])
 }
 
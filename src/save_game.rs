@@ -0,0 +1,326 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use bevy_ecs::prelude::*;
+use cgmath::{Vector3, Matrix3};
+use speedy::{Readable, Writable};
+use dreamfield_system::components::Transform;
+use dreamfield_system::systems::entity_spawner::{EntitySpawnResource, SpawnedWorldEntity};
+use dreamfield_system::world::world_chunk::{EntityId, WorldChunkEntity};
+use dreamfield_system::world::world_build_manifest::hash_contents;
+use dreamfield_system::file_header::FileHeader;
+use crate::app_state::AppState;
+use crate::sim::{PlayerMovement, Minecart};
+
+/// The default save game filename
+pub const SAVE_GAME_FILENAME: &'static str = "dreamfield.save";
+
+/// The save game format version, bump this whenever the format changes
+pub const SAVE_GAME_VERSION: u32 = 2;
+
+/// The header at the start of save games
+const SAVE_GAME_HEADER: FileHeader = FileHeader::new(*b"DFSG", SAVE_GAME_VERSION);
+
+/// The save game settings resource
+pub struct SaveGameSettings {
+    /// Whether to load and save the game, this is disabled for headless runs and input recordings
+    /// so that they always start from the same state
+    pub enabled: bool,
+    pub path: PathBuf,
+}
+
+impl SaveGameSettings {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            path: SAVE_GAME_FILENAME.into(),
+        }
+    }
+}
+
+impl Default for SaveGameSettings {
+    fn default() -> Self {
+        Self::new(true)
+    }
+}
+
+/// The saved player state
+#[derive(Readable, Writable, Clone, Debug)]
+pub struct SavedPlayer {
+    pub pos: [f32; 3],
+    pub pitch_yaw: [f32; 2],
+    pub velocity: [f32; 3],
+}
+
+/// The saved minecart state
+#[derive(Readable, Writable, Clone, Debug)]
+pub struct SavedMinecart {
+    pub track_pos: f32,
+    pub velocity: f32,
+}
+
+/// The saved state of a world chunk entity
+#[derive(Readable, Writable, Clone, Debug)]
+pub struct SavedEntity {
+    pub entity_id: EntityId,
+    pub pos: [f32; 3],
+    pub rot: [[f32; 3]; 3],
+    pub minecart: Option<SavedMinecart>,
+}
+
+impl SavedEntity {
+    /// Save the state of a world chunk entity
+    pub fn new(entity_id: EntityId, transform: &Transform, minecart: Option<&Minecart>) -> Self {
+        Self {
+            entity_id,
+            pos: transform.pos.into(),
+            rot: transform.rot.into(),
+            minecart: minecart.map(|minecart| SavedMinecart {
+                track_pos: minecart.track_pos(),
+                velocity: minecart.velocity(),
+            }),
+        }
+    }
+}
+
+/// A save game
+#[derive(Readable, Writable, Clone, Debug)]
+pub struct SaveGame {
+    header: FileHeader,
+    pub player: SavedPlayer,
+    /// Every world chunk entity that had been spawned, including ones that no longer exist
+    pub spawned_entities: Vec<EntityId>,
+    /// The state of the world chunk entities that still exist
+    pub entities: Vec<SavedEntity>,
+    /// The chunk data of the entities with a saved state, so they can be spawned where they were
+    /// left instead of waiting for the player to come near the chunk they started in
+    pub entity_info: Vec<WorldChunkEntity>,
+}
+
+impl SaveGame {
+    /// Load a save game from a file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        Self::read(&std::fs::read(path)?)
+    }
+
+    /// Read a save game from its data
+    pub fn read(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        // Check the header first, so that old saves give a useful error
+        SAVE_GAME_HEADER.check(data, "save game")?;
+        Ok(Self::read_from_buffer(data)?)
+    }
+
+    /// Save the save game to a file
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        self.write_to_file(path)?;
+        Ok(())
+    }
+}
+
+/// The saved entity states resource. When a world chunk entity that has a saved state gets
+/// spawned, the saved state is applied instead of the state in the chunk data. States get saved
+/// here both when loading a save game, and when entities get despawned for being far away.
+#[derive(Default)]
+pub struct SavedEntityStates {
+    states: HashMap<EntityId, SavedEntity>,
+}
+
+impl SavedEntityStates {
+    /// Take the saved state for an entity, if it has one
+    pub fn take(&mut self, entity_id: EntityId) -> Option<SavedEntity> {
+        self.states.remove(&entity_id)
+    }
+
+    /// Keep the state of an entity until it gets spawned again
+    pub fn insert(&mut self, saved: SavedEntity) {
+        self.states.insert(saved.entity_id, saved);
+    }
+
+    /// Iterate over the saved states
+    pub fn iter(&self) -> impl Iterator<Item=&SavedEntity> + '_ {
+        self.states.values()
+    }
+}
+
+/// Get the saved player and the hash of the save game that loading with these settings would start
+/// from, or None if it would start a new game, so that input recordings can tell what they started
+/// from
+pub fn saved_player_and_hash(settings: &SaveGameSettings) -> Option<(SavedPlayer, u64)> {
+    if !settings.enabled {
+        return None;
+    }
+
+    let data = std::fs::read(&settings.path).ok()?;
+    let save_game = SaveGame::read(&data).ok()?;
+    Some((save_game.player, hash_contents(&data)))
+}
+
+/// Load the save game if there is one, restoring the spawned entities and the saved entity states,
+/// and returning the saved player state
+pub fn load_save_game(settings: &SaveGameSettings, spawned: &mut EntitySpawnResource,
+    saved_states: &mut SavedEntityStates) -> Option<SavedPlayer>
+{
+    spawned.clear();
+    saved_states.states.clear();
+
+    if !settings.enabled || !settings.path.exists() {
+        return None;
+    }
+
+    let save_game = match SaveGame::load(&settings.path) {
+        Ok(save_game) => save_game,
+        Err(err) => {
+            log::error!("Failed to load save game from {}: {err}", settings.path.display());
+            return None;
+        }
+    };
+
+    log::info!("Loaded save game from {}", settings.path.display());
+
+    // Entities with a saved state get spawned again when the player comes near where they were
+    // left, and then have their state restored. The rest no longer exist, so mark them as already
+    // spawned.
+    let mut entity_info: HashMap<EntityId, WorldChunkEntity> = save_game.entity_info
+        .into_iter()
+        .map(|entity_info| (entity_info.entity_id(), entity_info))
+        .collect();
+
+    for entity in save_game.entities.iter() {
+        if let Some(entity_info) = entity_info.remove(&entity.entity_id) {
+            spawned.mark_streamed_out(entity_info, Vector3::from(entity.pos));
+        }
+    }
+
+    saved_states.states = save_game.entities
+        .into_iter()
+        .map(|entity| (entity.entity_id, entity))
+        .collect();
+
+    for entity_id in save_game.spawned_entities {
+        if !saved_states.states.contains_key(&entity_id) {
+            spawned.mark_spawned(entity_id);
+        }
+    }
+
+    Some(save_game.player)
+}
+
+/// Apply a saved state to a newly spawned world chunk entity
+pub fn apply_saved_entity(saved: &SavedEntity, transform: &mut Transform, minecart: Option<&mut Minecart>) {
+    transform.pos = Vector3::from(saved.pos);
+    transform.rot = Matrix3::from(saved.rot);
+
+    if let (Some(minecart), Some(saved_minecart)) = (minecart, &saved.minecart) {
+        minecart.set_state(saved_minecart.track_pos, saved_minecart.velocity);
+    }
+}
+
+/// Save the game when exiting, if there's a game in progress
+pub fn save_game_on_exit(world: &mut World) {
+    let in_game = world
+        .get_resource::<State<AppState>>()
+        .map(|state| matches!(state.current(), AppState::MainGame | AppState::Paused | AppState::ControlsMenu))
+        .unwrap_or(false);
+
+    if in_game {
+        let mut system = IntoSystem::into_system(save_game_system);
+        system.initialize(world);
+        system.run((), world);
+    }
+}
+
+/// The save game system, which saves the player and world chunk entities, including ones that have
+/// been despawned for being far away
+pub fn save_game_system(settings: Res<SaveGameSettings>,
+                        spawned: Res<EntitySpawnResource>,
+                        saved_states: Res<SavedEntityStates>,
+                        player_query: Query<(&Transform, &PlayerMovement)>,
+                        entity_query: Query<(&SpawnedWorldEntity, &Transform, Option<&Minecart>)>)
+{
+    if !settings.enabled {
+        return;
+    }
+
+    let (player_transform, player_movement) = match player_query.get_single() {
+        Ok(player) => player,
+        Err(_) => {
+            log::warn!("Not saving game, as there's no player");
+            return;
+        }
+    };
+
+    let player = SavedPlayer {
+        pos: player_transform.pos.into(),
+        pitch_yaw: player_movement.pitch_yaw.into(),
+        velocity: player_movement.velocity.into(),
+    };
+
+    let entities: Vec<SavedEntity> = entity_query
+        .iter()
+        .map(|(world_entity, transform, minecart)| SavedEntity::new(world_entity.entity_id, transform, minecart))
+        .chain(saved_states.iter().cloned())
+        .collect();
+
+    let entity_info = entities
+        .iter()
+        .filter_map(|entity| spawned.entity_info(entity.entity_id).cloned())
+        .collect();
+
+    let save_game = SaveGame {
+        header: SAVE_GAME_HEADER,
+        player,
+        spawned_entities: spawned.spawned_entities().chain(spawned.streamed_out_entities()).collect(),
+        entities,
+        entity_info,
+    };
+
+    match save_game.save(&settings.path) {
+        Ok(()) => log::info!("Saved game to {}", settings.path.display()),
+        Err(err) => log::error!("Failed to save game to {}: {err}", settings.path.display()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dreamfield_system::file_header::test_util::{temp_path, assert_rejects_bad_headers};
+
+    fn test_save_game() -> SaveGame {
+        SaveGame {
+            header: SAVE_GAME_HEADER,
+            player: SavedPlayer {
+                pos: [1.0, 2.0, 3.0],
+                pitch_yaw: [0.5, -0.25],
+                velocity: [0.0, -1.0, 0.0],
+            },
+            spawned_entities: vec![1, 2],
+            entities: vec![SavedEntity {
+                entity_id: 2,
+                pos: [200.0, 0.0, 10.0],
+                rot: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+                minecart: Some(SavedMinecart { track_pos: 150.0, velocity: 4.0 }),
+            }],
+            entity_info: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = temp_path("round_trip.save");
+        test_save_game().save(&path).unwrap();
+        let loaded = SaveGame::load(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(loaded.player.pos, [1.0, 2.0, 3.0]);
+        assert_eq!(loaded.player.pitch_yaw, [0.5, -0.25]);
+        assert_eq!(loaded.spawned_entities, vec![1, 2]);
+        assert_eq!(loaded.entities.len(), 1);
+        assert_eq!(loaded.entities[0].pos, [200.0, 0.0, 10.0]);
+        assert_eq!(loaded.entities[0].minecart.as_ref().map(|m| m.track_pos), Some(150.0));
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        assert_rejects_bad_headers(&test_save_game().write_to_vec().unwrap(), 0, SaveGame::read);
+    }
+}
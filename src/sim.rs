@@ -2,21 +2,28 @@ pub mod player_movement;
 pub mod ball;
 mod entity_spawner;
 mod minecart;
+mod rigid_body;
 
-use bevy_ecs::schedule::SystemSet;
+use bevy_ecs::schedule::{SystemSet, ParallelSystemDescriptorCoercion};
 
 // Components
 pub use player_movement::{PlayerMovement, PlayerMovementMode};
 pub use ball::Ball;
+pub use minecart::Minecart;
+pub use rigid_body::RigidBody;
 
-/// Sim systems
+/// Sim systems. These are explicitly ordered, so that updates are deterministic and input
+/// recordings replay the same way every time.
 pub fn systems() -> SystemSet {
     SystemSet::new()
         .label("sim")
-        .with_system(player_movement::player_update)
-        .with_system(ball::ball_update)
-        .with_system(entity_spawner::entity_spawner)
-        .with_system(minecart::update_minecart)
+        .with_system(entity_spawner::entity_despawner.label("entity_despawner"))
+        .with_system(entity_spawner::entity_spawner.label("entity_spawner").after("entity_despawner"))
+        .with_system(player_movement::player_update.label("player_update").after("entity_spawner"))
+        .with_system(minecart::update_minecart.label("update_minecart").after("player_update"))
+        .with_system(rigid_body::kick_rigid_bodies.label("kick_rigid_bodies").after("update_minecart"))
+        .with_system(rigid_body::update_rigid_bodies.label("update_rigid_bodies").after("kick_rigid_bodies"))
+        .with_system(ball::ball_update.after("update_rigid_bodies"))
 }
 
 // Test code for testing collisions, I'll leave it here for now until I'm sure I'm done...
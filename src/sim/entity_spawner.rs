@@ -1,35 +1,73 @@
-use bevy_ecs::{prelude::EventReader, system::Commands};
+use bevy_ecs::{prelude::EventReader, system::{Commands, ResMut, Query}};
 use cgmath::{Matrix4, Matrix3, Vector3, vec3};
 use dreamfield_renderer::components::{Visual, Animation};
-use dreamfield_system::{systems::entity_spawner::EntitySpawnEvent, components::{Transform, EntityName}, intersection::{Collider, Shape}};
+use dreamfield_system::{systems::entity_spawner::{EntitySpawnEvent, EntityDespawnEvent, SpawnedWorldEntity}, components::{Transform, EntityName}, intersection::{Collider, Shape}};
 
 use super::minecart::Minecart;
+use super::RigidBody;
+use crate::save_game::{SavedEntityStates, SavedEntity, apply_saved_entity};
 
 /// The entity spawner
-pub fn entity_spawner(mut commands: Commands, mut reader: EventReader<EntitySpawnEvent>) {
+pub fn entity_spawner(mut commands: Commands, mut reader: EventReader<EntitySpawnEvent>,
+    mut saved_states: ResMut<SavedEntityStates>)
+{
     for event in reader.iter() {
+        let entity_id = event.entity_info.entity_id();
+        let saved_state = saved_states.take(entity_id);
+
         let (pos, rot) = decompose_transform(event.entity_info.world_transform());
+        let mut transform = Transform::new(pos, rot);
+
         match event.entity_info.object_id() {
             "Elf" => {
+                if let Some(saved_state) = &saved_state {
+                    apply_saved_entity(saved_state, &mut transform, None);
+                }
+
                 commands.spawn()
-                    .insert(Transform::new(pos, rot))
+                    .insert(SpawnedWorldEntity::new(entity_id))
+                    .insert(transform)
                     .insert(EntityName::new("Elf"))
                     .insert(Collider::new(Shape::BoundingSpheroid(vec3(0.0, 1.0, 0.0), vec3(0.25, 1.0, 0.25))))
                     .insert(Visual::new("elf", "ps1", false, Some(Animation::Loop("Idle".to_string()))));
             },
             "Minecart" => {
+                let mut minecart = event.entity_info.mesh().map(|points| {
+                    let track_points = points.iter().map(|p| p.as_vec().clone()).collect();
+                    Minecart::new(track_points)
+                });
+
+                if minecart.is_none() {
+                    log::warn!("Minecart has no points");
+                }
+
+                if let Some(saved_state) = &saved_state {
+                    apply_saved_entity(saved_state, &mut transform, minecart.as_mut());
+                }
+
                 let mut entity = commands.spawn();
-                entity.insert(EntityName::new("Minecart"))
-                      .insert(Transform::new(pos, rot))
+                entity.insert(SpawnedWorldEntity::new(entity_id))
+                      .insert(EntityName::new("Minecart"))
+                      .insert(transform)
+                      .insert(Collider::new(Shape::BoundingBox(vec3(0.0, 0.52, 0.0), vec3(0.55, 0.52, 1.0))))
                       .insert(Visual::new("minecart", "ps1", false, None));
 
-                if let Some(points) = event.entity_info.mesh() {
-                    let track_points = points.iter().map(|p| p.as_vec().clone()).collect();
-                    entity.insert(Minecart::new(track_points));
+                if let Some(minecart) = minecart {
+                    entity.insert(minecart);
                 }
-                else {
-                    log::warn!("Minecart has no points");
+            },
+            "Crate" => {
+                if let Some(saved_state) = &saved_state {
+                    apply_saved_entity(saved_state, &mut transform, None);
                 }
+
+                commands.spawn()
+                    .insert(SpawnedWorldEntity::new(entity_id))
+                    .insert(EntityName::new("Crate"))
+                    .insert(transform)
+                    .insert(Collider::new(Shape::BoundingBox(vec3(0.0, 0.4, 0.0), vec3(0.4, 0.4, 0.4))))
+                    .insert(RigidBody::new(20.0).with_restitution(0.1).with_friction(0.6))
+                    .insert(Visual::new("crate", "ps1", false, None));
             },
             _ => {
                 log::warn!("Asked to spawn unknown entity: {:?}", event.entity_info);
@@ -38,6 +76,19 @@ pub fn entity_spawner(mut commands: Commands, mut reader: EventReader<EntitySpaw
     }
 }
 
+/// The entity despawner, which keeps the state of despawned entities so they can be restored when
+/// they get spawned again
+pub fn entity_despawner(mut commands: Commands, mut reader: EventReader<EntityDespawnEvent>,
+    mut saved_states: ResMut<SavedEntityStates>, query: Query<(&Transform, Option<&Minecart>)>)
+{
+    for event in reader.iter() {
+        if let Ok((transform, minecart)) = query.get(event.entity) {
+            saved_states.insert(SavedEntity::new(event.entity_id, transform, minecart));
+            commands.entity(event.entity).despawn();
+        }
+    }
+}
+
 /// Decompose a transform into a position and orientation
 fn decompose_transform(transform: &Matrix4<f32>) -> (Vector3<f32>, Matrix3<f32>) {
     let pos = transform.w.truncate();
@@ -49,6 +49,22 @@ impl Minecart {
         }
     }
 
+    /// Get the position along the track
+    pub fn track_pos(&self) -> f32 {
+        self.pos
+    }
+
+    /// Get the velocity along the track
+    pub fn velocity(&self) -> f32 {
+        self.velocity
+    }
+
+    /// Set the position and velocity along the track, e.g. when loading a save game
+    pub fn set_state(&mut self, track_pos: f32, velocity: f32) {
+        self.pos = track_pos;
+        self.velocity = velocity;
+    }
+
     fn get_segment(&self, pos: f32) -> Option<&TrackSegment> {
         for segment in self.track_segments.iter() {
             if pos < segment.segment_end {
@@ -79,6 +95,9 @@ pub fn update_minecart(sim_time: Res<SimTime>,
     const SPEED_LOSS_PER_SECOND: f32 = 2.5;
     const SPEED_LOSS_PER_SECOND_RIDING: f32 = 0.1;
     const STOP_SPEED: f32 = 1.0;
+    // The player pushes the minecart when they're this close, which is a little further than its
+    // collider lets them get to it end on
+    const PUSH_DISTANCE: f32 = 1.75;
     
     let (player_in_minecart, player_pos) = {
         let query = param_set.p1();
@@ -92,7 +111,7 @@ pub fn update_minecart(sim_time: Res<SimTime>,
         let forward_dir = transform.rot * vec3(0.0, 0.0, -1.0);
         let to_player = player_pos - transform.pos;
         let dist_to_player = f32::max(0.1, to_player.magnitude());
-        if dist_to_player < 1.5 && !player_in_minecart {
+        if dist_to_player < PUSH_DISTANCE && !player_in_minecart {
             minecart.velocity += 2.5 * -forward_dir.dot(to_player / dist_to_player);
         }
 
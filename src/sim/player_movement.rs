@@ -10,7 +10,8 @@ use dreamfield_system::components::Transform;
 use dreamfield_system::intersection::{Plane, Collider, Shape};
 use dreamfield_system::resources::{SimTime, InputName, InputState, Diagnostics};
 use dreamfield_system::world::WorldChunkManager;
-use dreamfield_system::world::world_collision::{WorldCollision, SpherecastResult};
+use dreamfield_system::world::world_chunk::CollisionLayer;
+use dreamfield_system::world::world_collision::{WorldCollision, SpherecastResult, CollisionLayers};
 
 /// The character's height
 const CHAR_HEIGHT: f32 = 1.8;
@@ -31,7 +32,7 @@ const CAM_LOOK_SPEED: f32 = 1.0;
 const CAM_LOOK_SPEED_FAST: f32 = 1.5;
 
 /// The gravity acceleration
-const GRAVITY_ACCELERATION: f32 = 9.8;
+pub const GRAVITY_ACCELERATION: f32 = 9.8;
 
 /// The character eye level. According to a cursory google, this is on average 4.5" below the top
 /// of your head, which is just over 10cm
@@ -55,6 +56,18 @@ const AIR_ACCELERATE: f32 = 20.0;
 /// The ground friction as percentage of speed to lose per second
 const GROUND_FRICTION: f32 = 20.0;
 
+/// The acceleration when on slippery ground
+const SLIPPERY_ACCELERATE: f32 = 4.0;
+
+/// The friction on slippery ground, which is much lower so that you slide around
+const SLIPPERY_FRICTION: f32 = 1.0;
+
+/// How far in front of the player to look for a ladder to climb
+const LADDER_REACH: f32 = 0.1;
+
+/// The speed the player climbs ladders at
+const LADDER_CLIMB_SPEED: f32 = 2.5;
+
 /// Maximum walking speed on the ground
 const GROUND_MAX_SPEED: f32 = 4.5;
 
@@ -84,6 +97,10 @@ pub struct PlayerMovement {
     pub velocity: Vector3<f32>,
     pub pitch_yaw: Vector2<f32>,
     pub ground_plane: Option<Plane>,
+    /// The collision layer of the ground we're standing on, if any
+    pub ground_layer: Option<CollisionLayer>,
+    /// Whether we're facing a ladder, in which case we climb it instead of falling
+    pub on_ladder: bool,
     pub walking: bool,
     /// Seconds since player started holding the jump button
     pub jump_timer: f32,
@@ -103,6 +120,8 @@ impl PlayerMovement {
             pitch_yaw,
             velocity: Vector3::zero(),
             ground_plane: None,
+            ground_layer: None,
+            on_ladder: false,
             walking: false,
             jump_timer: 0.0,
         }
@@ -123,6 +142,12 @@ impl PlayerMovement {
         self.orientation() * WORLD_RIGHT
     }
 
+    /// Get the direction the player is facing along the ground, which unlike forward() is still
+    /// defined when they're looking straight up or down
+    pub fn ground_forward(&self) -> Vector3<f32> {
+        Quaternion::from_axis_angle(WORLD_UP, Rad(self.pitch_yaw.y)) * WORLD_FORWARD
+    }
+
     pub fn collider() -> Collider {
         Collider::new(Shape::BoundingSpheroid(
             vec3(0.0, 0.5 * CHAR_HEIGHT, 0.0),
@@ -187,7 +212,9 @@ fn player_move(collision: &mut WorldCollision, world: &mut WorldChunkManager, pl
     {
         let position_es = (player_transform.pos + collider_offset).mul_element_wise(collider_cbm);
         let velocity_es = vec3(0.0, -0.05, 0.0).mul_element_wise(collider_cbm);
-        player_movement.ground_plane = sweep_unit(collision, world, &collider_cbm, position_es, velocity_es, ignore_entity).map(|hit| {
+        let ground_hit = sweep_unit(collision, world, &collider_cbm, position_es, velocity_es, ignore_entity);
+        player_movement.ground_layer = ground_hit.as_ref().map(|hit| hit.layer());
+        player_movement.ground_plane = ground_hit.map(|hit| {
                 let hit_point_world = hit.point().div_element_wise(collider_cbm);
                 let hit_normal_world = hit.normal().div_element_wise(collider_cbm).normalize();
                 if hit.toi() == 0.0 {
@@ -198,13 +225,30 @@ fn player_move(collision: &mut WorldCollision, world: &mut WorldChunkManager, pl
             });
     }
 
-    // Apply gravity acceleration
-    player_movement.velocity.y -= GRAVITY_ACCELERATION * time_delta;
+    // Check whether we're facing a ladder, by sweeping a little way forward
+    {
+        let position_es = (player_transform.pos + collider_offset).mul_element_wise(collider_cbm);
+        let velocity_es = (player_movement.ground_forward() * LADDER_REACH).mul_element_wise(collider_cbm);
+        player_movement.on_ladder = sweep_unit(collision, world, &collider_cbm, position_es, velocity_es, ignore_entity)
+            .map(|hit| hit.layer() == CollisionLayer::Ladder)
+            .unwrap_or(false);
+    }
+
+    // Apply gravity acceleration, or climb if we're on a ladder, where moving forwards and
+    // backwards goes up and down
+    if player_movement.on_ladder {
+        let (forward_input, _) = input_state.get_movement_input();
+        player_movement.velocity.y = forward_input * LADDER_CLIMB_SPEED;
+    }
+    else {
+        player_movement.velocity.y -= GRAVITY_ACCELERATION * time_delta;
+    }
 
     // Cancel out gravity if we're standing on the ground and it's not too steep
     let mut steep_slope = true;
     let mut acceleration = AIR_ACCELERATE;
     let mut max_speed = GROUND_MAX_SPEED;
+    let mut ground_friction = GROUND_FRICTION;
     if let Some(ground_plane) = player_movement.ground_plane {
         if ground_plane.normal().y >= MIN_WALK_NORMAL {
             if player_movement.velocity.y < 0.0 {
@@ -213,6 +257,12 @@ fn player_move(collision: &mut WorldCollision, world: &mut WorldChunkManager, pl
             steep_slope = false;
             acceleration = ACCELERATE;
             player_movement.jump_timer = 0.0;
+
+            // Slippery ground is hard to get going on and hard to stop on
+            if player_movement.ground_layer == Some(CollisionLayer::Slippery) {
+                acceleration = SLIPPERY_ACCELERATE;
+                ground_friction = SLIPPERY_FRICTION;
+            }
         }
     }
 
@@ -239,12 +289,13 @@ fn player_move(collision: &mut WorldCollision, world: &mut WorldChunkManager, pl
     player_movement.velocity += vec3(input_vector.x, 0.0, input_vector.z) * acceleration * time_delta;
 
     // Friction (only apply it if there's no directional input). It's modelled as a constant
-    // deceleration of GROUND_FRICTION, and the max speed is capped at GROUND_MAX_SPEED.
+    // deceleration of GROUND_FRICTION (or SLIPPERY_FRICTION on slippery ground), and the max speed
+    // is capped at GROUND_MAX_SPEED.
     let speed = vec2(player_movement.velocity.x, player_movement.velocity.z).magnitude();
     if speed > 0.0 {
         // Only apply friction if there's no movement input
         let apply_friction = input_vector.x == 0.0 && input_vector.z == 0.0;
-        let friction = if apply_friction { GROUND_FRICTION } else { 0.0 };
+        let friction = if apply_friction { ground_friction } else { 0.0 };
         let frame_friction = friction * time_delta;
 
         // Apply friction (if necessary) and clamp speed
@@ -288,7 +339,7 @@ fn player_move(collision: &mut WorldCollision, world: &mut WorldChunkManager, pl
 fn sweep_unit(collision: &mut WorldCollision, world: &mut WorldChunkManager, cbm: &Vector3<f32>,
     position: Vector3<f32>, velocity: Vector3<f32>, ignore_entity: Entity) -> Option<SpherecastResult>
 {
-    collision.sweep_unit_sphere(world, position, velocity, *cbm, Some(ignore_entity))
+    collision.sweep_unit_sphere(world, position, velocity, *cbm, CollisionLayers::PLAYER, Some(ignore_entity))
 }
 
 /// Move through the world sliding on surfaces we collide with
@@ -364,6 +415,14 @@ fn update_view_angles(player_movement: &mut PlayerMovement, input_state: &InputS
 
     pitch_yaw.x = f32::clamp(pitch_yaw.x + vert_input * look_speed * time_delta, PITCH_MIN, PITCH_MAX);
     pitch_yaw.y = pitch_yaw.y + horz_input * look_speed * time_delta;
+
+    // Mouse look. The mouse diff is already scaled to radians by the game host, and only contains
+    // the movement since the last update, so this doesn't depend on the framerate.
+    if input_state.cursor_captured {
+        let (mouse_dx, mouse_dy) = input_state.mouse_diff;
+        pitch_yaw.x = f32::clamp(pitch_yaw.x - mouse_dy as f32, PITCH_MIN, PITCH_MAX);
+        pitch_yaw.y = pitch_yaw.y - mouse_dx as f32;
+    }
 }
 
 /// Get the movement vector based on the player's input
@@ -390,3 +449,21 @@ fn player_move_noclip(player_transform: &mut Transform, player_movement: &mut Pl
     // Update position
     player_transform.pos += player_movement.velocity * time_delta;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ground_forward_looking_straight_up_or_down() {
+        for pitch in [std::f32::consts::FRAC_PI_2, -std::f32::consts::FRAC_PI_2, 0.0] {
+            let player_movement = PlayerMovement::new_pos_look(PlayerMovementMode::Normal, vec2(pitch, 1.0));
+            let ground_forward = player_movement.ground_forward();
+
+            assert!((ground_forward.magnitude() - 1.0).abs() < 0.001, "pitch {pitch}: {ground_forward:?}");
+            assert_eq!(ground_forward.y, 0.0);
+            assert!((ground_forward - vec3(-f32::sin(1.0), 0.0, -f32::cos(1.0))).magnitude() < 0.001,
+                "pitch {pitch}: {ground_forward:?}");
+        }
+    }
+}
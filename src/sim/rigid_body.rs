@@ -0,0 +1,603 @@
+use bevy_ecs::prelude::{Component, Entity};
+use bevy_ecs::query::Without;
+use bevy_ecs::system::{Res, ResMut, Query};
+use cgmath::{Vector3, Matrix3, vec3, Zero, InnerSpace, ElementWise};
+use dreamfield_system::components::Transform;
+use dreamfield_system::intersection::{self, Collider, Shape, Polytope, Triangle};
+use dreamfield_system::resources::{SimTime, InputState, InputName};
+use dreamfield_system::world::WorldChunkManager;
+use dreamfield_system::world::world_collision::{WorldCollision, CollisionLayers};
+
+use super::PlayerMovement;
+use super::player_movement::GRAVITY_ACCELERATION;
+
+/// The closest a body gets to a surface it hits, so that it doesn't end up touching it and get
+/// stuck on the next sweep
+const MIN_DISTANCE_FROM_SURFACES: f32 = 0.01;
+
+/// How far apart two bodies can be and still be in contact, which has to be further than they stop
+/// from each other when sweeping so that they can push each other
+const CONTACT_DISTANCE: f32 = 0.05;
+
+/// The number of times a body can hit something and slide along it in one update
+const MAX_SLIDES: usize = 4;
+
+/// Hits slower than this don't bounce, so that bodies come to rest rather than jittering
+const REST_SPEED: f32 = 1.0;
+
+/// The steepest surface that a body counts as being on the ground on
+const MIN_GROUND_NORMAL: f32 = 0.7;
+
+/// How close the player has to be to a body to kick it
+const KICK_DISTANCE: f32 = 2.0;
+
+/// The speed a body gets kicked at, whatever its mass
+const KICK_SPEED: f32 = 6.0;
+
+/// How far up a kicked body goes, relative to forwards
+const KICK_LIFT: f32 = 0.5;
+
+/// The RigidBody component, for dynamic props like crates and throwable items. Bodies move
+/// through the world by sweeping their collider, and push each other and get pushed by the
+/// player. They don't rotate, but a box keeps the rotation it was given, and is swept and pushed as
+/// that oriented box, so it rests flat on the ground.
+#[derive(Component)]
+pub struct RigidBody {
+    mass: f32,
+    pub velocity: Vector3<f32>,
+    /// How much gravity affects the body, 0 for a body that floats
+    gravity: f32,
+    /// How much of its speed the body keeps when it bounces, from 0 to 1
+    restitution: f32,
+    /// How much the body slows down when sliding along a surface, as a coefficient of friction
+    friction: f32,
+    on_ground: bool,
+}
+
+impl RigidBody {
+    /// Create a rigid body with a mass in kg, which is affected by gravity, barely bounces, and
+    /// slides a little before stopping
+    pub fn new(mass: f32) -> Self {
+        Self {
+            mass,
+            velocity: Vector3::zero(),
+            gravity: 1.0,
+            restitution: 0.2,
+            friction: 0.5,
+            on_ground: false,
+        }
+    }
+
+    /// Set how much of its speed the body keeps when it bounces
+    pub fn with_restitution(mut self, restitution: f32) -> Self {
+        self.restitution = restitution;
+        self
+    }
+
+    /// Set the body's coefficient of friction
+    pub fn with_friction(mut self, friction: f32) -> Self {
+        self.friction = friction;
+        self
+    }
+
+    pub fn mass(&self) -> f32 {
+        self.mass
+    }
+
+    /// Get whether the body was resting or sliding on the ground at the end of the last update
+    pub fn on_ground(&self) -> bool {
+        self.on_ground
+    }
+
+    /// Apply an impulse to the body, e.g. to throw it
+    pub fn apply_impulse(&mut self, impulse: Vector3<f32>) {
+        self.velocity += impulse / self.mass;
+    }
+
+    /// Bounce off a surface with a given normal, losing some of the speed along it to friction
+    fn bounce(&mut self, normal: Vector3<f32>) {
+        let approach_speed = -self.velocity.dot(normal);
+        if approach_speed <= 0.0 {
+            return;
+        }
+
+        let restitution = if approach_speed < REST_SPEED { 0.0 } else { self.restitution };
+        let normal_change = (1.0 + restitution) * approach_speed;
+        self.velocity += normal * normal_change;
+
+        // Friction takes away speed in proportion to how hard the body hit the surface, which for
+        // a body resting on the ground is the speed gravity gave it this update
+        let tangent = self.velocity - normal * self.velocity.dot(normal);
+        let tangent_speed = tangent.magnitude();
+        if tangent_speed > 0.0 {
+            self.velocity -= tangent * f32::min(self.friction * normal_change / tangent_speed, 1.0);
+        }
+    }
+}
+
+/// The shape a body has for contacts with other bodies. Spheroids and boxes have contacts with their
+/// real shape, and every other shape uses the spheroid around its world space aabb.
+enum ContactShape {
+    /// An upright spheroid with a center and radiuses
+    Spheroid(Vector3<f32>, Vector3<f32>),
+    /// An oriented box with a center, rotation and half extents
+    Box(Vector3<f32>, Matrix3<f32>, Vector3<f32>),
+}
+
+impl ContactShape {
+    fn new(transform: &Transform, collider: &Collider) -> Self {
+        match &collider.shape {
+            Shape::BoundingSpheroid(offset, radius) => ContactShape::Spheroid(transform.pos + offset, *radius),
+            Shape::BoundingBox(offset, half_extents) =>
+                ContactShape::Box(transform.pos + transform.rot * offset, transform.rot, *half_extents),
+            shape => {
+                // The spheroid around a box is the unit sphere around a unit cube, scaled by its half extents
+                let (min, max) = shape.world_aabb(&transform.pos, &transform.rot);
+                ContactShape::Spheroid(0.5 * min + 0.5 * max, (0.5 * max - 0.5 * min) * f32::sqrt(3.0))
+            }
+        }
+    }
+
+    /// Find the contact between two shapes, as the normal pointing from b to a and how far they
+    /// overlap along it, which is negative if they're apart but within the contact distance
+    fn contact(a: &ContactShape, b: &ContactShape) -> Option<(Vector3<f32>, f32)> {
+        let contact = match (a, b) {
+            (ContactShape::Spheroid(a_center, a_radius), ContactShape::Spheroid(b_center, b_radius)) => {
+                // A spheroid against a point, in the space where their combined radius is a unit sphere
+                let cbm = (a_radius + b_radius).map(|r| 1.0 / r);
+                Self::unit_sphere_contact(a_center.mul_element_wise(cbm) - b_center.mul_element_wise(cbm), cbm)
+            },
+            (ContactShape::Spheroid(center, radius), ContactShape::Box(box_center, rot, half_extents)) =>
+                Self::spheroid_box_contact(center, radius, box_center, rot, half_extents),
+            (ContactShape::Box(box_center, rot, half_extents), ContactShape::Spheroid(center, radius)) =>
+                Self::spheroid_box_contact(center, radius, box_center, rot, half_extents)
+                    .map(|(normal, depth)| (-normal, depth)),
+            (ContactShape::Box(a_center, a_rot, a_half_extents), ContactShape::Box(b_center, b_rot, b_half_extents)) =>
+                Polytope::from_box(a_center, a_rot, a_half_extents)
+                    .contact(&Polytope::from_box(b_center, b_rot, b_half_extents)),
+        };
+
+        contact.filter(|(_, depth)| *depth > -CONTACT_DISTANCE)
+    }
+
+    /// Find the contact between a spheroid and a box, as the normal pointing from the box to the
+    /// spheroid and how far they overlap along it
+    fn spheroid_box_contact(center: &Vector3<f32>, radius: &Vector3<f32>, box_center: &Vector3<f32>,
+        rot: &Matrix3<f32>, half_extents: &Vector3<f32>) -> Option<(Vector3<f32>, f32)>
+    {
+        // If the spheroid's center is inside the box, push it out through the nearest face, by the
+        // distance to the face plus how far the spheroid reaches along the face's normal
+        let offset = center - box_center;
+        let faces = [(rot.x, half_extents.x), (rot.y, half_extents.y), (rot.z, half_extents.z)].map(|(axis, half)| {
+            let local = offset.dot(axis.normalize());
+            let normal = if local < 0.0 { -axis.normalize() } else { axis.normalize() };
+            (normal, axis.magnitude() * half - local.abs())
+        });
+
+        if faces.iter().all(|(_, inside)| *inside > 0.0) {
+            return faces
+                .into_iter()
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map(|(normal, inside)| (normal, inside + radius.mul_element_wise(normal).magnitude()));
+        }
+
+        // Otherwise it's a unit sphere against the closest point on the box's surface, in ellipsoid space
+        let cbm = radius.map(|r| 1.0 / r);
+        let center_es = center.mul_element_wise(cbm);
+        let corners = intersection::box_corners(box_center, rot, half_extents);
+        let closest_es = intersection::BOX_FACES
+            .iter()
+            .map(|[i0, i1, i2]| {
+                let triangle = Triangle::new(corners[*i0], corners[*i1], corners[*i2]).apply_cbm(cbm);
+                intersection::closest_point_on_triangle(center_es, &triangle)
+            })
+            .min_by(|a, b| (center_es - a).magnitude2().total_cmp(&(center_es - b).magnitude2()))?;
+
+        Self::unit_sphere_contact(center_es - closest_es, cbm)
+    }
+
+    /// Get the contact between a unit sphere and a point, given the offset from the point to the
+    /// sphere's center in ellipsoid space and the change of basis matrix for that space
+    fn unit_sphere_contact(offset_es: Vector3<f32>, cbm: Vector3<f32>) -> Option<(Vector3<f32>, f32)> {
+        let distance_es = offset_es.magnitude();
+        let normal_es = match distance_es > 0.0 {
+            true => offset_es / distance_es,
+            false => vec3(0.0, 1.0, 0.0)
+        };
+
+        // Normals scale the opposite way to points on their way out of ellipsoid space
+        let normal = normal_es.mul_element_wise(cbm);
+        let normal_length = normal.magnitude();
+        Some((normal / normal_length, (1.0 - distance_es) / normal_length))
+    }
+}
+
+/// A body taking part in contacts. The player takes part too, as a body that can't be pushed.
+struct ContactBody {
+    entity: Entity,
+    shape: ContactShape,
+    velocity: Vector3<f32>,
+    inverse_mass: f32,
+    restitution: f32,
+    /// How far the body has to move to stop overlapping the bodies it's in contact with
+    push: Vector3<f32>,
+}
+
+/// The kick system, which kicks the bodies on the ground in front of the player when they press use
+pub fn kick_rigid_bodies(input: Res<InputState>, mut bodies: Query<(&mut RigidBody, &Transform)>,
+    players: Query<(&Transform, &PlayerMovement), Without<RigidBody>>)
+{
+    if !input.is_just_pressed(InputName::Use) {
+        return;
+    }
+
+    for (player_transform, movement) in players.iter() {
+        let forward = movement.forward();
+        let forward = vec3(forward.x, 0.0, forward.z);
+        if !movement.enabled || forward.magnitude2() == 0.0 {
+            continue;
+        }
+        let forward = forward.normalize();
+
+        for (mut body, transform) in bodies.iter_mut() {
+            let offset = transform.pos - player_transform.pos;
+            if body.on_ground() && offset.magnitude2() < KICK_DISTANCE * KICK_DISTANCE && offset.dot(forward) > 0.0 {
+                let impulse = (forward + vec3(0.0, KICK_LIFT, 0.0)) * KICK_SPEED * body.mass();
+                body.apply_impulse(impulse);
+            }
+        }
+    }
+}
+
+/// The rigid body update system, which applies gravity, resolves contacts between bodies, then
+/// moves each body through the world
+pub fn update_rigid_bodies(sim_time: Res<SimTime>, mut collision: ResMut<WorldCollision>,
+    mut world: ResMut<WorldChunkManager>,
+    mut bodies: Query<(Entity, &mut RigidBody, &mut Transform, &Collider)>,
+    players: Query<(Entity, &Transform, &Collider, &PlayerMovement), Without<RigidBody>>)
+{
+    let time_delta = sim_time.sim_time_delta as f32;
+
+    // Apply gravity
+    for (_, mut body, _, _) in bodies.iter_mut() {
+        body.velocity.y -= GRAVITY_ACCELERATION * body.gravity * time_delta;
+    }
+
+    // Gather everything that takes part in contacts
+    let mut contact_bodies: Vec<ContactBody> = bodies
+        .iter()
+        .map(|(entity, body, transform, collider)| ContactBody {
+            entity,
+            shape: ContactShape::new(transform, collider),
+            velocity: body.velocity,
+            inverse_mass: 1.0 / body.mass,
+            restitution: body.restitution,
+            push: Vector3::zero(),
+        })
+        .collect();
+    let body_count = contact_bodies.len();
+
+    for (entity, transform, collider, movement) in players.iter() {
+        if movement.enabled {
+            contact_bodies.push(ContactBody {
+                entity,
+                shape: ContactShape::new(transform, collider),
+                velocity: movement.velocity,
+                inverse_mass: 0.0,
+                restitution: 0.0,
+                push: Vector3::zero(),
+            });
+        }
+    }
+
+    resolve_contacts(&mut contact_bodies, body_count);
+
+    // Move each body through the world
+    for contact_body in contact_bodies.iter().take(body_count) {
+        if let Ok((entity, mut body, mut transform, collider)) = bodies.get_mut(contact_body.entity) {
+            body.velocity = contact_body.velocity;
+            let movement = body.velocity * time_delta + contact_body.push;
+            move_body(collision.as_mut(), world.as_mut(), entity, &mut body, &mut transform, &collider.shape,
+                movement);
+        }
+    }
+}
+
+/// Resolve contacts between each pair of bodies, where the first body_count are rigid bodies and
+/// the rest can't be pushed. There aren't many bodies, so it's fine to check every pair. Contacts
+/// are frictionless, and as bouncy as the less bouncy of the two bodies.
+fn resolve_contacts(contact_bodies: &mut [ContactBody], body_count: usize) {
+    for i in 0..body_count {
+        for j in i + 1..contact_bodies.len() {
+            let (a, b) = (&contact_bodies[i], &contact_bodies[j]);
+            let inverse_mass_sum = a.inverse_mass + b.inverse_mass;
+            let contact = ContactShape::contact(&a.shape, &b.shape);
+            let (normal, depth) = match (contact, inverse_mass_sum > 0.0) {
+                (Some(contact), true) => contact,
+                _ => continue
+            };
+
+            let mut impulse = Vector3::zero();
+            let approach_speed = (b.velocity - a.velocity).dot(normal);
+            if approach_speed > 0.0 {
+                let restitution = if approach_speed < REST_SPEED { 0.0 } else { f32::min(a.restitution, b.restitution) };
+                impulse = normal * (1.0 + restitution) * approach_speed / inverse_mass_sum;
+            }
+
+            let push = normal * f32::max(depth, 0.0) / inverse_mass_sum;
+            let (a_inverse_mass, b_inverse_mass) = (a.inverse_mass, b.inverse_mass);
+
+            contact_bodies[i].velocity += impulse * a_inverse_mass;
+            contact_bodies[i].push += push * a_inverse_mass;
+            contact_bodies[j].velocity -= impulse * b_inverse_mass;
+            contact_bodies[j].push -= push * b_inverse_mass;
+        }
+    }
+}
+
+/// Move a body through the world, sweeping its collider and sliding along what it hits
+fn move_body(collision: &mut WorldCollision, world: &mut WorldChunkManager, entity: Entity, body: &mut RigidBody,
+    transform: &mut Transform, shape: &Shape, mut movement: Vector3<f32>)
+{
+    let mut position = transform.pos;
+    body.on_ground = false;
+
+    for _ in 0..MAX_SLIDES {
+        let movement_length = movement.magnitude();
+        if movement_length == 0.0 {
+            break;
+        }
+
+        let hit = collision.sweep_collider(world, &position, &transform.rot, shape, movement,
+            CollisionLayers::RIGID_BODY, Some(entity));
+        let hit = match hit {
+            Some(hit) => hit,
+            None => {
+                position += movement;
+                break;
+            }
+        };
+
+        // Move to just before the hit
+        let direction = movement / movement_length;
+        position += direction * f32::max(hit.toi() * movement_length - MIN_DISTANCE_FROM_SURFACES, 0.0);
+
+        let normal = *hit.normal();
+        body.bounce(normal);
+        if normal.y >= MIN_GROUND_NORMAL {
+            body.on_ground = true;
+        }
+
+        // Slide the rest of the way along the surface
+        let remaining = movement * (1.0 - hit.toi());
+        movement = remaining - normal * f32::min(remaining.dot(normal), 0.0);
+    }
+
+    // Only touch the transform if the body moved, so that resting bodies don't keep updating the
+    // chunks they're in
+    if position != transform.pos {
+        transform.pos = position;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+    use cgmath::{SquareMatrix, MetricSpace, Rad};
+    use speedy::Writable;
+    use dreamfield_system::world::aabb::Aabb;
+    use dreamfield_system::world::chunk_source::{ChunkSource, ChunkSourceError};
+    use dreamfield_system::world::world_build_manifest::{WorldBuildManifest, WORLD_BUILD_MANIFEST_FILENAME};
+    use dreamfield_system::world::world_chunk::{CollisionLayer, WorldChunk, WorldChunkCollisionMesh,
+        WorldChunkIndices, DEFAULT_CHUNK_HEIGHT};
+
+    /// A world with a flat square of ground at y = 2 in chunk 0, 0, 0
+    struct Ground;
+
+    impl ChunkSource for Ground {
+        fn read_file(&self, filename: &str) -> Result<Option<Cow<'_, [u8]>>, ChunkSourceError> {
+            if filename == WORLD_BUILD_MANIFEST_FILENAME {
+                let manifest = WorldBuildManifest::new(Vec::new(), Vec::new(), false, DEFAULT_CHUNK_HEIGHT, 0.0,
+                    Vec::new(), Default::default());
+                return Ok(Some(Cow::Owned(manifest.write_to_vec().unwrap())));
+            }
+            if filename != WorldChunk::filename((0, 0, 0)) {
+                return Ok(None);
+            }
+
+            let mut aabb = Aabb::new();
+            aabb.set_min_max(&vec3(0.0, 2.0, 0.0), &vec3(16.0, 2.0, 16.0));
+            let positions = vec![0.0, 2.0, 0.0, 16.0, 2.0, 16.0, 16.0, 2.0, 0.0, 0.0, 2.0, 16.0];
+            let indices = WorldChunkIndices::new(vec![0, 1, 2, 0, 3, 1]);
+
+            let mut chunk = WorldChunk::new();
+            chunk.add_collision_mesh(WorldChunkCollisionMesh::new(aabb, CollisionLayer::Solid, positions, indices));
+            Ok(Some(Cow::Owned(chunk.write_to_file_data((0, 0, 0), DEFAULT_CHUNK_HEIGHT).unwrap())))
+        }
+
+        fn describe(&self) -> String {
+            "test ground".to_string()
+        }
+    }
+
+    fn contact_body(center: Vector3<f32>, velocity: Vector3<f32>, inverse_mass: f32) -> ContactBody {
+        ContactBody {
+            entity: Entity::from_raw(0),
+            shape: ContactShape::Spheroid(center, vec3(0.5, 0.5, 0.5)),
+            velocity,
+            inverse_mass,
+            restitution: 0.0,
+            push: Vector3::zero(),
+        }
+    }
+
+    #[test]
+    fn bounce_reflects_fast_hits() {
+        let mut body = RigidBody::new(1.0).with_restitution(0.5).with_friction(0.0);
+        body.velocity = vec3(0.0, -4.0, 0.0);
+        body.bounce(vec3(0.0, 1.0, 0.0));
+
+        assert!(body.velocity.distance(vec3(0.0, 2.0, 0.0)) < 0.0001, "{:?}", body.velocity);
+    }
+
+    #[test]
+    fn bounce_loses_speed_to_friction() {
+        let mut body = RigidBody::new(1.0).with_restitution(0.0).with_friction(0.1);
+        body.velocity = vec3(3.0, -5.0, 0.0);
+        body.bounce(vec3(0.0, 1.0, 0.0));
+
+        // The normal speed changes by 5, so friction takes 0.5 off the tangent speed
+        assert!(body.velocity.distance(vec3(2.5, 0.0, 0.0)) < 0.0001, "{:?}", body.velocity);
+    }
+
+    #[test]
+    fn bounce_friction_never_reverses() {
+        let mut body = RigidBody::new(1.0).with_restitution(0.0).with_friction(10.0);
+        body.velocity = vec3(1.0, -5.0, 0.0);
+        body.bounce(vec3(0.0, 1.0, 0.0));
+
+        assert!(body.velocity.magnitude() < 0.0001, "{:?}", body.velocity);
+    }
+
+    #[test]
+    fn slow_hits_come_to_rest() {
+        let mut body = RigidBody::new(1.0).with_restitution(0.9);
+        body.velocity = vec3(0.0, -0.5 * REST_SPEED, 0.0);
+        body.bounce(vec3(0.0, 1.0, 0.0));
+
+        assert_eq!(body.velocity.y, 0.0);
+    }
+
+    #[test]
+    fn bounce_ignores_surfaces_moving_away() {
+        let mut body = RigidBody::new(1.0);
+        body.velocity = vec3(1.0, 2.0, 0.0);
+        body.bounce(vec3(0.0, 1.0, 0.0));
+
+        assert_eq!(body.velocity, vec3(1.0, 2.0, 0.0));
+    }
+
+    fn box_contact_body(center: Vector3<f32>, velocity: Vector3<f32>, inverse_mass: f32) -> ContactBody {
+        ContactBody {
+            shape: ContactShape::Box(center, Matrix3::identity(), vec3(0.5, 0.5, 0.5)),
+            ..contact_body(center, velocity, inverse_mass)
+        }
+    }
+
+    #[test]
+    fn box_contact_shape_is_the_oriented_box() {
+        let rot = Matrix3::from_angle_y(Rad(std::f32::consts::FRAC_PI_2));
+        let transform = Transform::new(vec3(1.0, 2.0, 3.0), rot);
+        let collider = Collider::new(Shape::BoundingBox(vec3(1.0, 0.5, 0.0), vec3(0.5, 0.5, 1.0)));
+
+        match ContactShape::new(&transform, &collider) {
+            ContactShape::Box(center, shape_rot, half_extents) => {
+                assert!(center.distance(vec3(1.0, 2.5, 2.0)) < 0.0001, "{:?}", center);
+                assert_eq!(shape_rot, rot);
+                assert_eq!(half_extents, vec3(0.5, 0.5, 1.0));
+            },
+            _ => panic!("A box's contact shape should be a box")
+        }
+    }
+
+    #[test]
+    fn spheroid_box_contacts() {
+        let unit_box = ContactShape::Box(vec3(0.0, 0.0, 0.0), Matrix3::identity(), vec3(0.5, 0.5, 0.5));
+
+        // Resting on top, with its center inside, and too far away past a corner
+        let (normal, depth) = ContactShape::contact(&ContactShape::Spheroid(vec3(0.2, 0.95, 0.0), vec3(0.5, 0.5, 0.5)),
+            &unit_box).unwrap();
+        assert!(normal.distance(vec3(0.0, 1.0, 0.0)) < 0.0001, "{:?}", normal);
+        assert!((depth - 0.05).abs() < 0.0001, "{depth}");
+
+        let (normal, depth) = ContactShape::contact(&unit_box,
+            &ContactShape::Spheroid(vec3(0.0, 0.0, 0.3), vec3(0.5, 0.5, 0.25))).unwrap();
+        assert!(normal.distance(vec3(0.0, 0.0, -1.0)) < 0.0001, "{:?}", normal);
+        assert!((depth - 0.45).abs() < 0.0001, "{depth}");
+
+        assert!(ContactShape::contact(&ContactShape::Spheroid(vec3(1.0, 1.0, 0.0), vec3(0.5, 0.5, 0.5)), &unit_box)
+            .is_none());
+    }
+
+    #[test]
+    fn box_pushes_box() {
+        let mut bodies = vec![
+            box_contact_body(vec3(0.0, 0.0, 0.0), vec3(4.0, 0.0, 0.0), 1.0),
+            box_contact_body(vec3(0.9, 0.2, 0.0), vec3(0.0, 0.0, 0.0), 1.0),
+        ];
+        resolve_contacts(&mut bodies, 2);
+
+        // The boxes overlap least along x, so they get pushed apart along it by 0.05 each
+        assert!(bodies[0].velocity.distance(vec3(2.0, 0.0, 0.0)) < 0.0001, "{:?}", bodies[0].velocity);
+        assert!(bodies[1].velocity.distance(vec3(2.0, 0.0, 0.0)) < 0.0001, "{:?}", bodies[1].velocity);
+        assert!(bodies[0].push.distance(vec3(-0.05, 0.0, 0.0)) < 0.0001, "{:?}", bodies[0].push);
+        assert!(bodies[1].push.distance(vec3(0.05, 0.0, 0.0)) < 0.0001, "{:?}", bodies[1].push);
+    }
+
+    #[test]
+    fn box_rests_flat_on_the_ground() {
+        let mut world = WorldChunkManager::new(Box::new(Ground));
+        let mut collision = WorldCollision::default();
+        let shape = Shape::BoundingBox(vec3(0.0, 0.5, 0.0), vec3(0.5, 0.5, 0.5));
+        let mut transform = Transform::new(vec3(8.0, 4.0, 8.0), Matrix3::identity());
+        let mut body = RigidBody::new(10.0);
+
+        // Drop the box for two seconds
+        let time_delta = 1.0 / 60.0;
+        for _ in 0..120 {
+            body.velocity.y -= GRAVITY_ACCELERATION * time_delta;
+            let movement = body.velocity * time_delta;
+            move_body(&mut collision, &mut world, Entity::from_raw(0), &mut body, &mut transform, &shape, movement);
+        }
+
+        // The box's base is at its position, so it should be resting just above the ground
+        assert!(body.on_ground());
+        assert!(transform.pos.y >= 2.0 && transform.pos.y < 2.0 + 2.0 * MIN_DISTANCE_FROM_SURFACES,
+            "{:?}", transform.pos);
+        assert!((transform.pos.x - 8.0).abs() < 0.0001 && (transform.pos.z - 8.0).abs() < 0.0001, "{:?}", transform.pos);
+    }
+
+    #[test]
+    fn body_pushes_body() {
+        // Two touching bodies of the same mass, where one runs into the other
+        let mut bodies = vec![
+            contact_body(vec3(0.0, 0.0, 0.0), vec3(4.0, 0.0, 0.0), 1.0),
+            contact_body(vec3(0.9, 0.0, 0.0), vec3(0.0, 0.0, 0.0), 1.0),
+        ];
+        resolve_contacts(&mut bodies, 2);
+
+        // They don't bounce, so they share the speed, and get pushed apart equally
+        assert!(bodies[0].velocity.distance(vec3(2.0, 0.0, 0.0)) < 0.0001, "{:?}", bodies[0].velocity);
+        assert!(bodies[1].velocity.distance(vec3(2.0, 0.0, 0.0)) < 0.0001, "{:?}", bodies[1].velocity);
+        assert!(bodies[0].push.x < 0.0 && bodies[1].push.x > 0.0);
+        assert!((bodies[1].push.x + bodies[0].push.x).abs() < 0.0001);
+    }
+
+    #[test]
+    fn body_rests_on_unpushable_body() {
+        // A body landing slowly on something that can't be pushed, like the player
+        let mut bodies = vec![
+            contact_body(vec3(0.0, 0.95, 0.0), vec3(0.0, -0.5, 0.0), 1.0),
+            contact_body(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0), 0.0),
+        ];
+        resolve_contacts(&mut bodies, 1);
+
+        assert!(bodies[0].velocity.magnitude() < 0.0001, "{:?}", bodies[0].velocity);
+        assert!(bodies[0].push.y > 0.0);
+        assert_eq!(bodies[1].velocity, Vector3::zero());
+        assert_eq!(bodies[1].push, Vector3::zero());
+    }
+
+    #[test]
+    fn bodies_apart_have_no_contact() {
+        let mut bodies = vec![
+            contact_body(vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), 1.0),
+            contact_body(vec3(2.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0), 1.0),
+        ];
+        resolve_contacts(&mut bodies, 2);
+
+        assert_eq!(bodies[0].velocity, vec3(1.0, 0.0, 0.0));
+        assert_eq!(bodies[1].velocity, Vector3::zero());
+    }
+}
@@ -2,3 +2,4 @@ pub mod splash_screen;
 pub mod title_screen;
 pub mod main_game;
 pub mod pause_menu;
+pub mod controls_menu;
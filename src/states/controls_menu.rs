@@ -0,0 +1,148 @@
+use bevy_ecs::prelude::*;
+use cgmath::vec2;
+use dreamfield_renderer::components::TextBox;
+use dreamfield_system::resources::{InputState, InputName};
+use dreamfield_system::input_map::{InputMap, BindingError, INPUT_MAP_FILENAME};
+use crate::app_state::AppState;
+
+/// A tag component for entities we create as part of the controls menu
+#[derive(Component)]
+struct ControlsMenuEntity;
+
+/// The controls menu resource
+struct ControlsMenuResource {
+    selected: usize,
+}
+
+/// Initialize controls menu state
+pub fn init_controls_menu(stage: &mut SystemStage) {
+    stage.add_system_set(SystemSet::on_enter(AppState::ControlsMenu)
+        .with_system(enter_controls_menu));
+
+    stage.add_system_set(SystemSet::on_update(AppState::ControlsMenu)
+        .with_system(update_controls_menu));
+
+    stage.add_system_set(SystemSet::on_exit(AppState::ControlsMenu)
+        .with_system(leave_controls_menu));
+}
+
+/// Create entities when entering the controls menu
+fn enter_controls_menu(mut commands: Commands) {
+    log::info!("Entering controls menu");
+
+    commands.insert_resource(ControlsMenuResource { selected: 0 });
+
+    // Create text, this gets filled in by the update
+    commands.spawn()
+        .insert(ControlsMenuEntity)
+        .insert(TextBox::new("text", "medieval", "Vx8", "", None, vec2(10.0, 80.0), None));
+}
+
+/// Remove entities when leaving the controls menu
+fn leave_controls_menu(mut commands: Commands, mut input_map: ResMut<InputMap>,
+    query: Query<Entity, With<ControlsMenuEntity>>)
+{
+    log::info!("Leaving controls menu");
+
+    input_map.cancel_rebind();
+    commands.remove_resource::<ControlsMenuResource>();
+
+    query.for_each(|entity| {
+        commands.entity(entity).despawn();
+    });
+}
+
+/// Update the controls menu
+fn update_controls_menu(
+    mut local: ResMut<ControlsMenuResource>,
+    mut input: ResMut<InputState>,
+    mut input_map: ResMut<InputMap>,
+    mut app_state: ResMut<State<AppState>>,
+    mut query: Query<&mut TextBox, With<ControlsMenuEntity>>)
+{
+    // While waiting for a rebind, the game host swallows the next key or button press
+    if let Some(input_name) = input_map.pending_rebind() {
+        let mut text = format!("Press a key or button for {} (pause: cancel)", input_label(input_name));
+        match input_map.rebind_error() {
+            Some(BindingError::AlreadyBound(other)) => text += &format!("\n\nThat's already bound to {}", input_label(other)),
+            Some(BindingError::PauseNeedsKey) => text += "\n\nPause has to be bound to a key",
+            None => {}
+        }
+        query.for_each_mut(|mut text_box| text_box.text = text.clone());
+        return;
+    }
+
+    if input.is_just_pressed(InputName::Pause) {
+        // Clear the input so that the pause menu doesn't also see it during this update
+        input.clear_just_pressed(InputName::Pause);
+        app_state.pop().unwrap();
+        return;
+    }
+
+    // Navigate with the movement inputs, so that it works with whatever they're bound to
+    let count = InputName::ALL.len();
+    if input.is_just_pressed(InputName::CamForwards) {
+        local.selected = (local.selected + count - 1) % count;
+    }
+    if input.is_just_pressed(InputName::CamBackwards) {
+        local.selected = (local.selected + 1) % count;
+    }
+
+    if input.is_just_pressed(InputName::Jump) {
+        input_map.start_rebind(InputName::ALL[local.selected]);
+    }
+    else if input.is_just_pressed(InputName::Use) {
+        log::info!("Resetting controls to defaults");
+        reset_controls(&mut input_map, InputMap::default());
+    }
+    else if input.is_just_pressed(InputName::Run) {
+        log::info!("Resetting controls to colemak defaults");
+        reset_controls(&mut input_map, InputMap::colemak());
+    }
+
+    // Build the list of bindings
+    let mut text = "Controls (jump: rebind, use: reset defaults, run: colemak defaults, pause: back)\n\n".to_string();
+    for (i, input_name) in InputName::ALL.iter().enumerate() {
+        let bindings = input_map.bindings(*input_name)
+            .iter()
+            .map(|binding| binding.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let cursor = if i == local.selected { ">" } else { " " };
+        text += &format!("{cursor} {}: {bindings}\n", input_label(*input_name));
+    }
+
+    query.for_each_mut(|mut text_box| text_box.text = text.clone());
+}
+
+/// Reset the key and button bindings to a set of default controls and save them, keeping the mouse
+/// and gamepad settings
+fn reset_controls(input_map: &mut InputMap, defaults: InputMap) {
+    input_map.reset_bindings(&defaults);
+
+    if let Err(err) = input_map.save(INPUT_MAP_FILENAME) {
+        log::error!("Failed to save input map: {err}");
+    }
+}
+
+/// Get the label to show for an input
+fn input_label(input_name: InputName) -> &'static str {
+    match input_name {
+        InputName::CamForwards => "Forwards",
+        InputName::CamBackwards => "Backwards",
+        InputName::CamStrafeLeft => "Strafe left",
+        InputName::CamStrafeRight => "Strafe right",
+        InputName::CamLookUp => "Look up",
+        InputName::CamLookLeft => "Look left",
+        InputName::CamLookDown => "Look down",
+        InputName::CamLookRight => "Look right",
+        InputName::Run => "Run",
+        InputName::Jump => "Jump",
+        InputName::Use => "Use",
+        InputName::Debug => "Debug",
+        InputName::Pause => "Pause",
+        InputName::EnableDiagnostics => "Diagnostics",
+        InputName::Last => "",
+    }
+}
@@ -1,8 +1,9 @@
 use bevy_ecs::prelude::*;
 use cgmath::{vec2, perspective, Deg, Matrix4, vec3, Matrix3, SquareMatrix, Vector3, Vector2};
 use dreamfield_renderer::components::{PlayerCamera, Visual, Animation, DiagnosticsTextBox, TextBox, ScreenEffect, RunTime};
-use dreamfield_system::{components::{Transform, EntityName}, systems::entity_spawner::EntitySpawnRadius, resources::{InputState, InputName}};
+use dreamfield_system::{components::{Transform, EntityName}, systems::entity_spawner::{EntitySpawnRadius, EntitySpawnResource}, resources::{InputState, InputName}};
 use crate::{app_state::AppState, sim::{PlayerMovement, PlayerMovementMode, Ball}};
+use crate::save_game::{self, SaveGameSettings, SavedEntityStates, SavedPlayer};
 
 /// The player position entering the village
 const _VILLAGE_ENTRANCE: (Vector3<f32>, Vector2<f32>) = (vec3(-125.1, 5.8, 123.8), vec2(0.063, -0.5));
@@ -30,16 +31,26 @@ pub fn init_main_game(stage: &mut SystemStage) {
         .with_system(enter_main_game));
     stage.add_system_set(SystemSet::on_update(AppState::MainGame)
         .with_system(update_main_game));
+    stage.add_system_set(SystemSet::on_exit(AppState::MainGame)
+        .with_system(crate::save_game::save_game_system));
     stage.add_system_set(dreamfield_system::systems()
+        .after("sim")
         .with_run_criteria(State::<AppState>::on_update(AppState::MainGame)));
     stage.add_system_set(crate::sim::systems()
         .with_run_criteria(State::<AppState>::on_update(AppState::MainGame)));
 }
 
 /// Create main game entities when entering the main game state
-fn enter_main_game(mut commands: Commands) {
+fn enter_main_game(mut commands: Commands,
+    save_game_settings: Res<SaveGameSettings>,
+    mut spawned: ResMut<EntitySpawnResource>,
+    mut saved_states: ResMut<SavedEntityStates>)
+{
     log::info!("Entering main game");
 
+    // Load save game
+    let saved_player = save_game::load_save_game(&save_game_settings, &mut spawned, &mut saved_states);
+
     // Add resource
     commands.insert_resource(MainGameResource {
         diagnostics_entity: None,
@@ -49,13 +60,19 @@ fn enter_main_game(mut commands: Commands) {
     commands.spawn()
         .insert(ScreenEffect::new(RunTime::PreScene, "sky", Some("sky")));
 
-    // Create player
-    let (initial_pos, initial_rot) = _VILLAGE_ENTRANCE;
+    // Create player, at the entrance to the village unless there's a save game
+    let (initial_pos, initial_rot) = player_start(saved_player.as_ref());
+    let player_transform = Transform::new(initial_pos, Matrix3::identity());
+    let mut player_movement = PlayerMovement::new_pos_look(PlayerMovementMode::Normal, initial_rot);
+
+    if let Some(saved_player) = saved_player {
+        player_movement.velocity = saved_player.velocity.into();
+    }
+
     commands.spawn()
         .insert(EntityName::new("Player"))
-        // Entrance to village
-        .insert(Transform::new(initial_pos, Matrix3::identity()))
-        .insert(PlayerMovement::new_pos_look(PlayerMovementMode::Normal, initial_rot))
+        .insert(player_transform)
+        .insert(player_movement)
         .insert(PlayerMovement::collider())
         .insert(create_player_camera())
         .insert(EntitySpawnRadius::new(10.0));
@@ -67,6 +84,15 @@ fn enter_main_game(mut commands: Commands) {
         .insert(Visual::new("fire_orb", "ps1", false, Some(Animation::Loop("Orb".to_string()))));
 }
 
+/// Get where the player starts and which way they're looking, which is where they were saved if
+/// there's a save game, and the entrance to the village otherwise
+pub fn player_start(saved_player: Option<&SavedPlayer>) -> (Vector3<f32>, Vector2<f32>) {
+    match saved_player {
+        Some(saved_player) => (saved_player.pos.into(), saved_player.pitch_yaw.into()),
+        None => _VILLAGE_ENTRANCE
+    }
+}
+
 /// Update the main game
 fn update_main_game(
     mut local: ResMut<MainGameResource>,
@@ -11,7 +11,8 @@ struct PauseMenuEntity;
 /// Initialize pause menu state
 pub fn init_pause_menu(stage: &mut SystemStage) {
     stage.add_system_set(SystemSet::on_enter(AppState::Paused)
-        .with_system(enter_pause_menu));
+        .with_system(enter_pause_menu)
+        .with_system(crate::save_game::save_game_system));
 
     stage.add_system_set(SystemSet::on_update(AppState::Paused)
         .with_system(update_pause_menu));
@@ -27,7 +28,7 @@ fn enter_pause_menu(mut commands: Commands) {
     // Create text
     commands.spawn()
         .insert(PauseMenuEntity)
-        .insert(TextBox::new("text", "medieval", "Vx8", "Paused", None, vec2(10.0, 60.0), None));
+        .insert(TextBox::new("text", "medieval", "Vx8", "Paused - press use for controls", None, vec2(10.0, 60.0), None));
 }
 
 /// Create entities when leaving the pause menu
@@ -45,4 +46,9 @@ fn update_pause_menu(mut input: ResMut<InputState>, mut app_state: ResMut<State<
         input.clear_just_pressed(InputName::Pause);
         app_state.pop().unwrap();
     }
+    else if input.is_just_pressed(InputName::Use) {
+        // Clear the input so that the controls menu doesn't also see it during this update
+        input.clear_just_pressed(InputName::Use);
+        app_state.push(AppState::ControlsMenu).unwrap();
+    }
 }